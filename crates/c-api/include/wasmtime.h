@@ -183,6 +183,7 @@
 #include <wasmtime/conf.h>
 // clang-format off
 // IWYU pragma: begin_exports
+#include <wasmtime/chain.h>
 #include <wasmtime/config.h>
 #include <wasmtime/engine.h>
 #include <wasmtime/error.h>
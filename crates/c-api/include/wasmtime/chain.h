@@ -0,0 +1,209 @@
+/**
+ * \file wasmtime/chain.h
+ *
+ * \brief API for chains of events recorded by Wasmtime
+ */
+
+#ifndef WASMTIME_CHAIN_H
+#define WASMTIME_CHAIN_H
+
+#include <wasm.h>
+#include <wasmtime/conf.h>
+#include <wasmtime/error.h>
+#include <wasmtime/store.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/**
+ * \brief A hash-linked chain of events.
+ *
+ * Every event holds the hash of the event before it, so the whole history of
+ * a chain is committed to by the hash of its last event, its head.
+ *
+ * Chains created with #wasmtime_chain_new, #wasmtime_chain_open or
+ * #wasmtime_chain_from_bytes are owned by the caller and must be deleted with
+ * #wasmtime_chain_delete. The chain every store records into is borrowed
+ * with #wasmtime_context_chain instead.
+ *
+ * For more information see the Rust documentation at:
+ * https://docs.wasmtime.dev/api/wasmtime/chain/struct.Chain.html
+ */
+typedef struct wasmtime_chain wasmtime_chain_t;
+
+/**
+ * \brief The hash of an event in a chain.
+ */
+typedef struct wasmtime_chain_digest {
+  /// The bytes of the hash.
+  uint8_t bytes[32];
+} wasmtime_chain_digest_t;
+
+/**
+ * \brief Creates a new, empty chain held in memory.
+ */
+WASM_API_EXTERN wasmtime_chain_t *wasmtime_chain_new(void);
+
+/**
+ * \brief Deletes a chain.
+ */
+WASM_API_EXTERN void wasmtime_chain_delete(/* own */ wasmtime_chain_t *chain);
+
+/**
+ * \brief Opens or creates the chain file at `path`.
+ *
+ * \param path the path of the file, a nul-terminated UTF-8 string
+ * \param chain_out where the opened chain is stored on success, to be
+ *        deleted by the caller
+ *
+ * Every event added to the chain is appended to the file. Files written in
+ * older formats are upgraded as they're opened.
+ *
+ * \return An error if the file couldn't be opened or holds an invalid chain,
+ *         otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_chain_open(const char *path,
+                    /* own */ wasmtime_chain_t **chain_out);
+
+/**
+ * \brief Decodes a chain serialized with #wasmtime_chain_to_bytes.
+ *
+ * \param bytes the serialized chain
+ * \param len the length of `bytes`
+ * \param chain_out where the decoded chain is stored on success, to be
+ *        deleted by the caller
+ *
+ * \return An error if `bytes` don't hold a valid chain, otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_chain_from_bytes(const uint8_t *bytes, size_t len,
+                          /* own */ wasmtime_chain_t **chain_out);
+
+/**
+ * \brief Serializes a chain into a compact binary form.
+ *
+ * \param chain the chain to serialize
+ * \param out where the bytes are stored on success, to be deleted with
+ *        #wasm_byte_vec_delete
+ *
+ * \return An error if the chain couldn't be serialized, otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_chain_to_bytes(const wasmtime_chain_t *chain,
+                        /* own */ wasm_byte_vec_t *out);
+
+/**
+ * \brief Appends an event to a chain.
+ *
+ * \param chain the chain to append to
+ * \param type the type of the event, a UTF-8 string
+ * \param type_len the length of `type`
+ * \param data the payload of the event
+ * \param data_len the length of `data`
+ * \param hash_out where the hash of the new event is stored on success, may
+ *        be `NULL`
+ *
+ * \return An error if the event was refused, for example by the chain's
+ *         schemas, or couldn't be persisted, otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_chain_add(wasmtime_chain_t *chain, const char *type, size_t type_len,
+                   const uint8_t *data, size_t data_len,
+                   wasmtime_chain_digest_t *hash_out);
+
+/**
+ * \brief Returns the number of events in a chain.
+ */
+WASM_API_EXTERN size_t wasmtime_chain_len(const wasmtime_chain_t *chain);
+
+/**
+ * \brief Gets the hash of the last event of a chain.
+ *
+ * \return `false` if the chain is empty, otherwise `true` after storing the
+ *         hash in `hash_out`.
+ */
+WASM_API_EXTERN bool wasmtime_chain_head(const wasmtime_chain_t *chain,
+                                         wasmtime_chain_digest_t *hash_out);
+
+/**
+ * \brief Looks up an event of a chain by its hash.
+ *
+ * \param chain the chain to look in
+ * \param hash the hash of the event
+ * \param type_out where the type of the event is stored, to be deleted with
+ *        #wasm_name_delete
+ * \param data_out where the payload of the event is stored, to be deleted
+ *        with #wasm_byte_vec_delete
+ * \param parent_out where the hash of the event before it is stored, all
+ *        zeros for the first event, may be `NULL`
+ *
+ * \return `false` if no event has the hash, in which case none of the
+ *         outputs are written, otherwise `true`.
+ */
+WASM_API_EXTERN bool wasmtime_chain_get(const wasmtime_chain_t *chain,
+                                        const wasmtime_chain_digest_t *hash,
+                                        /* own */ wasm_name_t *type_out,
+                                        /* own */ wasm_byte_vec_t *data_out,
+                                        wasmtime_chain_digest_t *parent_out);
+
+/**
+ * \brief Gets the event at `index` in a chain, the first being at index 0.
+ *
+ * This is like #wasmtime_chain_get, but also stores the hash of the event in
+ * `hash_out`.
+ *
+ * \return `false` if the chain has no more than `index` events, otherwise
+ *         `true`.
+ */
+WASM_API_EXTERN bool wasmtime_chain_nth(const wasmtime_chain_t *chain,
+                                        size_t index,
+                                        wasmtime_chain_digest_t *hash_out,
+                                        /* own */ wasm_name_t *type_out,
+                                        /* own */ wasm_byte_vec_t *data_out,
+                                        wasmtime_chain_digest_t *parent_out);
+
+/**
+ * \brief Checks that every event of a chain links to the one before it and
+ * hashes to its recorded hash.
+ *
+ * \return An error describing the first broken link, otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_chain_verify(const wasmtime_chain_t *chain);
+
+/**
+ * \brief Flushes the events appended to a persisted chain through to its
+ * storage.
+ *
+ * \return An error if they couldn't be flushed, otherwise `NULL`.
+ */
+WASM_API_EXTERN wasmtime_error_t *wasmtime_chain_flush(wasmtime_chain_t *chain);
+
+/**
+ * \brief Returns the chain a store records into.
+ *
+ * The chain is borrowed from the store and must not be deleted. It's valid
+ * until the store is next used mutably, and events must be added to it with
+ * #wasmtime_context_chain_add.
+ */
+WASM_API_EXTERN const wasmtime_chain_t *
+wasmtime_context_chain(const wasmtime_context_t *context);
+
+/**
+ * \brief Appends an event to the chain a store records into.
+ *
+ * This is like #wasmtime_chain_add for the chain returned by
+ * #wasmtime_context_chain.
+ */
+WASM_API_EXTERN wasmtime_error_t *
+wasmtime_context_chain_add(wasmtime_context_t *context, const char *type,
+                           size_t type_len, const uint8_t *data,
+                           size_t data_len, wasmtime_chain_digest_t *hash_out);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif
+
+#endif // WASMTIME_CHAIN_H
@@ -0,0 +1,224 @@
+use crate::{
+    bad_utf8, handle_result, wasm_byte_vec_t, wasm_name_t, wasmtime_error_t, WasmtimeStoreContext,
+    WasmtimeStoreContextMut,
+};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::str;
+use wasmtime::chain::{Chain, Digest, Event, MetaEvent};
+
+/// A chain of events. Owned chains are created with `wasmtime_chain_new` and
+/// friends, while the chain of a store is borrowed through its context.
+#[repr(transparent)]
+pub struct wasmtime_chain_t {
+    chain: Chain,
+}
+
+wasmtime_c_api_macros::declare_own!(wasmtime_chain_t);
+
+impl wasmtime_chain_t {
+    fn from_chain(chain: &Chain) -> &wasmtime_chain_t {
+        // SAFETY: `wasmtime_chain_t` is a transparent wrapper of `Chain`.
+        unsafe { &*(chain as *const Chain).cast() }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct wasmtime_chain_digest_t {
+    pub bytes: [u8; 32],
+}
+
+impl From<Digest> for wasmtime_chain_digest_t {
+    fn from(digest: Digest) -> wasmtime_chain_digest_t {
+        wasmtime_chain_digest_t { bytes: digest.0 }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_new() -> Box<wasmtime_chain_t> {
+    Box::new(wasmtime_chain_t {
+        chain: Chain::new(),
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasmtime_chain_open(
+    path: *const c_char,
+    out: &mut *mut wasmtime_chain_t,
+) -> Option<Box<wasmtime_error_t>> {
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return bad_utf8(),
+    };
+    handle_result(Chain::open(path), |chain| {
+        *out = Box::into_raw(Box::new(wasmtime_chain_t { chain }));
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasmtime_chain_from_bytes(
+    bytes: *const u8,
+    len: usize,
+    out: &mut *mut wasmtime_chain_t,
+) -> Option<Box<wasmtime_error_t>> {
+    let bytes = crate::slice_from_raw_parts(bytes, len);
+    handle_result(Chain::from_bytes(bytes), |chain| {
+        *out = Box::into_raw(Box::new(wasmtime_chain_t { chain }));
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_to_bytes(
+    chain: &wasmtime_chain_t,
+    out: &mut wasm_byte_vec_t,
+) -> Option<Box<wasmtime_error_t>> {
+    handle_result(chain.chain.to_bytes(), |bytes| out.set_buffer(bytes))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasmtime_chain_add(
+    chain: &mut wasmtime_chain_t,
+    event_type: *const u8,
+    event_type_len: usize,
+    data: *const u8,
+    data_len: usize,
+    hash: Option<&mut wasmtime_chain_digest_t>,
+) -> Option<Box<wasmtime_error_t>> {
+    add(
+        &mut chain.chain,
+        event_type,
+        event_type_len,
+        data,
+        data_len,
+        hash,
+    )
+}
+
+unsafe fn add(
+    chain: &mut Chain,
+    event_type: *const u8,
+    event_type_len: usize,
+    data: *const u8,
+    data_len: usize,
+    hash: Option<&mut wasmtime_chain_digest_t>,
+) -> Option<Box<wasmtime_error_t>> {
+    let event_type = match str::from_utf8(crate::slice_from_raw_parts(event_type, event_type_len)) {
+        Ok(event_type) => event_type,
+        Err(_) => return bad_utf8(),
+    };
+    let data = crate::slice_from_raw_parts(data, data_len).to_vec();
+    handle_result(
+        chain.try_add(Event::new(event_type.to_string(), data)),
+        |digest| {
+            if let Some(hash) = hash {
+                *hash = digest.into();
+            }
+        },
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_len(chain: &wasmtime_chain_t) -> usize {
+    chain.chain.len()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_head(
+    chain: &wasmtime_chain_t,
+    hash: &mut wasmtime_chain_digest_t,
+) -> bool {
+    match chain.chain.head() {
+        Some(head) => {
+            *hash = head.into();
+            true
+        }
+        None => false,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_get(
+    chain: &wasmtime_chain_t,
+    hash: &wasmtime_chain_digest_t,
+    event_type: &mut wasm_name_t,
+    data: &mut wasm_byte_vec_t,
+    parent: Option<&mut wasmtime_chain_digest_t>,
+) -> bool {
+    match chain.chain.get_event_by_hash(Digest(hash.bytes)) {
+        Some(node) => {
+            read(node, event_type, data, parent);
+            true
+        }
+        None => false,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_nth(
+    chain: &wasmtime_chain_t,
+    index: usize,
+    hash: &mut wasmtime_chain_digest_t,
+    event_type: &mut wasm_name_t,
+    data: &mut wasm_byte_vec_t,
+    parent: Option<&mut wasmtime_chain_digest_t>,
+) -> bool {
+    match chain.chain.store().get(index) {
+        Some(node) => {
+            *hash = node.hash().into();
+            read(node, event_type, data, parent);
+            true
+        }
+        None => false,
+    }
+}
+
+fn read(
+    node: &MetaEvent,
+    event_type: &mut wasm_name_t,
+    data: &mut wasm_byte_vec_t,
+    parent: Option<&mut wasmtime_chain_digest_t>,
+) {
+    let event = node.event();
+    event_type.set_buffer(event.type_().as_bytes().to_vec());
+    data.set_buffer(event.data().into_owned());
+    if let Some(parent) = parent {
+        *parent = event.parent().unwrap_or_default().into();
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_verify(chain: &wasmtime_chain_t) -> Option<Box<wasmtime_error_t>> {
+    handle_result(chain.chain.verify().map_err(Into::into), |()| {})
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_chain_flush(
+    chain: &mut wasmtime_chain_t,
+) -> Option<Box<wasmtime_error_t>> {
+    handle_result(chain.chain.flush(), |()| {})
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wasmtime_context_chain(store: WasmtimeStoreContext<'_>) -> &wasmtime_chain_t {
+    wasmtime_chain_t::from_chain(store.chain())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasmtime_context_chain_add(
+    mut store: WasmtimeStoreContextMut<'_>,
+    event_type: *const u8,
+    event_type_len: usize,
+    data: *const u8,
+    data_len: usize,
+    hash: Option<&mut wasmtime_chain_digest_t>,
+) -> Option<Box<wasmtime_error_t>> {
+    add(
+        store.chain_mut(),
+        event_type,
+        event_type_len,
+        data,
+        data_len,
+        hash,
+    )
+}
@@ -16,6 +16,7 @@
 
 pub use wasmtime;
 
+mod chain;
 mod config;
 mod engine;
 mod error;
@@ -37,6 +38,7 @@ mod types;
 mod val;
 mod vec;
 
+pub use crate::chain::*;
 pub use crate::config::*;
 pub use crate::engine::*;
 pub use crate::error::*;
@@ -0,0 +1,153 @@
+//! # Guest access to Wasmtime event chains
+//!
+//! This crate provides a host implementation of the `wasmtime:chain` WIT
+//! interfaces defined in this crate's `wit` directory. With it, components
+//! can append events to a [`Chain`] kept by the host and walk its history
+//! from inside the guest.
+//!
+//! The usage of this crate is very similar to WASI API implementations such
+//! as `wasmtime-wasi-config`: the host decides which chain a component sees
+//! through a closure passed to [`add_to_linker`].
+//!
+//! ```
+//! use wasmtime::chain::Chain;
+//! use wasmtime::component::Linker;
+//! use wasmtime::{Engine, Result, Store};
+//! use wasmtime_chain::GuestChain;
+//!
+//! fn main() -> Result<()> {
+//!     let engine = Engine::default();
+//!     let mut store = Store::new(&engine, Ctx { chain: Chain::new() });
+//!
+//!     let mut linker = Linker::<Ctx>::new(&engine);
+//!     wasmtime_chain::add_to_linker(&mut linker, |h: &mut Ctx| {
+//!         GuestChain::new(&mut h.chain)
+//!     })?;
+//!
+//!     // ... use `linker` to instantiate within `store` ...
+//!
+//!     Ok(())
+//! }
+//!
+//! struct Ctx {
+//!     chain: Chain,
+//! }
+//! ```
+
+#![deny(missing_docs)]
+
+use anyhow::{bail, Result};
+use wasmtime::chain::{Chain, Digest, Event, MetaEvent};
+use wasmtime::component::Resource;
+
+mod gen_ {
+    ::wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasmtime:chain/host",
+        trappable_imports: true,
+        // The `wasmtime` package name would otherwise shadow the crate.
+        wasmtime_crate: ::wasmtime,
+        with: {
+            "wasmtime:chain/chain/chain": crate::ChainHandle,
+        },
+    });
+}
+use self::gen_::wasmtime::chain::chain as generated;
+
+/// Host representation of the `chain` resource.
+///
+/// Every handle refers to the chain of the [`GuestChain`] it was handed out
+/// by, so handles carry no state of their own.
+pub struct ChainHandle;
+
+/// A wrapper capturing the chain a component has access to.
+pub struct GuestChain<'a> {
+    chain: &'a mut Chain,
+}
+
+impl<'a> GuestChain<'a> {
+    /// Create a new view giving a component access to `chain`.
+    pub fn new(chain: &'a mut Chain) -> Self {
+        Self { chain }
+    }
+}
+
+impl<'a> From<&'a mut Chain> for GuestChain<'a> {
+    fn from(chain: &'a mut Chain) -> Self {
+        Self { chain }
+    }
+}
+
+fn digest(hash: &[u8]) -> Result<Digest> {
+    match <[u8; Digest::LEN]>::try_from(hash) {
+        Ok(bytes) => Ok(Digest(bytes)),
+        Err(_) => bail!("digest must be {} bytes, found {}", Digest::LEN, hash.len()),
+    }
+}
+
+fn to_wit(node: &MetaEvent) -> generated::Event {
+    let event = node.event();
+    generated::Event {
+        hash: node.hash().as_bytes().to_vec(),
+        parent: event.parent().map(|p| p.as_bytes().to_vec()),
+        event_type: event.type_().to_string(),
+        data: event.data().to_vec(),
+    }
+}
+
+impl generated::Host for GuestChain<'_> {
+    fn current(&mut self) -> Result<Resource<ChainHandle>> {
+        Ok(Resource::new_own(0))
+    }
+}
+
+impl generated::HostChain for GuestChain<'_> {
+    fn add_event(
+        &mut self,
+        _: Resource<ChainHandle>,
+        event_type: String,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let hash = self.chain.try_add(Event::new(event_type, data))?;
+        Ok(hash.as_bytes().to_vec())
+    }
+
+    fn head(&mut self, _: Resource<ChainHandle>) -> Result<Option<Vec<u8>>> {
+        Ok(self.chain.head().map(|h| h.as_bytes().to_vec()))
+    }
+
+    fn len(&mut self, _: Resource<ChainHandle>) -> Result<u64> {
+        Ok(u64::try_from(self.chain.len())?)
+    }
+
+    fn get_event(
+        &mut self,
+        _: Resource<ChainHandle>,
+        hash: Vec<u8>,
+    ) -> Result<Option<generated::Event>> {
+        let hash = digest(&hash)?;
+        Ok(self.chain.get_event_by_hash(hash).map(to_wit))
+    }
+
+    fn get_parent(
+        &mut self,
+        _: Resource<ChainHandle>,
+        hash: Vec<u8>,
+    ) -> Result<Option<generated::Event>> {
+        let hash = digest(&hash)?;
+        Ok(self.chain.get_parent(hash).map(to_wit))
+    }
+
+    fn drop(&mut self, _: Resource<ChainHandle>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Add the `wasmtime:chain/chain` interface to a [`wasmtime::component::Linker`].
+pub fn add_to_linker<T>(
+    l: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> GuestChain<'_> + Send + Sync + Copy + 'static,
+) -> Result<()> {
+    generated::add_to_linker_get_host(l, f)?;
+    Ok(())
+}
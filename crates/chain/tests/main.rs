@@ -0,0 +1,89 @@
+use anyhow::Result;
+use wasmtime::chain::Chain;
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Store};
+use wasmtime_chain::GuestChain;
+
+struct Ctx {
+    chain: Chain,
+}
+
+const GUEST: &str = r#"
+    (component
+        (import "wasmtime:chain/chain@0.1.0" (instance $chain
+            (export $c "chain" (type (sub resource)))
+            (export "current" (func (result (own $c))))
+            (export "[method]chain.add-event" (func (param "self" (borrow $c)) (param "event-type" string) (param "data" (list u8)) (result (list u8))))
+            (export "[method]chain.head" (func (param "self" (borrow $c)) (result (option (list u8)))))
+        ))
+        (alias export $chain "chain" (type $chain-type))
+        (core module $libc
+            (memory (export "memory") 1)
+            (global $bump (mut i32) (i32.const 1024))
+            (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                (local $ret i32)
+                (local.set $ret (global.get $bump))
+                (global.set $bump (i32.add (global.get $bump) (local.get 3)))
+                (local.get $ret))
+            (data (i32.const 0) "greeting")
+            (data (i32.const 16) "hello"))
+        (core instance $libc (instantiate $libc))
+        (core func $current (canon lower (func $chain "current")))
+        (core func $drop (canon resource.drop $chain-type))
+        (core func $add-event (canon lower (func $chain "[method]chain.add-event")
+            (memory $libc "memory") (realloc (func $libc "realloc"))))
+        (core func $head (canon lower (func $chain "[method]chain.head")
+            (memory $libc "memory") (realloc (func $libc "realloc"))))
+        (core module $m
+            (import "libc" "memory" (memory 1))
+            (import "chain" "current" (func $current (result i32)))
+            (import "chain" "drop" (func $drop (param i32)))
+            (import "chain" "add-event" (func $add-event (param i32 i32 i32 i32 i32 i32)))
+            (import "chain" "head" (func $head (param i32 i32)))
+            (func (export "run") (result i32)
+                (local $chain i32)
+                (local.set $chain (call $current))
+                (call $add-event (local.get $chain)
+                    (i32.const 0) (i32.const 8) (i32.const 16) (i32.const 5) (i32.const 32))
+                (call $head (local.get $chain) (i32.const 48))
+                (call $drop (local.get $chain))
+                ;; the head is the digest `add-event` returned
+                (if (i32.ne (i32.load8_u (i32.const 48)) (i32.const 1)) (then unreachable))
+                (if (i32.ne (i32.load (i32.load (i32.const 32))) (i32.load (i32.load (i32.const 52))))
+                    (then unreachable))
+                (i32.load (i32.const 36))))
+        (core instance $i (instantiate $m
+            (with "libc" (instance $libc))
+            (with "chain" (instance
+                (export "current" (func $current))
+                (export "drop" (func $drop))
+                (export "add-event" (func $add-event))
+                (export "head" (func $head))))))
+        (func (export "run") (result u32) (canon lift (core func $i "run")))
+    )
+"#;
+
+#[test]
+fn guest_appends_events() -> Result<()> {
+    let engine = Engine::default();
+    let component = Component::new(&engine, GUEST)?;
+    let mut linker = Linker::<Ctx>::new(&engine);
+    wasmtime_chain::add_to_linker(&mut linker, |h: &mut Ctx| GuestChain::new(&mut h.chain))?;
+
+    let mut store = Store::new(
+        &engine,
+        Ctx {
+            chain: Chain::new(),
+        },
+    );
+    let instance = linker.instantiate(&mut store, &component)?;
+    let run = instance.get_typed_func::<(), (u32,)>(&mut store, "run")?;
+    let (len,) = run.call(&mut store, ())?;
+    assert_eq!(len, 32);
+
+    let chain = &store.data().chain;
+    let head = chain.get_event_by_hash(chain.head().unwrap()).unwrap();
+    assert_eq!(head.event().type_(), "greeting");
+    assert_eq!(head.event().data(), &b"hello"[..]);
+    Ok(())
+}
@@ -18,6 +18,9 @@ pub struct ComponentArtifacts {
     pub types: ComponentTypes,
     /// Serialized metadata about all included core wasm modules.
     pub static_modules: PrimaryMap<StaticModuleIndex, CompiledModuleInfo>,
+    /// SHA-256 digest of the component's binary as it was compiled, if the
+    /// engine compiling it records instantiations.
+    pub sha256: Option<[u8; 32]>,
 }
 
 /// Runtime state that a component retains to support its operation.
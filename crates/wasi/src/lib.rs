@@ -200,6 +200,7 @@ pub mod preview0;
 #[cfg(feature = "preview1")]
 pub mod preview1;
 mod random;
+mod replay;
 pub mod runtime;
 mod stdio;
 mod stream;
@@ -214,6 +215,7 @@ pub use self::filesystem::{DirPerms, FileInputStream, FilePerms, FsError, FsResu
 pub use self::network::{Network, SocketAddrUse, SocketError, SocketResult};
 pub use self::poll::{subscribe, ClosureFuture, MakeFuture, Pollable, PollableFuture, Subscribe};
 pub use self::random::{thread_rng, Deterministic};
+pub use self::replay::ChainReplay;
 pub use self::stdio::{
     stderr, stdin, stdout, AsyncStdinStream, AsyncStdoutStream, IsATTY, OutputFile, Stderr, Stdin,
     StdinStream, Stdout, StdoutStream,
@@ -0,0 +1,329 @@
+//! Clocks and randomness answered from a recorded [`Chain`].
+//!
+//! A chain recorded with `Config::chain_record_wasi` enabled holds the value
+//! every `wasi:clocks` and `wasi:random` call returned. Installing a
+//! [`ChainReplay`] into a [`WasiCtxBuilder`] makes those interfaces return the
+//! recorded values again, in order, so a component which reads the clock or
+//! draws random numbers behaves the same way when it's replayed.
+
+use crate::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+use anyhow::{bail, Result};
+use cap_rand::RngCore;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wasmtime::chain::{record, Chain, ImportReturn, SerializableVal};
+
+/// Values recorded for each replayed WASI function, oldest first.
+#[derive(Default)]
+struct Recorded {
+    wall_now: VecDeque<Duration>,
+    wall_resolution: VecDeque<Duration>,
+    monotonic_now: VecDeque<u64>,
+    monotonic_resolution: VecDeque<u64>,
+    random: VecDeque<u64>,
+    insecure_random: VecDeque<u64>,
+    insecure_seed: Option<u128>,
+}
+
+/// WASI clocks and random number generators which replay the values recorded
+/// in a chain.
+///
+/// Once a recorded value has been handed out it's gone, so each kind of value
+/// is replayed once in the order it was recorded. When the recording runs out
+/// clocks keep returning the last recorded time and generators return zeros;
+/// a replay with chain recording enabled reports that as a divergence.
+#[derive(Clone)]
+pub struct ChainReplay {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+impl ChainReplay {
+    /// Collects the results of the clock and random calls recorded in
+    /// `chain`.
+    pub fn new(chain: &Chain) -> Result<ChainReplay> {
+        let mut recorded = Recorded::default();
+        for node in chain.store().iter_from(0) {
+            if node.event().type_() != record::IMPORT_RETURN {
+                continue;
+            }
+            let ret = ImportReturn::decode(node.event())?;
+            let Some(results) = &ret.results else {
+                continue;
+            };
+            let Some((interface, func)) = ret.name.split_once('#') else {
+                continue;
+            };
+            let interface = interface.split('@').next().unwrap_or(interface);
+            match (interface, func, &results[..]) {
+                ("wasi:clocks/wall-clock", "now", [time]) => {
+                    recorded.wall_now.push_back(datetime(time)?);
+                }
+                ("wasi:clocks/wall-clock", "resolution", [time]) => {
+                    recorded.wall_resolution.push_back(datetime(time)?);
+                }
+                ("wasi:clocks/monotonic-clock", "now", [SerializableVal::U64(n)]) => {
+                    recorded.monotonic_now.push_back(*n);
+                }
+                ("wasi:clocks/monotonic-clock", "resolution", [SerializableVal::U64(n)]) => {
+                    recorded.monotonic_resolution.push_back(*n);
+                }
+                ("wasi:random/random", "get-random-bytes", [bytes]) => {
+                    recorded.random.extend(draws(bytes)?);
+                }
+                ("wasi:random/random", "get-random-u64", [SerializableVal::U64(n)]) => {
+                    recorded.random.push_back(*n);
+                }
+                ("wasi:random/insecure", "get-insecure-random-bytes", [bytes]) => {
+                    recorded.insecure_random.extend(draws(bytes)?);
+                }
+                ("wasi:random/insecure", "get-insecure-random-u64", [SerializableVal::U64(n)]) => {
+                    recorded.insecure_random.push_back(*n);
+                }
+                ("wasi:random/insecure-seed", "insecure-seed", [SerializableVal::Tuple(t)]) => {
+                    if let [SerializableVal::U64(lo), SerializableVal::U64(hi)] = &t[..] {
+                        recorded.insecure_seed = Some(u128::from(*lo) | (u128::from(*hi) << 64));
+                    }
+                }
+                (i, f, _) if i.starts_with("wasi:clocks/") || i.starts_with("wasi:random/") => {
+                    bail!("unexpected results recorded for `{i}#{f}`")
+                }
+                _ => {}
+            }
+        }
+        Ok(ChainReplay {
+            recorded: Arc::new(Mutex::new(recorded)),
+        })
+    }
+
+    /// Configures `builder` to answer `wasi:clocks` and `wasi:random` calls
+    /// from the recording.
+    pub fn install<'a>(&self, builder: &'a mut WasiCtxBuilder) -> &'a mut WasiCtxBuilder {
+        builder
+            .wall_clock(self.wall_clock())
+            .monotonic_clock(self.monotonic_clock())
+            .secure_random(self.random())
+            .insecure_random(self.insecure_random());
+        if let Some(seed) = self.recorded.lock().unwrap().insecure_seed {
+            builder.insecure_random_seed(seed);
+        }
+        builder
+    }
+
+    /// A `wasi:clocks/wall-clock` returning the recorded times.
+    pub fn wall_clock(&self) -> impl HostWallClock {
+        ReplayClock {
+            recorded: self.recorded.clone(),
+            last: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// A `wasi:clocks/monotonic-clock` returning the recorded instants.
+    pub fn monotonic_clock(&self) -> impl HostMonotonicClock {
+        ReplayClock {
+            recorded: self.recorded.clone(),
+            last: Mutex::new(0),
+        }
+    }
+
+    /// A generator for `wasi:random/random` returning the recorded values.
+    pub fn random(&self) -> impl RngCore + Send {
+        ReplayRandom {
+            recorded: self.recorded.clone(),
+            insecure: false,
+        }
+    }
+
+    /// A generator for `wasi:random/insecure` returning the recorded values.
+    pub fn insecure_random(&self) -> impl RngCore + Send {
+        ReplayRandom {
+            recorded: self.recorded.clone(),
+            insecure: true,
+        }
+    }
+}
+
+fn datetime(val: &SerializableVal) -> Result<Duration> {
+    if let SerializableVal::Record(fields) = val {
+        if let [(_, SerializableVal::U64(seconds)), (_, SerializableVal::U32(nanos))] = &fields[..]
+        {
+            return Ok(Duration::new(*seconds, *nanos));
+        }
+    }
+    bail!("expected a recorded `datetime`")
+}
+
+/// The host draws one value per random byte, see `host/random.rs`, so each
+/// recorded byte is replayed as a separate draw.
+fn draws(bytes: &SerializableVal) -> Result<Vec<u64>> {
+    let SerializableVal::List(bytes) = bytes else {
+        bail!("expected recorded random bytes");
+    };
+    bytes
+        .iter()
+        .map(|b| match b {
+            SerializableVal::U8(b) => Ok(u64::from(*b)),
+            _ => bail!("expected recorded random bytes"),
+        })
+        .collect()
+}
+
+struct ReplayClock<T> {
+    recorded: Arc<Mutex<Recorded>>,
+    last: Mutex<T>,
+}
+
+impl HostWallClock for ReplayClock<Duration> {
+    fn resolution(&self) -> Duration {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded
+            .wall_resolution
+            .pop_front()
+            .unwrap_or(Duration::from_nanos(1))
+    }
+
+    fn now(&self) -> Duration {
+        let mut last = self.last.lock().unwrap();
+        if let Some(now) = self.recorded.lock().unwrap().wall_now.pop_front() {
+            *last = now;
+        }
+        *last
+    }
+}
+
+impl HostMonotonicClock for ReplayClock<u64> {
+    fn resolution(&self) -> u64 {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.monotonic_resolution.pop_front().unwrap_or(1)
+    }
+
+    fn now(&self) -> u64 {
+        let mut last = self.last.lock().unwrap();
+        if let Some(now) = self.recorded.lock().unwrap().monotonic_now.pop_front() {
+            *last = now;
+        }
+        *last
+    }
+}
+
+struct ReplayRandom {
+    recorded: Arc<Mutex<Recorded>>,
+    insecure: bool,
+}
+
+impl ReplayRandom {
+    fn next(&mut self) -> u64 {
+        let mut recorded = self.recorded.lock().unwrap();
+        let draws = if self.insecure {
+            &mut recorded.insecure_random
+        } else {
+            &mut recorded.random
+        };
+        draws.pop_front().unwrap_or(0)
+    }
+}
+
+impl RngCore for ReplayRandom {
+    fn next_u32(&mut self) -> u32 {
+        self.next() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.next()
+    }
+
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            *b = self.next() as u8;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), cap_rand::Error> {
+        self.fill_bytes(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cap_rand::{distributions::Standard, Rng};
+    use wasmtime::chain::{Event, ImportCall};
+
+    fn import(chain: &mut Chain, name: &str, results: Vec<SerializableVal>) {
+        let call = ImportCall {
+            name: name.to_string(),
+            params: Some(Vec::new()),
+            call_parent: None,
+        };
+        let ret = ImportReturn {
+            name: name.to_string(),
+            results: Some(results),
+            call_parent: None,
+        };
+        chain.add(Event::new(
+            record::IMPORT_CALL.to_string(),
+            serde_json::to_vec(&call).unwrap(),
+        ));
+        chain.add(Event::new(
+            record::IMPORT_RETURN.to_string(),
+            serde_json::to_vec(&ret).unwrap(),
+        ));
+    }
+
+    #[test]
+    fn replays_recorded_values() -> Result<()> {
+        let mut chain = Chain::new();
+        let datetime = |s| {
+            SerializableVal::Record(vec![
+                ("seconds".to_string(), SerializableVal::U64(s)),
+                ("nanoseconds".to_string(), SerializableVal::U32(5)),
+            ])
+        };
+        import(
+            &mut chain,
+            "wasi:clocks/wall-clock@0.2.3#now",
+            vec![datetime(10)],
+        );
+        import(
+            &mut chain,
+            "wasi:clocks/wall-clock@0.2.3#now",
+            vec![datetime(11)],
+        );
+        import(
+            &mut chain,
+            "wasi:clocks/monotonic-clock@0.2.3#now",
+            vec![SerializableVal::U64(99)],
+        );
+        import(
+            &mut chain,
+            "wasi:random/random@0.2.3#get-random-bytes",
+            vec![SerializableVal::List(vec![
+                SerializableVal::U8(7),
+                SerializableVal::U8(8),
+            ])],
+        );
+        import(
+            &mut chain,
+            "wasi:random/random@0.2.3#get-random-u64",
+            vec![SerializableVal::U64(u64::MAX)],
+        );
+
+        let replay = ChainReplay::new(&chain)?;
+        let wall = replay.wall_clock();
+        assert_eq!(wall.now(), Duration::new(10, 5));
+        assert_eq!(wall.now(), Duration::new(11, 5));
+        assert_eq!(wall.now(), Duration::new(11, 5));
+        assert_eq!(replay.monotonic_clock().now(), 99);
+
+        let mut random = replay.random();
+        let bytes = (&mut random)
+            .sample_iter(Standard)
+            .take(2)
+            .collect::<Vec<u8>>();
+        assert_eq!(bytes, [7, 8]);
+        assert_eq!(random.sample::<u64, _>(Standard), u64::MAX);
+        assert_eq!(random.next_u64(), 0);
+        Ok(())
+    }
+}
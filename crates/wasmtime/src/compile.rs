@@ -131,6 +131,7 @@ pub(crate) fn build_component_artifacts<T: FinishedObject>(
     _dwarf_package: Option<&[u8]>,
     obj_state: &T::State,
 ) -> Result<(T, Option<wasmtime_environ::component::ComponentArtifacts>)> {
+    use sha2::Digest as _;
     use wasmtime_environ::component::{
         CompiledComponentInfo, ComponentArtifacts, ComponentTypesBuilder,
     };
@@ -185,6 +186,10 @@ pub(crate) fn build_component_artifacts<T: FinishedObject>(
         ty,
         types,
         static_modules: compilation_artifacts.modules,
+        sha256: engine
+            .config()
+            .chain_records_instantiation()
+            .then(|| sha2::Sha256::digest(binary).into()),
     };
     object.serialize_info(&artifacts);
 
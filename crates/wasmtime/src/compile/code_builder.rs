@@ -310,6 +310,8 @@ impl std::hash::Hash for HashedEngineCompileEnv<'_> {
         self.0.tunables().hash(hasher);
         self.0.features().hash(hasher);
         config.wmemcheck.hash(hasher);
+        #[cfg(feature = "component-model")]
+        config.chain_records_instantiation().hash(hasher);
 
         // Catch accidental bugs of reusing across crate versions.
         config.module_version.hash(hasher);
@@ -1,3 +1,4 @@
+use crate::chain::ChainWasiPolicy;
 use crate::hash_map::HashMap;
 use crate::hash_set::HashSet;
 use crate::prelude::*;
@@ -163,6 +164,15 @@ pub struct Config {
     pub(crate) force_memory_init_memfd: bool,
     pub(crate) wmemcheck: bool,
     pub(crate) coredump_on_trap: bool,
+    pub(crate) chain_record: bool,
+    pub(crate) chain_wasi_policy: ChainWasiPolicy,
+    pub(crate) chain_per_instance: bool,
+    pub(crate) chain_record_fuel: bool,
+    pub(crate) chain_record_instantiation: bool,
+    pub(crate) chain_record_post_return: bool,
+    pub(crate) chain_record_types: bool,
+    pub(crate) chain_record_allow: Vec<String>,
+    pub(crate) chain_record_deny: Vec<String>,
     pub(crate) macos_use_mach_ports: bool,
     pub(crate) detect_host_feature: Option<fn(&str) -> Option<bool>>,
 }
@@ -265,6 +275,15 @@ impl Config {
             force_memory_init_memfd: false,
             wmemcheck: false,
             coredump_on_trap: false,
+            chain_record: false,
+            chain_wasi_policy: ChainWasiPolicy::none(),
+            chain_per_instance: false,
+            chain_record_fuel: false,
+            chain_record_instantiation: false,
+            chain_record_post_return: false,
+            chain_record_types: false,
+            chain_record_allow: Vec::new(),
+            chain_record_deny: Vec::new(),
             macos_use_mach_ports: !cfg!(miri),
             #[cfg(feature = "std")]
             detect_host_feature: Some(detect_host_feature),
@@ -1885,6 +1904,245 @@ impl Config {
         self
     }
 
+    /// Configures whether component calls are automatically recorded into
+    /// each store's [`Chain`](crate::chain::Chain).
+    ///
+    /// When enabled every successful call made through
+    /// [`component::Func::call`](crate::component::Func::call) appends a
+    /// `function-call` event holding the export's name along with its
+    /// parameters and results as
+    /// [`SerializableVal`](crate::chain::SerializableVal)s. Resources passed
+    /// or returned are registered with the store's
+    /// [`ResourceRegistry`](crate::chain::ResourceRegistry) as they're seen,
+    /// and their creation, transfer across the boundary and drop are recorded
+    /// as `resource-*` events.
+    /// Calls which trap or fail instead append a `trap` event, see
+    /// [`CallTrap`](crate::chain::CallTrap). Epoch deadlines being reached
+    /// and wasm yielding to the async executor are recorded as
+    /// `epoch-interrupt`, `yield` and `resume` events, and wasm growing a
+    /// memory or table as `memory-grow` and `table-grow` events. The chain
+    /// can be read back with [`Store::chain`](crate::Store::chain).
+    ///
+    /// This option is disabled by default.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record(&mut self, enable: bool) -> &mut Self {
+        self.chain_record = enable;
+        self
+    }
+
+    /// Configures whether calls to WASI imports are recorded when
+    /// [`Config::chain_record`] is enabled.
+    ///
+    /// WASI imports are those defined in `wasi:*` interfaces, such as the
+    /// ones added by `wasmtime_wasi::add_to_linker_sync`. Like other imports
+    /// they're recorded as `import-call` and `import-return` events holding
+    /// their arguments and results, except for functions whose signature
+    /// involves resources, such as most of `wasi:filesystem` and
+    /// `wasi:sockets`, which are recorded without values. Recording clocks
+    /// and randomness lets a chain be replayed deterministically.
+    ///
+    /// WASI calls can be very frequent, so this option is disabled by
+    /// default and has no effect unless `chain_record` is also enabled.
+    /// Enabling it records every WASI package, and
+    /// [`Config::chain_wasi_policy`] picks some of them instead.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_wasi(&mut self, enable: bool) -> &mut Self {
+        self.chain_wasi_policy = if enable {
+            ChainWasiPolicy::all()
+        } else {
+            ChainWasiPolicy::none()
+        };
+        self
+    }
+
+    /// Configures which WASI packages have their calls recorded when
+    /// [`Config::chain_record`] is enabled.
+    ///
+    /// This is a finer-grained [`Config::chain_record_wasi`]: a
+    /// [`ChainWasiPolicy`](crate::chain::ChainWasiPolicy) can, for example,
+    /// record `wasi:clocks` and `wasi:random` so that replays are
+    /// deterministic while leaving out `wasi:filesystem`, whose calls are
+    /// often too many to record.
+    ///
+    /// By default no WASI package is recorded.
+    #[cfg(feature = "component-model")]
+    pub fn chain_wasi_policy(&mut self, policy: ChainWasiPolicy) -> &mut Self {
+        self.chain_wasi_policy = policy;
+        self
+    }
+
+    /// Configures whether each component instance records into a chain of
+    /// its own when [`Config::chain_record`] is enabled.
+    ///
+    /// A store hosting many instances otherwise interleaves all of their
+    /// events in one chain. With this enabled the first event recorded for
+    /// an instance spawns a child of the store's chain for it, see
+    /// [`Chain::spawn`](crate::chain::Chain::spawn), and everything recorded
+    /// while one of its exports runs, including the imports it calls, goes
+    /// into that child. The store's own chain then holds a `spawn` event per
+    /// instance and the child chains are found with
+    /// [`Chain::children`](crate::chain::Chain::children).
+    ///
+    /// This option is disabled by default.
+    #[cfg(feature = "component-model")]
+    pub fn chain_per_instance(&mut self, enable: bool) -> &mut Self {
+        self.chain_per_instance = enable;
+        self
+    }
+
+    /// Configures whether the fuel consumed by each component call is
+    /// recorded when [`Config::chain_record`] is enabled.
+    ///
+    /// Each recorded `function-call` or `trap` event is then followed by a
+    /// `fuel` event holding how much fuel the call consumed and how much was
+    /// left, see [`FuelConsumed`](crate::chain::FuelConsumed), which makes
+    /// the chain a record of what each call cost.
+    ///
+    /// This option is disabled by default and has no effect unless
+    /// [`Config::consume_fuel`] is also enabled.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_fuel(&mut self, enable: bool) -> &mut Self {
+        self.chain_record_fuel = enable;
+        self
+    }
+
+    /// Configures whether instantiating a component is recorded when
+    /// [`Config::chain_record`] is enabled.
+    ///
+    /// Each instantiation then appends an `instantiate` event holding the
+    /// SHA-256 digest of the component's binary, the names of the imports the
+    /// linker satisfied, and the configuration affecting whether execution
+    /// is deterministic, see
+    /// [`Instantiation`](crate::chain::Instantiation). This ties a chain back
+    /// to the exact binary and settings which produced it.
+    ///
+    /// This option is disabled by default.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_instantiation(&mut self, enable: bool) -> &mut Self {
+        self.chain_record_instantiation = enable;
+        self
+    }
+
+    /// Configures whether finishing a component call with
+    /// [`Func::post_return`](crate::component::Func::post_return) is
+    /// recorded when [`Config::chain_record`] is enabled.
+    ///
+    /// Each recorded `function-call` event is then followed, once the host
+    /// calls `post_return`, by a `post-return` event pointing back at it, see
+    /// [`PostReturn`](crate::chain::PostReturn), and by a `resource-return`
+    /// event for each `borrow` handle the host lent to the call, see
+    /// [`ResourceReturn`](crate::chain::ResourceReturn). A guest which keeps
+    /// using a borrowed resource after its call returned otherwise leaves no
+    /// trace in a chain of calls alone.
+    ///
+    /// This option is disabled by default.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_post_return(&mut self, enable: bool) -> &mut Self {
+        self.chain_record_post_return = enable;
+        self
+    }
+
+    /// Configures whether the parameter and result types of each component
+    /// call are recorded when [`Config::chain_record`] is enabled.
+    ///
+    /// Each `function-call` event then carries the signature of the export
+    /// it called, see
+    /// [`FunctionSignature`](crate::chain::FunctionSignature), so the values
+    /// in a chain can be decoded and checked without the component which
+    /// produced it.
+    ///
+    /// This option is disabled by default.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_types(&mut self, enable: bool) -> &mut Self {
+        self.chain_record_types = enable;
+        self
+    }
+
+    /// Configures which calls are recorded when [`Config::chain_record`] is
+    /// enabled, by the names of the imports and exports called.
+    ///
+    /// Only calls whose name matches one of the `patterns` are recorded, as
+    /// `import-call` and `import-return` events for imports and
+    /// `function-call` or `trap` events for exports. Names are those recorded
+    /// in the events, such as `run` for an export and `host#double` for the
+    /// function `double` of the imported instance `host`. In patterns `*`
+    /// matches any run of characters and `?` any single character, so
+    /// `wasi:*` matches every WASI import.
+    ///
+    /// Recording every call is too expensive for functions on a hot path,
+    /// and this along with [`Config::chain_record_deny`] keeps them out of
+    /// the chain. WASI imports must still be enabled with
+    /// [`Config::chain_record_wasi`] or [`Config::chain_wasi_policy`] to be
+    /// recorded.
+    ///
+    /// The patterns are configured here, with the rest of what's recorded,
+    /// rather than on a [`Linker`](crate::component::Linker) or through
+    /// `bindgen!`: exports are called without going through either, and
+    /// one list then covers imports and exports alike.
+    ///
+    /// By default, or if `patterns` is empty, every call is recorded.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_allow(&mut self, patterns: &[&str]) -> &mut Self {
+        self.chain_record_allow = patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Configures calls which aren't recorded when [`Config::chain_record`]
+    /// is enabled, by the names of the imports and exports called.
+    ///
+    /// Calls whose name matches one of the `patterns` aren't recorded, even
+    /// if it also matches a pattern given to [`Config::chain_record_allow`].
+    /// Patterns are written as for `chain_record_allow`.
+    ///
+    /// By default no calls are left out.
+    #[cfg(feature = "component-model")]
+    pub fn chain_record_deny(&mut self, patterns: &[&str]) -> &mut Self {
+        self.chain_record_deny = patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Whether Cranelift was configured to canonicalize NaNs, see
+    /// [`Config::cranelift_nan_canonicalization`].
+    #[cfg(feature = "component-model")]
+    pub(crate) fn nan_canonicalization(&self) -> bool {
+        #[cfg(any(feature = "cranelift", feature = "winch"))]
+        {
+            self.compiler_config
+                .settings
+                .get("enable_nan_canonicalization")
+                .is_some_and(|v| v == "true")
+        }
+        #[cfg(not(any(feature = "cranelift", feature = "winch")))]
+        {
+            false
+        }
+    }
+
+    /// Whether calls to the import recorded as `name` are recorded into the
+    /// chain.
+    #[cfg(feature = "component-model")]
+    pub(crate) fn chain_records_import(&self, name: &str) -> bool {
+        self.chain_records_export(name)
+            && (!name.starts_with("wasi:") || self.chain_wasi_policy.records(name))
+    }
+
+    /// Whether calls to the export recorded as `name` are recorded into the
+    /// chain.
+    #[cfg(feature = "component-model")]
+    pub(crate) fn chain_records_export(&self, name: &str) -> bool {
+        self.chain_record
+            && (self.chain_record_allow.is_empty()
+                || self.chain_record_allow.iter().any(|p| glob_match(p, name)))
+            && !self.chain_record_deny.iter().any(|p| glob_match(p, name))
+    }
+
+    /// Whether instantiating a component is recorded into the chain, which
+    /// is all the digest of a component's binary is computed for.
+    #[cfg(feature = "component-model")]
+    pub(crate) fn chain_records_instantiation(&self) -> bool {
+        self.chain_record && self.chain_record_instantiation
+    }
+
     /// Enables memory error checking for wasm programs.
     ///
     /// This option is disabled by default.
@@ -3389,6 +3647,39 @@ impl PoolingAllocationConfig {
     }
 }
 
+/// Whether `name` matches the glob `pattern`, in which `*` matches any run
+/// of characters and `?` any one character.
+#[cfg(feature = "component-model")]
+fn glob_match(pattern: &str, name: &str) -> bool {
+    // Byte offsets into `pattern` and `name`, always on character
+    // boundaries.
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was and how much of the name it matches, to match
+    // one more character with it if what follows doesn't match.
+    let mut backtrack = None;
+    while let Some(c) = name[n..].chars().next() {
+        match pattern[p..].chars().next() {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(pc) if pc == '?' || pc == c => {
+                p += pc.len_utf8();
+                n += c.len_utf8();
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    let bn = bn + name[bn..].chars().next().unwrap().len_utf8();
+                    backtrack = Some((bp, bn));
+                    (p, n) = (bp, bn);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].chars().all(|c| c == '*')
+}
+
 #[cfg(feature = "std")]
 fn detect_host_feature(feature: &str) -> Option<bool> {
     #[cfg(target_arch = "aarch64")]
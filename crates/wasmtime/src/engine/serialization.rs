@@ -32,7 +32,7 @@ use serde_derive::{Deserialize, Serialize};
 use wasmtime_environ::obj;
 use wasmtime_environ::{FlagValue, ObjectKind, Tunables};
 
-const VERSION: u8 = 0;
+const VERSION: u8 = 1;
 
 /// Verifies that the serialized engine in `mmap` is compatible with the
 /// `engine` provided.
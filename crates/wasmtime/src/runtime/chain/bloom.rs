@@ -0,0 +1,164 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bloom filters over a chain's event hashes.
+//!
+//! A chain given a [`BloomFilter`] with [`Chain::set_bloom_filter`] adds the
+//! hash of every event to it as the event is added. The filter is small and
+//! serializable, so a peer can be sent it to check which of its own hashes
+//! this chain may already have, as when merging or syncing, without being
+//! sent the hashes themselves. A filter never misses a hash it was given,
+//! but may claim to hold one it wasn't, at about the rate it was sized for.
+
+use crate::chain::{Chain, Digest};
+use serde::{Deserialize, Serialize};
+use std::vec::Vec;
+
+/// A Bloom filter of event hashes, see the
+/// [module documentation](crate::chain::bloom).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// How many bits each hash sets.
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized to hold `items` hashes while wrongly
+    /// claiming to hold others at about `false_positive_rate`.
+    #[allow(clippy::cast_possible_truncation)] // the sizes are clamped above
+    pub fn new(items: usize, false_positive_rate: f64) -> BloomFilter {
+        let items = items.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = core::f64::consts::LN_2;
+        let bits = (-items * rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hashes = (bits / items * ln2).round().clamp(1.0, 32.0);
+        BloomFilter {
+            bits: vec![0; (bits as usize).div_ceil(64)],
+            hashes: hashes as u32,
+        }
+    }
+
+    /// Adds `hash` to the filter.
+    pub fn insert(&mut self, hash: Digest) {
+        for bit in self.bits_of(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether `hash` may have been added. `false` is always right.
+    pub fn contains(&self, hash: Digest) -> bool {
+        self.bits_of(hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Removes every hash.
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// The bits `hash` sets, derived from two words of the hash, which are
+    /// already uniformly distributed.
+    #[allow(clippy::cast_possible_truncation)] // indices are below `len`
+    fn bits_of(&self, hash: Digest) -> impl Iterator<Item = usize> + use<> {
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        // Odd, so the bits differ even for legacy hashes, which only fill
+        // the first word.
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+impl Chain {
+    /// Keeps `filter` up to date with the hash of every event in this chain,
+    /// starting with those already in it, or stops if `filter` is `None`.
+    ///
+    /// The filter is cleared first.
+    pub fn set_bloom_filter(&mut self, filter: Option<BloomFilter>) {
+        self.bloom = filter;
+        self.refill_bloom_filter();
+    }
+
+    /// The filter set with [`Chain::set_bloom_filter`], holding the hash of
+    /// every event in this chain.
+    pub fn bloom_filter(&self) -> Option<&BloomFilter> {
+        self.bloom.as_ref()
+    }
+
+    /// Whether this chain may hold an event with hash `hash`, answered by
+    /// its Bloom filter without touching the store.
+    ///
+    /// Without a filter this is exact, as [`Chain::get_event_by_hash`] is.
+    pub fn maybe_contains(&self, hash: Digest) -> bool {
+        match &self.bloom {
+            Some(filter) => filter.contains(hash),
+            None => self.position(hash).is_some(),
+        }
+    }
+
+    /// Fills the Bloom filter, if there is one, from scratch.
+    pub(crate) fn refill_bloom_filter(&mut self) {
+        let Some(filter) = &mut self.bloom else {
+            return;
+        };
+        filter.clear();
+        for node in self.store.iter_from(0) {
+            filter.insert(node.hash());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+    use crate::prelude::*;
+
+    #[test]
+    fn tracks_added_events() {
+        let mut chain = Chain::new();
+        let before = chain.add(Event::new("a".to_string(), vec![1]));
+        chain.set_bloom_filter(Some(BloomFilter::new(1000, 0.01)));
+        let after = chain.add(Event::new("b".to_string(), vec![2]));
+        assert!(chain.maybe_contains(before));
+        assert!(chain.maybe_contains(after));
+
+        let mut other = Chain::new();
+        let missing = (0..1000)
+            .map(|i: u16| {
+                other.add(Event::new(
+                    "c".to_string(),
+                    vec![i.to_le_bytes()[0]; usize::from(i % 7)],
+                ))
+            })
+            .filter(|&hash| chain.maybe_contains(hash))
+            .count();
+        assert!(missing < 50, "{missing} false positives");
+
+        chain.set_bloom_filter(None);
+        assert!(!chain.maybe_contains(Digest::from_u64(1)));
+    }
+
+    #[test]
+    fn sizes() {
+        let filter = BloomFilter::new(10_000, 0.01);
+        // About 9.6 bits and 7 hashes per item for 1%.
+        assert_eq!(filter.bits.len(), 1_498);
+        assert_eq!(filter.hashes, 7);
+        assert_eq!(BloomFilter::new(0, 0.5).bits.len(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persisting chain events in the background through an async backend.
+//!
+//! A [`BufferedChainStore`] keeps events in memory and queues them for an
+//! [`AsyncChainStore`], so adding an event never waits on I/O. The queue is
+//! drained by a future returned alongside the store, which the embedder
+//! spawns on its async runtime, such as with `tokio::spawn`. When it writes
+//! is decided by a [`FlushPolicy`].
+
+use crate::chain::{ChainStore, MemoryChainStore, MetaEvent};
+use crate::prelude::*;
+use core::fmt;
+use core::future::{poll_fn, Future};
+use core::mem;
+use core::task::{Poll, Waker};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+/// An asynchronous backend persisting chain events, written to by the
+/// flusher of a [`BufferedChainStore`].
+#[async_trait::async_trait]
+pub trait AsyncChainStore: Send {
+    /// Adds `events` after every event already stored, oldest first.
+    async fn append(&mut self, events: Vec<MetaEvent>) -> Result<()>;
+
+    /// Makes sure every appended event has reached durable storage.
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// When the flusher of a [`BufferedChainStore`] writes queued events.
+///
+/// Whatever the policy, [`ChainStore::flush`] writes everything queued so
+/// far, as does dropping the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write every event as soon as it's added.
+    EveryEvent,
+    /// Write events once this many are queued.
+    Batch(usize),
+    /// Only write events when the store is flushed or dropped.
+    OnFlush,
+}
+
+/// A [`ChainStore`] which keeps events in memory and writes them to an
+/// [`AsyncChainStore`] in the background, see the
+/// [module documentation](crate::chain::buffered).
+pub struct BufferedChainStore {
+    events: MemoryChainStore,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    written: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    queued: Vec<MetaEvent>,
+    /// Number of events appended to the store and written by the flusher.
+    appended: usize,
+    written: usize,
+    flush: bool,
+    closed: bool,
+    error: Option<String>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl BufferedChainStore {
+    /// Creates an empty store writing to `backend` according to `policy`.
+    ///
+    /// The returned future writes queued events until the store is dropped
+    /// and must be spawned for events to be persisted. It stops with the
+    /// first error from `backend`, after which the store refuses new events.
+    pub fn new(
+        backend: impl AsyncChainStore + 'static,
+        policy: FlushPolicy,
+    ) -> (
+        BufferedChainStore,
+        impl Future<Output = Result<()>> + Send + 'static,
+    ) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            written: Condvar::new(),
+        });
+        let store = BufferedChainStore {
+            events: MemoryChainStore::new(),
+            shared: shared.clone(),
+        };
+        (store, run(shared, backend, policy))
+    }
+}
+
+async fn run(
+    shared: Arc<Shared>,
+    mut backend: impl AsyncChainStore,
+    policy: FlushPolicy,
+) -> Result<()> {
+    loop {
+        let (batch, closed) = poll_fn(|cx| {
+            let mut state = shared.lock();
+            let ready = match policy {
+                FlushPolicy::EveryEvent => !state.queued.is_empty(),
+                FlushPolicy::Batch(n) => state.queued.len() >= n,
+                FlushPolicy::OnFlush => false,
+            };
+            if ready || state.flush || state.closed {
+                state.flush = false;
+                Poll::Ready((mem::take(&mut state.queued), state.closed))
+            } else {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let len = batch.len();
+        if len > 0 {
+            let result = match backend.append(batch).await {
+                Ok(()) => backend.flush().await,
+                Err(e) => Err(e),
+            };
+            let mut state = shared.lock();
+            match result {
+                Ok(()) => state.written += len,
+                Err(e) => {
+                    state.error = Some(format!("{e:#}"));
+                    shared.written.notify_all();
+                    return Err(e.context("failed to write chain events"));
+                }
+            }
+        }
+        shared.written.notify_all();
+        if closed {
+            return Ok(());
+        }
+    }
+}
+
+impl ChainStore for BufferedChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        self.append_batch(vec![event])
+    }
+
+    /// Queues `events` together, so the flusher writes all of them in the
+    /// same call to [`AsyncChainStore::append`].
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut state = self.shared.lock();
+        if let Some(error) = &state.error {
+            bail!("chain events can no longer be written: {error}");
+        }
+        self.events.append_batch(events.clone())?;
+        state.appended += events.len();
+        state.queued.extend(events);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    /// Blocks until the flusher has written every event appended so far.
+    ///
+    /// This never returns if the flusher isn't running, so it shouldn't be
+    /// called from a task of the runtime the flusher is spawned on.
+    fn flush(&mut self) -> Result<()> {
+        let mut state = self.shared.lock();
+        state.flush = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        while state.written < state.appended && state.error.is_none() {
+            state = self
+                .shared
+                .written
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        match &state.error {
+            Some(error) => bail!("failed to write chain events: {error}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BufferedChainStore {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl fmt::Debug for BufferedChainStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.lock();
+        f.debug_struct("BufferedChainStore")
+            .field("len", &self.events.len())
+            .field("queued", &state.queued.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Chain, Event, Sha256Hasher};
+    use core::pin::pin;
+    use core::task::Context;
+    use std::sync::mpsc::{self, Sender};
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    /// Sends every batch it's given.
+    struct Batches(Sender<usize>);
+
+    #[async_trait::async_trait]
+    impl AsyncChainStore for Batches {
+        async fn append(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+            self.0.send(events.len())?;
+            Ok(())
+        }
+    }
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Runs `future` to completion on a new thread.
+    fn spawn<F: Future + Send + 'static>(future: F) -> thread::JoinHandle<F::Output>
+    where
+        F::Output: Send,
+    {
+        thread::spawn(move || {
+            let waker = Waker::from(Arc::new(Unpark(thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = pin!(future);
+            loop {
+                match future.as_mut().poll(&mut cx) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => thread::park(),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn batches_events() -> Result<()> {
+        let (sender, batches) = mpsc::channel();
+        let (store, flusher) = BufferedChainStore::new(Batches(sender), FlushPolicy::Batch(3));
+        let flusher = spawn(flusher);
+        let mut chain = Chain::with_store(Arc::new(Sha256Hasher), Box::new(store))?;
+        for i in 0..7u8 {
+            chain.add(Event::new("event".to_string(), vec![i]));
+        }
+        chain.flush()?;
+        assert_eq!(chain.len(), 7);
+        drop(chain);
+        flusher.join().unwrap()?;
+
+        let batches = batches.try_iter().collect::<Vec<_>>();
+        assert_eq!(batches.iter().sum::<usize>(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn drop_writes_queued_events() -> Result<()> {
+        let (sender, batches) = mpsc::channel();
+        let (mut store, flusher) = BufferedChainStore::new(Batches(sender), FlushPolicy::OnFlush);
+        let mut chain = Chain::new();
+        for i in 0..3u8 {
+            chain.add(Event::new("event".to_string(), vec![i]));
+        }
+        for node in chain.iter() {
+            store.append(node.clone())?;
+        }
+        drop(store);
+        spawn(flusher).join().unwrap()?;
+        assert_eq!(batches.try_iter().collect::<Vec<_>>(), [3]);
+        Ok(())
+    }
+
+    #[test]
+    fn backend_errors_stop_appends() -> Result<()> {
+        let (sender, batches) = mpsc::channel();
+        drop(batches);
+        let (mut store, flusher) =
+            BufferedChainStore::new(Batches(sender), FlushPolicy::EveryEvent);
+        let flusher = spawn(flusher);
+        let mut chain = Chain::new();
+        let event = chain.add(Event::new("event".to_string(), vec![]));
+        store.append(chain.get_event_by_hash(event).unwrap().clone())?;
+        assert!(store.flush().is_err());
+        assert!(flusher.join().unwrap().is_err());
+        assert!(store.append(chain.store().get(0).unwrap().clone()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Several writers appending to logically the same chain.
+//!
+//! A chain has a single linear history, so nodes which all record into what
+//! is meant to be one chain each keep their own replica, and stamp the
+//! events they add with a [`CausalWriter`]: the event is wrapped in a
+//! [`CAUSAL`] event naming the writer and carrying its [`VectorClock`],
+//! which counts the events of every writer it had seen when it was added.
+//! Writers learn of each other's events by [observing](CausalWriter::observe)
+//! them.
+//!
+//! [`Chain::merge_causal`] then puts the stamped events of any number of
+//! replicas into one order which respects the clocks, breaking ties between
+//! concurrent events by writer id, so every node merging the same events
+//! ends up with the same chain, hashes and all.
+
+use crate::chain::{Chain, Digest, Event};
+use crate::prelude::*;
+use core::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Event type of an event stamped by a [`CausalWriter`].
+pub const CAUSAL: &str = "causal";
+
+/// How many events of each writer have been seen.
+///
+/// Clocks are partially ordered: one is before another if it has seen no
+/// more of any writer's events and fewer of some, and two clocks neither of
+/// which is before the other belong to concurrent events.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+impl VectorClock {
+    pub fn new() -> VectorClock {
+        VectorClock::default()
+    }
+
+    /// How many of `writer`'s events this clock has seen.
+    pub fn get(&self, writer: &str) -> u64 {
+        self.0.get(writer).copied().unwrap_or(0)
+    }
+
+    /// Counts another event by `writer`, returning its number.
+    pub fn tick(&mut self, writer: &str) -> u64 {
+        let count = self.0.entry(writer.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Takes in everything `other` has seen.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (writer, &count) in &other.0 {
+            let mine = self.0.entry(writer.clone()).or_insert(0);
+            *mine = (*mine).max(count);
+        }
+    }
+
+    /// Whether the events of the two clocks happened independently.
+    pub fn concurrent_with(&self, other: &VectorClock) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+
+    /// The number of events seen, which grows along every causal path, so
+    /// sorting by it respects the clocks' order.
+    fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &VectorClock) -> Option<Ordering> {
+        let writers = self.0.keys().chain(other.0.keys());
+        let mut ordering = Ordering::Equal;
+        for writer in writers {
+            match (ordering, self.get(writer).cmp(&other.get(writer))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, o) => ordering = o,
+                (a, b) if a != b => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+}
+
+/// The payload of a [`CAUSAL`] event, encoded as JSON: an event stamped
+/// with the writer which added it and what it had seen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalEvent {
+    pub writer: String,
+    /// The writer's clock, counting this event.
+    pub clock: VectorClock,
+    /// Type of the stamped event.
+    pub type_: String,
+    /// Payload of the stamped event.
+    pub data: Vec<u8>,
+}
+
+impl CausalEvent {
+    /// Decodes the payload of a [`CAUSAL`] event.
+    pub fn decode(event: &Event) -> Result<CausalEvent> {
+        if event.type_() != CAUSAL {
+            bail!("expected a `{CAUSAL}` event, found `{}`", event.type_());
+        }
+        Ok(serde_json::from_slice(&event.data())?)
+    }
+
+    /// The stamped event, without its stamp.
+    pub fn into_event(self) -> Event {
+        Event::new(self.type_, self.data)
+    }
+
+    fn to_event(&self) -> Result<Event> {
+        Ok(Event::new(CAUSAL.to_string(), serde_json::to_vec(self)?))
+    }
+}
+
+/// Stamps the events one writer adds to its replica of a chain, see the
+/// [module documentation](crate::chain::causal).
+#[derive(Debug, Clone)]
+pub struct CausalWriter {
+    writer: String,
+    clock: VectorClock,
+}
+
+impl CausalWriter {
+    /// A writer which hasn't seen any events yet.
+    pub fn new(writer: impl Into<String>) -> CausalWriter {
+        CausalWriter {
+            writer: writer.into(),
+            clock: VectorClock::new(),
+        }
+    }
+
+    /// A writer which has seen every stamped event in `chain`, for picking up
+    /// where it left off.
+    pub fn resume(writer: impl Into<String>, chain: &Chain) -> Result<CausalWriter> {
+        let mut resumed = CausalWriter::new(writer);
+        for node in chain.events_of_type(CAUSAL) {
+            resumed.observe(&CausalEvent::decode(node.event())?.clock);
+        }
+        Ok(resumed)
+    }
+
+    pub fn writer(&self) -> &str {
+        &self.writer
+    }
+
+    pub fn clock(&self) -> &VectorClock {
+        &self.clock
+    }
+
+    /// Takes in an event of another writer, so the events this one adds
+    /// from now on come after it.
+    pub fn observe(&mut self, clock: &VectorClock) {
+        self.clock.merge(clock);
+    }
+
+    /// Stamps `event` and adds it to `chain`.
+    pub fn add(&mut self, chain: &mut Chain, event: Event) -> Result<Digest> {
+        let mut clock = self.clock.clone();
+        clock.tick(&self.writer);
+        let stamped = CausalEvent {
+            writer: self.writer.clone(),
+            clock,
+            type_: event.type_().to_string(),
+            data: event.data().into_owned(),
+        };
+        let hash = chain.try_add(stamped.to_event()?)?;
+        self.clock = stamped.clock;
+        Ok(hash)
+    }
+}
+
+impl Chain {
+    /// Merges the stamped events of several replicas of a chain into a new
+    /// chain, in a total order which respects their clocks, see the
+    /// [module documentation](crate::chain::causal).
+    ///
+    /// Events held by several replicas are only taken once. Events which
+    /// aren't stamped are left out. The replicas must use the same hasher.
+    pub fn merge_causal(replicas: &[&Chain]) -> Result<Chain> {
+        let Some(first) = replicas.first() else {
+            return Ok(Chain::new());
+        };
+        let hasher = first.hasher.clone();
+        let mut events = HashMap::<(String, u64), CausalEvent>::new();
+        for replica in replicas {
+            ensure!(
+                replica.hasher().name() == hasher.name(),
+                "cannot merge a chain hashed with `{}` with one hashed with `{}`",
+                replica.hasher().name(),
+                hasher.name()
+            );
+            for node in replica.events_of_type(CAUSAL) {
+                let event = CausalEvent::decode(node.event())?;
+                let key = (event.writer.clone(), event.clock.get(&event.writer));
+                match events.get(&key) {
+                    Some(seen) if *seen != event => bail!(
+                        "writer `{}` recorded two different events as its event {}",
+                        key.0,
+                        key.1
+                    ),
+                    Some(_) => {}
+                    None => {
+                        events.insert(key, event);
+                    }
+                }
+            }
+        }
+
+        let mut events = events.into_values().collect::<Vec<_>>();
+        events.sort_by(|a, b| (a.clock.total(), &a.writer).cmp(&(b.clock.total(), &b.writer)));
+        let mut merged = Chain::with_hasher(hasher);
+        for event in &events {
+            merged.try_add(event.to_event()?)?;
+        }
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(ty: &str, data: u8) -> Event {
+        Event::new(ty.to_string(), vec![data])
+    }
+
+    #[test]
+    fn clocks() {
+        let mut a = VectorClock::new();
+        a.tick("a");
+        let mut b = a.clone();
+        b.tick("b");
+        assert!(a < b);
+        assert_eq!(b.get("b"), 1);
+
+        let mut c = a.clone();
+        c.tick("c");
+        assert!(b.concurrent_with(&c));
+        c.merge(&b);
+        assert!(b < c);
+        assert_eq!((c.get("a"), c.get("b"), c.get("c")), (1, 1, 1));
+    }
+
+    #[test]
+    fn merges_writers_deterministically() -> Result<()> {
+        let (mut chain_a, mut chain_b) = (Chain::new(), Chain::new());
+        let mut a = CausalWriter::new("a");
+        let mut b = CausalWriter::new("b");
+
+        a.add(&mut chain_a, event("x", 1))?;
+        // `b` sees `a`'s first event before adding its own.
+        b.observe(a.clock());
+        b.add(&mut chain_b, event("y", 2))?;
+        a.add(&mut chain_a, event("x", 3))?;
+
+        let merged = Chain::merge_causal(&[&chain_a, &chain_b])?;
+        let again = Chain::merge_causal(&[&chain_b, &chain_a, &chain_a])?;
+        assert_eq!(merged.head(), again.head());
+        let order = merged
+            .events()
+            .map(|node| {
+                let event = CausalEvent::decode(node.event()).unwrap();
+                (event.writer, event.data[0])
+            })
+            .collect::<Vec<_>>();
+        // `a`'s first event comes first; its second is concurrent with
+        // `b`'s, and both have seen two events, so `a` goes first.
+        assert_eq!(
+            order,
+            [
+                ("a".to_string(), 1),
+                ("a".to_string(), 3),
+                ("b".to_string(), 2)
+            ]
+        );
+
+        let resumed = CausalWriter::resume("b", &merged)?;
+        assert_eq!(resumed.clock().get("a"), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CBOR for chains and values.
+//!
+//! JSON turns NaNs into `null`, many JSON readers round 64-bit integers and
+//! event payloads become arrays of numbers. CBOR keeps all three, storing
+//! payloads as byte strings, and can be read without Wasmtime's types: a
+//! [`SerializableVal`] is a map from its case's name to its contents.
+//!
+//! Like [`Chain::from_bytes`], [`Chain::from_cbor`] doesn't check the
+//! chain's hashes, which [`Chain::verify`] does.
+
+use crate::chain::{Chain, SerializableVal};
+use crate::prelude::*;
+
+impl Chain {
+    /// Encodes this chain as CBOR, see the [module docs](crate::chain::cbor).
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        to_cbor(self)
+    }
+
+    /// Decodes a chain encoded by [`Chain::to_cbor`].
+    pub fn from_cbor(bytes: &[u8]) -> Result<Chain> {
+        from_cbor(bytes)
+    }
+}
+
+impl SerializableVal {
+    /// Encodes this value as CBOR, see the [module docs](crate::chain::cbor).
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        to_cbor(self)
+    }
+
+    /// Decodes a value encoded by [`SerializableVal::to_cbor`], within the
+    /// current [`ValLimits`](crate::chain::ValLimits).
+    pub fn from_cbor(bytes: &[u8]) -> Result<SerializableVal> {
+        from_cbor(bytes)
+    }
+}
+
+fn to_cbor<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).map_err(|e| anyhow!("encoding CBOR: {e}"))?;
+    Ok(bytes)
+}
+
+fn from_cbor<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    ciborium::de::from_reader(bytes).map_err(|e| anyhow!("decoding CBOR: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn round_trips_chains() -> Result<()> {
+        let mut chain = Chain::new();
+        let payload = (0..=255).collect::<Vec<u8>>();
+        chain.add(Event::new("binary".to_string(), payload.clone()));
+        let decoded = Chain::from_cbor(&chain.to_cbor()?)?;
+        decoded.verify()?;
+        assert_eq!(decoded.head(), chain.head());
+        assert_eq!(decoded.events().last().unwrap().event().data()[..], payload);
+
+        // The payload is a byte string rather than an array of numbers.
+        let cbor = chain.to_cbor()?;
+        assert!(cbor.windows(payload.len()).any(|w| w == payload));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_values() -> Result<()> {
+        let some = |v| Some(Box::new(v));
+        let values = [
+            SerializableVal::U64(u64::MAX),
+            SerializableVal::S64(i64::MIN),
+            SerializableVal::Float32(f32::NAN),
+            SerializableVal::Float64(-0.0),
+            SerializableVal::Char('\u{10ffff}'),
+            SerializableVal::List((0..=255).map(SerializableVal::U8).collect()),
+            SerializableVal::Record(vec![
+                ("a".to_string(), SerializableVal::S8(-1)),
+                (
+                    "b".to_string(),
+                    SerializableVal::Flags(vec!["x".to_string()]),
+                ),
+            ]),
+            SerializableVal::Variant(
+                "outer".to_string(),
+                some(SerializableVal::Variant("inner".to_string(), None)),
+            ),
+            SerializableVal::Result(Err(some(SerializableVal::Enum("e".to_string())))),
+            SerializableVal::Option(some(SerializableVal::Tuple(vec![
+                SerializableVal::Bool(true),
+                SerializableVal::String(String::new()),
+            ]))),
+        ];
+        for value in values {
+            let decoded = SerializableVal::from_cbor(&value.to_cbor()?)?;
+            assert_eq!(decoded, value);
+        }
+
+        // Cases are tagged by name, so other readers can tell them apart.
+        let cbor = SerializableVal::U64(u64::MAX).to_cbor()?;
+        let value: ciborium::value::Value = from_cbor(&cbor)?;
+        let map = value.as_map().unwrap();
+        assert_eq!(map[0].0.as_text(), Some("U64"));
+        assert!(SerializableVal::from_cbor(&cbor[..cbor.len() - 1]).is_err());
+        Ok(())
+    }
+}
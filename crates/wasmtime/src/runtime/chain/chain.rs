@@ -13,30 +13,247 @@
 // limitations under the License.
 
 //use crate::chain::SerializableVal;
+use crate::chain::bloom::BloomFilter;
+use crate::chain::compact::{Checkpoint, Compaction, CHECKPOINT};
+use crate::chain::file::FileChainStore;
+use crate::chain::hasher::{hasher_by_name, ChainHasher, Sha256Hasher};
+use crate::chain::intern;
+use crate::chain::metrics;
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{
+    ChainRedactor, ChainSigner, ChainStore, Compression, Digest, IntegrityError,
+    IntegrityErrorKind, MemoryChainStore, SchemaRegistry, Severity,
+};
 use crate::component::__internal::{
     CanonicalAbiInfo, InstanceType, InterfaceType, LiftContext, LowerContext,
 };
 use crate::component::{ComponentType, Lift, Lower};
-use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::mem::MaybeUninit;
+use crate::ValRaw;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest as _, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::{self, MaybeUninit};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::vec::Vec;
 
 // If you need error handling
 use crate::prelude::*;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct MetaEvent {
-    hash: u64,
+    hash: Digest,
     event: Event,
+    /// Set when the chain has a [`ChainSigner`]. This isn't covered by
+    /// `hash`.
+    #[serde(default)]
+    signature: Option<Vec<u8>>,
+}
+
+/// Unsigned events leave the signature out of human-readable formats, so
+/// they look the same as before events could be signed.
+impl Serialize for MetaEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let skip = serializer.is_human_readable() && self.signature.is_none();
+        let mut s = serializer.serialize_struct("MetaEvent", if skip { 2 } else { 3 })?;
+        s.serialize_field("hash", &self.hash)?;
+        s.serialize_field("event", &self.event)?;
+        if skip {
+            s.skip_field("signature")?;
+        } else {
+            s.serialize_field("signature", &self.signature)?;
+        }
+        s.end()
+    }
 }
 
-#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Deserialize)]
 pub struct Event {
     type_: String,
-    parent: Option<u64>,
-    data: Vec<u8>,
+    parent: Option<Digest>,
+    /// The payload, compressed as given by `compression`.
+    pub(crate) data: EventData,
+    #[serde(default)]
+    pub(crate) compression: Compression,
+    /// See [`Event::severity`].
+    #[serde(default)]
+    pub(crate) severity: Option<Severity>,
+    /// See [`Event::schema_version`].
+    #[serde(default)]
+    pub(crate) schema_version: Option<u32>,
+}
+
+/// An event payload, either owned or borrowed from a chain file mapped into
+/// memory by a [`MappedChainStore`](crate::chain::MappedChainStore).
+///
+/// Both serialize as the bytes they hold.
+#[derive(Clone)]
+pub(crate) enum EventData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "chain-mmap")]
+    Mapped(Arc<memmap2::Mmap>, core::ops::Range<usize>),
+}
+
+impl EventData {
+    /// The payload as an owned `Vec`, copying it out of the mapping first if
+    /// it's mapped.
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<u8> {
+        #[cfg(feature = "chain-mmap")]
+        if let EventData::Mapped(..) = self {
+            *self = EventData::Owned(self.to_vec());
+        }
+        match self {
+            EventData::Owned(data) => data,
+            #[cfg(feature = "chain-mmap")]
+            EventData::Mapped(..) => unreachable!(),
+        }
+    }
+}
+
+impl core::ops::Deref for EventData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            EventData::Owned(data) => data,
+            #[cfg(feature = "chain-mmap")]
+            EventData::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+impl core::fmt::Debug for EventData {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl core::hash::Hash for EventData {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+/// Payloads are byte strings in formats which have them, such as CBOR and
+/// MessagePack, and arrays of numbers in JSON. Postcard encodes both the same
+/// way, and payloads written as arrays by older versions are still read.
+impl Serialize for EventData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for EventData {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = EventData;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str("an event payload")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<EventData, E> {
+                Ok(EventData::Owned(bytes.to_vec()))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, bytes: Vec<u8>) -> Result<EventData, E> {
+                Ok(EventData::Owned(bytes))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<EventData, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(EventData::Owned(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(Visitor)
+    }
+}
+
+/// Uncompressed events, and events without a severity or schema version,
+/// leave those out of human-readable formats, so they look the same as before
+/// payloads could be compressed or events given a severity or schema version.
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let human_readable = serializer.is_human_readable();
+        let skip_compression = human_readable && self.compression == Compression::None;
+        let skip_severity = human_readable && self.severity.is_none();
+        let skip_version = human_readable && self.schema_version.is_none();
+        let len = 6
+            - usize::from(skip_compression)
+            - usize::from(skip_severity)
+            - usize::from(skip_version);
+        let mut s = serializer.serialize_struct("Event", len)?;
+        s.serialize_field("type_", &self.type_)?;
+        s.serialize_field("parent", &self.parent)?;
+        s.serialize_field("data", &self.data)?;
+        if skip_compression {
+            s.skip_field("compression")?;
+        } else {
+            s.serialize_field("compression", &self.compression)?;
+        }
+        if skip_severity {
+            s.skip_field("severity")?;
+        } else {
+            s.serialize_field("severity", &self.severity)?;
+        }
+        if skip_version {
+            s.skip_field("schema_version")?;
+        } else {
+            s.serialize_field("schema_version", &self.schema_version)?;
+        }
+        s.end()
+    }
+}
+
+impl MetaEvent {
+    /// Pairs `event` with `hash`, for [`ChainStore`]s loading events they
+    /// persisted. [`Chain::with_store`] verifies that the two match.
+    pub fn new(hash: Digest, event: Event) -> Self {
+        MetaEvent {
+            hash,
+            event,
+            signature: None,
+        }
+    }
+
+    /// Attaches a signature previously produced for this event.
+    pub fn with_signature(mut self, signature: Option<Vec<u8>>) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    pub fn hash(&self) -> Digest {
+        self.hash
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
+
+    /// The bytes a [`ChainSigner`] signs for this event: its hash followed
+    /// by its parent link, or 32 zero bytes for the first event.
+    pub fn signing_input(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 * Digest::LEN);
+        bytes.extend_from_slice(self.hash.as_bytes());
+        bytes.extend_from_slice(self.event.parent.unwrap_or_default().as_bytes());
+        bytes
+    }
 }
 
 impl Event {
@@ -44,64 +261,729 @@ impl Event {
         Event {
             type_,
             parent: None,
-            data,
+            data: EventData::Owned(data),
+            compression: Compression::None,
+            severity: None,
+            schema_version: None,
         }
     }
 
-    fn calculate_hash(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+    pub fn type_(&self) -> &str {
+        &self.type_
+    }
+
+    pub fn parent(&self) -> Option<Digest> {
+        self.parent
+    }
+
+    /// The payload, decompressed if it's stored compressed.
+    ///
+    /// A compressed payload which can't be decompressed is returned as it's
+    /// stored, which [`Chain::verify`] then reports as a hash mismatch; use
+    /// [`Event::try_data`] to get the error instead.
+    pub fn data(&self) -> Cow<'_, [u8]> {
+        self.try_data().unwrap_or(Cow::Borrowed(&*self.data))
+    }
+
+    /// The payload, decompressed if it's stored compressed.
+    pub fn try_data(&self) -> Result<Cow<'_, [u8]>> {
+        self.compression.decompress(&self.data)
+    }
+
+    /// How the payload is stored, see the
+    /// [`compress` module](crate::chain::compress).
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// The payload as it's stored, which is compressed unless
+    /// [`Event::compression`] is [`Compression::None`].
+    pub fn stored_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Sets the parent link, for [`ChainStore`]s loading events they
+    /// persisted. [`Chain::add`] overwrites this with the chain's head.
+    pub fn set_parent(&mut self, parent: Option<Digest>) {
+        self.parent = parent;
+    }
+
+    /// The bytes a [`ChainHasher`] hashes for this event.
+    ///
+    /// This is a length-prefixed encoding of the type, parent link and data,
+    /// so it's stable across processes and platforms.
+    ///
+    /// The data is hashed uncompressed, so compressing an event doesn't
+    /// change its hash. The [severity](Event::severity) follows the data, if
+    /// there is one, and then the [schema version](Event::schema_version),
+    /// if there is one, after a `0xff` byte.
+    pub fn hash_input(&self) -> Vec<u8> {
+        let data = self.data();
+        let mut bytes = Vec::with_capacity(self.type_.len() + data.len() + 49);
+        bytes.extend_from_slice(&u64::try_from(self.type_.len()).unwrap().to_le_bytes());
+        bytes.extend_from_slice(self.type_.as_bytes());
+        match &self.parent {
+            Some(parent) => {
+                bytes.push(1);
+                bytes.extend_from_slice(parent.as_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&u64::try_from(data.len()).unwrap().to_le_bytes());
+        bytes.extend_from_slice(&data);
+        if let Some(severity) = self.severity {
+            bytes.push(severity as u8);
+        }
+        if let Some(version) = self.schema_version {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&version.to_le_bytes());
+        }
+        bytes
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "SerializedChain")]
 pub struct Chain {
+    pub(crate) hasher: Arc<dyn ChainHasher>,
+    pub(crate) store: Box<dyn ChainStore>,
+    #[serde(skip)]
+    pub(crate) signer: Option<Arc<dyn ChainSigner>>,
+    /// Rewrites payloads before they're added, see [`Chain::set_redactor`].
+    pub(crate) redactor: Option<Arc<dyn ChainRedactor>>,
+    /// Checks events before they're added, see [`Chain::set_schemas`].
+    pub(crate) schemas: Option<Arc<SchemaRegistry>>,
+    /// Payloads larger than this are compressed, see
+    /// [`Chain::compression_threshold`].
+    pub(crate) compression_threshold: Option<usize>,
+    /// Sequence number of the first event with a given hash. This is
+    /// derived from `store` when the chain is created.
+    index: HashMap<Digest, usize>,
+    /// Sequence numbers of the events of each type, in order, see
+    /// [`Chain::events_of_type`]. Also derived from `store`.
+    types: HashMap<String, Vec<usize>>,
+    /// Get a copy of every event added, see [`Chain::subscribe`].
+    subscribers: Vec<Sender<MetaEvent>>,
+    /// Chains spawned from this one, keyed by their `spawn` event's hash,
+    /// see [`Chain::spawn`].
+    pub(crate) children: Vec<(Digest, Chain)>,
+    /// Folds old events into a checkpoint as the chain grows, see
+    /// [`Chain::set_compaction`].
+    pub(crate) compaction: Option<Arc<Compaction>>,
+    /// Commitment to every event hash in `store`, see [`Chain::digest`].
+    digest: Digest,
+    /// Holds every event hash in `store`, see [`Chain::set_bloom_filter`].
+    pub(crate) bloom: Option<BloomFilter>,
+}
+
+/// Clones are held in memory, whatever store the original uses.
+impl Clone for Chain {
+    fn clone(&self) -> Chain {
+        Chain {
+            hasher: self.hasher.clone(),
+            store: Box::new(MemoryChainStore::from(
+                self.events().cloned().collect::<Vec<_>>(),
+            )),
+            signer: self.signer.clone(),
+            redactor: self.redactor.clone(),
+            schemas: self.schemas.clone(),
+            compression_threshold: self.compression_threshold,
+            index: self.index.clone(),
+            types: self.types.clone(),
+            subscribers: Vec::new(),
+            children: self.children.clone(),
+            compaction: self.compaction.clone(),
+            digest: self.digest,
+            bloom: self.bloom.clone(),
+        }
+    }
+}
+
+/// The [format version](crate::chain::migrate) is serialized first, then
+/// the hasher by name, see [`hasher_by_name`], followed by the events oldest
+/// first.
+impl Serialize for Chain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Chain", 3)?;
+        s.serialize_field("format", &FORMAT_VERSION)?;
+        s.serialize_field("hasher", self.hasher.name())?;
+        s.serialize_field("events", &self.events().collect::<Vec<_>>())?;
+        s.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedChain {
+    // Chains serialized before formats were versioned have none, and their
+    // events decode with the fields they lack defaulted.
+    #[serde(default)]
+    format: Option<u64>,
+    // Chains recorded before hashers were configurable have no name and were
+    // hashed with what is now `LegacyHasher`.
+    #[serde(default = "legacy_hasher_name")]
+    hasher: String,
     events: Vec<MetaEvent>,
 }
 
+fn legacy_hasher_name() -> String {
+    "legacy".to_string()
+}
+
+impl TryFrom<SerializedChain> for Chain {
+    type Error = Error;
+
+    fn try_from(chain: SerializedChain) -> Result<Chain> {
+        if let Some(format) = chain.format {
+            migrate::check_version(format)?;
+        }
+        let hasher = match hasher_by_name(&chain.hasher) {
+            Some(hasher) => hasher,
+            None => bail!("chain uses unknown hasher `{}`", chain.hasher),
+        };
+        Ok(Chain::from_store_unverified(
+            hasher,
+            Box::new(MemoryChainStore::from(chain.events)),
+        ))
+    }
+}
+
 impl Chain {
+    /// Creates an empty chain hashed with SHA-256.
     pub fn new() -> Self {
-        Chain { events: Vec::new() }
+        Chain::with_hasher(Arc::new(Sha256Hasher))
+    }
+
+    /// Creates an empty chain whose event hashes are computed by `hasher`.
+    pub fn with_hasher(hasher: Arc<dyn ChainHasher>) -> Self {
+        Chain::from_store_unverified(hasher, Box::new(MemoryChainStore::new()))
+    }
+
+    /// Creates a chain backed by `store`, which may already hold events
+    /// hashed with `hasher`.
+    ///
+    /// Existing events are checked with [`Chain::verify`].
+    pub fn with_store(hasher: Arc<dyn ChainHasher>, store: Box<dyn ChainStore>) -> Result<Self> {
+        let chain = Chain::from_store_unverified(hasher, store);
+        chain.verify()?;
+        Ok(chain)
     }
 
-    pub fn add(&mut self, mut event: Event) -> u64 {
-        let hash = event.calculate_hash();
-        let parent_hash = self.events.last().map(|last| last.hash);
-        event.parent = parent_hash;
+    pub(crate) fn from_store_unverified(
+        hasher: Arc<dyn ChainHasher>,
+        store: Box<dyn ChainStore>,
+    ) -> Self {
+        let mut chain = Chain {
+            hasher,
+            store,
+            signer: None,
+            redactor: None,
+            schemas: None,
+            compression_threshold: None,
+            index: HashMap::new(),
+            types: HashMap::new(),
+            subscribers: Vec::new(),
+            children: Vec::new(),
+            compaction: None,
+            digest: Digest::default(),
+            bloom: None,
+        };
+        chain.reindex();
+        chain
+    }
 
-        let node = MetaEvent { event, hash };
+    /// Rebuilds the lookup tables derived from the store, after it's been
+    /// replaced or rewritten.
+    pub(crate) fn reindex(&mut self) {
+        self.index.clear();
+        self.types.clear();
+        self.digest = Digest::default();
+        for (i, node) in self.store.iter_from(0).enumerate() {
+            self.index.entry(node.hash).or_insert(i);
+            push_type(&mut self.types, &node.event.type_, i);
+            self.digest = roll(self.digest, node.hash);
+        }
+        self.refill_bloom_filter();
+    }
 
-        self.events.push(node);
-        hash
+    /// Opens the chain persisted at `path`, creating an empty SHA-256 chain
+    /// there if the file doesn't exist.
+    ///
+    /// This is a chain backed by a [`FileChainStore`]: every event added
+    /// afterwards is appended to the file before [`Chain::try_add`] returns,
+    /// so the chain survives the process crashing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let store = FileChainStore::open(path, Sha256Hasher.name())?;
+        let hasher = match hasher_by_name(store.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!(
+                "chain file `{}` uses unknown hasher `{}`",
+                path.display(),
+                store.hasher()
+            ),
+        };
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain file `{}` failed verification", path.display()))
     }
 
-    pub fn get_event_by_hash(&self, hash: u64) -> Option<&MetaEvent> {
-        self.events.iter().find(|node| node.hash == hash)
+    /// The store holding this chain's events.
+    pub fn store(&self) -> &dyn ChainStore {
+        &*self.store
     }
 
-    pub fn get_parent(&self, hash: u64) -> Option<&MetaEvent> {
-        self.events
-            .iter()
-            .find(|node| node.hash == hash)
+    /// Makes sure every event added so far has reached durable storage, for
+    /// stores which persist events.
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.flush()
+    }
+
+    pub fn hasher(&self) -> &dyn ChainHasher {
+        &*self.hasher
+    }
+
+    /// Sets the signer used for events added from now on, or stops signing
+    /// new events if `signer` is `None`.
+    ///
+    /// Events already in the chain keep whatever signature they had.
+    pub fn set_signer(&mut self, signer: Option<Arc<dyn ChainSigner>>) {
+        self.signer = signer;
+    }
+
+    /// Returns a receiver which gets a copy of every event added from now
+    /// on, in order, until it's dropped.
+    ///
+    /// Events wait in the receiver until they're received, so one which
+    /// falls behind holds on to them. Clones of the chain don't send events
+    /// to its subscribers.
+    pub fn subscribe(&mut self) -> Receiver<MetaEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Appends `event` to the chain, linking it to the current head.
+    ///
+    /// The returned hash covers the parent link so reordering or splicing
+    /// events changes every hash that follows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain is persisted to a file and writing to it fails,
+    /// or if `event` doesn't match the schema registered for its type, see
+    /// [`Chain::set_schemas`]; use [`Chain::try_add`] to handle those
+    /// instead.
+    pub fn add(&mut self, event: Event) -> Digest {
+        self.try_add(event).expect("failed to add chain event")
+    }
+
+    /// Like [`Chain::add`], but returns an error if the event can't be
+    /// persisted, in which case the chain is left unchanged.
+    pub fn try_add(&mut self, event: Event) -> Result<Digest> {
+        let node = self.prepare(event, self.head())?;
+        let hash = node.hash;
+        let index = self.store.len();
+        if let Err(e) = self.store.append(node) {
+            metrics::report(|m| m.append_failed());
+            return Err(e);
+        }
+        self.appended(index);
+        self.compact_after_add();
+        Ok(hash)
+    }
+
+    /// Appends `events` to the chain in order, as a group: either all of
+    /// them are persisted or, if that fails, none are and the chain is left
+    /// unchanged. Subscribers only receive the events once all of them are
+    /// in the chain.
+    ///
+    /// This is for recording a logical transaction made of several events.
+    /// Returns the hashes of the events, in order.
+    pub fn add_batch(&mut self, events: Vec<Event>) -> Result<Vec<Digest>> {
+        let mut parent = self.head();
+        let mut nodes = Vec::with_capacity(events.len());
+        for event in events {
+            let node = self.prepare(event, parent)?;
+            parent = Some(node.hash);
+            nodes.push(node);
+        }
+        let hashes = nodes.iter().map(|node| node.hash).collect();
+        let start = self.store.len();
+        if let Err(e) = self.store.append_batch(nodes) {
+            metrics::report(|m| m.append_failed());
+            return Err(e);
+        }
+        for index in start..self.store.len() {
+            self.appended(index);
+        }
+        self.compact_after_add();
+        Ok(hashes)
+    }
+
+    /// Links `event` to `parent`, then checks, redacts, hashes, compresses
+    /// and signs it as configured for this chain.
+    fn prepare(&self, mut event: Event, parent: Option<Digest>) -> Result<MetaEvent> {
+        if let Some(schemas) = &self.schemas {
+            schemas.stamp(&mut event)?;
+        }
+        if let Some(redactor) = &self.redactor {
+            event.decompress()?;
+            let data = mem::take(event.data.to_mut());
+            event.data = EventData::Owned(redactor.redact(&event.type_, data));
+        }
+        event.parent = parent;
+        let hash = self.hasher.hash_event(&event);
+        if let Some(threshold) = self.compression_threshold {
+            event.compress(threshold);
+        }
+
+        let mut node = MetaEvent::new(hash, event);
+        if let Some(signer) = &self.signer {
+            node.signature = Some(signer.sign(&node.signing_input()));
+        }
+        Ok(node)
+    }
+
+    /// Indexes the event the store just appended at `index` and hands it
+    /// to subscribers.
+    fn appended(&mut self, index: usize) {
+        let Some(node) = self.store.get(index) else {
+            return;
+        };
+        self.index.entry(node.hash).or_insert(index);
+        self.digest = roll(self.digest, node.hash);
+        if let Some(filter) = &mut self.bloom {
+            filter.insert(node.hash);
+        }
+        metrics::report(|m| m.event_appended(&node.event.type_, node.event.data.len()));
+        push_type(&mut self.types, &node.event.type_, index);
+        self.subscribers.retain(|s| s.send(node.clone()).is_ok());
+        #[cfg(feature = "chain-tracing")]
+        trace_event(node, index);
+    }
+
+    fn compact_after_add(&mut self) {
+        // The events are in the chain by now, so a failed compaction is only
+        // logged; it's tried again when the next event is added.
+        if let Err(e) = self.compact_if_needed() {
+            log::warn!("failed to compact chain: {e:?}");
+        }
+    }
+
+    pub fn get_event_by_hash(&self, hash: Digest) -> Option<&MetaEvent> {
+        self.index.get(&hash).and_then(|&i| self.store.get(i))
+    }
+
+    /// The sequence number of the event with hash `hash` in this chain's
+    /// store.
+    pub(crate) fn position(&self, hash: Digest) -> Option<usize> {
+        self.index.get(&hash).copied()
+    }
+
+    pub fn get_parent(&self, hash: Digest) -> Option<&MetaEvent> {
+        self.get_event_by_hash(hash)
             .and_then(|node| node.event.parent)
             .and_then(|parent_hash| self.get_event_by_hash(parent_hash))
     }
 
-    pub fn head(&self) -> Option<u64> {
-        self.events.last().map(|node| node.hash)
+    /// A commitment to the hash of every event in this chain, in order,
+    /// kept up to date as events are added so it's free to read.
+    ///
+    /// Starting from all zeroes, each event's hash is folded in as
+    /// `digest = SHA-256(digest || hash)`, whatever [`ChainHasher`] the chain
+    /// uses, so embedders can publish the digest after every call as a
+    /// commitment to the history so far. After a compaction it covers the
+    /// events left, starting with the checkpoint.
+    pub fn digest(&self) -> Digest {
+        self.digest
+    }
+
+    pub fn head(&self) -> Option<Digest> {
+        self.store.head().map(|node| node.hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The events of this chain, oldest first.
+    pub(crate) fn events(&self) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.store.iter_from(0)
+    }
+
+    /// Iterates over the events of this chain, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &MetaEvent> + '_ {
+        self.events()
+    }
+
+    /// Iterates over the events of this chain from the one with hash `hash`
+    /// onwards, oldest first, or over nothing if there's no such event.
+    pub fn iter_from(&self, hash: Digest) -> impl Iterator<Item = &MetaEvent> + '_ {
+        let index = self.index.get(&hash).copied().unwrap_or(self.len());
+        self.store.iter_from(index)
+    }
+
+    /// Iterates over the events of type `type_`, oldest first.
+    ///
+    /// This is looked up in an index kept as events are added, so finding
+    /// the latest event of a type with `next_back` doesn't scan the chain.
+    pub fn events_of_type<'a>(
+        &'a self,
+        type_: &'a str,
+    ) -> impl DoubleEndedIterator<Item = &'a MetaEvent> + 'a {
+        self.types
+            .get(type_)
+            .map_or(&[][..], |indices| &indices[..])
+            .iter()
+            .filter_map(|&i| self.store.get(i))
+    }
+
+    /// Returns the newest event matching `predicate`, searching backwards
+    /// from the head.
+    ///
+    /// Filtering by type first with [`Chain::events_of_type`] avoids looking
+    /// at unrelated events.
+    pub fn find(&self, mut predicate: impl FnMut(&MetaEvent) -> bool) -> Option<&MetaEvent> {
+        (0..self.len())
+            .rev()
+            .filter_map(|i| self.store.get(i))
+            .find(|node| predicate(node))
+    }
+
+    /// Follows parent links back from the event with hash `hash`, yielding
+    /// the events before it newest first.
+    ///
+    /// Stops at the first event, or at one whose parent isn't in this chain,
+    /// such as a `checkpoint` left by [`Chain::compact`].
+    pub fn ancestors(&self, hash: Digest) -> impl Iterator<Item = &MetaEvent> + '_ {
+        let start = self.get_parent(hash);
+        core::iter::successors(start, move |node| self.get_parent(node.hash))
+    }
+
+    /// Drops every event added after the one with hash `hash`, making it the
+    /// head again, and returns the dropped events oldest first.
+    ///
+    /// This is for discarding speculative events, such as those recorded by
+    /// a transaction which then aborted. Child chains spawned by a dropped
+    /// event are dropped too. The store must support
+    /// [`ChainStore::rewrite`], which may mean rewriting all of it.
+    pub fn rollback_to(&mut self, hash: Digest) -> Result<Vec<MetaEvent>> {
+        let Some(&index) = self.index.get(&hash) else {
+            bail!("no event with hash {hash} in this chain");
+        };
+        self.truncate(index + 1)
+    }
+
+    /// Drops every event after the first `keep`, see [`Chain::rollback_to`].
+    pub(crate) fn truncate(&mut self, keep: usize) -> Result<Vec<MetaEvent>> {
+        if keep >= self.len() {
+            return Ok(Vec::new());
+        }
+        let events = self.events().cloned().collect::<Vec<_>>();
+        let (kept, dropped) = events.split_at(keep);
+        self.store.rewrite(kept.to_vec())?;
+        self.reindex();
+        self.children
+            .retain(|(spawn, _)| !dropped.iter().any(|node| node.hash == *spawn));
+        Ok(dropped.to_vec())
+    }
+
+    /// Walks the chain from the start, checking that every event links to
+    /// the one before it and that every stored hash matches its contents.
+    ///
+    /// Stops at the first problem and describes where it is, which makes this
+    /// suitable for validating chains received from untrusted parties.
+    ///
+    /// A chain starting with a `checkpoint` event, see [`Chain::compact`],
+    /// can't be checked before that point: the checkpoint is only required
+    /// to carry the hash of the last event it folded.
+    pub fn verify(&self) -> Result<(), IntegrityError> {
+        let result = self.check_links();
+        if let Err(e) = &result {
+            metrics::report(|m| m.verification_failed(e));
+        }
+        result
+    }
+
+    fn check_links(&self) -> Result<(), IntegrityError> {
+        let mut parent = None;
+        for (index, node) in self.events().enumerate() {
+            if index == 0 && node.event.type_ == CHECKPOINT && node.event.parent.is_none() {
+                let head = Checkpoint::decode(&node.event).ok().map(|c| c.head);
+                if head != Some(node.hash) {
+                    return Err(IntegrityError {
+                        index,
+                        kind: IntegrityErrorKind::HashMismatch,
+                        expected: head,
+                        found: Some(node.hash),
+                    });
+                }
+                parent = Some(node.hash);
+                continue;
+            }
+            if node.event.parent != parent {
+                return Err(IntegrityError {
+                    index,
+                    kind: IntegrityErrorKind::BrokenLink,
+                    expected: parent,
+                    found: node.event.parent,
+                });
+            }
+            let hash = self.hasher.hash_event(&node.event);
+            if hash != node.hash {
+                return Err(IntegrityError {
+                    index,
+                    kind: IntegrityErrorKind::HashMismatch,
+                    expected: Some(hash),
+                    found: Some(node.hash),
+                });
+            }
+            parent = Some(node.hash);
+        }
+        Ok(())
+    }
+
+    /// Encodes this chain in its compact binary form, with event types and
+    /// repeated payloads interned, see the [`intern`](crate::chain::intern)
+    /// module.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        intern::encode(self)
     }
+
+    /// Decodes a chain previously encoded with [`Chain::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match intern::decode(bytes)? {
+            Some(chain) => Ok(chain),
+            // Encoded before interning.
+            None => migrate::decode_uninterned(bytes),
+        }
+    }
+}
+
+/// Emits a `tracing` event for a node just added to a chain.
+///
+/// Events are emitted at `DEBUG` level under the `wasmtime::chain` target, so
+/// subscribers can pick them out from the rest of Wasmtime's instrumentation.
+#[cfg(feature = "chain-tracing")]
+fn trace_event(node: &MetaEvent, index: usize) {
+    tracing::debug!(
+        target: "wasmtime::chain",
+        hash = %node.hash,
+        event_type = node.event.type_.as_str(),
+        parent = node.event.parent.map(tracing::field::display),
+        index,
+        size = node.event.data.len(),
+        "chain event added"
+    );
 }
+
+/// Folds `hash` into a rolling [`Chain::digest`].
+fn roll(digest: Digest, hash: Digest) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(digest.as_bytes());
+    hasher.update(hash.as_bytes());
+    Digest(hasher.finalize().into())
+}
+
+fn push_type(types: &mut HashMap<String, Vec<usize>>, type_: &str, index: usize) {
+    match types.get_mut(type_) {
+        Some(indices) => indices.push(index),
+        None => {
+            types.insert(type_.to_string(), vec![index]);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Chain {
+    type Item = &'a MetaEvent;
+    type IntoIter = Box<dyn Iterator<Item = &'a MetaEvent> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events()
+    }
+}
+
+/// An event as it crosses the component boundary, matching the `event` record
+/// of the `wasmtime:chain/chain` WIT interface.
+#[derive(ComponentType, Lift, Lower)]
+#[component(record)]
+#[component(wasmtime_crate = crate)]
+struct WitEvent {
+    hash: Vec<u8>,
+    parent: Option<Vec<u8>>,
+    #[component(name = "event-type")]
+    event_type: String,
+    data: Vec<u8>,
+}
+
+impl From<&MetaEvent> for WitEvent {
+    fn from(node: &MetaEvent) -> WitEvent {
+        WitEvent {
+            hash: node.hash.as_bytes().to_vec(),
+            parent: node.event.parent.map(|p| p.as_bytes().to_vec()),
+            event_type: node.event.type_.clone(),
+            data: node.event.data().into_owned(),
+        }
+    }
+}
+
+impl TryFrom<WitEvent> for MetaEvent {
+    type Error = Error;
+
+    fn try_from(event: WitEvent) -> Result<MetaEvent> {
+        let digest = |bytes: Vec<u8>| match <[u8; Digest::LEN]>::try_from(bytes) {
+            Ok(bytes) => Ok(Digest(bytes)),
+            Err(bytes) => Err(anyhow!(
+                "event hash must be {} bytes, found {}",
+                Digest::LEN,
+                bytes.len()
+            )),
+        };
+        Ok(MetaEvent::new(
+            digest(event.hash)?,
+            Event {
+                type_: event.event_type,
+                parent: event.parent.map(digest).transpose()?,
+                data: EventData::Owned(event.data),
+                compression: Compression::None,
+                severity: None,
+                schema_version: None,
+            },
+        ))
+    }
+}
+
+impl Chain {
+    fn to_wit(&self) -> Vec<WitEvent> {
+        self.events().map(WitEvent::from).collect()
+    }
+
+    fn from_wit(events: Vec<WitEvent>) -> Result<Chain> {
+        let events = events
+            .into_iter()
+            .map(MetaEvent::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Chain::from_store_unverified(
+            Arc::new(Sha256Hasher),
+            Box::new(MemoryChainStore::from(events)),
+        ))
+    }
+}
+
+// Chains cross the component boundary as a `list<event>`, oldest first, using
+// the `event` record published in the `wasmtime:chain/chain` WIT interface.
+// The hasher isn't part of that type so lifted chains are taken to be hashed
+// with SHA-256, and like other decoded chains they aren't verified.
 unsafe impl ComponentType for Chain {
-    type Lower = <String as ComponentType>::Lower; // Use String instead of str
+    type Lower = [ValRaw; 2];
 
     const ABI: CanonicalAbiInfo = CanonicalAbiInfo::POINTER_PAIR;
 
-    fn typecheck(ty: &InterfaceType, _types: &InstanceType<'_>) -> Result<()> {
-        match ty {
-            InterfaceType::String => Ok(()),
-            other => bail!("expected string found {:?}", other),
-        }
+    fn typecheck(ty: &InterfaceType, types: &InstanceType<'_>) -> Result<()> {
+        <Vec<WitEvent> as ComponentType>::typecheck(ty, types)
     }
 }
 
@@ -112,10 +994,7 @@ unsafe impl Lower for Chain {
         ty: InterfaceType,
         dst: &mut MaybeUninit<Self::Lower>,
     ) -> Result<()> {
-        // Convert Chain to JSON string
-        let json = serde_json::to_string(self)?;
-        // Use existing string lowering
-        <String as Lower>::lower(&json, cx, ty, dst)
+        <Vec<WitEvent> as Lower>::lower(&self.to_wit(), cx, ty, dst)
     }
 
     fn store<T>(
@@ -124,21 +1003,417 @@ unsafe impl Lower for Chain {
         ty: InterfaceType,
         offset: usize,
     ) -> Result<()> {
-        let json = serde_json::to_string(self)?;
-        <String as Lower>::store(&json, cx, ty, offset)
+        <Vec<WitEvent> as Lower>::store(&self.to_wit(), cx, ty, offset)
     }
 }
 
 unsafe impl Lift for Chain {
     fn lift(cx: &mut LiftContext<'_>, ty: InterfaceType, src: &Self::Lower) -> Result<Self> {
-        // Get the string using existing string lifting
-        let json = <String as Lift>::lift(cx, ty, src)?;
-        // Parse JSON back to Chain
-        Ok(serde_json::from_str(&json)?)
+        Chain::from_wit(<Vec<WitEvent> as Lift>::lift(cx, ty, src)?)
     }
 
     fn load(cx: &mut LiftContext<'_>, ty: InterfaceType, bytes: &[u8]) -> Result<Self> {
-        let json = <String as Lift>::load(cx, ty, bytes)?;
-        Ok(serde_json::from_str(&json)?)
+        Chain::from_wit(<Vec<WitEvent> as Lift>::load(cx, ty, bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rebuilds `chain` with its events edited by `f`, bypassing verification.
+    fn tamper(chain: &Chain, f: impl FnOnce(&mut Vec<MetaEvent>)) -> Chain {
+        let mut events = chain.events().cloned().collect();
+        f(&mut events);
+        Chain::from_store_unverified(
+            chain.hasher.clone(),
+            Box::new(MemoryChainStore::from(events)),
+        )
+    }
+
+    #[test]
+    fn bytes_round_trip() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.add(Event::new("first".to_string(), vec![1, 2, 3]));
+        let head = chain.add(Event::new("second".to_string(), vec![]));
+
+        let bytes = chain.to_bytes()?;
+        assert!(bytes.len() < serde_json::to_vec(&chain)?.len());
+
+        let decoded = Chain::from_bytes(&bytes)?;
+        assert_eq!(decoded.head(), Some(head));
+        assert!(decoded.get_event_by_hash(head).is_some());
+        assert!(decoded.get_parent(head).is_some());
+        assert_eq!(decoded.len(), 2);
+        assert!(Chain::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rolling_digest() -> Result<()> {
+        let mut chain = Chain::new();
+        assert_eq!(chain.digest(), Digest::default());
+        let a = chain.add(Event::new("a".to_string(), vec![1]));
+        let after_a = chain.digest();
+        assert_eq!(after_a, roll(Digest::default(), a));
+        let b = chain.add(Event::new("b".to_string(), vec![2]));
+        assert_eq!(chain.digest(), roll(after_a, b));
+
+        let decoded = Chain::from_bytes(&chain.to_bytes()?)?;
+        assert_eq!(decoded.digest(), chain.digest());
+        Ok(())
+    }
+
+    #[test]
+    fn subscribers_get_added_events() -> Result<()> {
+        let mut chain = Chain::new();
+        let events = chain.subscribe();
+        let dropped = chain.subscribe();
+        drop(dropped);
+        let tick = chain.add(Event::new("tick".to_string(), vec![1]));
+        let tock = chain.add(Event::new("tock".to_string(), vec![2]));
+        assert_eq!(chain.subscribers.len(), 1);
+
+        // Nothing is sent by clones.
+        chain
+            .clone()
+            .add(Event::new("elsewhere".to_string(), vec![]));
+        let received = events.try_iter().map(|n| n.hash()).collect::<Vec<_>>();
+        assert_eq!(received, [tick, tock]);
+
+        let thread = std::thread::spawn(move || events.recv().map(|n| n.hash()));
+        let again = chain.add(Event::new("again".to_string(), vec![]));
+        assert_eq!(thread.join().unwrap()?, again);
+        Ok(())
+    }
+
+    #[test]
+    fn batches_are_all_or_nothing() -> Result<()> {
+        /// Fails to store events once it holds `limit`.
+        #[derive(Debug)]
+        struct Limited {
+            events: MemoryChainStore,
+            limit: usize,
+        }
+
+        impl ChainStore for Limited {
+            fn append(&mut self, event: MetaEvent) -> Result<()> {
+                ensure!(self.events.len() < self.limit, "store is full");
+                self.events.append(event)
+            }
+            fn get(&self, index: usize) -> Option<&MetaEvent> {
+                self.events.get(index)
+            }
+            fn len(&self) -> usize {
+                self.events.len()
+            }
+            fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+                self.events.iter_from(index)
+            }
+            fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+                self.events.rewrite(events)
+            }
+        }
+
+        let store = Limited {
+            events: MemoryChainStore::new(),
+            limit: 3,
+        };
+        let mut chain = Chain::with_store(Arc::new(Sha256Hasher), Box::new(store))?;
+        let events = chain.subscribe();
+        let first = chain.add(Event::new("a".to_string(), vec![]));
+        let batch = chain.add_batch(vec![
+            Event::new("b".to_string(), vec![1]),
+            Event::new("c".to_string(), vec![2]),
+        ])?;
+        assert_eq!(chain.head(), Some(batch[1]));
+        assert_eq!(chain.get_parent(batch[0]).unwrap().hash(), first);
+        assert_eq!(chain.get_parent(batch[1]).unwrap().hash(), batch[0]);
+        assert_eq!(chain.events_of_type("c").count(), 1);
+        chain.verify()?;
+
+        chain.rollback_to(first)?;
+        let before = events.try_iter().count();
+        assert!(chain
+            .add_batch(vec![
+                Event::new("b".to_string(), vec![1]),
+                Event::new("c".to_string(), vec![2]),
+                Event::new("d".to_string(), vec![3]),
+            ])
+            .is_err());
+        assert_eq!(chain.head(), Some(first));
+        assert_eq!(chain.store().len(), 1);
+        assert_eq!(chain.events_of_type("b").count(), 0);
+        assert_eq!(before, 3);
+        assert_eq!(events.try_iter().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_as_event_list() -> Result<()> {
+        use crate::component::{Component, Linker};
+        use crate::{Engine, Store};
+
+        let engine = Engine::default();
+        let component = Component::new(
+            &engine,
+            r#"
+                (component
+                    (type $e (record
+                        (field "hash" (list u8))
+                        (field "parent" (option (list u8)))
+                        (field "event-type" string)
+                        (field "data" (list u8))))
+                    (export $event "event" (type $e))
+                    (core module $m
+                        (memory (export "memory") 1)
+                        (global $bump (mut i32) (i32.const 16))
+                        (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                            (local $ret i32)
+                            (local.set $ret (global.get $bump))
+                            (global.set $bump (i32.add (global.get $bump) (local.get 3)))
+                            (local.get $ret))
+                        (func (export "echo") (param i32 i32) (result i32)
+                            (i32.store (i32.const 0) (local.get 0))
+                            (i32.store (i32.const 4) (local.get 1))
+                            (i32.const 0)))
+                    (core instance $i (instantiate $m))
+                    (func (export "echo") (param "chain" (list $event)) (result (list $event))
+                        (canon lift (core func $i "echo")
+                            (memory $i "memory") (realloc (func $i "realloc"))))
+                )
+            "#,
+        )?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let echo = instance.get_typed_func::<(&Chain,), (Chain,)>(&mut store, "echo")?;
+
+        let mut chain = Chain::new();
+        chain.add(Event::new("first".to_string(), vec![1, 2, 3]));
+        let head = chain.add(Event::new("second".to_string(), vec![]));
+        let (echoed,) = echo.call(&mut store, (&chain,))?;
+        assert_eq!(echoed.head(), Some(head));
+        assert_eq!(echoed.len(), 2);
+        echoed.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn rollback() -> Result<()> {
+        let mut chain = Chain::new();
+        let first = chain.add(Event::new("a".to_string(), vec![]));
+        let spawn = chain.spawn("speculative")?;
+        let last = chain.add(Event::new("b".to_string(), vec![]));
+
+        let dropped = chain.rollback_to(first)?;
+        let dropped = dropped.iter().map(|n| n.hash).collect::<Vec<_>>();
+        assert_eq!(dropped, [spawn, last]);
+        assert_eq!(chain.head(), Some(first));
+        assert!(chain.get_event_by_hash(last).is_none());
+        assert!(chain.child(spawn).is_none());
+        assert!(chain.rollback_to(first)?.is_empty());
+        assert!(chain.rollback_to(last).is_err());
+
+        chain.add(Event::new("c".to_string(), vec![]));
+        assert_eq!(chain.len(), 2);
+        chain.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn iterators() {
+        let mut chain = Chain::new();
+        let hashes = (0..5u8)
+            .map(|i| chain.add(Event::new("event".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+        let hashes_of = |nodes: Vec<&MetaEvent>| nodes.iter().map(|n| n.hash).collect::<Vec<_>>();
+
+        assert_eq!(hashes_of(chain.iter().collect()), hashes);
+        assert_eq!(hashes_of((&chain).into_iter().collect()), hashes);
+        assert_eq!(hashes_of(chain.iter_from(hashes[3]).collect()), hashes[3..]);
+        assert_eq!(chain.iter_from(Digest::default()).count(), 0);
+        assert_eq!(
+            hashes_of(chain.ancestors(hashes[3]).collect()),
+            [hashes[2], hashes[1], hashes[0]]
+        );
+        assert_eq!(chain.ancestors(hashes[0]).count(), 0);
+        assert_eq!(chain.ancestors(Digest::default()).count(), 0);
+        let data = chain
+            .iter()
+            .filter(|n| n.event.data[0] % 2 == 0)
+            .map(|n| n.event.data[0])
+            .collect::<Vec<_>>();
+        assert_eq!(data, [0, 2, 4]);
+    }
+
+    #[test]
+    fn search_by_type() -> Result<()> {
+        let mut chain = Chain::new();
+        for i in 0..10u8 {
+            let ty = if i % 3 == 0 { "http-request" } else { "other" };
+            chain.add(Event::new(ty.to_string(), vec![i]));
+        }
+        let data = |node: Option<&MetaEvent>| node.map(|n| n.event.data[0]);
+        let requests = chain
+            .events_of_type("http-request")
+            .map(|n| n.event.data[0])
+            .collect::<Vec<_>>();
+        assert_eq!(requests, [0, 3, 6, 9]);
+        assert_eq!(
+            data(chain.events_of_type("http-request").next_back()),
+            Some(9)
+        );
+        assert_eq!(chain.events_of_type("missing").count(), 0);
+        assert_eq!(data(chain.find(|n| n.event.data[0] < 5)), Some(4));
+        assert_eq!(data(chain.find(|n| n.event.data[0] > 10)), None);
+
+        // The index follows the chain being rewritten.
+        let hash = chain.iter().nth(4).unwrap().hash;
+        chain.rollback_to(hash)?;
+        assert_eq!(chain.events_of_type("http-request").count(), 2);
+        let decoded = Chain::from_bytes(&chain.to_bytes()?)?;
+        assert_eq!(decoded.events_of_type("other").count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let mut chain = Chain::new();
+        let hashes = (0..100u8)
+            .map(|i| chain.add(Event::new("event".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+        for (i, hash) in hashes.iter().enumerate() {
+            let node = chain.get_event_by_hash(*hash).unwrap();
+            assert_eq!(*node.event.data, [u8::try_from(i).unwrap()]);
+            let parent = chain.get_parent(*hash).map(|p| p.hash);
+            assert_eq!(parent, i.checked_sub(1).map(|p| hashes[p]));
+        }
+        assert!(chain.get_event_by_hash(Digest::default()).is_none());
+    }
+
+    #[test]
+    fn hash_covers_parent() -> Result<()> {
+        let mut a = Chain::new();
+        let first = a.add(Event::new("x".to_string(), vec![]));
+        let second = a.add(Event::new("x".to_string(), vec![]));
+        assert_ne!(first, second);
+        a.verify()?;
+
+        // Swapping two events breaks the links even though each event's
+        // contents are intact.
+        let mut b = Chain::new();
+        b.add(Event::new("one".to_string(), vec![]));
+        b.add(Event::new("two".to_string(), vec![]));
+        let b = tamper(&b, |events| events.swap(0, 1));
+        let err = b.verify().unwrap_err();
+        assert_eq!(err.index, 0);
+        assert_eq!(err.kind, IntegrityErrorKind::BrokenLink);
+        assert_eq!(err.expected, None);
+
+        let c = tamper(&a, |events| events[1].event.data.to_mut().push(1));
+        let err = c.verify().unwrap_err();
+        assert_eq!(err.index, 1);
+        assert_eq!(err.kind, IntegrityErrorKind::HashMismatch);
+        assert_eq!(err.found, Some(second));
+        Ok(())
+    }
+
+    #[test]
+    fn json_hashes() -> Result<()> {
+        let mut chain = Chain::new();
+        let head = chain.add(Event::new("event".to_string(), vec![]));
+        let json = serde_json::to_string(&chain)?;
+        assert!(json.contains(&head.to_hex()));
+        let decoded: Chain = serde_json::from_str(&json)?;
+        assert_eq!(decoded.head(), Some(head));
+
+        // Chains from before digests were widened stored `u64` hashes.
+        let legacy = r#"{"events":[{"hash":7,"event":{"type_":"a","parent":null,"data":[]}}]}"#;
+        let decoded: Chain = serde_json::from_str(legacy)?;
+        assert_eq!(decoded.head(), Some(Digest::from_u64(7)));
+        assert_eq!(decoded.hasher().name(), "legacy");
+        Ok(())
+    }
+
+    #[test]
+    fn hashers() -> Result<()> {
+        use crate::chain::hasher::LegacyHasher;
+
+        let mut legacy = Chain::with_hasher(Arc::new(LegacyHasher));
+        legacy.add(Event::new("a".to_string(), vec![1]));
+        legacy.add(Event::new("b".to_string(), vec![2]));
+        legacy.verify()?;
+
+        let json = serde_json::to_string(&legacy)?;
+        let decoded: Chain = serde_json::from_str(&json)?;
+        assert_eq!(decoded.hasher().name(), "legacy");
+        decoded.verify()?;
+
+        let mut sha = Chain::new();
+        let hash = sha.add(Event::new("a".to_string(), vec![1]));
+        assert_ne!(Some(hash), legacy.events().next().map(|e| e.hash));
+
+        let unknown = json.replace("\"legacy\"", "\"md5\"");
+        assert!(serde_json::from_str::<Chain>(&unknown).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "chain-tracing")]
+    #[test]
+    fn traces_added_events() {
+        use std::fmt::Debug;
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Metadata, Subscriber};
+
+        /// Collects the fields of every event as strings.
+        #[derive(Default)]
+        struct Fields(Mutex<Vec<HashMap<String, String>>>);
+
+        struct Visitor<'a>(&'a mut HashMap<String, String>);
+
+        impl Visit for Visitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{value:?}"));
+            }
+        }
+
+        impl Subscriber for &'static Fields {
+            fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+                metadata.target() == "wasmtime::chain"
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &tracing::Event<'_>) {
+                let mut fields = HashMap::new();
+                event.record(&mut Visitor(&mut fields));
+                self.0.lock().unwrap().push(fields);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let fields: &'static Fields = Box::leak(Box::default());
+        let mut chain = Chain::new();
+        let (first, second) = tracing::subscriber::with_default(fields, || {
+            let first = chain.add(Event::new("first".to_string(), vec![1, 2]));
+            let second = chain.add(Event::new("second".to_string(), vec![]));
+            (first, second)
+        });
+
+        let events = fields.0.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["hash"], first.to_string());
+        assert_eq!(events[0]["event_type"], "\"first\"");
+        assert_eq!(events[0]["size"], "2");
+        assert!(!events[0].contains_key("parent"));
+        assert_eq!(events[1]["parent"], first.to_string());
+        assert_eq!(events[1]["hash"], second.to_string());
+        assert_eq!(events[1]["index"], "1");
     }
 }
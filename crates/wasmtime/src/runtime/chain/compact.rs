@@ -0,0 +1,243 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capping a chain's length by folding old events into a checkpoint.
+//!
+//! [`Chain::compact`] replaces every event but the newest few with a single
+//! `checkpoint` event holding a digest of the application's state at that
+//! point. The checkpoint keeps the hash of the last event it replaced, so the
+//! events after it still link to it and the rest of the chain verifies as
+//! before. What happened before the checkpoint can no longer be checked, only
+//! trusted, or vouched for by the checkpoint's signature.
+
+use crate::chain::record::decode;
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Type of the event standing in for the events folded by
+/// [`Chain::compact`].
+pub const CHECKPOINT: &str = "checkpoint";
+
+/// Payload of a [`CHECKPOINT`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Digest of the application's state after the folded events, as given
+    /// to [`Chain::compact`].
+    pub state: Digest,
+    /// Hash of the last folded event, which the checkpoint also takes as its
+    /// own.
+    pub head: Digest,
+    /// Number of events folded, including those folded into earlier
+    /// checkpoints.
+    pub events: u64,
+    /// The chain's [id](Chain::id), which its folded `genesis` event held.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Digest>,
+}
+
+impl Checkpoint {
+    pub fn decode(event: &Event) -> Result<Checkpoint> {
+        decode(event, CHECKPOINT)
+    }
+}
+
+/// When and how a chain compacts itself, see [`Chain::set_compaction`].
+pub struct Compaction {
+    max_events: usize,
+    keep: usize,
+    state: Box<dyn Fn(&Chain) -> Digest + Send + Sync>,
+}
+
+impl Compaction {
+    /// Compacts the chain once it holds more than `max_events` events,
+    /// keeping the newest `keep` of them after the checkpoint.
+    ///
+    /// `state` is called with the chain just before it's compacted and
+    /// returns the digest to store in the checkpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keep` isn't less than `max_events`.
+    pub fn new(
+        max_events: usize,
+        keep: usize,
+        state: impl Fn(&Chain) -> Digest + Send + Sync + 'static,
+    ) -> Compaction {
+        assert!(keep < max_events, "compaction must drop some events");
+        Compaction {
+            max_events,
+            keep,
+            state: Box::new(state),
+        }
+    }
+}
+
+impl fmt::Debug for Compaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compaction")
+            .field("max_events", &self.max_events)
+            .field("keep", &self.keep)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Chain {
+    /// Replaces all but the newest `keep` events with a `checkpoint` event
+    /// recording `state`, which should digest whatever the folded events
+    /// built up.
+    ///
+    /// A checkpoint at the start of the chain is folded into the new one.
+    /// Does nothing if the chain has no more than `keep` events. The store
+    /// must support [`ChainStore::rewrite`](crate::chain::ChainStore::rewrite).
+    pub fn compact(&mut self, keep: usize, state: Digest) -> Result<()> {
+        let len = self.len();
+        if len <= keep {
+            return Ok(());
+        }
+        let fold = len - keep;
+        let mut folded = u64::try_from(fold)?;
+        if let Some(first) = self.store.get(0) {
+            if first.event().type_() == CHECKPOINT {
+                folded += Checkpoint::decode(first.event())?.events - 1;
+            }
+        }
+        let head = self.store.get(fold - 1).unwrap().hash();
+        let checkpoint = Checkpoint {
+            state,
+            head,
+            events: folded,
+            id: self.id(),
+        };
+        let event = Event::new(CHECKPOINT.to_string(), serde_json::to_vec(&checkpoint)?);
+        let mut node = MetaEvent::new(head, event);
+        if let Some(signer) = &self.signer {
+            let signature = signer.sign(&node.signing_input());
+            node = node.with_signature(Some(signature));
+        }
+
+        let events = core::iter::once(node)
+            .chain(self.store.iter_from(fold).cloned())
+            .collect::<Vec<_>>();
+        self.store.rewrite(events)?;
+        self.reindex();
+        Ok(())
+    }
+
+    /// Compacts this chain automatically as events are added, or stops doing
+    /// so if `compaction` is `None`.
+    ///
+    /// Compaction happens as part of adding an event; if it fails the event
+    /// is still added, and compaction is tried again with the next one.
+    pub fn set_compaction(&mut self, compaction: Option<Compaction>) {
+        self.compaction = compaction.map(Arc::new);
+    }
+
+    pub(crate) fn compact_if_needed(&mut self) -> Result<()> {
+        let Some(compaction) = self.compaction.clone() else {
+            return Ok(());
+        };
+        if self.len() <= compaction.max_events {
+            return Ok(());
+        }
+        let state = (compaction.state)(self);
+        self.compact(compaction.keep, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryChainStore;
+
+    fn add(chain: &mut Chain, n: u8) -> Vec<Digest> {
+        (0..n)
+            .map(|i| chain.add(Event::new("event".to_string(), vec![i])))
+            .collect()
+    }
+
+    #[test]
+    fn compact_keeps_links() -> Result<()> {
+        let mut chain = Chain::new();
+        let hashes = add(&mut chain, 5);
+        chain.compact(2, Digest([7; Digest::LEN]))?;
+
+        assert_eq!(chain.len(), 3);
+        chain.verify()?;
+        let first = chain.store().get(0).unwrap();
+        assert_eq!(first.hash(), hashes[2]);
+        let checkpoint = Checkpoint::decode(first.event())?;
+        assert_eq!(checkpoint.state, Digest([7; Digest::LEN]));
+        assert_eq!(checkpoint.events, 3);
+        assert_eq!(chain.get_parent(hashes[3]).unwrap().hash(), hashes[2]);
+        assert!(chain.get_event_by_hash(hashes[0]).is_none());
+
+        // Later events link on as usual, and another compaction counts the
+        // events the first one folded.
+        add(&mut chain, 2);
+        chain.compact(1, Digest::default())?;
+        chain.verify()?;
+        let first = chain.store().get(0).unwrap();
+        assert_eq!(Checkpoint::decode(first.event())?.events, 6);
+        Ok(())
+    }
+
+    #[test]
+    fn forged_checkpoint_fails() -> Result<()> {
+        let mut chain = Chain::new();
+        add(&mut chain, 3);
+        chain.compact(1, Digest::default())?;
+        let mut events = chain.events().cloned().collect::<Vec<_>>();
+        events[0] = MetaEvent::new(Digest::default(), events[0].event().clone());
+        let forged = Chain::from_store_unverified(
+            chain.hasher.clone(),
+            Box::new(MemoryChainStore::from(events)),
+        );
+        assert_eq!(forged.verify().unwrap_err().index, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn automatic_compaction() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.set_compaction(Some(Compaction::new(4, 1, |chain| chain.head().unwrap())));
+        add(&mut chain, 10);
+        assert!(chain.len() <= 4);
+        chain.verify()?;
+        let first = chain.store().get(0).unwrap();
+        assert_eq!(
+            Checkpoint::decode(first.event())?.events + chain.len() as u64 - 1,
+            10
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact_chain_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let mut chain = Chain::open(&path)?;
+        add(&mut chain, 4);
+        chain.compact(1, Digest::default())?;
+        let head = chain.add(Event::new("after".to_string(), vec![]));
+        drop(chain);
+
+        let chain = Chain::open(&path)?;
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.head(), Some(head));
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compressing large event payloads.
+//!
+//! A chain with a compression threshold, see
+//! [`Chain::set_compression_threshold`], compresses the payload of every
+//! event larger than the threshold with zstd as the event is added, both in
+//! memory and in its store. Each event records whether its payload is
+//! compressed, see [`Event::compression`], and [`Event::data`] decompresses
+//! it again, so compression is invisible to everything reading events.
+//! Hashes are computed over uncompressed payloads, so compressing a chain's
+//! events doesn't change its hashes.
+//!
+//! Compressing needs the `chain-zstd` feature, as does reading events which
+//! were compressed. [`SqliteChainStore`](crate::chain::SqliteChainStore)
+//! stores payloads uncompressed.
+
+use crate::chain::chain::EventData;
+use crate::chain::{Chain, Event};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// How an event's payload is stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    /// As it is.
+    #[default]
+    None,
+    /// As a zstd frame.
+    Zstd,
+}
+
+/// The zstd level payloads are compressed at, which favors speed since
+/// events are compressed as they're recorded.
+#[cfg(feature = "chain-zstd")]
+const LEVEL: i32 = 3;
+
+impl Compression {
+    /// Decompresses `data`, stored with this compression.
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Cow<'_, [u8]>> {
+        match self {
+            Compression::None => Ok(Cow::Borrowed(data)),
+            #[cfg(feature = "chain-zstd")]
+            Compression::Zstd => Ok(Cow::Owned(
+                zstd::stream::decode_all(data).context("failed to decompress event payload")?,
+            )),
+            #[cfg(not(feature = "chain-zstd"))]
+            Compression::Zstd => {
+                bail!("event payload is zstd-compressed but the `chain-zstd` feature is disabled")
+            }
+        }
+    }
+}
+
+impl Event {
+    /// Compresses the payload if it's larger than `threshold` bytes and
+    /// compressing it makes it smaller.
+    pub(crate) fn compress(&mut self, threshold: usize) {
+        if self.compression != Compression::None || self.data.len() <= threshold {
+            return;
+        }
+        #[cfg(feature = "chain-zstd")]
+        if let Ok(compressed) = zstd::stream::encode_all(&*self.data, LEVEL) {
+            if compressed.len() < self.data.len() {
+                self.data = EventData::Owned(compressed);
+                self.compression = Compression::Zstd;
+            }
+        }
+    }
+
+    /// Stores the payload uncompressed.
+    pub(crate) fn decompress(&mut self) -> Result<()> {
+        if let Cow::Owned(data) = self.compression.decompress(&self.data)? {
+            self.data = EventData::Owned(data);
+        }
+        self.compression = Compression::None;
+        Ok(())
+    }
+}
+
+impl Chain {
+    /// Compresses the payloads of events added from now on, and of events
+    /// added to child chains spawned from now on, when they're larger than
+    /// `threshold` bytes, or stops compressing them if `threshold` is `None`.
+    ///
+    /// Events already in the chain are left as they are.
+    #[cfg(feature = "chain-zstd")]
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
+    }
+
+    /// The threshold set with `Chain::set_compression_threshold`.
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+}
+
+#[cfg(all(test, feature = "chain-zstd"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_large_payloads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let large = b"a recorded payload ".repeat(100);
+
+        let mut plain = Chain::new();
+        plain.add(Event::new("small".to_string(), vec![1, 2, 3]));
+        let plain = plain.add(Event::new("big".to_string(), large.clone()));
+
+        let mut chain = Chain::open(&path)?;
+        chain.set_compression_threshold(Some(64));
+        let small = chain.add(Event::new("small".to_string(), vec![1, 2, 3]));
+        let big = chain.add(Event::new("big".to_string(), large.clone()));
+        assert_eq!(big, plain);
+
+        let node = chain.get_event_by_hash(big).unwrap();
+        assert_eq!(node.event().compression(), Compression::Zstd);
+        assert!(node.event().stored_data().len() < large.len() / 5);
+        assert_eq!(node.event().data(), large.as_slice());
+        let node = chain.get_event_by_hash(small).unwrap();
+        assert_eq!(node.event().compression(), Compression::None);
+        drop(chain);
+
+        let chain = Chain::open(&path)?;
+        chain.verify()?;
+        let node = chain.get_event_by_hash(big).unwrap();
+        assert_eq!(node.event().compression(), Compression::Zstd);
+        assert_eq!(node.event().data(), large.as_slice());
+
+        let json: Chain = serde_json::from_str(&serde_json::to_string(&chain)?)?;
+        json.verify()?;
+        let bytes = Chain::from_bytes(&chain.to_bytes()?)?;
+        assert_eq!(bytes.head(), Some(big));
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording core wasm values.
+//!
+//! [`SerializableVal`](crate::chain::SerializableVal) mirrors component
+//! values, while [`SerializableCoreVal`] mirrors the [`Val`]s passed to and
+//! returned from the functions of plain core modules, so calls to those can
+//! be recorded as [`CoreCall`](crate::chain::CoreCall) events too.
+//!
+//! Numbers are recorded exactly, floats by their bits. References only have
+//! meaning inside the store holding them, so like resources they're recorded
+//! as numbers handed out by a registry kept in the store, see
+//! [`CoreRefRegistry`]. Converting a recorded value back with
+//! [`SerializableCoreVal::to_val`] resolves those numbers in the same store.
+//! `anyref`s, from the GC proposal, can't be recorded.
+
+use crate::chain::ChainValueError;
+use crate::prelude::*;
+use crate::{AsContextMut, ExternRef, Func, ManuallyRooted, Rooted, StoreContextMut, Val, V128};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// A serializable mirror of a core wasm [`Val`], see the
+/// [module documentation](crate::chain::core_val).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SerializableCoreVal {
+    I32(i32),
+    I64(i64),
+    /// The bits of an `f32`, as in [`Val::F32`].
+    F32(u32),
+    /// The bits of an `f64`, as in [`Val::F64`].
+    F64(u64),
+    V128(u128),
+    /// A `funcref` numbered by the store's [`CoreRefRegistry`], or null.
+    FuncRef(Option<u32>),
+    /// An `externref` numbered by the store's [`CoreRefRegistry`], or null.
+    ExternRef(Option<u32>),
+}
+
+/// Per-store numbering of the functions and external references which
+/// appear in recorded core values.
+///
+/// References are numbered in the order they're first recorded, functions
+/// and external references separately. Registered external references are
+/// kept alive for as long as the store.
+#[derive(Default)]
+pub struct CoreRefRegistry {
+    funcs: Vec<Func>,
+    externs: Vec<ManuallyRooted<ExternRef>>,
+    /// Numbers taken by functions and external references registered before
+    /// the store was restored from a [`ChainState`](crate::chain::ChainState).
+    bases: (u32, u32),
+}
+
+impl fmt::Debug for CoreRefRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoreRefRegistry")
+            .field("funcs", &self.funcs.len())
+            .field("externs", &self.externs.len())
+            .finish()
+    }
+}
+
+impl CoreRefRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number `func` is recorded under, numbering it if it
+    /// hasn't been seen before.
+    fn register_func<T>(&mut self, store: &mut StoreContextMut<'_, T>, func: Func) -> u32 {
+        let key = func.hash_key(store.0);
+        let index = match self.funcs.iter().position(|f| f.hash_key(store.0) == key) {
+            Some(index) => index,
+            None => {
+                self.funcs.push(func);
+                self.funcs.len() - 1
+            }
+        };
+        self.bases.0 + u32::try_from(index).unwrap()
+    }
+
+    /// Returns the number `r` is recorded under, numbering and rooting it if
+    /// it hasn't been seen before.
+    fn register_extern<T>(
+        &mut self,
+        store: &mut StoreContextMut<'_, T>,
+        r: &Rooted<ExternRef>,
+    ) -> Result<u32> {
+        for (index, known) in self.externs.iter().enumerate() {
+            if Rooted::ref_eq(&*store, known, r)? {
+                return Ok(self.bases.1 + u32::try_from(index).unwrap());
+            }
+        }
+        self.externs.push(r.to_manually_rooted(&mut *store)?);
+        Ok(self.bases.1 + u32::try_from(self.externs.len() - 1).unwrap())
+    }
+
+    /// The numbers the next function and external reference registered
+    /// would get, were they new.
+    pub(crate) fn next_ids(&self) -> (u32, u32) {
+        (
+            self.bases.0 + u32::try_from(self.funcs.len()).unwrap(),
+            self.bases.1 + u32::try_from(self.externs.len()).unwrap(),
+        )
+    }
+
+    /// Makes this empty registry continue the numbering of one whose next
+    /// numbers were `next_ids`, so that references registered from now on
+    /// don't reuse them.
+    pub(crate) fn continue_numbering(&mut self, next_ids: (u32, u32)) {
+        debug_assert!(self.is_empty());
+        self.bases = next_ids;
+    }
+
+    pub fn len(&self) -> usize {
+        self.funcs.len() + self.externs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SerializableCoreVal {
+    /// Converts `val`, numbering the references in it through the store's
+    /// [`CoreRefRegistry`].
+    pub fn from_val(
+        mut store: impl AsContextMut,
+        val: &Val,
+    ) -> Result<SerializableCoreVal, ChainValueError> {
+        let mut store = store.as_context_mut();
+        with_registry(&mut store, |registry, store| {
+            registry_from_val(registry, store, val)
+        })
+    }
+
+    /// Converts a list of parameters or results.
+    pub fn from_vals(
+        mut store: impl AsContextMut,
+        vals: &[Val],
+    ) -> Result<Vec<SerializableCoreVal>, ChainValueError> {
+        let mut store = store.as_context_mut();
+        with_registry(&mut store, |registry, store| {
+            vals.iter()
+                .map(|val| registry_from_val(registry, store, val))
+                .collect()
+        })
+    }
+
+    /// Rebuilds a core [`Val`], resolving references through the store's
+    /// [`CoreRefRegistry`].
+    pub fn to_val(&self, mut store: impl AsContextMut) -> Result<Val, ChainValueError> {
+        let mut store = store.as_context_mut();
+        Ok(match *self {
+            SerializableCoreVal::I32(n) => Val::I32(n),
+            SerializableCoreVal::I64(n) => Val::I64(n),
+            SerializableCoreVal::F32(bits) => Val::F32(bits),
+            SerializableCoreVal::F64(bits) => Val::F64(bits),
+            SerializableCoreVal::V128(bits) => Val::V128(V128::from(bits)),
+            SerializableCoreVal::FuncRef(None) => Val::FuncRef(None),
+            SerializableCoreVal::FuncRef(Some(id)) => {
+                let registry = store.0.core_ref_registry_mut();
+                let index = id.checked_sub(registry.bases.0);
+                match index.and_then(|index| registry.funcs.get(index as usize)) {
+                    Some(func) => Val::FuncRef(Some(*func)),
+                    None => return Err(ChainValueError::UnknownReference("funcref", id)),
+                }
+            }
+            SerializableCoreVal::ExternRef(None) => Val::ExternRef(None),
+            SerializableCoreVal::ExternRef(Some(id)) => {
+                let registry = core::mem::take(store.0.core_ref_registry_mut());
+                let rooted = id
+                    .checked_sub(registry.bases.1)
+                    .and_then(|index| registry.externs.get(index as usize))
+                    .map(|r| r.to_rooted(&mut store));
+                *store.0.core_ref_registry_mut() = registry;
+                match rooted {
+                    Some(r) => Val::ExternRef(Some(r)),
+                    None => return Err(ChainValueError::UnknownReference("externref", id)),
+                }
+            }
+        })
+    }
+
+    /// Rebuilds a list of parameters or results.
+    pub fn to_vals(
+        vals: &[SerializableCoreVal],
+        mut store: impl AsContextMut,
+    ) -> Result<Vec<Val>, ChainValueError> {
+        let mut store = store.as_context_mut();
+        vals.iter().map(|v| v.to_val(&mut store)).collect()
+    }
+}
+
+/// Runs `f` with the store's registry taken out of it, so both can be used
+/// at once.
+fn with_registry<T, R>(
+    store: &mut StoreContextMut<'_, T>,
+    f: impl FnOnce(&mut CoreRefRegistry, &mut StoreContextMut<'_, T>) -> R,
+) -> R {
+    let mut registry = core::mem::take(store.0.core_ref_registry_mut());
+    let result = f(&mut registry, store);
+    *store.0.core_ref_registry_mut() = registry;
+    result
+}
+
+fn registry_from_val<T>(
+    registry: &mut CoreRefRegistry,
+    store: &mut StoreContextMut<'_, T>,
+    val: &Val,
+) -> Result<SerializableCoreVal, ChainValueError> {
+    Ok(match val {
+        Val::I32(n) => SerializableCoreVal::I32(*n),
+        Val::I64(n) => SerializableCoreVal::I64(*n),
+        Val::F32(bits) => SerializableCoreVal::F32(*bits),
+        Val::F64(bits) => SerializableCoreVal::F64(*bits),
+        Val::V128(v) => SerializableCoreVal::V128(v.as_u128()),
+        Val::FuncRef(func) => {
+            SerializableCoreVal::FuncRef(func.map(|func| registry.register_func(store, func)))
+        }
+        Val::ExternRef(None) => SerializableCoreVal::ExternRef(None),
+        Val::ExternRef(Some(r)) => match registry.register_extern(store, r) {
+            Ok(id) => SerializableCoreVal::ExternRef(Some(id)),
+            // The reference was unrooted before it could be recorded.
+            Err(_) => return Err(ChainValueError::Unsupported("unrooted externref")),
+        },
+        Val::AnyRef(_) => return Err(ChainValueError::Unsupported("anyref")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Store;
+
+    #[test]
+    fn numbers_references() -> Result<()> {
+        let mut store = Store::<()>::default();
+        let f = Func::wrap(&mut store, || {});
+        let g = Func::wrap(&mut store, |x: i32| x);
+        let vals = [
+            Val::I32(-1),
+            Val::F32(f32::NAN.to_bits() | 1),
+            Val::V128(V128::from(u128::MAX)),
+            Val::FuncRef(Some(f)),
+            Val::FuncRef(Some(g)),
+            Val::FuncRef(Some(f)),
+            Val::FuncRef(None),
+        ];
+        let recorded = SerializableCoreVal::from_vals(&mut store, &vals)?;
+        assert_eq!(
+            recorded,
+            [
+                SerializableCoreVal::I32(-1),
+                SerializableCoreVal::F32(f32::NAN.to_bits() | 1),
+                SerializableCoreVal::V128(u128::MAX),
+                SerializableCoreVal::FuncRef(Some(0)),
+                SerializableCoreVal::FuncRef(Some(1)),
+                SerializableCoreVal::FuncRef(Some(0)),
+                SerializableCoreVal::FuncRef(None),
+            ]
+        );
+        assert_eq!(store.core_ref_registry().len(), 2);
+
+        let rebuilt = SerializableCoreVal::to_vals(&recorded, &mut store)?;
+        assert_eq!(
+            SerializableCoreVal::from_vals(&mut store, &rebuilt)?,
+            recorded
+        );
+        assert_eq!(
+            SerializableCoreVal::FuncRef(Some(5))
+                .to_val(&mut store)
+                .unwrap_err(),
+            ChainValueError::UnknownReference("funcref", 5)
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "gc")]
+    fn numbers_extern_refs() -> Result<()> {
+        let mut store = Store::<()>::default();
+        let a = ExternRef::new(&mut store, 1u32)?;
+        let b = ExternRef::new(&mut store, 2u32)?;
+        let vals = [
+            Val::ExternRef(Some(a)),
+            Val::ExternRef(Some(b)),
+            Val::ExternRef(Some(a)),
+        ];
+        let recorded = SerializableCoreVal::from_vals(&mut store, &vals)?;
+        assert_eq!(
+            recorded,
+            [
+                SerializableCoreVal::ExternRef(Some(0)),
+                SerializableCoreVal::ExternRef(Some(1)),
+                SerializableCoreVal::ExternRef(Some(0)),
+            ]
+        );
+        let rebuilt = recorded[1].to_val(&mut store)?;
+        assert_eq!(
+            SerializableCoreVal::from_val(&mut store, &rebuilt)?,
+            recorded[1]
+        );
+        assert_eq!(store.core_ref_registry().len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Moving an instance to the point in a recording where an event was added.
+//!
+//! [`Debugger::seek`] restores the last `snapshot` event, see
+//! [`Store::snapshot_to_chain`](crate::Store::snapshot_to_chain), at or
+//! before the target event and then [replays](crate::chain::replay) the
+//! export calls recorded between the two. Seeking works backwards as well as
+//! forwards, any number of times, on the same instance.
+//!
+//! Replayed calls can only be stopped once they return, so the target must be
+//! an event recorded between export calls, such as a `function-call` event or
+//! a snapshot. Snapshots should likewise be taken between calls, and a
+//! recording to seek in should take one right after instantiation so that
+//! every later event has a snapshot before it.
+
+use crate::chain::replay::Replay;
+use crate::chain::snapshot::{Snapshot, SNAPSHOT};
+use crate::chain::{Chain, Digest, MetaEvent};
+use crate::component::{Component, Instance, Linker};
+use crate::prelude::*;
+use crate::AsContextMut;
+
+/// Seeks an instance to events of a recorded [`Chain`], see the
+/// [module documentation](crate::chain::debugger).
+///
+/// Import stubs are added to a [`Linker`] with [`Debugger::add_to_linker`],
+/// and instances created from that linker can then be moved with
+/// [`Debugger::seek`].
+#[derive(Clone)]
+pub struct Debugger {
+    chain: Chain,
+    replay: Replay,
+}
+
+impl Debugger {
+    /// Prepares to seek through `chain`.
+    pub fn new(chain: Chain) -> Result<Debugger> {
+        let replay = Replay::new(&Chain::new())?;
+        Ok(Debugger { chain, replay })
+    }
+
+    /// The recording being seeked through.
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
+    /// Defines every import of `component` in `linker` as a function which
+    /// returns the recorded result for the call being replayed, see
+    /// [`Replay::add_to_linker`].
+    pub fn add_to_linker<T>(&self, linker: &mut Linker<T>, component: &Component) -> Result<()> {
+        self.replay.add_to_linker(linker, component)
+    }
+
+    /// Puts `instance` and its store back into the state they were in when
+    /// the event `hash` was added to the recording.
+    ///
+    /// Afterwards the store's chain holds the recording up to and including
+    /// that event. When the store records calls, the replayed calls are
+    /// checked against the recording as by [`Replay::run`].
+    pub fn seek(
+        &self,
+        mut store: impl AsContextMut,
+        instance: &Instance,
+        hash: Digest,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let mut events = Vec::new();
+        for node in self.chain.iter() {
+            events.push(node.clone());
+            if node.hash() == hash {
+                break;
+            }
+        }
+        ensure!(
+            events.last().map(MetaEvent::hash) == Some(hash),
+            "no event with hash {hash} in the recording"
+        );
+        let Some(start) = events
+            .iter()
+            .rposition(|node| node.event().type_() == SNAPSHOT)
+        else {
+            bail!("no snapshot was taken before event {hash}");
+        };
+
+        Snapshot::decode(events[start].event())?.restore(&mut store)?;
+        *store.chain_mut() = self.prefix(events[start].hash())?;
+        self.replay.reset(events, start + 1)?;
+        self.replay.run(&mut store, instance)?;
+        *store.chain_mut() = self.prefix(hash)?;
+        Ok(())
+    }
+
+    /// The recording up to and including the event `hash`.
+    fn prefix(&self, hash: Digest) -> Result<Chain> {
+        let mut chain = self.chain.clone();
+        chain.rollback_to(hash)?;
+        Ok(chain)
+    }
+}
+
+/// Instantiates `component` in `store` with imports answered from `chain`
+/// and seeks the instance to the event `hash`, see [`Debugger::seek`].
+///
+/// `store` should be new, so that it has the same instances as the store
+/// which took the recording's snapshots.
+pub fn seek<T>(
+    mut store: impl AsContextMut<Data = T>,
+    component: &Component,
+    chain: &Chain,
+    hash: Digest,
+) -> Result<Instance> {
+    let debugger = Debugger::new(chain.clone())?;
+    let mut linker = Linker::new(component.engine());
+    debugger.add_to_linker(&mut linker, component)?;
+    let instance = linker.instantiate(&mut store, component)?;
+    debugger.seek(&mut store, &instance, hash)?;
+    Ok(instance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::record::FUNCTION_CALL;
+    use crate::component::{Func, Val};
+    use crate::{Config, Engine, Store};
+    use std::sync::{Arc, Mutex};
+
+    const COMPONENT: &str = r#"
+        (component
+            (import "host" (instance $host
+                (export "next" (func (result u32)))
+            ))
+            (core func $next (canon lower (func $host "next")))
+            (core module $m
+                (import "" "next" (func $next (result i32)))
+                (global $total (mut i32) (i32.const 0))
+                (func (export "add") (param i32)
+                    (global.set $total
+                        (i32.add (global.get $total)
+                            (i32.add (local.get 0) (call $next)))))
+                (func (export "total") (result i32) (global.get $total)))
+            (core instance $i (instantiate $m
+                (with "" (instance (export "next" (func $next))))))
+            (func (export "add") (param "x" u32)
+                (canon lift (core func $i "add")))
+            (func (export "total") (result u32)
+                (canon lift (core func $i "total")))
+        )
+    "#;
+
+    fn total<T>(store: &mut Store<T>, instance: &Instance) -> Result<Val> {
+        let total = instance.get_func(&mut *store, "total").unwrap();
+        let mut results = [Val::U32(0)];
+        total.call(&mut *store, &[], &mut results)?;
+        total.post_return(&mut *store)?;
+        Ok(results[0].clone())
+    }
+
+    #[test]
+    fn seeks_backwards_and_forwards() -> Result<()> {
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, COMPONENT)?;
+
+        let mut linker = Linker::new(&engine);
+        let counter = Arc::new(Mutex::new(0u32));
+        let c = counter.clone();
+        linker.instance("host")?.func_wrap("next", move |_, ()| {
+            let mut n = c.lock().unwrap();
+            *n += 100;
+            Ok((*n,))
+        })?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        store.snapshot_to_chain()?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        let call = |store: &mut Store<()>, func: Func, x: u32| -> Result<Digest> {
+            func.call(&mut *store, &[Val::U32(x)], &mut [])?;
+            func.post_return(&mut *store)?;
+            Ok(store.chain().head().unwrap())
+        };
+        // Totals: 101, 303, then 606 after the second snapshot.
+        let first = call(&mut store, add, 1)?;
+        let second = call(&mut store, add, 2)?;
+        store.snapshot_to_chain()?;
+        let third = call(&mut store, add, 3)?;
+        assert_eq!(
+            store
+                .chain()
+                .get_event_by_hash(third)
+                .unwrap()
+                .event()
+                .type_(),
+            FUNCTION_CALL
+        );
+        let recording = store.chain().clone();
+
+        let mut store = Store::new(&engine, ());
+        let instance = seek(&mut store, &component, &recording, second)?;
+        assert_eq!(store.chain().head(), Some(second));
+        assert_eq!(total(&mut store, &instance)?, Val::U32(303));
+
+        let mut store = Store::new(&engine, ());
+        let debugger = Debugger::new(recording.clone())?;
+        let mut linker = Linker::new(&engine);
+        debugger.add_to_linker(&mut linker, &component)?;
+        let instance = linker.instantiate(&mut store, &component)?;
+        for (hash, expected) in [(third, 606), (first, 101), (third, 606)] {
+            debugger.seek(&mut store, &instance, hash)?;
+            assert_eq!(store.chain().head(), Some(hash));
+            assert_eq!(total(&mut store, &instance)?, Val::U32(expected));
+        }
+        assert_eq!(*counter.lock().unwrap(), 300);
+
+        // The first call can't be stopped at its import.
+        let import = recording.get_parent(first).unwrap().hash();
+        assert!(debugger.seek(&mut store, &instance, import).is_err());
+        Ok(())
+    }
+}
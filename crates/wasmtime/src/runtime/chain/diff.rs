@@ -0,0 +1,498 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structural differences between recorded values.
+//!
+//! [`SerializableVal::diff`] compares two values and describes how to get
+//! from the first to the second as a [`ValDiff`]: which record fields
+//! changed, which list elements were inserted or removed, which variant case
+//! was switched to, and so on down to the values which were replaced
+//! outright. Its `Display` lists one change per line along with the path to
+//! it, which is how [replay divergences](crate::chain::ReplayDivergence) of
+//! recorded calls are explained.
+//!
+//! A diff serializes along with the values it holds and can be
+//! [applied](ValDiff::apply) to the first value to get the second, so
+//! recording diffs rather than whole values keeps events about state which
+//! changes a little at a time small.
+
+use crate::chain::SerializableVal;
+use crate::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// How one [`SerializableVal`] differs from another, see the
+/// [module documentation](crate::chain::diff).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValDiff {
+    /// The values are equal.
+    Same,
+    /// The values are unrelated, such as different numbers or values of
+    /// different types.
+    Replaced {
+        old: SerializableVal,
+        new: SerializableVal,
+    },
+    /// Changes to the fields of a record, in the order of the new record
+    /// followed by removed fields.
+    Fields(Vec<FieldDiff>),
+    /// Changes to the elements of a list or tuple.
+    Elements(Vec<ElementDiff>),
+    /// A variant, enum, option or result switched to case `to`, with
+    /// `payload`. Options switch between `none` and `some`, and results
+    /// between `ok` and `err`.
+    Switched {
+        from: String,
+        to: String,
+        payload: Option<SerializableVal>,
+    },
+    /// The payload of a variant, option or result changed within the case
+    /// `case`.
+    Payload { case: String, diff: Box<ValDiff> },
+    /// Flags were set and cleared.
+    Flags {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+/// A change to a record field, see [`ValDiff::Fields`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldDiff {
+    Added(String, SerializableVal),
+    Removed(String, SerializableVal),
+    Changed(String, ValDiff),
+}
+
+/// A change to a list or tuple element, see [`ValDiff::Elements`].
+///
+/// Removed and changed elements are at their index in the old list, and
+/// inserted elements at their index in the new one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ElementDiff {
+    Inserted(usize, SerializableVal),
+    Removed(usize, SerializableVal),
+    Changed(usize, ValDiff),
+}
+
+/// Lists whose differing stretches would take more comparisons than this
+/// to align are compared element by element instead.
+const MAX_ALIGN: usize = 1 << 20;
+
+impl SerializableVal {
+    /// Describes how `other` differs from this value, see the
+    /// [module documentation](crate::chain::diff).
+    pub fn diff(&self, other: &SerializableVal) -> ValDiff {
+        use SerializableVal as V;
+
+        if self == other {
+            return ValDiff::Same;
+        }
+        let replaced = || ValDiff::Replaced {
+            old: self.clone(),
+            new: other.clone(),
+        };
+        match (self, other) {
+            (V::Record(a), V::Record(b)) => {
+                let mut fields = Vec::new();
+                for (name, new) in b {
+                    match a.iter().find(|(n, _)| n == name) {
+                        Some((_, old)) if old == new => {}
+                        Some((_, old)) => {
+                            fields.push(FieldDiff::Changed(name.clone(), old.diff(new)))
+                        }
+                        None => fields.push(FieldDiff::Added(name.clone(), new.clone())),
+                    }
+                }
+                for (name, old) in a {
+                    if !b.iter().any(|(n, _)| n == name) {
+                        fields.push(FieldDiff::Removed(name.clone(), old.clone()));
+                    }
+                }
+                ValDiff::Fields(fields)
+            }
+            (V::List(a), V::List(b)) => ValDiff::Elements(diff_lists(a, b)),
+            (V::Tuple(a), V::Tuple(b)) if a.len() == b.len() => ValDiff::Elements(
+                a.iter()
+                    .zip(b)
+                    .enumerate()
+                    .filter(|(_, (a, b))| a != b)
+                    .map(|(i, (a, b))| ElementDiff::Changed(i, a.diff(b)))
+                    .collect(),
+            ),
+            (V::Variant(a, x), V::Variant(b, y)) => switch(a, b, x.as_deref(), y.as_deref()),
+            (V::Enum(a), V::Enum(b)) => switch(a, b, None, None),
+            (V::Option(x), V::Option(y)) => {
+                let case = |v: &Option<_>| if v.is_some() { "some" } else { "none" };
+                switch(case(x), case(y), x.as_deref(), y.as_deref())
+            }
+            (V::Result(x), V::Result(y)) => {
+                let ((a, x), (b, y)) = (result_case(x), result_case(y));
+                switch(a, b, x, y)
+            }
+            (V::Flags(a), V::Flags(b)) => ValDiff::Flags {
+                added: b.iter().filter(|f| !a.contains(f)).cloned().collect(),
+                removed: a.iter().filter(|f| !b.contains(f)).cloned().collect(),
+            },
+            _ => replaced(),
+        }
+    }
+}
+
+fn result_case(
+    result: &Result<Option<Box<SerializableVal>>, Option<Box<SerializableVal>>>,
+) -> (&'static str, Option<&SerializableVal>) {
+    match result {
+        Ok(v) => ("ok", v.as_deref()),
+        Err(v) => ("err", v.as_deref()),
+    }
+}
+
+/// Diffs two values of the cases `a` and `b` of a variant-like type, with
+/// payloads `x` and `y`.
+fn switch(a: &str, b: &str, x: Option<&SerializableVal>, y: Option<&SerializableVal>) -> ValDiff {
+    match (x, y) {
+        (Some(x), Some(y)) if a == b => ValDiff::Payload {
+            case: a.to_string(),
+            diff: Box::new(x.diff(y)),
+        },
+        _ => ValDiff::Switched {
+            from: a.to_string(),
+            to: b.to_string(),
+            payload: y.cloned(),
+        },
+    }
+}
+
+/// Aligns `a` and `b` on their longest common subsequence, pairing up
+/// elements removed and inserted at the same point as changed ones.
+fn diff_lists(a: &[SerializableVal], b: &[SerializableVal]) -> Vec<ElementDiff> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // Pairs of indices into `a_mid` and `b_mid` of the elements kept.
+    let mut kept = Vec::new();
+    if a_mid.len().saturating_mul(b_mid.len()) <= MAX_ALIGN {
+        // lcs[i][j] is the length of the longest common subsequence of
+        // a_mid[i..] and b_mid[j..].
+        let mut lcs = vec![vec![0usize; b_mid.len() + 1]; a_mid.len() + 1];
+        for i in (0..a_mid.len()).rev() {
+            for j in (0..b_mid.len()).rev() {
+                lcs[i][j] = if a_mid[i] == b_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a_mid.len() && j < b_mid.len() {
+            if a_mid[i] == b_mid[j] {
+                kept.push((i, j));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    kept.push((a_mid.len(), b_mid.len()));
+
+    let mut diffs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in kept {
+        let paired = (next_i - i).min(next_j - j);
+        for k in 0..paired {
+            let (old, new) = (&a_mid[i + k], &b_mid[j + k]);
+            diffs.push(ElementDiff::Changed(prefix + i + k, old.diff(new)));
+        }
+        for k in i + paired..next_i {
+            diffs.push(ElementDiff::Removed(prefix + k, a_mid[k].clone()));
+        }
+        for k in j + paired..next_j {
+            diffs.push(ElementDiff::Inserted(prefix + k, b_mid[k].clone()));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    diffs
+}
+
+impl ValDiff {
+    /// Whether the values compared were equal.
+    pub fn is_same(&self) -> bool {
+        matches!(self, ValDiff::Same)
+    }
+
+    /// Returns the value `old` was diffed against, given the value the diff
+    /// was made from.
+    ///
+    /// Fails if this diff can't have been made from `old`, such as one
+    /// changing record fields applied to a list. Record fields which were
+    /// added and flags which were set are put last.
+    pub fn apply(&self, old: &SerializableVal) -> Result<SerializableVal> {
+        use SerializableVal as V;
+
+        let mismatch = || anyhow!("diff does not apply to a value of type {}", old.desc());
+        Ok(match (self, old) {
+            (ValDiff::Same, _) => old.clone(),
+            (ValDiff::Replaced { new, .. }, _) => new.clone(),
+            (ValDiff::Fields(diffs), V::Record(fields)) => {
+                let mut fields = fields.clone();
+                for diff in diffs {
+                    match diff {
+                        FieldDiff::Added(name, value) => fields.push((name.clone(), value.clone())),
+                        FieldDiff::Removed(name, _) => fields.retain(|(n, _)| n != name),
+                        FieldDiff::Changed(name, diff) => {
+                            let Some((_, value)) = fields.iter_mut().find(|(n, _)| n == name)
+                            else {
+                                bail!("record has no field `{name}`");
+                            };
+                            *value = diff.apply(value)?;
+                        }
+                    }
+                }
+                V::Record(fields)
+            }
+            (ValDiff::Elements(diffs), V::List(items) | V::Tuple(items)) => {
+                let mut items = items.iter().cloned().map(Some).collect::<Vec<_>>();
+                let mut inserted = Vec::new();
+                for diff in diffs {
+                    match diff {
+                        ElementDiff::Changed(i, diff) => {
+                            let Some(Some(item)) = items.get_mut(*i) else {
+                                bail!("no element {i} to change");
+                            };
+                            *item = diff.apply(item)?;
+                        }
+                        ElementDiff::Removed(i, _) => match items.get_mut(*i) {
+                            Some(item @ Some(_)) => *item = None,
+                            _ => bail!("no element {i} to remove"),
+                        },
+                        ElementDiff::Inserted(i, value) => inserted.push((*i, value.clone())),
+                    }
+                }
+                let mut items = items.into_iter().flatten().collect::<Vec<_>>();
+                inserted.sort_by_key(|(i, _)| *i);
+                for (i, value) in inserted {
+                    ensure!(i <= items.len(), "no position {i} to insert at");
+                    items.insert(i, value);
+                }
+                match old {
+                    V::Tuple(_) => V::Tuple(items),
+                    _ => V::List(items),
+                }
+            }
+            (ValDiff::Switched { to, payload, .. }, _) => {
+                let payload = payload.clone().map(Box::new);
+                match (old, to.as_str()) {
+                    (V::Variant(..), _) => V::Variant(to.clone(), payload),
+                    (V::Enum(_), _) => V::Enum(to.clone()),
+                    (V::Option(_), "some") => V::Option(Some(payload.ok_or_else(mismatch)?)),
+                    (V::Option(_), "none") => V::Option(None),
+                    (V::Result(_), "ok") => V::Result(Ok(payload)),
+                    (V::Result(_), "err") => V::Result(Err(payload)),
+                    _ => return Err(mismatch()),
+                }
+            }
+            (ValDiff::Payload { diff, .. }, V::Variant(case, Some(payload))) => {
+                V::Variant(case.clone(), Some(Box::new(diff.apply(payload)?)))
+            }
+            (ValDiff::Payload { diff, .. }, V::Option(Some(payload))) => {
+                V::Option(Some(Box::new(diff.apply(payload)?)))
+            }
+            (ValDiff::Payload { diff, .. }, V::Result(Ok(Some(payload)))) => {
+                V::Result(Ok(Some(Box::new(diff.apply(payload)?))))
+            }
+            (ValDiff::Payload { diff, .. }, V::Result(Err(Some(payload)))) => {
+                V::Result(Err(Some(Box::new(diff.apply(payload)?))))
+            }
+            (ValDiff::Flags { added, removed }, V::Flags(flags)) => {
+                let mut flags = flags.clone();
+                flags.retain(|f| !removed.contains(f));
+                let set = added
+                    .iter()
+                    .filter(|f| !flags.contains(f))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                flags.extend(set);
+                V::Flags(flags)
+            }
+            _ => return Err(mismatch()),
+        })
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, path: &str) -> fmt::Result {
+        match self {
+            ValDiff::Same => Ok(()),
+            ValDiff::Replaced { old, new } => writeln!(f, "{path}: {old:#} -> {new:#}"),
+            ValDiff::Fields(diffs) => {
+                for diff in diffs {
+                    match diff {
+                        FieldDiff::Added(name, value) => {
+                            writeln!(f, "{path}.{name}: added {value:#}")?
+                        }
+                        FieldDiff::Removed(name, value) => {
+                            writeln!(f, "{path}.{name}: removed {value:#}")?
+                        }
+                        FieldDiff::Changed(name, diff) => {
+                            diff.write(f, &format!("{path}.{name}"))?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ValDiff::Elements(diffs) => {
+                for diff in diffs {
+                    match diff {
+                        ElementDiff::Inserted(i, value) => {
+                            writeln!(f, "{path}[{i}]: inserted {value:#}")?
+                        }
+                        ElementDiff::Removed(i, value) => {
+                            writeln!(f, "{path}[{i}]: removed {value:#}")?
+                        }
+                        ElementDiff::Changed(i, diff) => diff.write(f, &format!("{path}[{i}]"))?,
+                    }
+                }
+                Ok(())
+            }
+            ValDiff::Switched { from, to, payload } => {
+                write!(f, "{path}: {from} -> {to}")?;
+                match payload {
+                    Some(payload) => writeln!(f, "({payload:#})"),
+                    None => writeln!(f),
+                }
+            }
+            ValDiff::Payload { case, diff } => diff.write(f, &format!("{path}.{case}")),
+            ValDiff::Flags { added, removed } => {
+                write!(f, "{path}:")?;
+                for flag in added {
+                    write!(f, " +{flag}")?;
+                }
+                for flag in removed {
+                    write!(f, " -{flag}")?;
+                }
+                writeln!(f)
+            }
+        }
+    }
+}
+
+/// One change per line, each prefixed by its path from `$`, the value
+/// itself, such as `$.items[2].name: "a" -> "b"`, with values written in
+/// WIT syntax shortened by [`WitFormat::SHORT`](crate::chain::WitFormat).
+impl fmt::Display for ValDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, "$")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SerializableVal as V;
+
+    fn s(s: &str) -> V {
+        V::String(s.to_string())
+    }
+
+    fn round_trip(a: &V, b: &V) -> Result<ValDiff> {
+        let diff = a.diff(b);
+        assert_eq!(&diff.apply(a)?, b);
+        let json = serde_json::to_string(&diff)?;
+        assert_eq!(serde_json::from_str::<ValDiff>(&json)?, diff);
+        Ok(diff)
+    }
+
+    #[test]
+    fn diffs_records_and_lists() -> Result<()> {
+        let a = V::Record(vec![
+            ("name".to_string(), s("ann")),
+            ("tags".to_string(), V::List(vec![s("a"), s("b"), s("c")])),
+            ("age".to_string(), V::U32(30)),
+        ]);
+        let b = V::Record(vec![
+            ("name".to_string(), s("bob")),
+            ("tags".to_string(), V::List(vec![s("a"), s("c"), s("d")])),
+            ("age".to_string(), V::U32(30)),
+        ]);
+        let diff = round_trip(&a, &b)?;
+        assert_eq!(
+            diff,
+            ValDiff::Fields(vec![
+                FieldDiff::Changed(
+                    "name".to_string(),
+                    ValDiff::Replaced {
+                        old: s("ann"),
+                        new: s("bob")
+                    }
+                ),
+                FieldDiff::Changed(
+                    "tags".to_string(),
+                    ValDiff::Elements(vec![
+                        ElementDiff::Removed(1, s("b")),
+                        ElementDiff::Inserted(2, s("d")),
+                    ])
+                ),
+            ])
+        );
+        assert_eq!(
+            diff.to_string(),
+            "$.name: \"ann\" -> \"bob\"\n\
+             $.tags[1]: removed \"b\"\n\
+             $.tags[2]: inserted \"d\"\n"
+        );
+
+        let list = |n: &[u32]| V::List(n.iter().map(|n| V::U32(*n)).collect());
+        round_trip(&list(&[1, 2, 3]), &list(&[1, 5, 3, 4]))?;
+        round_trip(&list(&[1, 2, 3]), &list(&[]))?;
+        round_trip(&list(&[]), &list(&[7, 8]))?;
+        assert!(a.diff(&a).is_same());
+        Ok(())
+    }
+
+    #[test]
+    fn diffs_variants() -> Result<()> {
+        let some = |v| V::Option(Some(Box::new(v)));
+        let diff = round_trip(&some(V::U8(1)), &some(V::U8(2)))?;
+        assert!(matches!(diff, ValDiff::Payload { ref case, .. } if case == "some"));
+        let diff = round_trip(&some(V::U8(1)), &V::Option(None))?;
+        assert_eq!(diff.to_string(), "$: some -> none\n");
+
+        let ok = V::Result(Ok(Some(Box::new(V::U8(1)))));
+        let err = V::Result(Err(Some(Box::new(s("boom")))));
+        assert_eq!(
+            round_trip(&ok, &err)?.to_string(),
+            "$: ok -> err(\"boom\")\n"
+        );
+
+        let v = |case: &str| V::Variant(case.to_string(), None);
+        round_trip(&v("a"), &v("b"))?;
+        round_trip(&V::Enum("x".to_string()), &V::Enum("y".to_string()))?;
+        let flags = |f: &[&str]| V::Flags(f.iter().map(|f| f.to_string()).collect());
+        let diff = round_trip(&flags(&["r", "w"]), &flags(&["r", "w", "x"]))?;
+        assert_eq!(diff.to_string(), "$: +x\n");
+
+        assert!(round_trip(&V::U8(1), &s("1")).is_ok());
+        assert!(ValDiff::Fields(Vec::new()).apply(&V::U8(1)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering chains as Graphviz graphs.
+//!
+//! [`Chain::to_dot`] and [`to_dot`] draw every event as a node, labelled
+//! with its index, type and the start of its hash, and every chain as a
+//! cluster of nodes. Edges show:
+//!
+//! * parent links, as solid arrows;
+//! * the nesting of [recorded calls](Chain::call_tree), as dotted blue
+//!   arrows from each call to the events recorded inside it;
+//! * `spawn` events, as dashed green arrows to the `genesis` event of the
+//!   child chain, and from a `genesis` event back to the parent's head;
+//! * `merge` events, as dashed red arrows from the head of the other chain.
+//!
+//! Events referred to but missing from the rendered chains, such as the
+//! genesis of a child chain which wasn't passed in, are drawn as dashed
+//! placeholders. Render the output with, for example, `dot -Tsvg`.
+
+use crate::chain::merge::{Merged, MERGE};
+use crate::chain::spawn::{Genesis, Spawn, GENESIS, SPAWN};
+use crate::chain::tree::CallNode;
+use crate::chain::{Chain, Digest};
+use crate::prelude::*;
+use core::fmt::Write as _;
+use std::collections::HashSet;
+
+const CALL: &str = "style=dotted, color=blue";
+const SPAWNED: &str = "style=dashed, color=darkgreen";
+const MERGED: &str = "style=dashed, color=red, label=\"merge\"";
+
+impl Chain {
+    /// Renders this chain and the child chains spawned from it as a
+    /// Graphviz `digraph`, see the [module documentation](crate::chain::dot).
+    pub fn to_dot(&self) -> String {
+        to_dot(&[self])
+    }
+}
+
+/// Renders `chains`, along with the child chains spawned from each, as a
+/// single Graphviz `digraph`, see the [module documentation](self).
+///
+/// Edges between chains are drawn when both ends are among the chains, such
+/// as for a child chain and its parent loaded from separate files.
+pub fn to_dot(chains: &[&Chain]) -> String {
+    let mut all = Vec::new();
+    fn flatten<'a>(chain: &'a Chain, all: &mut Vec<&'a Chain>) {
+        all.push(chain);
+        for (_, child) in chain.children() {
+            flatten(child, all);
+        }
+    }
+    for chain in chains {
+        flatten(chain, &mut all);
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph chain {{");
+    let _ = writeln!(out, "    rankdir=LR;");
+    let _ = writeln!(out, "    node [shape=box, fontname=\"monospace\"];");
+    let mut known = HashSet::new();
+    for (i, chain) in all.iter().enumerate() {
+        let _ = writeln!(out, "    subgraph cluster_{i} {{");
+        let _ = writeln!(out, "        label=\"{}\";", escape(&name(chain, i)));
+        for (index, node) in chain.events().enumerate() {
+            known.insert(node.hash());
+            let _ = writeln!(
+                out,
+                "        \"{}\" [label=\"{index}: {}\\n{}\"];",
+                node.hash(),
+                escape(node.event().type_()),
+                short(node.hash()),
+            );
+        }
+        let _ = writeln!(out, "    }}");
+    }
+
+    let mut edges = Vec::new();
+    for chain in &all {
+        for node in chain.events() {
+            let event = node.event();
+            if let Some(parent) = event.parent() {
+                edges.push((parent, node.hash(), ""));
+            }
+            match event.type_() {
+                SPAWN => {
+                    if let Ok(spawn) = Spawn::decode(event) {
+                        edges.push((node.hash(), spawn.child, SPAWNED));
+                    }
+                }
+                GENESIS => {
+                    if let Ok(Genesis {
+                        parent: Some(parent),
+                        ..
+                    }) = Genesis::decode(event)
+                    {
+                        edges.push((parent, node.hash(), SPAWNED));
+                    }
+                }
+                MERGE => {
+                    if let Ok(merged) = Merged::decode(event) {
+                        edges.push((merged.theirs, node.hash(), MERGED));
+                    }
+                }
+                _ => {}
+            }
+        }
+        fn calls(node: &CallNode<'_>, edges: &mut Vec<(Digest, Digest, &str)>) {
+            for child in &node.children {
+                edges.push((node.event.hash(), child.event.hash(), CALL));
+                calls(child, edges);
+            }
+        }
+        for root in chain.call_tree() {
+            calls(&root, &mut edges);
+        }
+    }
+
+    let mut missing = HashSet::new();
+    for (from, to, _) in &edges {
+        for hash in [from, to] {
+            if !known.contains(hash) && missing.insert(*hash) {
+                let _ = writeln!(
+                    out,
+                    "    \"{hash}\" [label=\"{}\", style=dashed];",
+                    short(*hash)
+                );
+            }
+        }
+    }
+    for (from, to, attributes) in &edges {
+        let _ = writeln!(out, "    \"{from}\" -> \"{to}\" [{attributes}];");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The cluster label of a chain: the name it was spawned with, if any.
+fn name(chain: &Chain, i: usize) -> String {
+    chain
+        .events()
+        .next()
+        .filter(|node| node.event().type_() == GENESIS)
+        .and_then(|node| Genesis::decode(node.event()).ok())
+        .map(|genesis| genesis.name)
+        .unwrap_or_else(|| format!("chain {i}"))
+}
+
+fn short(hash: Digest) -> String {
+    hash.to_hex()[..12].to_string()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn draws_spawned_children() -> Result<()> {
+        let mut parent = Chain::new();
+        let first = parent.add(Event::new("a \"quoted\" type".to_string(), vec![]));
+        let spawn = parent.spawn("worker")?;
+        let genesis = parent.child(spawn).unwrap().head().unwrap();
+
+        let dot = parent.to_dot();
+        assert!(dot.starts_with("digraph chain {\n"));
+        assert!(dot.contains("label=\"worker\";"));
+        assert!(dot.contains("[label=\"0: a \\\"quoted\\\" type\\n"));
+        assert!(dot.contains(&format!("\"{first}\" -> \"{spawn}\" [];")));
+        assert!(dot.contains(&format!("\"{spawn}\" -> \"{genesis}\" [{SPAWNED}];")));
+        assert!(dot.contains(&format!("\"{first}\" -> \"{genesis}\" [{SPAWNED}];")));
+        assert!(!dot.contains("style=dashed]"));
+
+        // Without the child, its genesis event is a placeholder.
+        let alone = Chain::from_bytes(&parent.to_bytes()?)?;
+        assert!(alone.to_dot().contains(&format!(
+            "\"{genesis}\" [label=\"{}\", style=dashed];",
+            short(genesis)
+        )));
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encrypting chains at rest.
+//!
+//! An [`EncryptedChainStore`] wraps another [`ChainStore`], such as a
+//! [`FileChainStore`], and hands it every event sealed with
+//! ChaCha20-Poly1305 under a 32-byte key provided by the embedder. The
+//! wrapped store only ever sees `encrypted` events, whose payload is the
+//! nonce followed by the ciphertext of a header holding the
+//! [format version](crate::chain::migrate) and the postcard encoding of the
+//! original [`MetaEvent`], hash and signature included. Their hashes are the
+//! SHA-256 digests of their payloads, and each links to the one before, so
+//! nothing about the original events is left in the clear beyond their number
+//! and approximate size.
+//!
+//! The first event is sealed with a random nonce and every later one with
+//! the leading bytes of the hash of the sealed event before it, while the sequence number of each event is authenticated
+//! along with it. Opening a store whose events were reordered, dropped from
+//! the middle or copied in from another chain therefore fails, as does
+//! opening it with the wrong key. Events dropped from the end can't be told
+//! apart from events never added.
+
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{
+    Chain, ChainHasher, ChainStore, Digest, Event, FileChainStore, MemoryChainStore, MetaEvent,
+    Sha256Hasher,
+};
+use crate::prelude::*;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use core::fmt;
+use sha2::{Digest as _, Sha256};
+use std::path::Path;
+
+/// Type of the events an [`EncryptedChainStore`] hands the store it wraps.
+pub const ENCRYPTED: &str = "encrypted";
+
+const NONCE_LEN: usize = 12;
+
+/// Starts the plaintext of sealed events, followed by the format version of
+/// the event's encoding. Events sealed before this was added start with their
+/// hash instead.
+const SEALED: &[u8] = b"\0wtseal";
+
+/// A [`ChainStore`] encrypting events before handing them to another store,
+/// see the [module documentation](self).
+///
+/// Decrypted events are kept in memory, so reading them doesn't go through
+/// the wrapped store.
+pub struct EncryptedChainStore {
+    inner: Box<dyn ChainStore>,
+    cipher: ChaCha20Poly1305,
+    events: MemoryChainStore,
+}
+
+// Keep the key out of logs.
+impl fmt::Debug for EncryptedChainStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedChainStore")
+            .field("inner", &self.inner)
+            .field("len", &self.events.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedChainStore {
+    /// Wraps `inner`, decrypting the events already in it with `key`.
+    ///
+    /// Fails if `inner` holds events which weren't sealed with `key` by an
+    /// `EncryptedChainStore`, or which were tampered with.
+    pub fn new(inner: Box<dyn ChainStore>, key: &[u8; 32]) -> Result<EncryptedChainStore> {
+        let mut store = EncryptedChainStore {
+            inner,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            events: MemoryChainStore::new(),
+        };
+        let mut events = Vec::new();
+        let mut last = None;
+        for (index, sealed) in store.inner.iter_from(0).enumerate() {
+            let event = store
+                .open(index, sealed, last)
+                .with_context(|| format!("failed to decrypt event {index}"))?;
+            last = Some(sealed);
+            events.push(event);
+        }
+        store.events = MemoryChainStore::from(events);
+        Ok(store)
+    }
+
+    /// The store the sealed events are handed to.
+    pub fn inner(&self) -> &dyn ChainStore {
+        &*self.inner
+    }
+
+    /// Decrypts `sealed`, the `index`th event of the wrapped store, which
+    /// follows `last`.
+    fn open(
+        &self,
+        index: usize,
+        sealed: &MetaEvent,
+        last: Option<&MetaEvent>,
+    ) -> Result<MetaEvent> {
+        let event = sealed.event();
+        ensure!(
+            event.type_() == ENCRYPTED,
+            "expected an `{ENCRYPTED}` event, found `{}`",
+            event.type_()
+        );
+        let data = event.stored_data();
+        ensure!(
+            sealed.hash() == seal_hash(data),
+            "encrypted event has the wrong hash"
+        );
+        let Some((nonce, ciphertext)) = data.split_first_chunk::<NONCE_LEN>() else {
+            bail!("encrypted event is truncated");
+        };
+        if last.is_some_and(|last| next_nonce(last) != *nonce) {
+            bail!("encrypted event is out of sequence");
+        }
+        let aad = (index as u64).to_le_bytes();
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("wrong key, or the encrypted event was tampered with"))?;
+        if let Some(plaintext) = plaintext.strip_prefix(SEALED) {
+            let Some((&version, event)) = plaintext.split_first() else {
+                bail!("decrypted event is truncated");
+            };
+            migrate::check_version(version.into())?;
+            return migrate::decode_event(version, event).context("decrypted event is corrupt");
+        }
+        // Sealed without a header, which was in format 5, 4 or 3 depending
+        // on when.
+        migrate::decode_event(5, &plaintext)
+            .or_else(|_| migrate::decode_event(4, &plaintext))
+            .or_else(|_| migrate::decode_event(3, &plaintext))
+            .context("decrypted event is corrupt")
+    }
+
+    /// Encrypts `event` as the `index`th event of the wrapped store, after
+    /// the sealed event `last`.
+    fn seal(&self, index: usize, event: &MetaEvent, last: Option<&MetaEvent>) -> Result<MetaEvent> {
+        let nonce: [u8; NONCE_LEN] = match last {
+            Some(last) => next_nonce(last),
+            None => ChaCha20Poly1305::generate_nonce(&mut OsRng).into(),
+        };
+        let aad = (index as u64).to_le_bytes();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &sealed_plaintext(event)?,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("failed to encrypt event"))?;
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        let hash = seal_hash(&data);
+        let mut sealed = Event::new(ENCRYPTED.to_string(), data);
+        sealed.set_parent(last.map(|last| last.hash()));
+        Ok(MetaEvent::new(hash, sealed))
+    }
+}
+
+/// The header of a sealed event followed by its encoding.
+fn sealed_plaintext(event: &MetaEvent) -> Result<Vec<u8>> {
+    let mut plaintext = SEALED.to_vec();
+    plaintext.push(FORMAT_VERSION);
+    Ok(postcard::to_extend(event, plaintext)?)
+}
+
+fn seal_hash(data: &[u8]) -> Digest {
+    Digest(Sha256::digest(data).into())
+}
+
+/// The nonce of the sealed event after `last`.
+fn next_nonce(last: &MetaEvent) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&last.hash().as_bytes()[..NONCE_LEN]);
+    nonce
+}
+
+impl ChainStore for EncryptedChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        let sealed = self.seal(self.inner.len(), &event, self.inner.head())?;
+        self.inner.append(sealed)?;
+        self.events.append(event)
+    }
+
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let start = self.inner.len();
+        let mut sealed: Vec<MetaEvent> = Vec::with_capacity(events.len());
+        for (i, event) in events.iter().enumerate() {
+            let next = self.seal(start + i, event, sealed.last().or(self.inner.head()))?;
+            sealed.push(next);
+        }
+        self.inner.append_batch(sealed)?;
+        self.events.append_batch(events)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    /// Seals `events` afresh, starting from a new random nonce, and rewrites
+    /// the wrapped store with them.
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut sealed = Vec::with_capacity(events.len());
+        for (index, event) in events.iter().enumerate() {
+            let next = self.seal(index, event, sealed.last())?;
+            sealed.push(next);
+        }
+        self.inner.rewrite(sealed)?;
+        self.events = MemoryChainStore::from(events);
+        Ok(())
+    }
+}
+
+impl Chain {
+    /// Like [`Chain::open`], but encrypts the events in the file with `key`,
+    /// see [`EncryptedChainStore`].
+    pub fn open_encrypted(path: impl AsRef<Path>, key: &[u8; 32]) -> Result<Chain> {
+        let path = path.as_ref();
+        let file = FileChainStore::open(path, Sha256Hasher.name())?;
+        let hasher = match hasher_by_name(file.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!(
+                "chain file `{}` uses unknown hasher `{}`",
+                path.display(),
+                file.hasher()
+            ),
+        };
+        let store = EncryptedChainStore::new(Box::new(file), key)
+            .with_context(|| format!("failed to decrypt chain file `{}`", path.display()))?;
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain file `{}` failed verification", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypts_persisted_events() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let key = [7; 32];
+
+        let mut chain = Chain::open_encrypted(&path, &key)?;
+        chain.add(Event::new("secret".to_string(), b"hunter2".to_vec()));
+        let head = chain.add(Event::new("secret".to_string(), b"hunter3".to_vec()));
+        drop(chain);
+
+        let contents = std::fs::read(&path)?;
+        assert!(!contents.windows(6).any(|w| w == b"hunter"));
+        assert!(!contents.windows(6).any(|w| w == b"secret"));
+
+        let mut chain = Chain::open_encrypted(&path, &key)?;
+        assert_eq!(chain.head(), Some(head));
+        let head = chain.add(Event::new("secret".to_string(), vec![]));
+        drop(chain);
+        assert_eq!(Chain::open_encrypted(&path, &key)?.head(), Some(head));
+
+        // The file on its own holds a chain of sealed events.
+        let sealed = FileChainStore::open(&path, Sha256Hasher.name())?;
+        assert_eq!(sealed.len(), 3);
+        let mut parent = None;
+        for node in sealed.iter_from(0) {
+            assert_eq!(node.event().type_(), ENCRYPTED);
+            assert_eq!(node.event().parent(), parent);
+            parent = Some(node.hash());
+        }
+
+        assert!(Chain::open_encrypted(&path, &[8; 32]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_reordered_events() -> Result<()> {
+        let key = [1; 32];
+        let mut store = EncryptedChainStore::new(Box::new(MemoryChainStore::new()), &key)?;
+        for data in [vec![1], vec![2], vec![3]] {
+            let event = Event::new("a".to_string(), data);
+            store.append(MetaEvent::new(Digest::from_u64(0), event))?;
+        }
+        let sealed = store.inner().iter_from(0).cloned().collect::<Vec<_>>();
+        let reopened = MemoryChainStore::from(sealed.clone());
+        assert_eq!(EncryptedChainStore::new(Box::new(reopened), &key)?.len(), 3);
+
+        let swapped = MemoryChainStore::from(vec![sealed[1].clone(), sealed[0].clone()]);
+        assert!(EncryptedChainStore::new(Box::new(swapped), &key).is_err());
+        let skipped = MemoryChainStore::from(vec![sealed[0].clone(), sealed[2].clone()]);
+        assert!(EncryptedChainStore::new(Box::new(skipped), &key).is_err());
+        let truncated = MemoryChainStore::from(sealed[..2].to_vec());
+        assert_eq!(
+            EncryptedChainStore::new(Box::new(truncated), &key)?.len(),
+            2
+        );
+        Ok(())
+    }
+}
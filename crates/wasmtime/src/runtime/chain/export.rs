@@ -0,0 +1,245 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Moving chains between services in a format both sides read.
+//!
+//! [`Chain::export`] encodes a chain in any [`ChainFormat`] and
+//! [`Chain::import`] decodes it again, so a service only needs to agree on a
+//! format with its peer rather than share Wasmtime's types. Each format has
+//! a media type, and [`ChainFormat::negotiate`] picks the one to send from
+//! an HTTP `Accept` header, for services exchanging chains over HTTP.
+//!
+//! CBOR needs the `chain-cbor` feature, without which
+//! [`ChainFormat::available`] leaves it out and exporting or importing it
+//! fails.
+
+use crate::chain::{Chain, MergeOutcome};
+use crate::prelude::*;
+use core::fmt;
+use core::str::FromStr;
+
+/// An encoding of a whole chain, see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChainFormat {
+    /// JSON, as written by serializing a [`Chain`] with `serde_json`.
+    Json,
+    /// [CBOR](crate::chain::cbor), as written by `Chain::to_cbor`.
+    Cbor,
+    /// The compact binary form written by [`Chain::to_bytes`].
+    Binary,
+}
+
+impl ChainFormat {
+    /// Every format, in order of preference where a peer accepts several
+    /// equally.
+    pub const ALL: [ChainFormat; 3] = [ChainFormat::Binary, ChainFormat::Cbor, ChainFormat::Json];
+
+    /// The formats this build of Wasmtime can export and import, in order of
+    /// preference.
+    pub fn available() -> impl Iterator<Item = ChainFormat> {
+        ChainFormat::ALL.into_iter().filter(|f| f.is_available())
+    }
+
+    /// Whether this build of Wasmtime can export and import this format.
+    pub fn is_available(self) -> bool {
+        match self {
+            ChainFormat::Json | ChainFormat::Binary => true,
+            ChainFormat::Cbor => cfg!(feature = "chain-cbor"),
+        }
+    }
+
+    /// The short name of this format, as parsed by [`ChainFormat::from_str`].
+    pub fn name(self) -> &'static str {
+        match self {
+            ChainFormat::Json => "json",
+            ChainFormat::Cbor => "cbor",
+            ChainFormat::Binary => "binary",
+        }
+    }
+
+    /// The media type of chains in this format, for `Content-Type` headers.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            ChainFormat::Json => "application/json",
+            ChainFormat::Cbor => "application/cbor",
+            ChainFormat::Binary => "application/vnd.wasmtime.chain",
+        }
+    }
+
+    /// The format with media type `media_type`, ignoring parameters such as
+    /// `charset`.
+    pub fn from_media_type(media_type: &str) -> Option<ChainFormat> {
+        let essence = media_type.split(';').next()?.trim();
+        ChainFormat::ALL
+            .into_iter()
+            .find(|f| f.media_type().eq_ignore_ascii_case(essence))
+    }
+
+    /// The available format a peer sending `accept`, the value of an HTTP
+    /// `Accept` header, prefers, or `None` if it accepts none of them.
+    ///
+    /// Formats with a higher `q` win, then the one listed first, then the
+    /// one first in [`ChainFormat::ALL`] for wildcards such as `*/*`.
+    pub fn negotiate(accept: &str) -> Option<ChainFormat> {
+        let mut best: Option<(f32, ChainFormat)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media_range = parts.next().unwrap_or_default().trim();
+            let mut q = 1.0;
+            for parameter in parts {
+                if let Some(value) = parameter.trim().strip_prefix("q=") {
+                    q = value.trim().parse().unwrap_or(0.0);
+                }
+            }
+            if q <= 0.0 {
+                continue;
+            }
+            let format = match media_range {
+                "*/*" | "application/*" => ChainFormat::available().next(),
+                _ => ChainFormat::from_media_type(media_range).filter(|f| f.is_available()),
+            };
+            if let Some(format) = format {
+                if best.map_or(true, |(best, _)| q > best) {
+                    best = Some((q, format));
+                }
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+}
+
+impl fmt::Display for ChainFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ChainFormat {
+    type Err = Error;
+
+    /// Parses a format's [name](ChainFormat::name) or media type.
+    fn from_str(s: &str) -> Result<ChainFormat> {
+        ChainFormat::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .or_else(|| ChainFormat::from_media_type(s))
+            .ok_or_else(|| anyhow!("unknown chain format `{s}`"))
+    }
+}
+
+impl Chain {
+    /// Encodes this chain in `format`, see the [module docs](crate::chain::export).
+    pub fn export(&self, format: ChainFormat) -> Result<Vec<u8>> {
+        match format {
+            ChainFormat::Json => Ok(serde_json::to_vec(self)?),
+            #[cfg(feature = "chain-cbor")]
+            ChainFormat::Cbor => self.to_cbor(),
+            ChainFormat::Binary => self.to_bytes(),
+            #[allow(unreachable_patterns)]
+            _ => bail!(unavailable(format)),
+        }
+    }
+
+    /// Decodes a chain exported in `format`, by this or another service,
+    /// and checks its hashes with [`Chain::verify`].
+    pub fn import(bytes: &[u8], format: ChainFormat) -> Result<Chain> {
+        let chain = match format {
+            ChainFormat::Json => serde_json::from_slice(bytes)?,
+            #[cfg(feature = "chain-cbor")]
+            ChainFormat::Cbor => Chain::from_cbor(bytes)?,
+            ChainFormat::Binary => Chain::from_bytes(bytes)?,
+            #[allow(unreachable_patterns)]
+            _ => bail!(unavailable(format)),
+        };
+        chain
+            .verify()
+            .with_context(|| format!("imported {format} chain failed verification"))?;
+        Ok(chain)
+    }
+
+    /// Decodes a chain exported in `format` like [`Chain::import`], then
+    /// merges its events into this chain with [`Chain::merge`].
+    ///
+    /// This fails if the imported chain has a different [id](Chain::id).
+    pub fn import_into(&mut self, bytes: &[u8], format: ChainFormat) -> Result<MergeOutcome> {
+        let other = Chain::import(bytes, format)?;
+        self.merge(&other)
+    }
+}
+
+fn unavailable(format: ChainFormat) -> String {
+    format!("{format} chains need Wasmtime's `chain-{format}` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Digest, Event};
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.add(Event::new("binary".to_string(), (0..=255).collect()));
+        for format in ChainFormat::available() {
+            let bytes = chain.export(format)?;
+            let imported = Chain::import(&bytes, format)?;
+            assert_eq!(imported.head(), chain.head(), "{format}");
+            assert_eq!(format.name().parse::<ChainFormat>()?, format);
+            assert!(Chain::import(&bytes[..bytes.len() - 1], format).is_err());
+        }
+
+        // Importing checks the hashes.
+        let json = String::from_utf8(chain.export(ChainFormat::Json)?)?;
+        let tampered = json.replace("\"binary\"", "\"binarz\"");
+        assert!(Chain::import(tampered.as_bytes(), ChainFormat::Json).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn import_into_checks_id() -> Result<()> {
+        let mut ours = Chain::genesis("actor", Digest::default())?;
+        let mut theirs = ours.clone();
+        theirs.add(Event::new("a".to_string(), vec![]));
+        let bytes = theirs.export(ChainFormat::Binary)?;
+        assert_eq!(
+            ours.import_into(&bytes, ChainFormat::Binary)?,
+            MergeOutcome::FastForward { added: 1 }
+        );
+
+        let other = Chain::genesis("actor", Digest([1; Digest::LEN]))?;
+        let bytes = other.export(ChainFormat::Binary)?;
+        assert!(ours.import_into(&bytes, ChainFormat::Binary).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn negotiates() {
+        let negotiate = ChainFormat::negotiate;
+        assert_eq!(negotiate("application/json"), Some(ChainFormat::Json));
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/vnd.wasmtime.chain"),
+            Some(ChainFormat::Binary)
+        );
+        assert_eq!(
+            negotiate("text/html, */*;q=0.1"),
+            ChainFormat::available().next()
+        );
+        assert_eq!(negotiate("application/json;q=0, text/html"), None);
+        assert_eq!(
+            ChainFormat::from_media_type("application/json; charset=utf-8"),
+            Some(ChainFormat::Json)
+        );
+        assert!("yaml".parse::<ChainFormat>().is_err());
+    }
+}
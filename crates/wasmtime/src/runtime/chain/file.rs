@@ -0,0 +1,343 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Append-only file persistence for a [`Chain`](crate::chain::Chain).
+//!
+//! The file starts with [`MAGIC`] followed by a frame holding the name of the
+//! chain's hasher. Every event is then appended as its own frame: a
+//! little-endian `u32` length followed by the postcard encoding of the
+//! [`MetaEvent`]. A frame cut short by a crash is dropped when the file is
+//! reopened.
+//!
+//! The last byte of [`MAGIC`] is the [format version](crate::chain::migrate)
+//! the events are encoded in. Files in older formats, down to those starting
+//! with [`MAGIC_V1`], are rewritten in the current format when they're
+//! opened, so that any event can be appended to them.
+
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{ChainStore, MemoryChainStore, MetaEvent};
+use crate::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Leading bytes of every chain file.
+pub const MAGIC: &[u8; 8] = b"wtchain\x05";
+
+/// Leading bytes of chain files which can't hold schema versions.
+pub const MAGIC_V4: &[u8; 8] = b"wtchain\x04";
+
+/// Leading bytes of chain files which can't hold event severities.
+pub const MAGIC_V3: &[u8; 8] = b"wtchain\x03";
+
+/// Leading bytes of chain files which can't hold compressed payloads.
+pub const MAGIC_V2: &[u8; 8] = b"wtchain\x02";
+
+/// Leading bytes of chain files which can't hold signatures.
+pub const MAGIC_V1: &[u8; 8] = b"wtchain\x01";
+
+/// Each format version, newest first, with its leading bytes.
+pub(crate) const MAGICS: [(u8, &[u8; 8]); 5] = [
+    (FORMAT_VERSION, MAGIC),
+    (4, MAGIC_V4),
+    (3, MAGIC_V3),
+    (2, MAGIC_V2),
+    (1, MAGIC_V1),
+];
+
+/// Finds the format version of the chain file `contents`, returning it along
+/// with the rest of the contents.
+pub(crate) fn strip_magic(contents: &[u8]) -> Result<(u8, &[u8])> {
+    if let Some((version, rest)) = MAGICS
+        .into_iter()
+        .find_map(|(version, magic)| Some((version, contents.strip_prefix(magic.as_slice())?)))
+    {
+        return Ok((version, rest));
+    }
+    match contents.strip_prefix(&MAGIC[..MAGIC.len() - 1]) {
+        Some([version, ..]) => {
+            migrate::check_version((*version).into())?;
+            bail!("unknown chain file format version {version}")
+        }
+        _ => bail!("missing chain file header"),
+    }
+}
+
+/// A [`ChainStore`] which appends each event to a file as it's added while
+/// also keeping them in memory.
+#[derive(Debug)]
+pub struct FileChainStore {
+    path: PathBuf,
+    file: File,
+    hasher: String,
+    events: MemoryChainStore,
+}
+
+impl FileChainStore {
+    /// Opens or creates the chain file at `path`.
+    ///
+    /// A new file is initialized for the hasher named `hasher`, while an
+    /// existing file keeps the hasher it was created with, see
+    /// [`FileChainStore::hasher`]. An existing file in an older format is
+    /// rewritten in the current one.
+    pub fn open(path: impl AsRef<Path>, hasher: &str) -> Result<FileChainStore> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open chain file `{}`", path.display()))?;
+        let mut store = FileChainStore {
+            path: path.to_path_buf(),
+            file: file.try_clone()?,
+            hasher: hasher.to_string(),
+            events: MemoryChainStore::new(),
+        };
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        if contents.is_empty() {
+            let mut header = MAGIC.to_vec();
+            push_frame(&mut header, hasher.as_bytes())?;
+            store.file.write_all(&header)?;
+            return Ok(store);
+        }
+
+        let context = || format!("invalid chain file `{}`", path.display());
+        let (version, rest) = strip_magic(&contents).with_context(context)?;
+        let mut frames = Frames::new(rest);
+        store.hasher = match frames.next() {
+            Some(name) => String::from_utf8(name.to_vec()).with_context(context)?,
+            None => bail!("{}: missing hasher name", context()),
+        };
+        let mut events = Vec::new();
+        while let Some(frame) = frames.next() {
+            let event = migrate::decode_event(version, frame)
+                .with_context(|| format!("{}: event {} is corrupt", context(), events.len()))?;
+            events.push(event);
+        }
+
+        if version < FORMAT_VERSION {
+            log::info!(
+                "upgrading chain file `{}` from format version {version} to {FORMAT_VERSION}",
+                path.display()
+            );
+            store.rewrite(events)?;
+            return Ok(store);
+        }
+        store.events = MemoryChainStore::from(events);
+
+        // Anything after the last complete frame was a write interrupted by a
+        // crash; drop it so new frames start from a clean boundary.
+        let valid = MAGIC.len() + frames.valid;
+        if valid < contents.len() {
+            log::warn!(
+                "discarding {} trailing bytes of chain file `{}`",
+                contents.len() - valid,
+                path.display()
+            );
+            file.set_len(u64::try_from(valid)?)?;
+        }
+        Ok(store)
+    }
+
+    /// Name of the hasher the chain in this file is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.hasher
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `frames` at the end of the file, truncating it back if that
+    /// fails so that none of them are read back when it's reopened.
+    fn write_frames(&mut self, frames: &[u8]) -> Result<()> {
+        let context = || format!("failed to append to `{}`", self.path.display());
+        let len = self.file.metadata().with_context(context)?.len();
+        if let Err(e) = self.file.write_all(frames) {
+            let _ = self.file.set_len(len);
+            return Err(e).with_context(context);
+        }
+        Ok(())
+    }
+}
+
+impl ChainStore for FileChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        let mut frame = Vec::new();
+        push_frame(&mut frame, &postcard::to_allocvec(&event)?)?;
+        self.write_frames(&frame)?;
+        self.events.append(event)
+    }
+
+    /// Writes the frames of `events` at once, so that either all of them or
+    /// none are read back when the file is reopened.
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut frames = Vec::new();
+        for event in &events {
+            push_frame(&mut frames, &postcard::to_allocvec(event)?)?;
+        }
+        self.write_frames(&frames)?;
+        self.events.append_batch(events)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    /// Writes `events` to a new file which then replaces this one, always in
+    /// the current format.
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut contents = MAGIC.to_vec();
+        push_frame(&mut contents, self.hasher.as_bytes())?;
+        for event in &events {
+            push_frame(&mut contents, &postcard::to_allocvec(event)?)?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let context = || format!("failed to rewrite `{}`", self.path.display());
+        let mut file = File::create(&tmp).with_context(context)?;
+        file.write_all(&contents).with_context(context)?;
+        file.sync_all().with_context(context)?;
+        drop(file);
+        std::fs::rename(&tmp, &self.path).with_context(context)?;
+
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(context)?;
+        self.events = MemoryChainStore::from(events);
+        Ok(())
+    }
+
+    /// Flushes appended events through to the underlying device.
+    fn flush(&mut self) -> Result<()> {
+        match self.file.sync_data() {
+            Ok(()) => Ok(()),
+            // Some platforms don't support syncing certain files, which isn't
+            // worth failing over.
+            Err(e) if e.kind() == ErrorKind::Unsupported => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+pub(crate) fn push_frame(dst: &mut Vec<u8>, payload: &[u8]) -> Result<()> {
+    dst.extend_from_slice(&u32::try_from(payload.len())?.to_le_bytes());
+    dst.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Iterates over complete frames, tracking how many bytes they covered.
+pub(crate) struct Frames<'a> {
+    rest: &'a [u8],
+    pub(crate) valid: usize,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(rest: &'a [u8]) -> Frames<'a> {
+        Frames { rest, valid: 0 }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let (len, rest) = self.rest.split_first_chunk::<4>()?;
+        let len = usize::try_from(u32::from_le_bytes(*len)).ok()?;
+        let payload = rest.get(..len)?;
+        self.rest = &rest[len..];
+        self.valid += 4 + len;
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chain::{Chain, Event};
+    use crate::prelude::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn resume_from_disk() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+
+        let mut chain = Chain::open(&path)?;
+        chain.add(Event::new("a".to_string(), vec![1]));
+        let head = chain.add(Event::new("b".to_string(), vec![2]));
+        drop(chain);
+
+        // Simulate a crash part way through writing a third event.
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[10, 0, 0, 0, 1, 2])?;
+        drop(file);
+
+        let mut chain = Chain::open(&path)?;
+        assert_eq!(chain.head(), Some(head));
+        let head = chain.add(Event::new("c".to_string(), vec![3]));
+        drop(chain);
+
+        let chain = Chain::open(&path)?;
+        assert_eq!(chain.head(), Some(head));
+        assert_eq!(chain.len(), 3);
+        chain.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn open_v1_file() -> Result<()> {
+        use crate::chain::file::{MAGIC, MAGIC_V1};
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let mut chain = Chain::open(&path)?;
+        let head = chain.add(Event::new("a".to_string(), vec![1]));
+        drop(chain);
+
+        // Rewrite the file the way unsigned events used to be framed.
+        let node = Chain::open(&path)?.get_event_by_hash(head).unwrap().clone();
+        let mut contents = std::fs::read(&path)?;
+        let header = contents.len() - 4 - postcard::to_allocvec(&node)?.len();
+        assert_eq!(&contents[..MAGIC.len()], MAGIC);
+        contents.truncate(header);
+        contents[..MAGIC_V1.len()].copy_from_slice(MAGIC_V1);
+        let event = node.event();
+        let legacy = (event.type_(), event.parent(), &*event.data());
+        let payload = postcard::to_allocvec(&(node.hash(), legacy))?;
+        contents.extend_from_slice(&u32::try_from(payload.len())?.to_le_bytes());
+        contents.extend_from_slice(&payload);
+        std::fs::write(&path, contents)?;
+
+        let mut chain = Chain::open(&path)?;
+        assert_eq!(chain.head(), Some(head));
+        let head = chain.add(Event::new("b".to_string(), vec![2]));
+        drop(chain);
+        assert_eq!(Chain::open(&path)?.head(), Some(head));
+        Ok(())
+    }
+}
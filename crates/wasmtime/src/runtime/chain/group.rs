@@ -0,0 +1,256 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Grouping speculative events into transactions.
+//!
+//! [`Chain::begin_group`] records a `group-begin` event, and the events
+//! added after it belong to the group until [`Chain::commit`] or
+//! [`Chain::abort`] ends it. Groups nest, and both end the innermost group
+//! still open. A committed group is closed by a `group-commit` event and its
+//! events are part of the chain's history like any other.
+//!
+//! An aborted group, such as the events of a handler which failed and is
+//! retried, is either [removed](AbortMode::Remove) from the chain, which
+//! needs a store supporting [`ChainStore::rewrite`](crate::chain::ChainStore),
+//! or [marked](AbortMode::Mark) by closing it with a `group-abort` event,
+//! which keeps the chain append-only. [`Chain::canonical`] iterates over the
+//! events outside of marked groups.
+//!
+//! Which groups are open is worked out from the chain's events, so groups
+//! left open stay open when a persisted chain is reopened.
+
+use crate::chain::record::decode;
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Type of the event starting a group.
+pub const GROUP_BEGIN: &str = "group-begin";
+
+/// Type of the event ending a committed group.
+pub const GROUP_COMMIT: &str = "group-commit";
+
+/// Type of the event ending a group aborted with [`AbortMode::Mark`].
+pub const GROUP_ABORT: &str = "group-abort";
+
+/// Payload of a [`GROUP_BEGIN`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupBegin {
+    /// Name given to the group.
+    pub name: String,
+}
+
+/// Payload of [`GROUP_COMMIT`] and [`GROUP_ABORT`] events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupEnd {
+    /// Name given to the group.
+    pub name: String,
+    /// Hash of the group's [`GROUP_BEGIN`] event.
+    pub begin: Digest,
+}
+
+impl GroupBegin {
+    pub fn decode(event: &Event) -> Result<GroupBegin> {
+        decode(event, GROUP_BEGIN)
+    }
+}
+
+impl GroupEnd {
+    /// Decodes a [`GROUP_COMMIT`] or [`GROUP_ABORT`] event.
+    pub fn decode(event: &Event) -> Result<GroupEnd> {
+        match event.type_() {
+            GROUP_ABORT => decode(event, GROUP_ABORT),
+            _ => decode(event, GROUP_COMMIT),
+        }
+    }
+}
+
+/// What [`Chain::abort`] does with the events of the aborted group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortMode {
+    /// Drops them, along with the `group-begin` event, as
+    /// [`Chain::rollback_to`] would.
+    Remove,
+    /// Keeps them and records a `group-abort` event, leaving them out of
+    /// [`Chain::canonical`].
+    Mark,
+}
+
+impl Chain {
+    /// Starts a group called `name`, returning the hash of its
+    /// `group-begin` event, see the [module documentation](crate::chain::group).
+    pub fn begin_group(&mut self, name: &str) -> Result<Digest> {
+        let begin = GroupBegin {
+            name: name.to_string(),
+        };
+        self.try_add(Event::new(
+            GROUP_BEGIN.to_string(),
+            serde_json::to_vec(&begin)?,
+        ))
+    }
+
+    /// The hashes of the `group-begin` events of the groups still open,
+    /// outermost first.
+    pub fn open_groups(&self) -> Vec<Digest> {
+        let mut open = Vec::new();
+        for node in self.events() {
+            match node.event().type_() {
+                GROUP_BEGIN => open.push(node.hash()),
+                GROUP_COMMIT | GROUP_ABORT => {
+                    if let Ok(end) = GroupEnd::decode(node.event()) {
+                        if let Some(i) = open.iter().rposition(|begin| *begin == end.begin) {
+                            open.truncate(i);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        open
+    }
+
+    /// The innermost open group's `group-begin` hash and payload.
+    fn innermost_group(&self) -> Result<(Digest, GroupBegin)> {
+        let Some(begin) = self.open_groups().pop() else {
+            bail!("no group is open in this chain");
+        };
+        let node = self.get_event_by_hash(begin).unwrap();
+        Ok((begin, GroupBegin::decode(node.event())?))
+    }
+
+    /// Ends the innermost open group, keeping its events, and returns the
+    /// hash of the `group-commit` event recorded for it.
+    pub fn commit(&mut self) -> Result<Digest> {
+        let (begin, GroupBegin { name }) = self.innermost_group()?;
+        let end = GroupEnd { name, begin };
+        self.try_add(Event::new(
+            GROUP_COMMIT.to_string(),
+            serde_json::to_vec(&end)?,
+        ))
+    }
+
+    /// Ends the innermost open group, discarding its events according to
+    /// `mode`, along with those of any groups nested in it.
+    ///
+    /// Events removed with [`AbortMode::Remove`] have already been sent to
+    /// [subscribers](Chain::subscribe), which aren't told about the removal.
+    pub fn abort(&mut self, mode: AbortMode) -> Result<()> {
+        let (begin, GroupBegin { name }) = self.innermost_group()?;
+        match mode {
+            AbortMode::Remove => {
+                let keep = self.position(begin).unwrap();
+                self.truncate(keep)?;
+            }
+            AbortMode::Mark => {
+                let end = GroupEnd { name, begin };
+                self.try_add(Event::new(
+                    GROUP_ABORT.to_string(),
+                    serde_json::to_vec(&end)?,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterates over the events of this chain, oldest first, leaving out the
+    /// groups aborted with [`AbortMode::Mark`] from their `group-begin` to
+    /// their `group-abort` event.
+    ///
+    /// Events of groups still open are included.
+    pub fn canonical(&self) -> impl Iterator<Item = &MetaEvent> + '_ {
+        let aborted = self
+            .events_of_type(GROUP_ABORT)
+            .filter_map(|node| GroupEnd::decode(node.event()).ok())
+            .map(|end| end.begin)
+            .collect::<HashSet<_>>();
+        // The `group-begin` hashes of the groups the walk is inside of, and
+        // how many of them are aborted.
+        let mut open = Vec::new();
+        let mut skipping = 0;
+        self.events().filter(move |node| {
+            let event = node.event();
+            match event.type_() {
+                GROUP_BEGIN => {
+                    let is_aborted = aborted.contains(&node.hash());
+                    open.push((node.hash(), is_aborted));
+                    skipping += usize::from(is_aborted);
+                    return skipping == 0;
+                }
+                GROUP_COMMIT | GROUP_ABORT => {
+                    let hidden = skipping > 0;
+                    if let Ok(end) = GroupEnd::decode(event) {
+                        if let Some(i) = open.iter().rposition(|(begin, _)| *begin == end.begin) {
+                            skipping -= open.drain(i..).filter(|(_, a)| *a).count();
+                        }
+                    }
+                    return !hidden;
+                }
+                _ => {}
+            }
+            skipping == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types<'a>(events: impl Iterator<Item = &'a MetaEvent>) -> Vec<&'a str> {
+        events.map(|node| node.event().type_()).collect()
+    }
+
+    #[test]
+    fn commits_and_aborts_groups() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.add(Event::new("a".to_string(), vec![]));
+        let outer = chain.begin_group("request")?;
+        chain.add(Event::new("b".to_string(), vec![]));
+        chain.begin_group("attempt")?;
+        chain.add(Event::new("c".to_string(), vec![]));
+        assert_eq!(chain.open_groups().len(), 2);
+        chain.abort(AbortMode::Mark)?;
+        assert_eq!(chain.open_groups(), [outer]);
+        chain.begin_group("attempt")?;
+        chain.add(Event::new("d".to_string(), vec![]));
+        chain.commit()?;
+        chain.commit()?;
+        assert!(chain.open_groups().is_empty());
+        assert!(chain.commit().is_err());
+        chain.verify()?;
+
+        assert_eq!(
+            types(chain.canonical()),
+            [
+                "a",
+                GROUP_BEGIN,
+                "b",
+                GROUP_BEGIN,
+                "d",
+                GROUP_COMMIT,
+                GROUP_COMMIT,
+            ]
+        );
+        assert_eq!(chain.len(), 10);
+
+        let head = chain.head();
+        chain.begin_group("speculative")?;
+        chain.add(Event::new("e".to_string(), vec![]));
+        chain.abort(AbortMode::Remove)?;
+        assert_eq!(chain.head(), head);
+        assert!(chain.open_groups().is_empty());
+        Ok(())
+    }
+}
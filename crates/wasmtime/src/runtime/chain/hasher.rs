@@ -0,0 +1,99 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::chain::{Digest, Event};
+use core::fmt;
+use sha2::{Digest as _, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Hash function used to compute event hashes in a [`Chain`].
+///
+/// A chain records the [`ChainHasher::name`] of its hasher when serialized so
+/// that it can be verified again after it's loaded. The built-in hashers are
+/// resolved automatically, see [`hasher_by_name`].
+///
+/// [`Chain`]: crate::chain::Chain
+pub trait ChainHasher: fmt::Debug + Send + Sync {
+    /// Stable identifier recorded with serialized chains.
+    fn name(&self) -> &str;
+
+    /// Computes the hash of `event`, including its parent link.
+    fn hash_event(&self, event: &Event) -> Digest;
+}
+
+/// SHA-256 over [`Event::hash_input`]. This is the default.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Sha256Hasher;
+
+impl ChainHasher for Sha256Hasher {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn hash_event(&self, event: &Event) -> Digest {
+        Digest(Sha256::digest(event.hash_input()).into())
+    }
+}
+
+/// BLAKE3 over [`Event::hash_input`].
+#[cfg(feature = "chain-blake3")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "chain-blake3")]
+impl ChainHasher for Blake3Hasher {
+    fn name(&self) -> &str {
+        "blake3"
+    }
+
+    fn hash_event(&self, event: &Event) -> Digest {
+        Digest(*blake3::hash(&event.hash_input()).as_bytes())
+    }
+}
+
+/// The original `DefaultHasher`-based hash, kept so chains recorded before
+/// digests were introduced still verify.
+///
+/// This hash is neither collision resistant nor guaranteed stable across Rust
+/// releases, and it doesn't cover the parent link. Don't use it for new
+/// chains.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LegacyHasher;
+
+impl ChainHasher for LegacyHasher {
+    fn name(&self) -> &str {
+        "legacy"
+    }
+
+    fn hash_event(&self, event: &Event) -> Digest {
+        // Legacy hashes were taken before the parent was assigned, so the
+        // parent is always hashed as `None` here.
+        let mut hasher = DefaultHasher::new();
+        (event.type_(), None::<u64>, event.data()).hash(&mut hasher);
+        Digest::from_u64(hasher.finish())
+    }
+}
+
+/// Returns the built-in hasher called `name`, if there is one.
+pub fn hasher_by_name(name: &str) -> Option<Arc<dyn ChainHasher>> {
+    match name {
+        "sha256" => Some(Arc::new(Sha256Hasher)),
+        #[cfg(feature = "chain-blake3")]
+        "blake3" => Some(Arc::new(Blake3Hasher)),
+        "legacy" => Some(Arc::new(LegacyHasher)),
+        _ => None,
+    }
+}
@@ -0,0 +1,553 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inspecting live chains over HTTP.
+//!
+//! A [`ChainServer`] serves chains by name as JSON, so the chains of running
+//! actors can be watched from a browser or `curl` without attaching a
+//! debugger:
+//!
+//! | Route                                  |                                      |
+//! |----------------------------------------|--------------------------------------|
+//! | `GET /chains`                          | every chain's name, length and head  |
+//! | `GET /chains/{name}`                   | one chain's name, length and head    |
+//! | `GET /chains/{name}/events`            | a [page](crate::chain::page) of events, with `?after=` and `?limit=` |
+//! | `GET /chains/{name}/events/{hash}`     | the event with a hash                |
+//! | `GET /chains/{name}/stream`            | server-sent events as they're added  |
+//! | `GET /chains/{name}/ws`                | a WebSocket of events as they're added |
+//!
+//! A stream starts after the event given by `?after=` or a `Last-Event-ID`
+//! header, or at the start of the chain, and sends each event as a
+//! server-sent event whose id is its sequence number and whose type is the
+//! event's type. Errors are JSON objects with an `error` message.
+//!
+//! With the `chain-websocket` feature, `ws` upgrades to a WebSocket which
+//! starts like a stream and sends each event as a text message holding
+//! `{"seq": …, "event": …}`, for live viewers in browsers. Messages from the
+//! client are ignored until it closes the socket.
+//!
+//! Chains are served from [`SharedChain`]s. A store records into a chain of
+//! its own, which [`ChainServer::follow`] and [`ChainServer::follow_store`]
+//! mirror into a shared one as events are added. Mirrored events are added
+//! as by [`Chain::pull`], so those added after following starts are served
+//! without their signatures.
+
+use crate::chain::{Chain, Digest, MetaEvent, SharedChain};
+use crate::prelude::*;
+use crate::Store;
+use bytes::Bytes;
+use core::convert::Infallible;
+use core::pin::Pin;
+use core::task::{Context as TaskContext, Poll};
+use core::time::Duration;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame, Incoming};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// How many events a page holds when no `limit` is given.
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// The most events a page holds, whatever `limit` is given.
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+type ResponseBody = BoxBody<Bytes, Infallible>;
+
+/// Serves chains over HTTP, see the [module documentation](self).
+///
+/// Clones of a server serve the same chains.
+#[derive(Debug, Clone)]
+pub struct ChainServer {
+    chains: Arc<RwLock<BTreeMap<String, SharedChain>>>,
+    poll_interval: Duration,
+}
+
+impl Default for ChainServer {
+    fn default() -> ChainServer {
+        ChainServer::new()
+    }
+}
+
+impl ChainServer {
+    /// A server with no chains to serve yet.
+    pub fn new() -> ChainServer {
+        ChainServer {
+            chains: Arc::default(),
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+
+    /// Sets how often streams check their chain for new events, 250ms by
+    /// default.
+    pub fn poll_interval(mut self, interval: Duration) -> ChainServer {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Serves `chain` as `name`, replacing any chain served as `name` so far.
+    pub fn add_chain(&self, name: impl Into<String>, chain: SharedChain) {
+        self.chains
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), chain);
+    }
+
+    /// Stops serving the chain served as `name`, returning it.
+    pub fn remove_chain(&self, name: &str) -> Option<SharedChain> {
+        self.chains
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+    }
+
+    /// The chain served as `name`.
+    pub fn chain(&self, name: &str) -> Option<SharedChain> {
+        self.chains
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+
+    /// Serves a copy of `chain` as `name`, which a background thread keeps
+    /// up to date with the events added to `chain` until it's dropped.
+    ///
+    /// Returns the copy being served.
+    pub fn follow(&self, name: impl Into<String>, chain: &mut Chain) -> SharedChain {
+        let name = name.into();
+        let receiver = chain.subscribe();
+        let mut copy = chain.clone();
+        // Events arrive as they were recorded, and must hash the same when
+        // added to the copy.
+        copy.set_redactor(None);
+        copy.set_signer(None);
+        let copy = SharedChain::new(copy);
+        let mirror = copy.clone();
+        let thread_name = name.clone();
+        std::thread::spawn(move || {
+            for node in receiver {
+                if let Err(e) = mirror.write().add_synced(&node) {
+                    log::warn!("stopped following chain `{thread_name}`: {e:#}");
+                    break;
+                }
+            }
+        });
+        self.add_chain(name, copy.clone());
+        copy
+    }
+
+    /// Follows the chain `store` records into as `name`, and each of its
+    /// child chains, such as those of instances with
+    /// [`Config::chain_per_instance`](crate::Config::chain_per_instance), as
+    /// `{name}:{hash}` where `hash` is the hash of the event which spawned
+    /// it.
+    ///
+    /// Only children spawned so far are followed, and a chain replaced with
+    /// [`Store::set_chain`] is no longer followed.
+    pub fn follow_store<T>(&self, name: &str, store: &mut Store<T>) {
+        let chain = store.chain_mut();
+        self.follow(name, chain);
+        for (spawn, child) in chain.children.iter_mut() {
+            self.follow(format!("{name}:{spawn}"), child);
+        }
+    }
+
+    /// Serves HTTP requests from `listener` until accepting a connection
+    /// fails.
+    ///
+    /// Connections are handled by tasks of their own, so this must be run
+    /// within a Tokio runtime.
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::task::spawn(async move {
+                let service = service_fn(move |req| server.clone().handle(req));
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .with_upgrades()
+                    .await
+                {
+                    log::debug!("error serving chain inspection request: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle(self, req: Request<Incoming>) -> Result<Response<ResponseBody>, Infallible> {
+        if req.method() != Method::GET {
+            return Ok(error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only GET requests are served",
+            ));
+        }
+        let path = req.uri().path().to_string();
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        if segments[..] == ["chains"] {
+            let chains = self.chains.read().unwrap_or_else(PoisonError::into_inner);
+            let chains = chains
+                .iter()
+                .map(|(name, chain)| summary(name, chain))
+                .collect::<Vec<_>>();
+            return Ok(json_response(StatusCode::OK, &chains));
+        }
+        let (name, rest) = match segments[..] {
+            ["chains", name, ref rest @ ..] => (name, rest),
+            _ => return Ok(error(StatusCode::NOT_FOUND, "no such route")),
+        };
+        let Some(chain) = self.chain(name) else {
+            return Ok(error(
+                StatusCode::NOT_FOUND,
+                &format!("no chain is served as `{name}`"),
+            ));
+        };
+        let query = match Query::parse(req.uri().query(), req.headers()) {
+            Ok(query) => query,
+            Err(e) => return Ok(error(StatusCode::BAD_REQUEST, &format!("{e:#}"))),
+        };
+        Ok(match rest {
+            [] => json_response(StatusCode::OK, &summary(name, &chain)),
+            ["events"] => {
+                let chain = chain.read();
+                let page = chain.page(query.after, query.limit);
+                json_response(StatusCode::OK, &page)
+            }
+            ["events", hash] => match hash.parse::<Digest>() {
+                Ok(hash) => match chain.get_event_by_hash(hash) {
+                    Some(node) => json_response(StatusCode::OK, &node),
+                    None => error(StatusCode::NOT_FOUND, &format!("no event has hash {hash}")),
+                },
+                Err(e) => error(StatusCode::BAD_REQUEST, &format!("{e:#}")),
+            },
+            ["stream"] => self.stream(chain, query.after),
+            #[cfg(feature = "chain-websocket")]
+            ["ws"] => self.websocket(req, chain, query.after),
+            _ => error(StatusCode::NOT_FOUND, "no such route"),
+        })
+    }
+
+    /// Starts streaming the events of `chain` after sequence number `after`.
+    fn stream(&self, chain: SharedChain, mut after: Option<u64>) -> Response<ResponseBody> {
+        let (sender, receiver) = mpsc::channel(16);
+        let mut interval = tokio::time::interval(self.poll_interval);
+        tokio::task::spawn(async move {
+            while !sender.is_closed() {
+                interval.tick().await;
+                let mut chunk = String::new();
+                for (seq, node) in new_events(&chain, &mut after) {
+                    let data =
+                        serde_json::to_string(&node).expect("chain JSON is always serializable");
+                    chunk += &format!(
+                        "id: {seq}\nevent: {}\ndata: {data}\n\n",
+                        node.event().type_()
+                    );
+                }
+                if !chunk.is_empty() && sender.send(Bytes::from(chunk)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let mut response = Response::new(EventStream(receiver).boxed());
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+
+    /// Upgrades `req` to a WebSocket streaming the events of `chain` after
+    /// sequence number `after`.
+    #[cfg(feature = "chain-websocket")]
+    fn websocket(
+        &self,
+        req: Request<Incoming>,
+        chain: SharedChain,
+        mut after: Option<u64>,
+    ) -> Response<ResponseBody> {
+        use futures::{SinkExt, StreamExt};
+        use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+        use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+        use tokio_tungstenite::tungstenite::protocol::Role;
+        use tokio_tungstenite::tungstenite::Message;
+        use tokio_tungstenite::WebSocketStream;
+
+        let headers = req.headers();
+        let upgrade = headers
+            .get(UPGRADE)
+            .and_then(|upgrade| upgrade.to_str().ok())
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        let Some(key) = headers.get(SEC_WEBSOCKET_KEY).filter(|_| upgrade) else {
+            return error(StatusCode::BAD_REQUEST, "expected a WebSocket upgrade");
+        };
+        let accept = derive_accept_key(key.as_bytes());
+
+        let mut interval = tokio::time::interval(self.poll_interval);
+        tokio::task::spawn(async move {
+            let upgraded = match hyper::upgrade::on(req).await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    log::debug!("failed to upgrade to a WebSocket: {e}");
+                    return;
+                }
+            };
+            let mut socket =
+                WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        for (seq, node) in new_events(&chain, &mut after) {
+                            let message = json!({ "seq": seq, "event": node }).to_string();
+                            if socket.send(Message::text(message)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    message = socket.next() => match message {
+                        Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                        Some(Ok(_)) => {}
+                    },
+                }
+            }
+        });
+
+        let mut response = Response::new(Full::new(Bytes::new()).boxed());
+        *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = response.headers_mut();
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(
+            SEC_WEBSOCKET_ACCEPT,
+            HeaderValue::from_str(&accept).expect("accept keys are base64"),
+        );
+        response
+    }
+}
+
+/// The events of `chain` after sequence number `after`, with their sequence
+/// numbers, moving `after` on to the last of them.
+fn new_events(chain: &SharedChain, after: &mut Option<u64>) -> Vec<(u64, MetaEvent)> {
+    let chain = chain.read();
+    let page = chain.page(*after, MAX_PAGE_LIMIT);
+    let start = after.map_or(0, |after| after + 1);
+    *after = page.next;
+    (start..).zip(page.events.into_iter().cloned()).collect()
+}
+
+/// The options of a request.
+struct Query {
+    after: Option<u64>,
+    limit: usize,
+}
+
+impl Query {
+    fn parse(query: Option<&str>, headers: &HeaderMap) -> Result<Query> {
+        let mut after = match headers.get("last-event-id") {
+            Some(id) => Some(
+                id.to_str()
+                    .ok()
+                    .and_then(|id| id.trim().parse().ok())
+                    .context("invalid Last-Event-ID header")?,
+            ),
+            None => None,
+        };
+        let mut limit = DEFAULT_PAGE_LIMIT;
+        for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "after" => {
+                    after = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid after `{value}`"))?,
+                    )
+                }
+                "limit" => {
+                    limit = value
+                        .parse::<usize>()
+                        .with_context(|| format!("invalid limit `{value}`"))?
+                        .min(MAX_PAGE_LIMIT)
+                }
+                _ => bail!("unknown query parameter `{key}`"),
+            }
+        }
+        Ok(Query { after, limit })
+    }
+}
+
+/// The body of a stream, sending the chunks its task produces.
+struct EventStream(mpsc::Receiver<Bytes>);
+
+impl Body for EventStream {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        self.0
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+    }
+}
+
+fn summary(name: &str, chain: &SharedChain) -> serde_json::Value {
+    json!({ "name": name, "len": chain.len(), "head": chain.head() })
+}
+
+fn json_response(status: StatusCode, body: &impl serde::Serialize) -> Response<ResponseBody> {
+    let body = serde_json::to_vec(body).expect("chain JSON is always serializable");
+    let mut response = Response::new(Full::new(Bytes::from(body)).boxed());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response<ResponseBody> {
+    json_response(status, &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+    use crate::Engine;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+
+    /// Runs `server` on a thread of its own, returning its address.
+    fn spawn(server: ChainServer) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let listener = TcpListener::from_std(listener).unwrap();
+                server.serve(listener).await.unwrap();
+            });
+        });
+        Ok(addr)
+    }
+
+    /// Sends a GET request for `path`, reading the response until it
+    /// contains `until` or the connection closes.
+    fn get(addr: SocketAddr, path: &str, until: Option<&str>) -> Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut response = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf)?;
+            response.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&response);
+            if n == 0 || until.is_some_and(|until| text.contains(until)) {
+                return Ok(text.into_owned());
+            }
+        }
+    }
+
+    #[test]
+    fn serves_live_chains() -> Result<()> {
+        let mut store = Store::new(&Engine::default(), ());
+        let first = store
+            .chain_mut()
+            .add(Event::new("start".to_string(), b"{}".to_vec()));
+        let server = ChainServer::new().poll_interval(Duration::from_millis(10));
+        server.follow_store("actor", &mut store);
+        let addr = spawn(server.clone())?;
+
+        let chains = get(addr, "/chains", None)?;
+        assert!(chains.starts_with("HTTP/1.1 200 OK"), "{chains}");
+        let (_, body) = chains.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body)?,
+            json!([{ "name": "actor", "len": 1, "head": first }])
+        );
+
+        let event = get(addr, &format!("/chains/actor/events/{first}"), None)?;
+        assert!(event.contains(r#""type_":"start""#), "{event}");
+        let missing = get(
+            addr,
+            &format!("/chains/actor/events/{}", Digest::default()),
+            None,
+        )?;
+        assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+        assert!(get(addr, "/chains/other", None)?.starts_with("HTTP/1.1 404"));
+        assert!(get(addr, "/chains/actor/events?limit=x", None)?.starts_with("HTTP/1.1 400"));
+
+        // Events added to the store's chain reach the stream.
+        let stream = std::thread::spawn(move || {
+            get(addr, "/chains/actor/stream?after=0", Some("event: later"))
+        });
+        store
+            .chain_mut()
+            .add(Event::new("later".to_string(), b"{}".to_vec()));
+        let stream = stream.join().unwrap()?;
+        assert!(stream.contains("text/event-stream"), "{stream}");
+        assert!(stream.contains("id: 1\nevent: later\n"), "{stream}");
+        assert!(!stream.contains("event: start"), "{stream}");
+
+        let page = get(addr, "/chains/actor/events?limit=1", None)?;
+        assert!(page.contains(r#""next":0,"more":true"#), "{page}");
+        assert_eq!(server.chain("actor").unwrap().head(), store.chain().head());
+        Ok(())
+    }
+
+    #[cfg(feature = "chain-websocket")]
+    #[test]
+    fn streams_over_websockets() -> Result<()> {
+        use tokio_tungstenite::tungstenite::{connect, Message};
+
+        let mut chain = Chain::new();
+        chain.add(Event::new("start".to_string(), b"{}".to_vec()));
+        let server = ChainServer::new().poll_interval(Duration::from_millis(10));
+        server.follow("actor", &mut chain);
+        let addr = spawn(server)?;
+        assert!(get(addr, "/chains/actor/ws", None)?.starts_with("HTTP/1.1 400"));
+
+        let (mut socket, _) = connect(format!("ws://{addr}/chains/actor/ws"))?;
+        let mut next = || -> Result<serde_json::Value> {
+            match socket.read()? {
+                Message::Text(text) => Ok(serde_json::from_str(&text)?),
+                message => bail!("unexpected message {message:?}"),
+            }
+        };
+        let first = next()?;
+        assert_eq!(first["seq"], 0);
+        assert_eq!(first["event"]["event"]["type_"], "start");
+        let later = chain.add(Event::new("later".to_string(), b"{}".to_vec()));
+        let second = next()?;
+        assert_eq!(second["seq"], 1);
+        assert_eq!(second["event"]["hash"], json!(later));
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interning in a chain's binary form.
+//!
+//! Most events share their type with many others, and recorded payloads
+//! often repeat exactly, such as calls made with the same arguments.
+//! [`Chain::to_bytes`] writes each event type once, into a table events
+//! refer to by index, and does the same for each non-empty payload which
+//! appears more than once. [`Chain::from_bytes`] expands them again, so the
+//! events read back are the same as the ones written.
+//!
+//! Chains encoded before interning, or before events could have a severity or
+//! schema version, still decode. JSON and CBOR aren't interned, so they stay
+//! readable without Wasmtime.
+
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::{Chain, Compression, Digest, Event, MemoryChainStore, MetaEvent, Severity};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Starts interned chains. The older form starts with the length of the
+/// hasher name, which is never zero.
+const MAGIC: &[u8] = b"\0chain-interned\x02";
+
+/// Starts interned chains from before events could be stamped with a schema
+/// version, whose events are [`UnstampedEvent`]s.
+const MAGIC_V1: &[u8] = b"\0chain-interned\x01";
+
+/// Starts interned chains from before events could have a severity, whose
+/// events are [`UnratedEvent`]s.
+const MAGIC_V0: &[u8] = b"\0chain-interned\0";
+
+#[derive(Serialize, Deserialize)]
+struct InternedChain<'a, E> {
+    hasher: Cow<'a, str>,
+    types: Vec<Cow<'a, str>>,
+    payloads: Vec<Cow<'a, [u8]>>,
+    events: Vec<E>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedEvent<'a> {
+    hash: Digest,
+    /// Index into [`InternedChain::types`].
+    type_: u32,
+    parent: Option<Digest>,
+    data: Payload<'a>,
+    compression: Compression,
+    signature: Option<Cow<'a, [u8]>>,
+    severity: Option<Severity>,
+    schema_version: Option<u32>,
+}
+
+/// An [`InternedEvent`] from before events could be stamped with a schema
+/// version.
+#[derive(Deserialize)]
+struct UnstampedEvent<'a> {
+    hash: Digest,
+    type_: u32,
+    parent: Option<Digest>,
+    data: Payload<'a>,
+    compression: Compression,
+    signature: Option<Cow<'a, [u8]>>,
+    severity: Option<Severity>,
+}
+
+/// An [`InternedEvent`] from before events could have a severity.
+#[derive(Deserialize)]
+struct UnratedEvent<'a> {
+    hash: Digest,
+    type_: u32,
+    parent: Option<Digest>,
+    data: Payload<'a>,
+    compression: Compression,
+    signature: Option<Cow<'a, [u8]>>,
+}
+
+impl<'a> From<UnstampedEvent<'a>> for InternedEvent<'a> {
+    fn from(e: UnstampedEvent<'a>) -> InternedEvent<'a> {
+        InternedEvent {
+            hash: e.hash,
+            type_: e.type_,
+            parent: e.parent,
+            data: e.data,
+            compression: e.compression,
+            signature: e.signature,
+            severity: e.severity,
+            schema_version: None,
+        }
+    }
+}
+
+impl<'a> From<UnratedEvent<'a>> for InternedEvent<'a> {
+    fn from(e: UnratedEvent<'a>) -> InternedEvent<'a> {
+        InternedEvent {
+            hash: e.hash,
+            type_: e.type_,
+            parent: e.parent,
+            data: e.data,
+            compression: e.compression,
+            signature: e.signature,
+            severity: None,
+            schema_version: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Payload<'a> {
+    Inline(Cow<'a, [u8]>),
+    /// Index into [`InternedChain::payloads`].
+    Interned(u32),
+}
+
+/// Hands out indices into a table, the first time each value is seen.
+struct Table<'a, T: ?Sized + ToOwned> {
+    indices: HashMap<&'a T, u32>,
+    values: Vec<Cow<'a, T>>,
+}
+
+impl<'a, T: ?Sized + Eq + core::hash::Hash + ToOwned> Table<'a, T> {
+    fn new() -> Self {
+        Table {
+            indices: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn intern(&mut self, value: &'a T) -> u32 {
+        if let Some(&index) = self.indices.get(value) {
+            return index;
+        }
+        let index = u32::try_from(self.values.len()).unwrap();
+        self.indices.insert(value, index);
+        self.values.push(Cow::Borrowed(value));
+        index
+    }
+}
+
+/// Encodes `chain` in its interned binary form.
+pub(crate) fn encode(chain: &Chain) -> Result<Vec<u8>> {
+    let mut seen = HashMap::<&[u8], usize>::new();
+    for node in chain.events() {
+        *seen.entry(node.event().stored_data()).or_default() += 1;
+    }
+
+    let mut types = Table::<str>::new();
+    let mut payloads = Table::<[u8]>::new();
+    let events = chain
+        .events()
+        .map(|node| {
+            let event = node.event();
+            let data = event.stored_data();
+            InternedEvent {
+                hash: node.hash(),
+                type_: types.intern(event.type_()),
+                parent: event.parent(),
+                data: if !data.is_empty() && seen[data] > 1 {
+                    Payload::Interned(payloads.intern(data))
+                } else {
+                    Payload::Inline(Cow::Borrowed(data))
+                },
+                compression: event.compression(),
+                signature: node.signature().map(Cow::Borrowed),
+                severity: event.severity(),
+                schema_version: event.schema_version(),
+            }
+        })
+        .collect();
+
+    let interned = InternedChain {
+        hasher: Cow::Borrowed(chain.hasher().name()),
+        types: types.values,
+        payloads: payloads.values,
+        events,
+    };
+    Ok(postcard::to_extend(&interned, MAGIC.to_vec())?)
+}
+
+/// Decodes a chain written by [`encode`], or returns `None` if `bytes` are
+/// in the form from before interning.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Option<Chain>> {
+    let chain = if let Some(bytes) = bytes.strip_prefix(MAGIC) {
+        postcard::from_bytes(bytes)?
+    } else if let Some(bytes) = bytes.strip_prefix(MAGIC_V1) {
+        upgrade::<UnstampedEvent<'_>>(postcard::from_bytes(bytes)?)
+    } else if let Some(bytes) = bytes.strip_prefix(MAGIC_V0) {
+        upgrade::<UnratedEvent<'_>>(postcard::from_bytes(bytes)?)
+    } else if bytes.starts_with(&MAGIC[..MAGIC.len() - 1]) {
+        bail!("binary chain is in a newer layout than this build supports");
+    } else {
+        return Ok(None);
+    };
+    let hasher = match hasher_by_name(&chain.hasher) {
+        Some(hasher) => hasher,
+        None => bail!("chain uses unknown hasher `{}`", chain.hasher),
+    };
+
+    let mut events = Vec::with_capacity(chain.events.len());
+    for (index, e) in chain.events.into_iter().enumerate() {
+        let type_ = match chain.types.get(e.type_ as usize) {
+            Some(type_) => type_.to_string(),
+            None => bail!("event {index} refers to unknown type {}", e.type_),
+        };
+        let data = match e.data {
+            Payload::Inline(data) => data.into_owned(),
+            Payload::Interned(i) => match chain.payloads.get(i as usize) {
+                Some(data) => data.to_vec(),
+                None => bail!("event {index} refers to unknown payload {i}"),
+            },
+        };
+        let mut event = Event::new(type_, data);
+        event.set_parent(e.parent);
+        event.compression = e.compression;
+        event.severity = e.severity;
+        event.schema_version = e.schema_version;
+        events.push(MetaEvent::new(e.hash, event).with_signature(e.signature.map(Cow::into_owned)));
+    }
+    Ok(Some(Chain::from_store_unverified(
+        hasher,
+        Box::new(MemoryChainStore::from(events)),
+    )))
+}
+
+/// Converts a chain decoded in an older form.
+fn upgrade<'a, E: Into<InternedEvent<'a>>>(
+    chain: InternedChain<'a, E>,
+) -> InternedChain<'a, InternedEvent<'a>> {
+    InternedChain {
+        hasher: chain.hasher,
+        types: chain.types,
+        payloads: chain.payloads,
+        events: chain.events.into_iter().map(Into::into).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_types_and_payloads() -> Result<()> {
+        let mut chain = Chain::new();
+        let payload = vec![7; 100];
+        for _ in 0..10 {
+            chain.add(Event::new("function-call".to_string(), payload.clone()));
+        }
+        let head = chain.add(Event::new("yield".to_string(), vec![1]));
+
+        let bytes = chain.to_bytes()?;
+        assert!(bytes.starts_with(MAGIC));
+        assert!(bytes.len() < 10 * payload.len());
+        let decoded = Chain::from_bytes(&bytes)?;
+        decoded.verify()?;
+        assert_eq!(decoded.head(), Some(head));
+        assert_eq!(decoded.len(), 11);
+        for (a, b) in decoded.events().zip(chain.events()) {
+            assert_eq!(a.event().type_(), b.event().type_());
+            assert_eq!(a.event().data(), b.event().data());
+        }
+        assert!(Chain::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_uninterned_chains() -> Result<()> {
+        let mut chain = Chain::new();
+        let head = chain.add(Event::new("a".to_string(), vec![1, 2]));
+        // Events as they were encoded in format 3.
+        let events = chain
+            .events()
+            .map(|node| {
+                let event = node.event();
+                let data = event.data().to_vec();
+                let event = (event.type_(), event.parent(), data, Compression::None);
+                (node.hash(), event, None::<Vec<u8>>)
+            })
+            .collect::<Vec<_>>();
+        let bytes = postcard::to_allocvec(&("sha256", events))?;
+        let decoded = Chain::from_bytes(&bytes)?;
+        assert_eq!(decoded.head(), Some(head));
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Converting recorded values to and from plain JSON.
+//!
+//! Chain payloads serialize [`SerializableVal`]s with their Rust variant
+//! names, such as `{"Record":[["name",{"String":"ann"}]]}`, which is exact
+//! but awkward for consumers that aren't written in Rust.
+//! [`SerializableVal::to_json_value`] instead writes values the way
+//! JavaScript bindings of components represent them:
+//!
+//! | WIT                      | JSON                                            |
+//! |--------------------------|-------------------------------------------------|
+//! | `bool`                   | `true`                                          |
+//! | `s8` to `u32`            | `1`                                             |
+//! | `s64`, `u64`             | `1`, or `"18446744073709551615"` beyond 2^53    |
+//! | `float32`, `float64`     | `1.5`, or `"nan"`, `"inf"` and `"-inf"`         |
+//! | `char`, `string`         | `"a"`                                           |
+//! | `list`, `tuple`          | `[1, 2]`                                        |
+//! | `record`                 | `{"name": "ann"}`                               |
+//! | `variant`, `result`      | `{"tag": "some-case", "val": 1}`, `val` omitted without a payload |
+//! | `enum`                   | `"some-case"`                                   |
+//! | `option`                 | `null` or the payload, tagged as `"some"` if it's an option |
+//! | `flags`                  | `["read", "write"]`                             |
+//! | `own`, `borrow`          | `{"type_id": 0, "rep": 1, "owned": true}`       |
+//!
+//! Integers outside of ±2^53 are written as strings since JavaScript numbers
+//! can't hold them exactly. JSON on its own doesn't say which of these types
+//! a value has, so [`SerializableVal::from_json_value`] needs the value's
+//! [`SerializableType`], such as one recorded in a
+//! [`FunctionSignature`](crate::chain::FunctionSignature). It accepts
+//! numbers and strings for every integer and float type. Converting a value
+//! to JSON and back gives the same value, except that NaN payloads are lost.
+
+use crate::chain::{SerializableResource, SerializableType, SerializableVal};
+use crate::prelude::*;
+use serde_json::{json, Value};
+
+/// The largest integer a JavaScript number holds exactly.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+impl SerializableVal {
+    /// Converts this value to plain JSON, see the
+    /// [module documentation](crate::chain::json).
+    pub fn to_json_value(&self) -> Value {
+        use SerializableVal as V;
+
+        let tagged = |tag: &str, val: Option<&V>| match val {
+            Some(val) => json!({ "tag": tag, "val": val.to_json_value() }),
+            None => json!({ "tag": tag }),
+        };
+        match self {
+            V::Bool(b) => Value::Bool(*b),
+            V::S8(n) => json!(n),
+            V::U8(n) => json!(n),
+            V::S16(n) => json!(n),
+            V::U16(n) => json!(n),
+            V::S32(n) => json!(n),
+            V::U32(n) => json!(n),
+            V::S64(n) if n.unsigned_abs() <= MAX_SAFE_INTEGER => json!(n),
+            V::S64(n) => Value::String(n.to_string()),
+            V::U64(n) if *n <= MAX_SAFE_INTEGER => json!(n),
+            V::U64(n) => Value::String(n.to_string()),
+            V::Float32(x) => float(f64::from(*x)),
+            V::Float64(x) => float(*x),
+            V::Char(c) => Value::String(c.to_string()),
+            V::String(s) => Value::String(s.clone()),
+            V::List(vals) | V::Tuple(vals) => {
+                Value::Array(vals.iter().map(|v| v.to_json_value()).collect())
+            }
+            V::Record(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, v)| (name.clone(), v.to_json_value()))
+                    .collect(),
+            ),
+            V::Variant(name, val) => tagged(name, val.as_deref()),
+            V::Enum(name) => Value::String(name.clone()),
+            V::Option(None) => Value::Null,
+            V::Option(Some(val)) if matches!(**val, V::Option(_)) => tagged("some", Some(&**val)),
+            V::Option(Some(val)) => val.to_json_value(),
+            V::Result(Ok(val)) => tagged("ok", val.as_deref()),
+            V::Result(Err(val)) => tagged("err", val.as_deref()),
+            V::Flags(flags) => Value::Array(flags.iter().cloned().map(Value::String).collect()),
+            V::Resource(r) => json!({ "type_id": r.type_id, "rep": r.rep, "owned": r.owned }),
+        }
+    }
+
+    /// Converts plain JSON written by [`SerializableVal::to_json_value`] back
+    /// into a value of type `ty`, naming the path to the first part which
+    /// doesn't fit, such as `$.tags[2]`.
+    pub fn from_json_value(value: &Value, ty: &SerializableType) -> Result<SerializableVal> {
+        from_json(value, ty, "$")
+    }
+}
+
+fn float(x: f64) -> Value {
+    if x.is_nan() {
+        json!("nan")
+    } else if x.is_infinite() {
+        json!(if x > 0.0 { "inf" } else { "-inf" })
+    } else {
+        json!(x)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)] // numbers are rounded to `float32`
+fn from_json(value: &Value, ty: &SerializableType, path: &str) -> Result<SerializableVal> {
+    use SerializableType as T;
+    use SerializableVal as V;
+
+    let mismatch = || anyhow!("{path}: expected {}, found {}", ty.desc(), kind(value));
+    // Integers are written as numbers or, beyond 2^53, as strings.
+    macro_rules! int {
+        ($variant:ident) => {
+            match value {
+                Value::Number(n) => n
+                    .as_i64()
+                    .and_then(|n| n.try_into().ok())
+                    .or_else(|| n.as_u64().and_then(|n| n.try_into().ok())),
+                Value::String(s) => s.parse().ok(),
+                _ => return Err(mismatch()),
+            }
+            .map(V::$variant)
+            .ok_or_else(|| anyhow!("{path}: {value} is out of range for {}", ty.desc()))?
+        };
+    }
+    let number = || match value {
+        Value::Number(n) => n.as_f64().ok_or_else(mismatch),
+        Value::String(s) => match s.as_str() {
+            "nan" => Ok(f64::NAN),
+            "inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            s => s.parse().map_err(|_| mismatch()),
+        },
+        _ => Err(mismatch()),
+    };
+    // The case name and payload of a `{"tag": …, "val": …}` object.
+    let tagged = || match value.get("tag") {
+        Some(Value::String(tag)) => Ok((tag.as_str(), value.get("val"))),
+        _ => Err(mismatch()),
+    };
+    let payload = |val: Option<&Value>, ty: Option<&T>, case: &str| -> Result<Option<Box<V>>> {
+        match (val, ty) {
+            (Some(val), Some(ty)) => Ok(Some(Box::new(from_json(
+                val,
+                ty,
+                &format!("{path}.{case}"),
+            )?))),
+            (None, None) => Ok(None),
+            (Some(_), None) => bail!("{path}: case `{case}` has no payload"),
+            (None, Some(_)) => bail!("{path}: case `{case}` requires a payload"),
+        }
+    };
+    let string = || value.as_str().ok_or_else(mismatch);
+
+    Ok(match ty {
+        T::Bool => V::Bool(value.as_bool().ok_or_else(mismatch)?),
+        T::S8 => int!(S8),
+        T::U8 => int!(U8),
+        T::S16 => int!(S16),
+        T::U16 => int!(U16),
+        T::S32 => int!(S32),
+        T::U32 => int!(U32),
+        T::S64 => int!(S64),
+        T::U64 => int!(U64),
+        T::Float32 => V::Float32(number()? as f32),
+        T::Float64 => V::Float64(number()?),
+        T::Char => {
+            let mut chars = string()?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => V::Char(c),
+                _ => bail!("{path}: expected a single character, found {value}"),
+            }
+        }
+        T::String => V::String(string()?.to_string()),
+        T::List(elem) => V::List(
+            value
+                .as_array()
+                .ok_or_else(mismatch)?
+                .iter()
+                .enumerate()
+                .map(|(i, v)| from_json(v, elem, &format!("{path}[{i}]")))
+                .collect::<Result<_>>()?,
+        ),
+        T::Tuple(types) => {
+            let vals = value.as_array().ok_or_else(mismatch)?;
+            ensure!(
+                vals.len() == types.len(),
+                "{path}: expected a tuple of {} element(s), found {}",
+                types.len(),
+                vals.len()
+            );
+            V::Tuple(
+                vals.iter()
+                    .zip(types)
+                    .enumerate()
+                    .map(|(i, (v, ty))| from_json(v, ty, &format!("{path}[{i}]")))
+                    .collect::<Result<_>>()?,
+            )
+        }
+        T::Record(fields) => {
+            let object = value.as_object().ok_or_else(mismatch)?;
+            if let Some(name) = object.keys().find(|k| !fields.iter().any(|(f, _)| f == *k)) {
+                bail!("{path}: unexpected record field `{name}`");
+            }
+            V::Record(
+                fields
+                    .iter()
+                    .map(|(name, ty)| -> Result<(String, V)> {
+                        match object.get(name) {
+                            Some(v) => {
+                                Ok((name.clone(), from_json(v, ty, &format!("{path}.{name}"))?))
+                            }
+                            None => bail!("{path}: missing record field `{name}`"),
+                        }
+                    })
+                    .collect::<Result<_>>()?,
+            )
+        }
+        T::Variant(cases) => {
+            let (tag, val) = tagged()?;
+            match cases.iter().find(|(name, _)| name == tag) {
+                Some((name, ty)) => {
+                    V::Variant(name.clone(), payload(val, ty.as_ref(), name.as_str())?)
+                }
+                None => bail!("{path}: unknown case `{tag}`"),
+            }
+        }
+        T::Enum(names) => {
+            let name = string()?;
+            ensure!(
+                names.iter().any(|n| n == name),
+                "{path}: unknown case `{name}`"
+            );
+            V::Enum(name.to_string())
+        }
+        T::Option(ty) if matches!(**ty, T::Option(_)) => match value {
+            Value::Null => V::Option(None),
+            _ => match tagged()? {
+                ("some", val) => V::Option(payload(val, Some(&**ty), "some")?),
+                (tag, _) => bail!("{path}: unknown case `{tag}`"),
+            },
+        },
+        T::Option(ty) => match value {
+            Value::Null => V::Option(None),
+            value => V::Option(Some(Box::new(from_json(
+                value,
+                ty,
+                &format!("{path}.some"),
+            )?))),
+        },
+        T::Result { ok, err } => match tagged()? {
+            ("ok", val) => V::Result(Ok(payload(val, ok.as_deref(), "ok")?)),
+            ("err", val) => V::Result(Err(payload(val, err.as_deref(), "err")?)),
+            (tag, _) => bail!("{path}: unknown case `{tag}`"),
+        },
+        T::Flags(names) => V::Flags(
+            value
+                .as_array()
+                .ok_or_else(mismatch)?
+                .iter()
+                .map(|flag| match flag.as_str() {
+                    Some(flag) if names.iter().any(|n| n == flag) => Ok(flag.to_string()),
+                    _ => bail!("{path}: unknown flag {flag}"),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        T::Own(_) | T::Borrow(_) => {
+            let resource: SerializableResource = serde_json::from_value(value.clone())
+                .with_context(|| format!("{path}: expected a resource handle"))?;
+            V::Resource(resource)
+        }
+    })
+}
+
+/// What kind of JSON value `value` is, for errors.
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SerializableType as T;
+    use SerializableVal as V;
+
+    fn round_trip(val: &V, ty: &T) -> Value {
+        let json = val.to_json_value();
+        assert_eq!(V::from_json_value(&json, ty).unwrap(), *val, "{json}");
+        json
+    }
+
+    #[test]
+    fn maps_values_to_plain_json() {
+        let ty = T::Record(vec![
+            ("name".to_string(), T::String),
+            ("initial".to_string(), T::Char),
+            ("big".to_string(), T::U64),
+            ("small".to_string(), T::S64),
+            ("ratio".to_string(), T::Float64),
+            ("tags".to_string(), T::List(Box::new(T::String))),
+            (
+                "mode".to_string(),
+                T::Flags(vec!["read".to_string(), "write".to_string()]),
+            ),
+            (
+                "shape".to_string(),
+                T::Variant(vec![
+                    ("circle".to_string(), Some(T::Float32)),
+                    ("empty".to_string(), None),
+                ]),
+            ),
+            ("next".to_string(), T::Option(Box::new(T::U8))),
+        ]);
+        let val = V::Record(vec![
+            ("name".to_string(), V::String("ann".to_string())),
+            ("initial".to_string(), V::Char('a')),
+            ("big".to_string(), V::U64(u64::MAX)),
+            ("small".to_string(), V::S64(-5)),
+            ("ratio".to_string(), V::Float64(f64::INFINITY)),
+            (
+                "tags".to_string(),
+                V::List(vec![V::String("x".to_string())]),
+            ),
+            ("mode".to_string(), V::Flags(vec!["write".to_string()])),
+            (
+                "shape".to_string(),
+                V::Variant("circle".to_string(), Some(Box::new(V::Float32(1.5)))),
+            ),
+            ("next".to_string(), V::Option(None)),
+        ]);
+        assert_eq!(
+            round_trip(&val, &ty),
+            json!({
+                "name": "ann",
+                "initial": "a",
+                "big": "18446744073709551615",
+                "small": -5,
+                "ratio": "inf",
+                "tags": ["x"],
+                "mode": ["write"],
+                "shape": { "tag": "circle", "val": 1.5 },
+                "next": null,
+            })
+        );
+
+        let nested = T::Option(Box::new(T::Option(Box::new(T::U8))));
+        let some_none = V::Option(Some(Box::new(V::Option(None))));
+        assert_eq!(
+            round_trip(&some_none, &nested),
+            json!({ "tag": "some", "val": null })
+        );
+        round_trip(&V::Option(None), &nested);
+        let result = T::Result {
+            ok: None,
+            err: Some(Box::new(T::String)),
+        };
+        assert_eq!(
+            round_trip(&V::Result(Ok(None)), &result),
+            json!({ "tag": "ok" })
+        );
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let ty = T::Record(vec![("tags".to_string(), T::List(Box::new(T::U8)))]);
+        let err = V::from_json_value(&json!({ "tags": [1, 300] }), &ty).unwrap_err();
+        assert_eq!(err.to_string(), "$.tags[1]: 300 is out of range for u8");
+        let err = V::from_json_value(&json!({ "tags": "x" }), &ty).unwrap_err();
+        assert_eq!(err.to_string(), "$.tags: expected list, found a string");
+        let err = V::from_json_value(&json!({ "tag": [] }), &ty).unwrap_err();
+        assert_eq!(err.to_string(), "$: unexpected record field `tag`");
+        assert_eq!(
+            V::from_json_value(&json!("42"), &T::U32).unwrap(),
+            V::U32(42)
+        );
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishing chain events to Kafka.
+//!
+//! A [`KafkaSink`] produces each event to a topic as a record keyed by the
+//! event's hash, holding the event as JSON, see
+//! [`sink::message`](crate::chain::sink::message). Records are produced in
+//! batches when the [`ChainPublisher`](crate::chain::ChainPublisher)
+//! flushes, and every broker in sync with a partition's leader must have
+//! them before they count as published.
+
+use crate::chain::{EventSink, MetaEvent};
+use crate::prelude::*;
+use core::time::Duration;
+use kafka::producer::{Producer, Record, RequiredAcks};
+
+/// An [`EventSink`] producing to a Kafka topic, see the
+/// [module documentation](crate::chain::kafka).
+pub struct KafkaSink {
+    producer: Producer,
+    topic: String,
+    pending: Vec<(String, Vec<u8>)>,
+}
+
+impl KafkaSink {
+    /// Connects to the Kafka cluster with the bootstrap brokers `hosts`,
+    /// such as `localhost:9092`, to produce to `topic`.
+    pub fn connect(hosts: &[String], topic: &str) -> Result<KafkaSink> {
+        let producer = Producer::from_hosts(hosts.to_vec())
+            .with_ack_timeout(Duration::from_secs(5))
+            .with_required_acks(RequiredAcks::All)
+            .create()
+            .map_err(|e| anyhow!("failed to connect to Kafka: {e}"))?;
+        Ok(KafkaSink::new(producer, topic))
+    }
+
+    /// Produces to `topic` with `producer`, configured as the caller likes.
+    pub fn new(producer: Producer, topic: &str) -> KafkaSink {
+        KafkaSink {
+            producer,
+            topic: topic.to_string(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl EventSink for KafkaSink {
+    fn send(&mut self, _seq: u64, event: &MetaEvent) -> Result<()> {
+        self.pending.push(crate::chain::sink::message(event)?);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let records = self
+            .pending
+            .iter()
+            .map(|(key, value)| Record::from_key_value(&self.topic, key.as_str(), &value[..]))
+            .collect::<Vec<_>>();
+        let confirms = self
+            .producer
+            .send_all(&records)
+            .map_err(|e| anyhow!("failed to produce to `{}`: {e}", self.topic))?;
+        for confirm in confirms {
+            for partition in confirm.partition_confirms {
+                if let Err(code) = partition.offset {
+                    bail!(
+                        "failed to produce to partition {} of `{}`: {code:?}",
+                        partition.partition,
+                        confirm.topic
+                    );
+                }
+            }
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
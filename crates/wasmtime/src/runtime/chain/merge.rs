@@ -0,0 +1,301 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merging chains recorded by replicas which have diverged.
+//!
+//! Because every hash covers its parent link, an event both chains hold means
+//! they agree on all of the history before it, so the common ancestor is the
+//! most recent event they share. What happens next depends on which sides
+//! added events after it:
+//!
+//! * If only the other chain did, its events are appended as they are and the
+//!   two chains end up identical.
+//! * If both did, their events are re-added on top of ours, which gives them
+//!   new hashes, followed by a [`MERGE`] event whose [`Merged`] payload links
+//!   back to their original head. The merge event is the second parent which
+//!   turns the histories into a DAG.
+//!
+//! Their events keep their type, payload, severity and schema version either
+//! way, and are signed by this chain's signer, if it has one.
+//!
+//! The re-added events don't share hashes with the other chain, so its heads
+//! named by earlier [`MERGE`] events count as shared too, standing in for
+//! everything up to them. Merging the same chain again is then up to date,
+//! and later merges only pick up the events it added since.
+//!
+//! Events of the same type recorded on both sides are taken to be competing
+//! updates to the same state, and are reported as a [`MergeConflict`]
+//! instead of being merged.
+
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Event type of the event closing a merge of diverged chains.
+pub const MERGE: &str = "merge";
+
+/// The payload of a [`MERGE`] event, encoded as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Merged {
+    /// The most recent event the two chains shared, if any.
+    pub ancestor: Option<Digest>,
+    /// Head of the other chain at the time of the merge.
+    pub theirs: Digest,
+}
+
+impl Merged {
+    /// Decodes the payload of a [`MERGE`] event.
+    pub fn decode(event: &Event) -> Result<Merged> {
+        if event.type_() != MERGE {
+            bail!("expected a `{MERGE}` event, found `{}`", event.type_());
+        }
+        Ok(serde_json::from_slice(&event.data())?)
+    }
+}
+
+/// What [`Chain::merge`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The other chain had nothing this one didn't already hold.
+    UpToDate,
+    /// This chain had nothing the other didn't already hold, so the other
+    /// chain's `added` new events were appended verbatim.
+    FastForward { added: usize },
+    /// Both chains had new events; the other chain's `added` events were
+    /// re-added after ours, followed by the [`MERGE`] event `head`.
+    Merged { head: Digest, added: usize },
+}
+
+/// Diverged chains which both hold events of the same type after their
+/// common ancestor.
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+    /// The most recent event the two chains shared, if any.
+    pub ancestor: Option<Digest>,
+    /// Conflicting events of this chain, oldest first.
+    pub ours: Vec<Digest>,
+    /// Conflicting events of the other chain, oldest first.
+    pub theirs: Vec<Digest>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.ancestor {
+            Some(ancestor) => write!(f, "chains diverged after {ancestor}")?,
+            None => write!(f, "chains share no history")?,
+        }
+        write!(
+            f,
+            " with {} conflicting events on our side and {} on theirs",
+            self.ours.len(),
+            self.theirs.len()
+        )
+    }
+}
+
+impl core::error::Error for MergeConflict {}
+
+impl Chain {
+    /// Merges the events of `other` into this chain.
+    ///
+    /// See the [module documentation](crate::chain::merge) for how chains are
+    /// merged. Diverged chains which conflict return a [`MergeConflict`],
+    /// leaving this chain unchanged. Both chains must use the same hasher
+    /// and have the same [id](Chain::id).
+    pub fn merge(&mut self, other: &Chain) -> Result<MergeOutcome> {
+        ensure!(
+            self.hasher().name() == other.hasher().name(),
+            "cannot merge a chain hashed with `{}` into one hashed with `{}`",
+            other.hasher().name(),
+            self.hasher().name()
+        );
+        if self.id() != other.id() {
+            let id = |id: Option<Digest>| id.map_or("no id".to_string(), |id| format!("id {id}"));
+            bail!(
+                "cannot merge a chain with {} into one with {}",
+                id(other.id()),
+                id(self.id())
+            );
+        }
+
+        // Heads of the other chain merged before, with the position of the
+        // merge event standing in for them.
+        let merges = self
+            .events_of_type(MERGE)
+            .filter_map(|node| {
+                let merged = Merged::decode(node.event()).ok()?;
+                Some((merged.theirs, self.position(node.hash())?))
+            })
+            .collect::<HashMap<_, _>>();
+        let theirs = other.events().collect::<Vec<_>>();
+        // The most recent event of theirs we hold, with its position in both
+        // chains, and whether we only hold it through a merge.
+        let shared = theirs.iter().enumerate().rev().find_map(|(i, node)| {
+            match self.position(node.hash()) {
+                Some(position) => Some((i, position, false)),
+                None => Some((i, *merges.get(&node.hash())?, true)),
+            }
+        });
+        let ancestor = shared.map(|(i, _, _)| theirs[i].hash());
+        let (ours, theirs, remerge) = match shared {
+            Some((i, position, remerge)) => (
+                self.events().skip(position + 1).collect::<Vec<_>>(),
+                &theirs[i + 1..],
+                remerge,
+            ),
+            None => (self.events().collect(), &theirs[..], false),
+        };
+
+        if theirs.is_empty() {
+            return Ok(MergeOutcome::UpToDate);
+        }
+        let mut events = theirs
+            .iter()
+            .map(|node| node.event().clone())
+            .collect::<Vec<_>>();
+        // Appending after a merge event gives their events new hashes, so
+        // that still needs a merge event of its own.
+        if ours.is_empty() && !remerge {
+            self.add_batch(events)?;
+            return Ok(MergeOutcome::FastForward {
+                added: theirs.len(),
+            });
+        }
+
+        let (our_types, their_types) = (types(&ours), types(theirs));
+        let conflict = MergeConflict {
+            ancestor,
+            ours: conflicting(&ours, &their_types),
+            theirs: conflicting(theirs, &our_types),
+        };
+        if !conflict.ours.is_empty() {
+            return Err(conflict.into());
+        }
+
+        let added = theirs.len();
+        let merged = Merged {
+            ancestor,
+            theirs: other.head().unwrap(),
+        };
+        events.push(Event::new(MERGE.to_string(), serde_json::to_vec(&merged)?));
+        let head = *self.add_batch(events)?.last().unwrap();
+        Ok(MergeOutcome::Merged { head, added })
+    }
+}
+
+fn types<'a>(nodes: &[&'a MetaEvent]) -> HashSet<&'a str> {
+    nodes.iter().map(|node| node.event().type_()).collect()
+}
+
+/// The events in `nodes` with any of the given types.
+fn conflicting(nodes: &[&MetaEvent], types: &HashSet<&str>) -> Vec<Digest> {
+    nodes
+        .iter()
+        .filter(|node| types.contains(node.event().type_()))
+        .map(|node| node.hash())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Severity;
+
+    fn event(ty: &str, data: u8) -> Event {
+        Event::new(ty.to_string(), vec![data])
+    }
+
+    #[test]
+    fn fast_forward_and_merge() -> Result<()> {
+        let mut a = Chain::new();
+        a.add(event("init", 0));
+        let mut b = a.clone();
+        b.add(event("counter", 1));
+        b.add(event("counter", 2));
+
+        assert_eq!(b.merge(&a)?, MergeOutcome::UpToDate);
+        assert_eq!(a.merge(&b)?, MergeOutcome::FastForward { added: 2 });
+        assert_eq!(a.head(), b.head());
+
+        let fork = b.head();
+        let mut c = b.clone();
+        b.add(event("counter", 3));
+        c.add(event("log", 4));
+        let MergeOutcome::Merged { head, added } = b.merge(&c)? else {
+            panic!("expected a merge");
+        };
+        assert_eq!(added, 1);
+        assert_eq!(b.len(), 6);
+        b.verify()?;
+        let merged = Merged::decode(b.get_event_by_hash(head).unwrap().event())?;
+        assert_eq!(merged.theirs, c.head().unwrap());
+        assert_eq!(merged.ancestor, fork);
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_events() -> Result<()> {
+        let mut a = Chain::new();
+        let ancestor = a.add(event("init", 0));
+        let mut b = a.clone();
+        let ours = a.add(event("counter", 1));
+        a.add(event("log", 2));
+        let theirs = b.add(event("counter", 3));
+
+        let len = a.len();
+        let err = a.merge(&b).unwrap_err();
+        let conflict = err.downcast_ref::<MergeConflict>().unwrap();
+        assert_eq!(conflict.ancestor, Some(ancestor));
+        assert_eq!(conflict.ours, [ours]);
+        assert_eq!(conflict.theirs, [theirs]);
+        assert_eq!(a.len(), len);
+        Ok(())
+    }
+
+    #[test]
+    fn merging_again_picks_up_from_the_last_merge() -> Result<()> {
+        let mut a = Chain::new();
+        a.add(event("init", 0));
+        let mut b = a.clone();
+        a.add(event("counter", 1));
+        b.add(event("log", 2).with_severity(Severity::Warn));
+
+        assert!(matches!(a.merge(&b)?, MergeOutcome::Merged { added: 1, .. }));
+        assert_eq!(a.merge(&b)?, MergeOutcome::UpToDate);
+        let copy = a.events_of_type("log").next().unwrap();
+        assert_eq!(copy.event().severity(), Some(Severity::Warn));
+
+        // Only their events since the last merge are merged, and checked
+        // against only ours since then.
+        b.add(event("log", 3));
+        a.add(event("counter", 4));
+        let MergeOutcome::Merged { head, added } = a.merge(&b)? else {
+            panic!("expected a merge");
+        };
+        assert_eq!(added, 1);
+        let merged = Merged::decode(a.get_event_by_hash(head).unwrap().event())?;
+        assert_eq!(merged.theirs, b.head().unwrap());
+
+        // Events appended after a merge event get new hashes, so they're
+        // merged rather than fast-forwarded.
+        b.add(event("log", 5));
+        assert!(matches!(a.merge(&b)?, MergeOutcome::Merged { added: 1, .. }));
+        assert_eq!(a.merge(&b)?, MergeOutcome::UpToDate);
+        assert_eq!(a.events_of_type("log").count(), 3);
+        a.verify()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merkle roots over a chain's history and proofs of inclusion against them.
+//!
+//! The event hashes of a chain, oldest first, are the leaves of a Merkle
+//! Mountain Range: a list of perfect binary trees ("peaks") whose sizes are
+//! the powers of two making up the number of events, largest first. The root
+//! commits to the number of events and every peak, so publishing it pins down
+//! the whole history, and an [`InclusionProof`] only needs the path to one
+//! peak plus the other peaks.
+//!
+//! Tree nodes are always hashed with SHA-256, whatever [`ChainHasher`] the
+//! chain uses, so proofs can be checked without knowing how the chain was
+//! configured. Leaves, inner nodes and the root are each prefixed with a
+//! distinct tag byte so one can't be passed off as another.
+//!
+//! [`ChainHasher`]: crate::chain::ChainHasher
+
+use crate::chain::{Chain, Digest};
+use crate::prelude::*;
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+const ROOT: u8 = 2;
+
+/// Proof that an event is part of the chain summarized by a root, see
+/// [`Chain::prove`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// Hash of the event being proven.
+    pub hash: Digest,
+    /// Position of the event in the chain.
+    pub index: u64,
+    /// Number of events in the chain the root was taken over.
+    pub len: u64,
+    /// Siblings on the way from the event up to its peak, lowest first.
+    pub path: Vec<Digest>,
+    /// Every other peak of the range, largest first.
+    pub peaks: Vec<Digest>,
+}
+
+impl InclusionProof {
+    /// Checks that this proof ties [`InclusionProof::hash`] to `root`.
+    pub fn verify(&self, root: Digest) -> bool {
+        let (Ok(index), Ok(len)) = (usize::try_from(self.index), usize::try_from(self.len)) else {
+            return false;
+        };
+        let ranges = peak_ranges(len).collect::<Vec<_>>();
+        let Some(pos) = ranges.iter().position(|r| r.contains(&index)) else {
+            return false;
+        };
+        let range = &ranges[pos];
+        if self.path.len() != range.len().trailing_zeros() as usize
+            || self.peaks.len() + 1 != ranges.len()
+        {
+            return false;
+        }
+
+        let mut offset = index - range.start;
+        let mut peak = leaf(self.hash);
+        for sibling in &self.path {
+            peak = if offset % 2 == 0 {
+                node(peak, *sibling)
+            } else {
+                node(*sibling, peak)
+            };
+            offset /= 2;
+        }
+        let mut peaks = self.peaks.clone();
+        peaks.insert(pos, peak);
+        bag(self.len, &peaks) == root
+    }
+}
+
+impl Chain {
+    /// Returns a digest committing to every event in this chain.
+    ///
+    /// Roots of the same chain taken at different lengths differ, and an
+    /// event's [`InclusionProof`] is only valid against the root of the
+    /// chain it was produced from.
+    pub fn root(&self) -> Digest {
+        let leaves = self.leaves();
+        let peaks = peak_ranges(leaves.len())
+            .map(|range| subtree(&leaves[range]))
+            .collect::<Vec<_>>();
+        bag(leaves.len() as u64, &peaks)
+    }
+
+    /// Returns a proof that the event `hash` is part of this chain, which
+    /// anyone holding [`Chain::root`] can check with
+    /// [`InclusionProof::verify`].
+    ///
+    /// Returns `None` if the chain has no such event.
+    pub fn prove(&self, hash: Digest) -> Option<InclusionProof> {
+        let index = self.events().position(|node| node.hash() == hash)?;
+        let leaves = self.leaves();
+        let mut path = Vec::new();
+        let mut peaks = Vec::new();
+        for range in peak_ranges(leaves.len()) {
+            if !range.contains(&index) {
+                peaks.push(subtree(&leaves[range]));
+                continue;
+            }
+            let mut offset = index - range.start;
+            let mut level = leaves[range].to_vec();
+            while level.len() > 1 {
+                path.push(level[offset ^ 1]);
+                level = level.chunks(2).map(|pair| node(pair[0], pair[1])).collect();
+                offset /= 2;
+            }
+        }
+        Some(InclusionProof {
+            hash,
+            index: index as u64,
+            len: leaves.len() as u64,
+            path,
+            peaks,
+        })
+    }
+
+    fn leaves(&self) -> Vec<Digest> {
+        self.events().map(|node| leaf(node.hash())).collect()
+    }
+}
+
+/// The ranges of leaves covered by each peak for a chain of `len` events.
+fn peak_ranges(len: usize) -> impl Iterator<Item = Range<usize>> {
+    let mut start = 0;
+    (0..usize::BITS).rev().filter_map(move |bit| {
+        let size = 1 << bit;
+        if len & size == 0 {
+            return None;
+        }
+        start += size;
+        Some(start - size..start)
+    })
+}
+
+fn subtree(leaves: &[Digest]) -> Digest {
+    debug_assert!(leaves.len().is_power_of_two());
+    match leaves {
+        [leaf] => *leaf,
+        _ => {
+            let (left, right) = leaves.split_at(leaves.len() / 2);
+            node(subtree(left), subtree(right))
+        }
+    }
+}
+
+fn leaf(hash: Digest) -> Digest {
+    sha256(&[&[LEAF], hash.as_bytes()])
+}
+
+fn node(left: Digest, right: Digest) -> Digest {
+    sha256(&[&[NODE], left.as_bytes(), right.as_bytes()])
+}
+
+fn bag(len: u64, peaks: &[Digest]) -> Digest {
+    let mut parts: Vec<&[u8]> = vec![&[ROOT]];
+    let len = len.to_le_bytes();
+    parts.push(&len);
+    parts.extend(peaks.iter().map(|peak| &peak.as_bytes()[..]));
+    sha256(&parts)
+}
+
+fn sha256(parts: &[&[u8]]) -> Digest {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    Digest(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn prove_every_event() {
+        let mut chain = Chain::new();
+        let empty = chain.root();
+        let mut hashes = Vec::new();
+        let mut roots = vec![empty];
+        for i in 0..13u8 {
+            hashes.push(chain.add(Event::new("event".to_string(), vec![i])));
+            roots.push(chain.root());
+            for hash in &hashes {
+                let proof = chain.prove(*hash).unwrap();
+                assert!(proof.verify(chain.root()));
+                assert!(!proof.verify(roots[roots.len() - 2]));
+            }
+        }
+        roots.sort();
+        roots.dedup();
+        assert_eq!(roots.len(), 14);
+        assert!(chain.prove(Digest::default()).is_none());
+    }
+
+    #[test]
+    fn tampered_proofs_fail() {
+        let mut chain = Chain::new();
+        let hashes = (0..6u8)
+            .map(|i| chain.add(Event::new("event".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+        let root = chain.root();
+        let proof = chain.prove(hashes[2]).unwrap();
+
+        let mut other = proof.clone();
+        other.hash = hashes[3];
+        assert!(!other.verify(root));
+        let mut other = proof.clone();
+        other.index = 3;
+        assert!(!other.verify(root));
+        let mut other = proof.clone();
+        other.len = 7;
+        assert!(!other.verify(root));
+        let mut other = proof;
+        other.path.pop();
+        assert!(!other.verify(root));
+    }
+}
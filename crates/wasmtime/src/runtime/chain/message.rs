@@ -0,0 +1,232 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Calls from one component instance to another, recorded as messages.
+//!
+//! With [`Config::chain_per_instance`](crate::Config::chain_per_instance)
+//! each instance is an actor with a chain of its own. When a host function
+//! imported by one instance calls an export of another, as a host composing
+//! instances does, the caller's chain gets a [`MESSAGE_SENT`] event and the
+//! callee's chain a [`MESSAGE_RECEIVED`] event, recorded before the callee
+//! runs. The receipt holds an [`EventRef`] to the sent event, and the sent
+//! event the id of the receiving chain, so either side can be followed to
+//! the other, see [`Chain::receipt`].
+//!
+//! Components composed into one component call each other through adapters
+//! compiled into the component, without the host seeing the calls, so these
+//! run as one instance and aren't recorded as messages.
+
+use crate::chain::record::decode;
+use crate::chain::{Chain, Digest, Event, EventRef, MetaEvent};
+use crate::prelude::*;
+use crate::store::StoreOpaque;
+use serde::{Deserialize, Serialize};
+
+/// Event type of an instance calling another's export.
+pub const MESSAGE_SENT: &str = "message-sent";
+
+/// Event type of an instance's export being called by another instance.
+pub const MESSAGE_RECEIVED: &str = "message-received";
+
+/// Payload of a [`MESSAGE_SENT`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageSent {
+    /// Name of the export called, as in
+    /// [`FunctionCall::name`](crate::chain::FunctionCall::name).
+    pub name: String,
+    /// The [id](Chain::id) of the chain of the instance called.
+    pub receiver: Digest,
+    /// See [`FunctionCall::call_parent`](crate::chain::FunctionCall::call_parent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl MessageSent {
+    /// Decodes the payload of a [`MESSAGE_SENT`] event.
+    pub fn decode(event: &Event) -> Result<MessageSent> {
+        decode(event, MESSAGE_SENT)
+    }
+}
+
+/// Payload of a [`MESSAGE_RECEIVED`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageReceived {
+    /// Name of the export called.
+    pub name: String,
+    /// The [`MESSAGE_SENT`] event in the caller's chain.
+    pub sent: EventRef,
+}
+
+impl MessageReceived {
+    /// Decodes the payload of a [`MESSAGE_RECEIVED`] event.
+    pub fn decode(event: &Event) -> Result<MessageReceived> {
+        decode(event, MESSAGE_RECEIVED)
+    }
+}
+
+impl Chain {
+    /// Looks up the [`MESSAGE_RECEIVED`] event for the message `sent` in this
+    /// chain or, recursively, in the children it spawned, such as in the
+    /// chain of a store recording each instance into a child.
+    pub fn receipt(&self, sent: &EventRef) -> Option<&MetaEvent> {
+        self.events_of_type(MESSAGE_RECEIVED)
+            .find(|node| {
+                MessageReceived::decode(node.event()).is_ok_and(|received| received.sent == *sent)
+            })
+            .or_else(|| {
+                self.children
+                    .iter()
+                    .find_map(|(_, child)| child.receipt(sent))
+            })
+    }
+}
+
+/// Records the instance `sender` calling the export `name` of the instance
+/// `receiver`, in both instances' chains.
+pub(crate) fn record(
+    store: &mut StoreOpaque,
+    sender: usize,
+    receiver: usize,
+    name: &str,
+) -> Result<()> {
+    let call_parent = store.call_parent();
+    let receiver_id = store
+        .instance_chain_mut(receiver)?
+        .id()
+        .expect("spawned chains have an id");
+    let sent = MessageSent {
+        name: name.to_string(),
+        receiver: receiver_id,
+        call_parent,
+    };
+    let chain = store.instance_chain_mut(sender)?;
+    let hash = chain.try_add(Event::new(
+        MESSAGE_SENT.to_string(),
+        serde_json::to_vec(&sent)?,
+    ))?;
+    let received = MessageReceived {
+        name: name.to_string(),
+        sent: chain.event_ref(hash).expect("spawned chains have an id"),
+    };
+    store.instance_chain_mut(receiver)?.try_add(Event::new(
+        MESSAGE_RECEIVED.to_string(),
+        serde_json::to_vec(&received)?,
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{FunctionCall, ImportCall};
+    use crate::component::{Component, Func, Linker, Val};
+    use crate::{Config, Engine, Store};
+
+    #[test]
+    fn records_calls_between_instances() -> Result<()> {
+        let caller = r#"
+            (component
+                (import "peer" (func $peer (param "x" u32) (result u32)))
+                (core func $peer (canon lower (func $peer)))
+                (core module $m
+                    (import "" "peer" (func $peer (param i32) (result i32)))
+                    (func (export "run") (param i32) (result i32)
+                        (call $peer (local.get 0))))
+                (core instance $i (instantiate $m
+                    (with "" (instance (export "peer" (func $peer))))))
+                (func (export "run") (param "x" u32) (result u32)
+                    (canon lift (core func $i "run")))
+            )
+        "#;
+        let callee = r#"
+            (component
+                (core module $m
+                    (func (export "id") (param i32) (result i32) local.get 0))
+                (core instance $i (instantiate $m))
+                (func (export "id") (param "x" u32) (result u32)
+                    (canon lift (core func $i "id")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).chain_per_instance(true);
+        let engine = Engine::new(&config)?;
+        let mut linker = Linker::<Option<Func>>::new(&engine);
+        // Forwards the caller's import to the callee's export.
+        linker.root().func_wrap("peer", |mut cx, (x,): (u32,)| {
+            let Some(id) = *cx.data() else {
+                bail!("no instance to forward to")
+            };
+            let mut results = [Val::U32(0)];
+            id.call(&mut cx, &[Val::U32(x)], &mut results)?;
+            id.post_return(&mut cx)?;
+            let Val::U32(y) = results[0] else {
+                bail!("expected u32")
+            };
+            Ok((y,))
+        })?;
+        let mut store = Store::new(&engine, None);
+        let callee = linker.instantiate(&mut store, &Component::new(&engine, callee)?)?;
+        let caller = linker.instantiate(&mut store, &Component::new(&engine, caller)?)?;
+        *store.data_mut() = callee.get_func(&mut store, "id");
+        let run = caller.get_func(&mut store, "run").unwrap();
+        let mut results = [Val::U32(0)];
+        run.call(&mut store, &[Val::U32(7)], &mut results)?;
+        run.post_return(&mut store)?;
+
+        let chain = store.chain();
+        let children = chain.children().map(|(_, c)| c).collect::<Vec<_>>();
+        let [caller, callee] = children[..] else {
+            panic!("expected two instance chains")
+        };
+        let types = |chain: &Chain| {
+            chain
+                .events()
+                .map(|e| e.event().type_().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            types(caller)[1..],
+            [
+                "import-call",
+                MESSAGE_SENT,
+                "import-return",
+                "function-call"
+            ]
+        );
+        assert_eq!(types(callee)[1..], [MESSAGE_RECEIVED, "function-call"]);
+
+        let sent_node = caller.events_of_type(MESSAGE_SENT).next().unwrap();
+        let sent = MessageSent::decode(sent_node.event())?;
+        assert_eq!(sent.name, "id");
+        assert_eq!(Some(sent.receiver), callee.id());
+        let import = caller.events_of_type("import-call").next().unwrap();
+        assert_eq!(ImportCall::decode(import.event())?.name, "peer");
+        assert_eq!(sent.call_parent, Some(import.hash()));
+
+        let received_node = callee.events_of_type(MESSAGE_RECEIVED).next().unwrap();
+        let received = MessageReceived::decode(received_node.event())?;
+        assert_eq!(received.sent, caller.event_ref(sent_node.hash()).unwrap());
+        assert_eq!(
+            chain.resolve(&received.sent).unwrap().hash(),
+            sent_node.hash()
+        );
+        assert_eq!(
+            chain.receipt(&received.sent).unwrap().hash(),
+            received_node.hash()
+        );
+        let call = callee.events_of_type("function-call").next().unwrap();
+        assert_eq!(FunctionCall::decode(call.event())?.name, "id");
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counting what chains do, for monitoring.
+//!
+//! Once a [`MetricsRecorder`] is installed with [`set_recorder`], every chain
+//! in the process reports to it when an event is appended or fails to be
+//! persisted, when [`Chain::verify`](crate::chain::Chain::verify) or
+//! [`Chain::verify_signatures`](crate::chain::Chain) finds a problem, and
+//! when a [replay](crate::chain::replay) diverges from its recording.
+//!
+//! [`PrometheusMetrics`] is a recorder keeping counters which it renders in
+//! the Prometheus text exposition format, for serving from a `/metrics`
+//! endpoint. Its `wasmtime_chain_last_append_timestamp_seconds` gauge is
+//! meant for alerting on recording stalls, such as with
+//! `time() - wasmtime_chain_last_append_timestamp_seconds > 60`.
+
+use crate::chain::{IntegrityError, IntegrityErrorKind, ReplayDivergence};
+use crate::prelude::*;
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Receives what chains do, see the [module documentation](self).
+///
+/// Every method does nothing by default. They're called while the chain
+/// involved is being modified or checked, so they should return quickly.
+pub trait MetricsRecorder: Send + Sync {
+    /// An event of type `event_type` with a payload of `bytes` bytes was
+    /// appended to a chain and handed to its store.
+    fn event_appended(&self, event_type: &str, bytes: usize) {
+        let _ = (event_type, bytes);
+    }
+
+    /// A chain's store failed to persist an event, which was left out of
+    /// the chain.
+    fn append_failed(&self) {}
+
+    /// Checking a chain's integrity or signatures failed.
+    fn verification_failed(&self, error: &IntegrityError) {
+        let _ = error;
+    }
+
+    /// A replay produced different events than were recorded.
+    fn replay_diverged(&self, divergence: &ReplayDivergence) {
+        let _ = divergence;
+    }
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+
+/// Makes `recorder` receive the activity of every chain in the process,
+/// replacing any recorder installed before.
+pub fn set_recorder(recorder: impl MetricsRecorder + 'static) {
+    *RECORDER.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(recorder));
+    INSTALLED.store(true, Ordering::Release);
+}
+
+/// Removes the recorder installed with [`set_recorder`], if any.
+pub fn remove_recorder() {
+    INSTALLED.store(false, Ordering::Release);
+    *RECORDER.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Calls `f` with the installed recorder, if there is one.
+pub(crate) fn report(f: impl FnOnce(&dyn MetricsRecorder)) {
+    if !INSTALLED.load(Ordering::Acquire) {
+        return;
+    }
+    let recorder = RECORDER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    if let Some(recorder) = recorder {
+        f(&*recorder);
+    }
+}
+
+/// A [`MetricsRecorder`] rendering what it receives in the Prometheus text
+/// exposition format, see the [module documentation](self).
+///
+/// Clones share their counters, so one clone can be installed with
+/// [`set_recorder`] and another rendered.
+#[derive(Clone, Default)]
+pub struct PrometheusMetrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    appended: BTreeMap<String, u64>,
+    bytes: u64,
+    append_failures: u64,
+    verification_failures: BTreeMap<&'static str, u64>,
+    replay_divergences: u64,
+    /// Seconds since the Unix epoch of the last append.
+    last_append: Option<f64>,
+}
+
+impl PrometheusMetrics {
+    /// Creates a recorder with every counter at zero.
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics::default()
+    }
+
+    fn counters(&self) -> MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.counters();
+        let mut out = String::new();
+        header(
+            &mut out,
+            "wasmtime_chain_events_appended_total",
+            "counter",
+            "Events appended to chains, by event type.",
+        );
+        for (type_, count) in &counters.appended {
+            let _ = writeln!(
+                out,
+                "wasmtime_chain_events_appended_total{{type=\"{}\"}} {count}",
+                escape(type_)
+            );
+        }
+        let mut sample = |name: &str, type_: &str, help: &str, value: &dyn fmt::Display| {
+            header(&mut out, name, type_, help);
+            let _ = writeln!(out, "{name} {value}");
+        };
+        sample(
+            "wasmtime_chain_payload_bytes_total",
+            "counter",
+            "Payload bytes of the events appended to chains.",
+            &counters.bytes,
+        );
+        sample(
+            "wasmtime_chain_append_failures_total",
+            "counter",
+            "Events chain stores failed to persist.",
+            &counters.append_failures,
+        );
+        if let Some(last) = counters.last_append {
+            sample(
+                "wasmtime_chain_last_append_timestamp_seconds",
+                "gauge",
+                "When an event was last appended to a chain.",
+                &last,
+            );
+        }
+        sample(
+            "wasmtime_chain_replay_divergences_total",
+            "counter",
+            "Replays which diverged from their recording.",
+            &counters.replay_divergences,
+        );
+        header(
+            &mut out,
+            "wasmtime_chain_verification_failures_total",
+            "counter",
+            "Chain verifications which found a problem, by kind of problem.",
+        );
+        for (kind, count) in &counters.verification_failures {
+            let _ = writeln!(
+                out,
+                "wasmtime_chain_verification_failures_total{{kind=\"{kind}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+impl MetricsRecorder for PrometheusMetrics {
+    fn event_appended(&self, event_type: &str, bytes: usize) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut counters = self.counters();
+        match counters.appended.get_mut(event_type) {
+            Some(count) => *count += 1,
+            None => {
+                counters.appended.insert(event_type.to_string(), 1);
+            }
+        }
+        counters.bytes += bytes as u64;
+        counters.last_append = Some(now);
+    }
+
+    fn append_failed(&self) {
+        self.counters().append_failures += 1;
+    }
+
+    fn verification_failed(&self, error: &IntegrityError) {
+        let kind = match error.kind {
+            IntegrityErrorKind::BrokenLink => "broken_link",
+            IntegrityErrorKind::HashMismatch => "hash_mismatch",
+            IntegrityErrorKind::Unsigned => "unsigned",
+            IntegrityErrorKind::BadSignature => "bad_signature",
+        };
+        *self
+            .counters()
+            .verification_failures
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    fn replay_diverged(&self, _divergence: &ReplayDivergence) {
+        self.counters().replay_divergences += 1;
+    }
+}
+
+/// Writes the `HELP` and `TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, type_: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {type_}");
+}
+
+/// Escapes a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Chain, Event};
+
+    #[test]
+    fn renders_counters() {
+        let metrics = PrometheusMetrics::new();
+        metrics.event_appended("function-call", 10);
+        metrics.event_appended("function-call", 5);
+        metrics.event_appended("say \"hi\"", 1);
+        metrics.verification_failed(&IntegrityError {
+            index: 3,
+            kind: IntegrityErrorKind::BrokenLink,
+            expected: None,
+            found: None,
+        });
+        let text = metrics.render();
+        assert!(text.contains("wasmtime_chain_events_appended_total{type=\"function-call\"} 2\n"));
+        assert!(text.contains("wasmtime_chain_events_appended_total{type=\"say \\\"hi\\\"\"} 1\n"));
+        assert!(text.contains("wasmtime_chain_payload_bytes_total 16\n"));
+        assert!(text.contains("wasmtime_chain_append_failures_total 0\n"));
+        assert!(
+            text.contains("wasmtime_chain_verification_failures_total{kind=\"broken_link\"} 1\n")
+        );
+        assert!(text.contains("# TYPE wasmtime_chain_last_append_timestamp_seconds gauge\n"));
+    }
+
+    #[test]
+    fn chains_report_to_the_installed_recorder() {
+        let metrics = PrometheusMetrics::new();
+        set_recorder(metrics.clone());
+        let mut chain = Chain::new();
+        chain.add(Event::new("metrics-test".to_string(), vec![0; 7]));
+        chain.add(Event::new("metrics-test".to_string(), vec![]));
+        remove_recorder();
+        chain.add(Event::new("metrics-test".to_string(), vec![]));
+
+        let counters = metrics.counters();
+        assert_eq!(counters.appended.get("metrics-test"), Some(&2));
+    }
+}
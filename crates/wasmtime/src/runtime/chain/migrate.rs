@@ -0,0 +1,256 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Format versions of persisted chains, and upgrading older ones.
+//!
+//! Every form a chain is persisted in records the version of the format its
+//! events are encoded in, [`FORMAT_VERSION`] when written by this build:
+//!
+//! * chain files end their [magic](crate::chain::file::MAGIC) with it,
+//! * the binary form written by [`Chain::to_bytes`] starts with a header
+//!   holding the version of its own layout, which implies it,
+//! * JSON and CBOR hold it in a `format` field next to the hasher,
+//! * SQLite databases hold it as their `user_version`, and
+//! * each event sealed by an
+//!   [`EncryptedChainStore`](crate::chain::EncryptedChainStore) starts with
+//!   it.
+//!
+//! Versions are bumped whenever [`MetaEvent`] gains a field, see
+//! [`FORMAT_VERSION`]. Events in older formats are upgraded to the current
+//! one as they're loaded, and a [`FileChainStore`](crate::chain::FileChainStore)
+//! or SQLite database opened for writing is rewritten in the current format,
+//! so every event appended to it is. Data in a format newer than this build
+//! knows is refused rather than misread.
+//!
+//! Forms written before they recorded a version are still read: JSON and
+//! CBOR without a `format` field decode as they did, and the binary form and
+//! sealed events without a header are taken to be from before their headers
+//! were added.
+
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::{Chain, Compression, Digest, Event, MemoryChainStore, MetaEvent, Severity};
+use crate::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// The version of the format this build writes chains in.
+///
+/// | Version | Added to events                          |
+/// |---------|------------------------------------------|
+/// | 1       |                                          |
+/// | 2       | signatures                               |
+/// | 3       | payload compression                      |
+/// | 4       | [severities](Event::severity)            |
+/// | 5       | [schema versions](Event::schema_version) |
+pub const FORMAT_VERSION: u8 = 5;
+
+/// Fails if data in format `version` is newer than this build can read.
+pub(crate) fn check_version(version: u64) -> Result<()> {
+    ensure!(
+        version <= u64::from(FORMAT_VERSION),
+        "chain format version {version} is newer than the latest supported, {FORMAT_VERSION}"
+    );
+    Ok(())
+}
+
+/// An event as encoded in formats 1 and 2, before payloads could be
+/// compressed.
+#[derive(Serialize, Deserialize)]
+struct LegacyEvent {
+    type_: String,
+    parent: Option<Digest>,
+    data: Vec<u8>,
+}
+
+impl LegacyEvent {
+    fn into_event(self) -> Event {
+        let mut event = Event::new(self.type_, self.data);
+        event.set_parent(self.parent);
+        event
+    }
+}
+
+/// An event as encoded in format 3, before events could have a severity.
+#[derive(Serialize, Deserialize)]
+struct UnratedEvent {
+    type_: String,
+    parent: Option<Digest>,
+    data: Vec<u8>,
+    compression: Compression,
+}
+
+impl UnratedEvent {
+    fn into_event(self) -> Event {
+        let mut event = Event::new(self.type_, self.data);
+        event.set_parent(self.parent);
+        event.compression = self.compression;
+        event
+    }
+}
+
+/// An event as encoded in format 4, before events could be stamped with a
+/// schema version.
+#[derive(Serialize, Deserialize)]
+struct UnstampedEvent {
+    type_: String,
+    parent: Option<Digest>,
+    data: Vec<u8>,
+    compression: Compression,
+    severity: Option<Severity>,
+}
+
+impl UnstampedEvent {
+    fn into_event(self) -> Event {
+        let mut event = Event::new(self.type_, self.data).with_severity(self.severity);
+        event.set_parent(self.parent);
+        event.compression = self.compression;
+        event
+    }
+}
+
+/// Decodes the postcard encoding of an event in format `version`, as framed
+/// in chain files and sealed by encrypted stores, upgrading it to the current
+/// format.
+pub(crate) fn decode_event(version: u8, bytes: &[u8]) -> Result<MetaEvent> {
+    Ok(match version {
+        1 => {
+            let (hash, event): (Digest, LegacyEvent) = decode_exact(bytes)?;
+            MetaEvent::new(hash, event.into_event())
+        }
+        2 => {
+            let (hash, event, signature): (Digest, LegacyEvent, Option<Vec<u8>>) =
+                decode_exact(bytes)?;
+            MetaEvent::new(hash, event.into_event()).with_signature(signature)
+        }
+        3 => {
+            let (hash, event, signature): (Digest, UnratedEvent, Option<Vec<u8>>) =
+                decode_exact(bytes)?;
+            MetaEvent::new(hash, event.into_event()).with_signature(signature)
+        }
+        4 => {
+            let (hash, event, signature): (Digest, UnstampedEvent, Option<Vec<u8>>) =
+                decode_exact(bytes)?;
+            MetaEvent::new(hash, event.into_event()).with_signature(signature)
+        }
+        FORMAT_VERSION => decode_exact(bytes)?,
+        _ => bail!("unknown chain format version {version}"),
+    })
+}
+
+/// Decodes all of `bytes`, so that an event in one format isn't taken for
+/// the start of one in another.
+fn decode_exact<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let (value, rest) = postcard::take_from_bytes(bytes)?;
+    ensure!(rest.is_empty(), "{} trailing bytes", rest.len());
+    Ok(value)
+}
+
+/// Decodes the binary form [`Chain::to_bytes`] wrote before it interned
+/// types and payloads, which was in format 3.
+pub(crate) fn decode_uninterned(bytes: &[u8]) -> Result<Chain> {
+    let (hasher, events): (String, Vec<(Digest, UnratedEvent, Option<Vec<u8>>)>) =
+        decode_exact(bytes)?;
+    let hasher = match hasher_by_name(&hasher) {
+        Some(hasher) => hasher,
+        None => bail!("chain uses unknown hasher `{hasher}`"),
+    };
+    let events = events
+        .into_iter()
+        .map(|(hash, event, signature)| {
+            MetaEvent::new(hash, event.into_event()).with_signature(signature)
+        })
+        .collect::<Vec<_>>();
+    Ok(Chain::from_store_unverified(
+        hasher,
+        Box::new(MemoryChainStore::from(events)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::file::{MAGIC, MAGIC_V3};
+
+    fn chain() -> Chain {
+        let mut chain = Chain::new();
+        chain.add(Event::new("a".to_string(), vec![1]));
+        chain.add(Event::new("b".to_string(), vec![2; 64]));
+        chain
+    }
+
+    #[test]
+    fn upgrades_old_chain_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let chain = chain();
+
+        // Write the chain the way format 3 files were.
+        let mut contents = MAGIC_V3.to_vec();
+        let mut frame = |payload: &[u8]| {
+            contents.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+            contents.extend_from_slice(payload);
+        };
+        frame(chain.hasher().name().as_bytes());
+        for node in chain.events() {
+            let e = node.event();
+            let event = (e.type_(), e.parent(), e.stored_data(), e.compression());
+            frame(&postcard::to_allocvec(&(
+                node.hash(),
+                event,
+                node.signature(),
+            ))?);
+        }
+        std::fs::write(&path, contents)?;
+
+        let mut opened = Chain::open(&path)?;
+        assert_eq!(opened.head(), chain.head());
+        assert_eq!(&std::fs::read(&path)?[..MAGIC.len()], MAGIC);
+        let head = opened.add(Event::new("c".to_string(), vec![]).with_schema_version(1));
+        drop(opened);
+        assert_eq!(Chain::open(&path)?.head(), Some(head));
+
+        let mut contents = std::fs::read(&path)?;
+        contents[MAGIC.len() - 1] = FORMAT_VERSION + 1;
+        std::fs::write(&path, contents)?;
+        assert!(Chain::open(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_unversioned_forms() -> Result<()> {
+        let chain = chain();
+        let json = serde_json::to_value(&chain)?;
+        assert_eq!(json["format"], FORMAT_VERSION);
+        let mut old = json.clone();
+        old.as_object_mut().unwrap().remove("format");
+        assert_eq!(serde_json::from_value::<Chain>(old)?.head(), chain.head());
+        let mut new = json;
+        new["format"] = (FORMAT_VERSION + 1).into();
+        assert!(serde_json::from_value::<Chain>(new).is_err());
+
+        let events = chain
+            .events()
+            .map(|node| {
+                let e = node.event();
+                let event = (e.type_(), e.parent(), e.stored_data(), e.compression());
+                (node.hash(), event, node.signature())
+            })
+            .collect::<Vec<_>>();
+        let bytes = postcard::to_allocvec(&(chain.hasher().name(), events))?;
+        let decoded = Chain::from_bytes(&bytes)?;
+        assert_eq!(decoded.head(), chain.head());
+        decoded.verify()?;
+        Ok(())
+    }
+}
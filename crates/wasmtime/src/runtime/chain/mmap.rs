@@ -0,0 +1,272 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading chain files without loading their payloads.
+//!
+//! [`FileChainStore`](crate::chain::FileChainStore) reads a whole chain file
+//! into memory when it's opened. [`MappedChainStore`] maps the file instead
+//! and keeps only each event's hash, type and parent link in memory; the
+//! payloads stay in the file, and [`Event::data`](crate::chain::Event::data)
+//! borrows uncompressed payloads straight from the mapping. Inspecting a
+//! chain file much larger than memory then only needs the pages actually
+//! read, which the OS can drop again as it likes.
+//!
+//! A mapped store is read-only, since appending would have to remap the
+//! file. Open the file with [`Chain::open`] to add events to it.
+
+use crate::chain::chain::EventData;
+use crate::chain::file::{strip_magic, Frames};
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::{Chain, ChainStore, Compression, Digest, Event, MetaEvent, Severity};
+use crate::prelude::*;
+use memmap2::Mmap;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A framed event, with the payload borrowed from the frame.
+#[derive(Deserialize)]
+struct FramedEvent<'a> {
+    type_: String,
+    parent: Option<Digest>,
+    data: &'a [u8],
+    compression: Compression,
+    severity: Option<Severity>,
+    schema_version: Option<u32>,
+}
+
+/// A framed event from before events could be stamped with a schema version.
+#[derive(Deserialize)]
+struct FramedUnstampedEvent<'a> {
+    type_: String,
+    parent: Option<Digest>,
+    data: &'a [u8],
+    compression: Compression,
+    severity: Option<Severity>,
+}
+
+/// A framed event from before events could have a severity.
+#[derive(Deserialize)]
+struct FramedUnratedEvent<'a> {
+    type_: String,
+    parent: Option<Digest>,
+    data: &'a [u8],
+    compression: Compression,
+}
+
+/// A framed event from before payloads could be compressed.
+#[derive(Deserialize)]
+struct FramedLegacyEvent<'a> {
+    type_: String,
+    parent: Option<Digest>,
+    data: &'a [u8],
+}
+
+impl<'a> From<FramedUnstampedEvent<'a>> for FramedEvent<'a> {
+    fn from(e: FramedUnstampedEvent<'a>) -> FramedEvent<'a> {
+        FramedEvent {
+            type_: e.type_,
+            parent: e.parent,
+            data: e.data,
+            compression: e.compression,
+            severity: e.severity,
+            schema_version: None,
+        }
+    }
+}
+
+impl<'a> From<FramedUnratedEvent<'a>> for FramedEvent<'a> {
+    fn from(e: FramedUnratedEvent<'a>) -> FramedEvent<'a> {
+        FramedEvent {
+            type_: e.type_,
+            parent: e.parent,
+            data: e.data,
+            compression: e.compression,
+            severity: None,
+            schema_version: None,
+        }
+    }
+}
+
+impl<'a> From<FramedLegacyEvent<'a>> for FramedEvent<'a> {
+    fn from(e: FramedLegacyEvent<'a>) -> FramedEvent<'a> {
+        FramedEvent {
+            type_: e.type_,
+            parent: e.parent,
+            data: e.data,
+            compression: Compression::None,
+            severity: None,
+            schema_version: None,
+        }
+    }
+}
+
+/// A read-only [`ChainStore`] over a memory-mapped chain file, see the
+/// [module documentation](crate::chain::mmap).
+#[derive(Debug)]
+pub struct MappedChainStore {
+    path: PathBuf,
+    hasher: String,
+    events: Vec<MetaEvent>,
+}
+
+impl MappedChainStore {
+    /// Maps the chain file at `path`, in any format
+    /// [`FileChainStore`](crate::chain::FileChainStore) reads.
+    ///
+    /// A frame cut short by a crash is ignored, but unlike with
+    /// `FileChainStore` the file isn't truncated.
+    pub fn open(path: impl AsRef<Path>) -> Result<MappedChainStore> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("failed to open chain file `{}`", path.display()))?;
+        // SAFETY: the mapping is only read, and chain files are only ever
+        // appended to or replaced by renaming, so the bytes mapped don't
+        // change underneath it.
+        let map = Arc::new(
+            unsafe { Mmap::map(&file) }
+                .with_context(|| format!("failed to map chain file `{}`", path.display()))?,
+        );
+
+        let context = || format!("invalid chain file `{}`", path.display());
+        let (version, rest) = strip_magic(&map).with_context(context)?;
+        let mut frames = Frames::new(rest);
+        let hasher = match frames.next() {
+            Some(name) => String::from_utf8(name.to_vec()).with_context(context)?,
+            None => bail!("{}: missing hasher name", context()),
+        };
+
+        let mut events = Vec::new();
+        for frame in frames {
+            let index = events.len();
+            let decoded = match version {
+                1 => postcard::from_bytes(frame)
+                    .map(|(hash, e): (Digest, FramedLegacyEvent)| (hash, e.into(), None)),
+                2 => postcard::from_bytes(frame).map(
+                    |(hash, e, signature): (Digest, FramedLegacyEvent, Option<Vec<u8>>)| {
+                        (hash, e.into(), signature)
+                    },
+                ),
+                3 => postcard::from_bytes(frame).map(
+                    |(hash, e, signature): (Digest, FramedUnratedEvent, Option<Vec<u8>>)| {
+                        (hash, e.into(), signature)
+                    },
+                ),
+                4 => postcard::from_bytes(frame).map(
+                    |(hash, e, signature): (Digest, FramedUnstampedEvent, Option<Vec<u8>>)| {
+                        (hash, e.into(), signature)
+                    },
+                ),
+                _ => postcard::from_bytes(frame),
+            };
+            let (hash, e, signature): (Digest, FramedEvent, Option<Vec<u8>>) =
+                decoded.with_context(|| format!("{}: event {index} is corrupt", context()))?;
+
+            // `data` was borrowed from `frame`, so from the mapping.
+            let start = e.data.as_ptr() as usize - map.as_ptr() as usize;
+            let mut event = Event::new(e.type_, Vec::new());
+            event.set_parent(e.parent);
+            event.data = EventData::Mapped(map.clone(), start..start + e.data.len());
+            event.compression = e.compression;
+            event.severity = e.severity;
+            event.schema_version = e.schema_version;
+            events.push(MetaEvent::new(hash, event).with_signature(signature));
+        }
+
+        Ok(MappedChainStore {
+            path: path.to_path_buf(),
+            hasher,
+            events,
+        })
+    }
+
+    /// Name of the hasher the chain in this file is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.hasher
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl ChainStore for MappedChainStore {
+    fn append(&mut self, _event: MetaEvent) -> Result<()> {
+        bail!("chain file `{}` is mapped read-only", self.path.display())
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        Box::new(self.events.get(index..).unwrap_or_default().iter())
+    }
+}
+
+impl Chain {
+    /// Opens the chain file at `path` read-only without loading its
+    /// payloads, see the [`mmap` module](crate::chain::mmap).
+    ///
+    /// Like [`Chain::open`], this verifies the chain, which reads every
+    /// payload once.
+    pub fn open_mapped(path: impl AsRef<Path>) -> Result<Chain> {
+        let path = path.as_ref();
+        let store = MappedChainStore::open(path)?;
+        let hasher = match hasher_by_name(store.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!(
+                "chain file `{}` uses unknown hasher `{}`",
+                path.display(),
+                store.hasher()
+            ),
+        };
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain file `{}` failed verification", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn borrows_payloads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+        let mut chain = Chain::open(&path)?;
+        chain.add(Event::new("a".to_string(), vec![1, 2, 3]));
+        let head = chain.add(Event::new("b".to_string(), vec![4; 1000]));
+        drop(chain);
+
+        let mut mapped = Chain::open_mapped(&path)?;
+        assert_eq!(mapped.len(), 2);
+        let node = mapped.get_event_by_hash(head).unwrap();
+        assert_eq!(node.event().type_(), "b");
+        assert!(matches!(node.event().data(), Cow::Borrowed(data) if data == [4; 1000]));
+        assert_eq!(
+            mapped.events().next().unwrap().event().data(),
+            [1, 2, 3].as_slice()
+        );
+        assert!(mapped.try_add(Event::new("c".to_string(), vec![])).is_err());
+        assert_eq!(mapped.head(), Some(head));
+        Ok(())
+    }
+}
@@ -13,8 +13,210 @@
 // limitations under the License.
 
 #![allow(missing_docs)]
+pub mod bloom;
+pub use bloom::BloomFilter;
+
+#[cfg(feature = "async")]
+pub mod buffered;
+#[cfg(feature = "async")]
+pub use buffered::{AsyncChainStore, BufferedChainStore, FlushPolicy};
+
+pub mod causal;
+pub use causal::{CausalEvent, CausalWriter, VectorClock};
+
+#[cfg(feature = "chain-cbor")]
+pub mod cbor;
+
 pub mod chain;
 pub use chain::{Chain, Event, MetaEvent};
 
+pub mod compact;
+pub use compact::{Checkpoint, Compaction};
+
+pub mod compress;
+pub use compress::Compression;
+
+pub mod core_val;
+pub use core_val::{CoreRefRegistry, SerializableCoreVal};
+
+pub mod debugger;
+pub use debugger::{seek, Debugger};
+
+pub mod diff;
+pub use diff::{ElementDiff, FieldDiff, ValDiff};
+
+pub mod digest;
+pub use digest::Digest;
+
+pub mod dot;
+pub use dot::to_dot;
+
+#[cfg(feature = "chain-encrypt")]
+pub mod encrypt;
+#[cfg(feature = "chain-encrypt")]
+pub use encrypt::EncryptedChainStore;
+
+pub mod export;
+pub use export::ChainFormat;
+
+pub mod file;
+pub use file::FileChainStore;
+
+pub mod group;
+pub use group::{AbortMode, GroupBegin, GroupEnd};
+
+pub mod hasher;
+#[cfg(feature = "chain-blake3")]
+pub use hasher::Blake3Hasher;
+pub use hasher::{ChainHasher, LegacyHasher, Sha256Hasher};
+
+#[cfg(feature = "chain-http")]
+pub mod http;
+#[cfg(feature = "chain-http")]
+pub use http::ChainServer;
+
+pub mod intern;
+
+pub mod json;
+
+#[cfg(feature = "chain-kafka")]
+pub mod kafka;
+#[cfg(feature = "chain-kafka")]
+pub use self::kafka::KafkaSink;
+
+pub mod merge;
+pub use merge::{MergeConflict, MergeOutcome, Merged};
+
+pub mod merkle;
+pub use merkle::InclusionProof;
+
+pub mod message;
+pub use message::{MessageReceived, MessageSent};
+
+pub mod metrics;
+pub use metrics::{MetricsRecorder, PrometheusMetrics};
+
+pub mod migrate;
+
+#[cfg(feature = "chain-mmap")]
+pub mod mmap;
+#[cfg(feature = "chain-mmap")]
+pub use mmap::MappedChainStore;
+
+#[cfg(feature = "chain-msgpack")]
+pub mod msgpack;
+
+#[cfg(feature = "chain-nats")]
+pub mod nats;
+#[cfg(feature = "chain-nats")]
+pub use self::nats::NatsSink;
+
+#[cfg(feature = "chain-object-store")]
+pub mod objects;
+#[cfg(feature = "chain-object-store")]
+pub use objects::ObjectChainStore;
+
+pub mod otel;
+pub use otel::{OtelSpan, OtelSpanEvent, OtelSpanKind};
+
+pub mod page;
+pub use page::ChainPage;
+
+#[cfg(feature = "chain-parquet")]
+pub mod parquet;
+
+pub mod record;
+pub use record::{
+    CallTrap, CoreCall, Determinism, EpochAction, EpochInterrupt, FuelConsumed, FunctionCall,
+    Growth, ImportCall, ImportReturn, Instantiation, PostReturn, ResourceLifecycle, ResourceReturn,
+    ResourceTransfer, TransferDirection, TrapFrame, YieldPoint, YieldReason,
+};
+
+pub mod redact;
+pub use redact::{ChainRedactor, FieldRedactor, Redaction};
+
+pub mod reference;
+pub use reference::{EventRef, Reference};
+
+pub mod replay;
+pub use replay::{
+    check_determinism, compare, replay, BuildDivergence, Nondeterminism, NondeterminismCause,
+    Replay, ReplayDivergence,
+};
+
+pub mod resume;
+
+pub mod registry;
+pub use registry::{ResourceRegistry, SerializableResource};
+
 pub mod values;
-pub use values::SerializableVal;
+pub use values::{ChainValueError, SerializableVal, ValLimits};
+
+#[cfg(feature = "chain-rocksdb")]
+pub mod rocks;
+#[cfg(feature = "chain-rocksdb")]
+pub use rocks::{RocksChainReader, RocksChainStore};
+
+#[cfg(feature = "chain-sqlite")]
+pub mod sqlite;
+#[cfg(feature = "chain-sqlite")]
+pub use sqlite::{SqliteChainReader, SqliteChainStore};
+
+pub mod sample;
+pub use sample::{Sampled, Sampling};
+
+pub mod schema;
+pub use schema::{EventSchema, PayloadSchema, SchemaRegistry};
+
+pub mod segment;
+pub use segment::{Rotation, Segment, SegmentedChainStore};
+
+pub mod severity;
+pub use severity::{ErrorEvent, Severity};
+
+pub mod shared;
+pub use shared::SharedChain;
+
+pub mod snapshot;
+pub use snapshot::{GlobalValue, MemorySnapshot, Snapshot};
+
+pub mod sign;
+pub use sign::ChainSigner;
+#[cfg(feature = "chain-ed25519")]
+pub use sign::Ed25519Signer;
+
+pub mod sink;
+pub use sink::{ChainPublisher, EventSink};
+
+pub mod spawn;
+pub use spawn::{Genesis, Spawn};
+
+pub mod state;
+pub use state::{ChainConfig, ChainState};
+
+pub mod stats;
+pub use stats::ChainStats;
+
+pub mod store;
+pub use store::{ChainStore, MemoryChainStore};
+
+pub mod sync;
+pub use sync::{SyncOutcome, SyncRequest, SyncResponse, SyncTransport};
+
+pub mod testgen;
+pub use testgen::{TestCase, TestFailure, TestImport, TestSuite};
+
+pub mod tree;
+pub use tree::CallNode;
+
+pub mod types;
+pub use types::{FunctionSignature, SerializableType};
+
+pub mod verify;
+pub use verify::{IntegrityError, IntegrityErrorKind};
+
+pub mod wasi;
+pub use wasi::ChainWasiPolicy;
+
+pub mod wit;
+pub use wit::WitFormat;
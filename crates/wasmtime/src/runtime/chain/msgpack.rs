@@ -0,0 +1,111 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MessagePack for chains and values.
+//!
+//! Like [CBOR](crate::chain::cbor), MessagePack keeps NaNs, 64-bit integers
+//! and binary event payloads intact where JSON doesn't. Structs are encoded
+//! as maps keyed by field name and a [`SerializableVal`] as a map from its
+//! case's name to its contents, so neither needs Wasmtime's types to read.
+//!
+//! Like [`Chain::from_bytes`], [`Chain::from_msgpack`] doesn't check the
+//! chain's hashes, which [`Chain::verify`] does.
+
+use crate::chain::{Chain, SerializableVal};
+use crate::prelude::*;
+
+impl Chain {
+    /// Encodes this chain as MessagePack, see the
+    /// [module docs](crate::chain::msgpack).
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        to_msgpack(self)
+    }
+
+    /// Decodes a chain encoded by [`Chain::to_msgpack`].
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Chain> {
+        from_msgpack(bytes)
+    }
+}
+
+impl SerializableVal {
+    /// Encodes this value as MessagePack, see the
+    /// [module docs](crate::chain::msgpack).
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        to_msgpack(self)
+    }
+
+    /// Decodes a value encoded by [`SerializableVal::to_msgpack`], within the
+    /// current [`ValLimits`](crate::chain::ValLimits).
+    pub fn from_msgpack(bytes: &[u8]) -> Result<SerializableVal> {
+        from_msgpack(bytes)
+    }
+}
+
+fn to_msgpack<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    rmp_serde::to_vec_named(value).map_err(|e| anyhow!("encoding MessagePack: {e}"))
+}
+
+fn from_msgpack<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    rmp_serde::from_slice(bytes).map_err(|e| anyhow!("decoding MessagePack: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn round_trips_chains() -> Result<()> {
+        let mut chain = Chain::new();
+        let payload = (0..=255).collect::<Vec<u8>>();
+        chain.add(Event::new("binary".to_string(), payload.clone()));
+        let msgpack = chain.to_msgpack()?;
+        let decoded = Chain::from_msgpack(&msgpack)?;
+        decoded.verify()?;
+        assert_eq!(decoded.head(), chain.head());
+        assert_eq!(decoded.events().last().unwrap().event().data()[..], payload);
+        assert!(msgpack.windows(payload.len()).any(|w| w == payload));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_values() -> Result<()> {
+        let some = |v| Some(Box::new(v));
+        let values = [
+            SerializableVal::U64(u64::MAX),
+            SerializableVal::S64(i64::MIN),
+            SerializableVal::Float64(f64::NAN),
+            SerializableVal::Float32(f32::MIN_POSITIVE),
+            SerializableVal::Char('\u{10ffff}'),
+            SerializableVal::List((0..=255).map(SerializableVal::U8).collect()),
+            SerializableVal::Record(vec![("a".to_string(), SerializableVal::Result(Ok(None)))]),
+            SerializableVal::Variant(
+                "outer".to_string(),
+                some(SerializableVal::Option(some(SerializableVal::Enum(
+                    "inner".to_string(),
+                )))),
+            ),
+        ];
+        for value in values {
+            let decoded = SerializableVal::from_msgpack(&value.to_msgpack()?)?;
+            assert_eq!(decoded, value);
+        }
+
+        // Cases are tagged by name, so other readers can tell them apart.
+        let msgpack = SerializableVal::S64(i64::MIN).to_msgpack()?;
+        assert!(msgpack.windows(3).any(|w| w == b"S64"));
+        assert!(SerializableVal::from_msgpack(&msgpack[..msgpack.len() - 1]).is_err());
+        Ok(())
+    }
+}
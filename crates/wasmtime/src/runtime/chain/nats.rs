@@ -0,0 +1,70 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishing chain events to NATS JetStream.
+//!
+//! A [`NatsSink`] publishes each event to a subject as a message holding the
+//! event as JSON, see [`sink::message`](crate::chain::sink::message), and
+//! waits for the stream capturing the subject to acknowledge it. The
+//! message id is the event's hash, so within the stream's duplicate window
+//! JetStream drops events which are published again.
+
+use crate::chain::{EventSink, MetaEvent};
+use crate::prelude::*;
+use nats::jetstream::{JetStream, PublishOptions};
+
+/// An [`EventSink`] publishing to a NATS JetStream subject, see the
+/// [module documentation](crate::chain::nats).
+pub struct NatsSink {
+    jetstream: JetStream,
+    subject: String,
+}
+
+impl NatsSink {
+    /// Connects to the NATS server at `url`, such as
+    /// `nats://localhost:4222`, to publish to `subject`.
+    pub fn connect(url: &str, subject: &str) -> Result<NatsSink> {
+        let connection =
+            nats::connect(url).with_context(|| format!("failed to connect to NATS at {url}"))?;
+        Ok(NatsSink::new(nats::jetstream::new(connection), subject))
+    }
+
+    /// Publishes to `subject` with `jetstream`, configured as the caller
+    /// likes.
+    pub fn new(jetstream: JetStream, subject: &str) -> NatsSink {
+        NatsSink {
+            jetstream,
+            subject: subject.to_string(),
+        }
+    }
+}
+
+impl EventSink for NatsSink {
+    /// Publishes `event`, blocking until JetStream acknowledges it.
+    fn send(&mut self, _seq: u64, event: &MetaEvent) -> Result<()> {
+        let (id, data) = crate::chain::sink::message(event)?;
+        let options = PublishOptions {
+            id: Some(id),
+            ..PublishOptions::default()
+        };
+        self.jetstream
+            .publish_with_options(&self.subject, data, &options)
+            .with_context(|| format!("failed to publish to `{}`", self.subject))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Archiving chains to object storage.
+//!
+//! An [`ObjectChainStore`] keeps a chain in any store the `object_store`
+//! crate supports, such as S3, GCS, Azure Blob Storage or a local
+//! directory, under a prefix of its own:
+//!
+//! * `segments/{generation}-{first}.seg` holds up to
+//!   [`DEFAULT_SEGMENT_EVENTS`] events, starting with sequence number
+//!   `first`, encoded like [`Chain::to_bytes`](crate::chain::Chain::to_bytes)
+//!   encodes its events.
+//! * `manifest.json` lists the segments in order, along with how many events
+//!   of each belong to the chain and the hash of the last of them.
+//!
+//! A segment is uploaded once it's full, so appending an event usually
+//! costs nothing, and [`ChainStore::flush`] uploads the events since the
+//! last full segment as a partial one which is replaced as it fills up.
+//! The manifest is written after the segments it lists, and names a
+//! segment's events by count, so a crash between the two leaves the
+//! previous manifest describing the chain as it was. Rewriting the chain,
+//! such as in [`Chain::compact`](crate::chain::Chain::compact), starts a new
+//! generation of segments and deletes the old one once the new manifest is
+//! in place.
+//!
+//! Like other stores, every event is also kept in memory.
+
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{ChainStore, Digest, MetaEvent};
+use crate::prelude::*;
+use core::future::Future;
+use core::mem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// How many events a segment holds unless configured otherwise with
+/// [`ObjectChainStore::segment_events`].
+pub const DEFAULT_SEGMENT_EVENTS: usize = 10_000;
+
+/// The contents of `manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    format: u64,
+    hasher: String,
+    /// Bumped by every rewrite, so a rewritten chain's segments don't
+    /// replace those the previous manifest lists.
+    generation: u64,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Segment {
+    /// The object's name under `segments/`.
+    name: String,
+    /// The sequence number of its first event.
+    first: u64,
+    /// How many of its events belong to the chain.
+    len: u64,
+    /// The hash of the last of them.
+    head: Digest,
+}
+
+/// A [`ChainStore`] uploading events to object storage in segments, see
+/// the [module documentation](crate::chain::objects).
+#[derive(Debug)]
+pub struct ObjectChainStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    runtime: Runtime,
+    manifest: Manifest,
+    segment_events: usize,
+    events: Vec<MetaEvent>,
+}
+
+impl ObjectChainStore {
+    /// Opens the chain kept under `prefix` in `store`, creating it if
+    /// there's none yet.
+    ///
+    /// A new chain is recorded as hashed by the hasher named `hasher`, while
+    /// an existing one keeps its hasher, see [`ObjectChainStore::hasher`].
+    pub fn open(
+        store: Arc<dyn ObjectStore>,
+        prefix: &str,
+        hasher: &str,
+    ) -> Result<ObjectChainStore> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut this = ObjectChainStore {
+            store,
+            prefix: Path::from(prefix),
+            runtime,
+            manifest: Manifest {
+                format: FORMAT_VERSION.into(),
+                hasher: hasher.to_string(),
+                generation: 0,
+                segments: Vec::new(),
+            },
+            segment_events: DEFAULT_SEGMENT_EVENTS,
+            events: Vec::new(),
+        };
+
+        let manifest_path = this.manifest_path();
+        let context = || format!("invalid chain manifest `{manifest_path}`");
+        let Some(manifest) = this.block_on(this.read(&manifest_path))? else {
+            return Ok(this);
+        };
+        let manifest: Manifest = serde_json::from_slice(&manifest).with_context(context)?;
+        migrate::check_version(manifest.format).with_context(context)?;
+        for segment in &manifest.segments {
+            let path = this.segment_path(&segment.name);
+            let context = || format!("invalid chain segment `{path}`");
+            let bytes = this
+                .block_on(this.read(&path))?
+                .with_context(|| format!("chain segment `{path}` is missing"))?;
+            let events: Vec<MetaEvent> = postcard::from_bytes(&bytes).with_context(context)?;
+            ensure!(
+                segment.first == u64::try_from(this.events.len())?,
+                "chain segment `{path}` doesn't follow on from the one before it"
+            );
+            let len = usize::try_from(segment.len)?;
+            ensure!(events.len() >= len, "chain segment `{path}` is truncated");
+            this.events.extend(events.into_iter().take(len));
+            ensure!(
+                this.events.last().map(|e| e.hash()) == Some(segment.head),
+                "chain segment `{path}` doesn't end with the manifest's head"
+            );
+        }
+        this.manifest = manifest;
+        Ok(this)
+    }
+
+    /// Sets how many events each segment holds, [`DEFAULT_SEGMENT_EVENTS`]
+    /// by default. Segments already uploaded keep their size.
+    pub fn segment_events(mut self, events: usize) -> ObjectChainStore {
+        self.segment_events = events.max(1);
+        self
+    }
+
+    /// Name of the hasher this chain is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.manifest.hasher
+    }
+
+    /// The prefix the chain is kept under.
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_ref()
+    }
+
+    fn manifest_path(&self) -> Path {
+        self.prefix.child("manifest.json")
+    }
+
+    fn segment_path(&self, name: &str) -> Path {
+        self.prefix.child("segments").child(name)
+    }
+
+    /// Runs `future` to completion on this store's runtime.
+    ///
+    /// `Runtime::block_on` panics on a thread already running a runtime,
+    /// such as when the chain is used from async code, so the future is run
+    /// on a thread of its own.
+    fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| self.runtime.block_on(future))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    /// Reads the object at `path`, if there's one.
+    async fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match self.store.get(path).await {
+            Ok(object) => Ok(Some(object.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Uploads the events before `end` which aren't in a full segment yet,
+    /// then a manifest listing them.
+    fn persist(&mut self, end: usize) -> Result<()> {
+        if self.manifest.segments.iter().map(|s| s.len).sum::<u64>() >= u64::try_from(end)? {
+            return Ok(());
+        }
+        self.upload(self.manifest.clone(), end)
+    }
+
+    /// Uploads the events before `end` which aren't in a full segment of
+    /// `manifest`, then `manifest` listing them, and makes it this store's.
+    fn upload(&mut self, mut manifest: Manifest, end: usize) -> Result<()> {
+        // A partial segment is replaced along with the events after it.
+        let partial = |segment: &Segment| segment.len < self.segment_events as u64;
+        if manifest.segments.last().is_some_and(partial) {
+            manifest.segments.pop();
+        }
+        let mut start = manifest
+            .segments
+            .iter()
+            .map(|s| s.len as usize)
+            .sum::<usize>();
+
+        let mut uploads = Vec::new();
+        while start < end {
+            let stop = end.min(start + self.segment_events);
+            let events = &self.events[start..stop];
+            let name = format!("{:08}-{start:020}.seg", manifest.generation);
+            uploads.push((self.segment_path(&name), postcard::to_allocvec(events)?));
+            manifest.segments.push(Segment {
+                name,
+                first: u64::try_from(start)?,
+                len: u64::try_from(events.len())?,
+                head: events[events.len() - 1].hash(),
+            });
+            start = stop;
+        }
+        let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
+        let manifest_path = self.manifest_path();
+        self.block_on(async {
+            for (path, bytes) in uploads {
+                self.store
+                    .put(&path, bytes.into())
+                    .await
+                    .with_context(|| format!("failed to upload chain segment `{path}`"))?;
+            }
+            self.store
+                .put(&manifest_path, manifest_bytes.into())
+                .await
+                .with_context(|| format!("failed to upload chain manifest `{manifest_path}`"))?;
+            Ok::<_, Error>(())
+        })?;
+        self.manifest = manifest;
+        Ok(())
+    }
+
+    /// Uploads the segments filled by the events from sequence number
+    /// `from` on, undoing their append if that fails.
+    fn persist_appended(&mut self, from: usize) -> Result<()> {
+        let full = self.events.len() - self.events.len() % self.segment_events;
+        if full <= from - from % self.segment_events {
+            return Ok(());
+        }
+        self.persist(full)
+            .inspect_err(|_| self.events.truncate(from))
+    }
+}
+
+impl ChainStore for ObjectChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        let from = self.events.len();
+        self.events.push(event);
+        self.persist_appended(from)
+    }
+
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let from = self.events.len();
+        self.events.extend(events);
+        self.persist_appended(from)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        Box::new(self.events.get(index..).unwrap_or_default().iter())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.persist(self.events.len())
+    }
+
+    /// Uploads `events` as a new generation of segments, then deletes the
+    /// old ones.
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let old_manifest = self.manifest.clone();
+        let old_events = mem::replace(&mut self.events, events);
+        let manifest = Manifest {
+            generation: old_manifest.generation + 1,
+            segments: Vec::new(),
+            ..old_manifest.clone()
+        };
+        if let Err(e) = self.upload(manifest, self.events.len()) {
+            self.events = old_events;
+            return Err(e);
+        }
+
+        let stale = old_manifest
+            .segments
+            .iter()
+            .map(|segment| self.segment_path(&segment.name))
+            .collect::<Vec<_>>();
+        self.block_on(async {
+            for path in stale {
+                // The new manifest no longer lists the segment, so failing
+                // to delete it only leaves garbage behind.
+                if let Err(e) = self.store.delete(&path).await {
+                    log::warn!("failed to delete stale chain segment `{path}`: {e}");
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Chain, Event, Sha256Hasher};
+    use object_store::memory::InMemory;
+
+    fn open(store: &Arc<InMemory>) -> Result<Chain> {
+        let store = ObjectChainStore::open(store.clone(), "actors/a", "sha256")?.segment_events(10);
+        Chain::with_store(Arc::new(Sha256Hasher), Box::new(store))
+    }
+
+    fn objects(store: &Arc<InMemory>) -> Result<Vec<String>> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let prefix = Path::from("actors/a/segments");
+        let list = runtime.block_on(store.list_with_delimiter(Some(&prefix)))?;
+        let mut names = list
+            .objects
+            .iter()
+            .map(|object| object.location.filename().unwrap().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn archives_chains_in_segments() -> Result<()> {
+        let objects_store = Arc::new(InMemory::new());
+        let mut chain = open(&objects_store)?;
+        for i in 0..25u8 {
+            chain.add(Event::new("a".to_string(), vec![i]));
+        }
+        // Only full segments are uploaded until the chain is flushed.
+        assert_eq!(open(&objects_store)?.len(), 20);
+        chain.flush()?;
+        assert_eq!(
+            objects(&objects_store)?,
+            [
+                "00000000-00000000000000000000.seg",
+                "00000000-00000000000000000010.seg",
+                "00000000-00000000000000000020.seg",
+            ]
+        );
+        chain.add(Event::new("b".to_string(), vec![]));
+        chain.flush()?;
+        let reopened = open(&objects_store)?;
+        reopened.verify()?;
+        assert_eq!(reopened.len(), 26);
+        assert_eq!(reopened.head(), chain.head());
+
+        let kept = chain.events().skip(20).cloned().collect::<Vec<_>>();
+        let mut store = ObjectChainStore::open(objects_store.clone(), "actors/a", "sha256")?;
+        store.rewrite(kept)?;
+        assert_eq!(
+            objects(&objects_store)?,
+            ["00000001-00000000000000000000.seg"]
+        );
+        let store = ObjectChainStore::open(objects_store.clone(), "actors/a", "sha256")?;
+        assert_eq!(store.len(), 6);
+        assert_eq!(store.head().unwrap().hash(), chain.head().unwrap());
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting recorded calls as OpenTelemetry spans.
+//!
+//! [`Chain::otel_spans`] turns every export call at the top of the
+//! [call tree](Chain::call_tree) into a trace, with a span for that call and
+//! one for each import or export call made inside it, nested as they were
+//! recorded. Other events recorded inside a call become events of its span,
+//! and events outside of any call are left out. [`Chain::to_otlp_json`]
+//! encodes the spans as an OTLP `ExportTraceServiceRequest` in JSON, which
+//! collectors, Jaeger and Tempo accept on their `/v1/traces` endpoint.
+//!
+//! Trace and span ids are the leading bytes of the hashes of the events the
+//! spans are built from, so exporting a chain twice gives the same ids.
+//! Chains don't record when events happened, so the `n`th event of a chain
+//! is placed `n` microseconds after a given start time. This keeps spans in
+//! the order and nesting they were recorded in, but their durations only say
+//! how many events were recorded during them.
+
+use crate::chain::record::{FUNCTION_CALL, IMPORT_CALL, IMPORT_RETURN, TRAP};
+use crate::chain::tree::CallNode;
+use crate::chain::{CallTrap, Chain, Digest, FunctionCall, ImportCall, MetaEvent};
+use crate::prelude::*;
+use core::time::Duration;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A recorded call as an OpenTelemetry span, see the
+/// [module documentation](crate::chain::otel).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtelSpan {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// The span of the call this one was made in, or `None` for the root
+    /// of a trace.
+    pub parent_span_id: Option<[u8; 8]>,
+    /// The export or import name.
+    pub name: String,
+    pub kind: OtelSpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// Hash of the `function-call`, `trap` or `import-call` event.
+    pub hash: Digest,
+    /// The other events recorded during the call, outside of nested calls.
+    pub events: Vec<OtelSpanEvent>,
+    /// The error message of a call which trapped.
+    pub error: Option<String>,
+}
+
+/// The side of a component boundary an [`OtelSpan`] is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtelSpanKind {
+    /// A call into a component export, handled by the component.
+    Server,
+    /// A call from a component into a host import.
+    Client,
+}
+
+/// An event recorded during the call of an [`OtelSpan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtelSpanEvent {
+    /// The event type.
+    pub name: String,
+    pub time: SystemTime,
+    pub hash: Digest,
+}
+
+impl Chain {
+    /// Converts the calls recorded in this chain into spans, placing the
+    /// chain's first event at `start`, see the
+    /// [module documentation](crate::chain::otel).
+    ///
+    /// Spans are returned in the order their calls started, each after the
+    /// span it's nested in.
+    pub fn otel_spans(&self, start: SystemTime) -> Vec<OtelSpan> {
+        let indices = self
+            .events()
+            .enumerate()
+            .map(|(i, node)| (node.hash(), i))
+            .collect::<HashMap<_, _>>();
+        let time = |index: usize| start + Duration::from_micros(index as u64);
+
+        fn collect(
+            node: &CallNode<'_>,
+            trace_id: [u8; 16],
+            parent_span_id: Option<[u8; 8]>,
+            indices: &HashMap<Digest, usize>,
+            time: &dyn Fn(usize) -> SystemTime,
+            spans: &mut Vec<OtelSpan>,
+        ) {
+            let (first, last) = extent(node, indices);
+            let (name, kind, error) = describe(node.event);
+            let span_id = span_id(node.event.hash());
+            let span = spans.len();
+            spans.push(OtelSpan {
+                trace_id,
+                span_id,
+                parent_span_id,
+                name,
+                kind,
+                start: time(first),
+                end: time(last + 1),
+                hash: node.event.hash(),
+                events: Vec::new(),
+                error,
+            });
+            for child in &node.children {
+                let type_ = child.event.event().type_();
+                if is_call(child.event) {
+                    collect(child, trace_id, Some(span_id), indices, time, spans);
+                } else if type_ != IMPORT_RETURN {
+                    let event = OtelSpanEvent {
+                        name: type_.to_string(),
+                        time: time(indices[&child.event.hash()]),
+                        hash: child.event.hash(),
+                    };
+                    spans[span].events.push(event);
+                }
+            }
+        }
+
+        let mut spans = Vec::new();
+        for root in self.call_tree() {
+            if !is_call(root.event) {
+                continue;
+            }
+            let mut trace_id = [0; 16];
+            trace_id.copy_from_slice(&root.event.hash().as_bytes()[..16]);
+            collect(&root, trace_id, None, &indices, &time, &mut spans);
+        }
+        spans
+    }
+
+    /// Encodes [`Chain::otel_spans`] as an OTLP `ExportTraceServiceRequest`
+    /// in JSON, with `service_name` as the `service.name` of the resource
+    /// the spans come from.
+    pub fn to_otlp_json(&self, service_name: &str, start: SystemTime) -> Result<Vec<u8>> {
+        let spans = self
+            .otel_spans(start)
+            .iter()
+            .map(span_json)
+            .collect::<Vec<_>>();
+        let request = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [attribute("service.name", service_name)],
+                },
+                "scopeSpans": [{
+                    "scope": {
+                        "name": "wasmtime-chain",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                    "spans": spans,
+                }],
+            }],
+        });
+        Ok(serde_json::to_vec(&request)?)
+    }
+}
+
+fn is_call(node: &MetaEvent) -> bool {
+    matches!(node.event().type_(), FUNCTION_CALL | TRAP | IMPORT_CALL)
+}
+
+/// The indices of the first and last events of `node` and its descendants.
+///
+/// An export call is recorded once it returns, after everything inside it,
+/// while an import call is recorded before its descendants.
+fn extent(node: &CallNode<'_>, indices: &HashMap<Digest, usize>) -> (usize, usize) {
+    let index = indices[&node.event.hash()];
+    node.children
+        .iter()
+        .map(|child| extent(child, indices))
+        .fold((index, index), |(first, last), (a, b)| {
+            (first.min(a), last.max(b))
+        })
+}
+
+/// The span name, kind and error of a call event, falling back to the event
+/// type as the name when its payload can't be decoded.
+fn describe(node: &MetaEvent) -> (String, OtelSpanKind, Option<String>) {
+    let event = node.event();
+    let fallback = || event.type_().to_string();
+    match event.type_() {
+        IMPORT_CALL => {
+            let name = ImportCall::decode(event).map_or_else(|_| fallback(), |c| c.name);
+            (name, OtelSpanKind::Client, None)
+        }
+        TRAP => match CallTrap::decode(event) {
+            Ok(trap) => (trap.name, OtelSpanKind::Server, Some(trap.message)),
+            Err(_) => (fallback(), OtelSpanKind::Server, Some(fallback())),
+        },
+        _ => {
+            let name = FunctionCall::decode(event).map_or_else(|_| fallback(), |c| c.name);
+            (name, OtelSpanKind::Server, None)
+        }
+    }
+}
+
+fn span_id(hash: Digest) -> [u8; 8] {
+    let mut id = [0; 8];
+    id.copy_from_slice(&hash.as_bytes()[..8]);
+    id
+}
+
+fn span_json(span: &OtelSpan) -> Value {
+    let mut value = json!({
+        "traceId": hex(&span.trace_id),
+        "spanId": hex(&span.span_id),
+        "name": span.name,
+        // `SPAN_KIND_SERVER` and `SPAN_KIND_CLIENT`.
+        "kind": match span.kind {
+            OtelSpanKind::Server => 2,
+            OtelSpanKind::Client => 3,
+        },
+        "startTimeUnixNano": nanos(span.start),
+        "endTimeUnixNano": nanos(span.end),
+        "attributes": [
+            attribute("wasmtime.chain.hash", &span.hash.to_hex()),
+        ],
+        "events": span.events.iter().map(|event| json!({
+            "timeUnixNano": nanos(event.time),
+            "name": event.name,
+            "attributes": [attribute("wasmtime.chain.hash", &event.hash.to_hex())],
+        })).collect::<Vec<_>>(),
+        // `STATUS_CODE_ERROR` or `STATUS_CODE_UNSET`.
+        "status": match &span.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({}),
+        },
+    });
+    if let Some(parent) = &span.parent_span_id {
+        value["parentSpanId"] = hex(parent).into();
+    }
+    value
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON gives 64-bit integers as strings.
+fn nanos(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since.as_nanos().to_string()
+}
+
+/// OTLP JSON gives trace and span ids in hex rather than base64.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Event, ImportReturn};
+
+    #[test]
+    fn nested_calls_become_spans() -> Result<()> {
+        let mut chain = Chain::new();
+        let import = chain.add(Event::new(
+            IMPORT_CALL.to_string(),
+            serde_json::to_vec(&ImportCall {
+                name: "host#next".to_string(),
+                params: None,
+                call_parent: None,
+            })?,
+        ));
+        chain.add(Event::new(
+            "wasi".to_string(),
+            serde_json::to_vec(&json!({ "call_parent": import }))?,
+        ));
+        chain.add(Event::new(
+            IMPORT_RETURN.to_string(),
+            serde_json::to_vec(&ImportReturn {
+                name: "host#next".to_string(),
+                results: None,
+                call_parent: Some(import),
+            })?,
+        ));
+        let call = chain.add(Event::new(
+            FUNCTION_CALL.to_string(),
+            serde_json::to_vec(&FunctionCall {
+                name: "run".to_string(),
+                params: Vec::new(),
+                results: Vec::new(),
+                call_parent: None,
+                signature: None,
+            })?,
+        ));
+        chain.add(Event::new("embedder".to_string(), vec![]));
+
+        let start = UNIX_EPOCH + Duration::from_secs(1);
+        let spans = chain.otel_spans(start);
+        assert_eq!(spans.len(), 2);
+        let (run, next) = (&spans[0], &spans[1]);
+        assert_eq!(run.name, "run");
+        assert_eq!(run.kind, OtelSpanKind::Server);
+        assert_eq!(run.hash, call);
+        assert_eq!(run.parent_span_id, None);
+        assert_eq!(run.start, start);
+        assert_eq!(run.end, start + Duration::from_micros(4));
+        assert!(run.events.is_empty());
+
+        assert_eq!(next.name, "host#next");
+        assert_eq!(next.kind, OtelSpanKind::Client);
+        assert_eq!(next.trace_id, run.trace_id);
+        assert_eq!(next.parent_span_id, Some(run.span_id));
+        assert_eq!(next.span_id, span_id(import));
+        assert_eq!(next.end, start + Duration::from_micros(3));
+        assert_eq!(next.events.len(), 1);
+        assert_eq!(next.events[0].name, "wasi");
+
+        let json: Value = serde_json::from_slice(&chain.to_otlp_json("test", start)?)?;
+        let spans = &json["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["spanId"], hex(&run.span_id));
+        assert_eq!(spans[0]["startTimeUnixNano"], "1000000000");
+        assert!(spans[0].get("parentSpanId").is_none());
+        assert_eq!(spans[1]["parentSpanId"], hex(&run.span_id));
+        assert_eq!(spans[1]["kind"], 3);
+        Ok(())
+    }
+}
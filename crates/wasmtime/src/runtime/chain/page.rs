@@ -0,0 +1,110 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading a chain a page at a time.
+//!
+//! [`Chain::page`] returns a bounded run of events along with a cursor for
+//! the run after it, so a chain can be served over an HTTP API, or followed
+//! as it grows, without collecting its whole history. Cursors are sequence
+//! numbers in the chain's store: they stay valid as events are added, but
+//! not across [`Chain::compact`] or [`Chain::rollback_to`], which renumber
+//! the events they keep.
+
+use crate::chain::{Chain, MetaEvent};
+use crate::prelude::*;
+use serde::Serialize;
+
+/// A run of a chain's events, see [`Chain::page`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainPage<'a> {
+    /// The events, oldest first.
+    pub events: Vec<&'a MetaEvent>,
+    /// The cursor to pass as `after` for the next page: the sequence number
+    /// of the last event of this page, or the `after` this page was read
+    /// with if it's empty.
+    pub next: Option<u64>,
+    /// Whether the chain had more events after this page when it was read.
+    pub more: bool,
+}
+
+impl Chain {
+    /// Returns up to `limit` events following the one with sequence number
+    /// `after`, or from the start of the chain if `after` is `None`, see the
+    /// [module documentation](crate::chain::page).
+    ///
+    /// Only the events returned are visited, so reading a page costs the
+    /// same however long the chain is.
+    pub fn page(&self, after: Option<u64>, limit: usize) -> ChainPage<'_> {
+        let start = match after {
+            Some(after) => usize::try_from(after)
+                .ok()
+                .and_then(|after| after.checked_add(1))
+                .unwrap_or(usize::MAX),
+            None => 0,
+        };
+        let start = start.min(self.len());
+        let events = self.store.iter_from(start).take(limit).collect::<Vec<_>>();
+        let end = start + events.len();
+        ChainPage {
+            next: if events.is_empty() {
+                after
+            } else {
+                Some((end - 1) as u64)
+            },
+            more: end < self.len(),
+            events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn pages_through_the_chain() {
+        let mut chain = Chain::new();
+        let hashes = (0..5u8)
+            .map(|i| chain.add(Event::new("a".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+
+        let page = chain.page(None, 2);
+        assert_eq!(
+            page.events.iter().map(|n| n.hash()).collect::<Vec<_>>(),
+            hashes[..2]
+        );
+        assert_eq!(page.next, Some(1));
+        assert!(page.more);
+
+        let page = chain.page(page.next, 10);
+        assert_eq!(page.events.len(), 3);
+        assert_eq!(page.events[0].hash(), hashes[2]);
+        assert_eq!(page.next, Some(4));
+        assert!(!page.more);
+
+        // Following the head: nothing new yet, then the next event.
+        let page = chain.page(Some(4), 10);
+        assert!(page.events.is_empty());
+        assert_eq!(page.next, Some(4));
+        let next = page.next;
+        let head = chain.add(Event::new("a".to_string(), vec![5]));
+        let page = chain.page(next, 10);
+        assert_eq!(page.events[0].hash(), head);
+        assert_eq!(page.next, Some(5));
+
+        assert!(Chain::new().page(None, 10).next.is_none());
+        assert!(chain.page(Some(u64::MAX), 10).events.is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting chains to Parquet for analysis.
+//!
+//! [`Chain::to_parquet`] writes a chain as a Parquet file with a row per
+//! event, which DuckDB, Polars or pandas can query directly:
+//!
+//! | Column      | Type                           | Holds                          |
+//! |-------------|--------------------------------|--------------------------------|
+//! | `index`     | `uint64`                       | position in the chain, from 0  |
+//! | `hash`      | `string`                       | hex hash of the event          |
+//! | `parent`    | `string`, nullable             | hex hash of the previous event |
+//! | `type`      | `string`                       | event type                     |
+//! | `timestamp` | `timestamp[us, UTC]`, nullable | when the event happened        |
+//! | `payload`   | `binary`                       | the payload, decompressed      |
+//!
+//! Chains don't record when events happened, so `timestamp` is null for
+//! now; it's part of the schema so that queries keep working once they do.
+//! Events are written in row groups of [`ROWS_PER_GROUP`] events, so a long
+//! chain is exported without holding all of it in memory twice.
+//!
+//! ```sql
+//! SELECT type, count(*), sum(octet_length(payload))
+//! FROM 'chain.parquet' GROUP BY type;
+//! ```
+
+use crate::chain::Chain;
+use crate::prelude::*;
+use arrow_array::builder::{
+    BinaryBuilder, StringBuilder, TimestampMicrosecondBuilder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of events in each row group of an exported chain.
+pub const ROWS_PER_GROUP: usize = 64 * 1024;
+
+/// The schema of an exported chain, see the
+/// [module documentation](crate::chain::parquet).
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("index", DataType::UInt64, false),
+        Field::new("hash", DataType::Utf8, false),
+        Field::new("parent", DataType::Utf8, true),
+        Field::new("type", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("payload", DataType::Binary, false),
+    ]))
+}
+
+/// The columns of the row group being exported.
+struct Columns {
+    index: UInt64Builder,
+    hash: StringBuilder,
+    parent: StringBuilder,
+    type_: StringBuilder,
+    timestamp: TimestampMicrosecondBuilder,
+    payload: BinaryBuilder,
+    len: usize,
+}
+
+impl Columns {
+    fn new() -> Columns {
+        Columns {
+            index: UInt64Builder::new(),
+            hash: StringBuilder::new(),
+            parent: StringBuilder::new(),
+            type_: StringBuilder::new(),
+            timestamp: TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            payload: BinaryBuilder::new(),
+            len: 0,
+        }
+    }
+
+    fn finish(&mut self, schema: &SchemaRef) -> Result<RecordBatch> {
+        self.len = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.index.finish()),
+            Arc::new(self.hash.finish()),
+            Arc::new(self.parent.finish()),
+            Arc::new(self.type_.finish()),
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.payload.finish()),
+        ];
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+impl Chain {
+    /// Writes this chain to `writer` as Parquet, see the
+    /// [module documentation](crate::chain::parquet).
+    ///
+    /// Fails if a compressed payload can't be decompressed.
+    pub fn to_parquet<W: Write + Send>(&self, writer: W) -> Result<()> {
+        let schema = schema();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROWS_PER_GROUP)
+            .build();
+        let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+        let mut columns = Columns::new();
+        for (index, node) in self.events().enumerate() {
+            let event = node.event();
+            columns.index.append_value(u64::try_from(index)?);
+            columns.hash.append_value(node.hash().to_string());
+            columns
+                .parent
+                .append_option(event.parent().map(|parent| parent.to_string()));
+            columns.type_.append_value(event.type_());
+            columns.timestamp.append_null();
+            let payload = event
+                .try_data()
+                .with_context(|| format!("decompressing the payload of event {index}"))?;
+            columns.payload.append_value(&payload);
+            columns.len += 1;
+            if columns.len == ROWS_PER_GROUP {
+                writer.write(&columns.finish(&schema)?)?;
+            }
+        }
+        if columns.len > 0 {
+            writer.write(&columns.finish(&schema)?)?;
+        }
+        writer.close()?;
+        Ok(())
+    }
+
+    /// Writes this chain to a Parquet file at `path`, replacing any file
+    /// already there.
+    pub fn write_parquet(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        self.to_parquet(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+    use arrow_array::{Array, BinaryArray, StringArray, UInt64Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn exports_events_as_rows() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.add(Event::new("start".to_string(), b"{}".to_vec()));
+        chain.add(Event::new("binary".to_string(), vec![0, 255, 7]));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain.parquet");
+        chain.write_parquet(&path)?;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)?.build()?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), schema());
+        assert_eq!(batch.num_rows(), 2);
+
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let index = column("index");
+        let index = index.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(index.values(), &[0, 1]);
+        let hash = column("hash");
+        let hash = hash.as_any().downcast_ref::<StringArray>().unwrap();
+        let parent = column("parent");
+        let parent = parent.as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(parent.is_null(0));
+        assert_eq!(parent.value(1), hash.value(0));
+        assert_eq!(hash.value(1), chain.head().unwrap().to_string());
+        let type_ = column("type");
+        let type_ = type_.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(type_.value(1), "binary");
+        assert_eq!(column("timestamp").null_count(), 2);
+        let payload = column("payload");
+        let payload = payload.as_any().downcast_ref::<BinaryArray>().unwrap();
+        assert_eq!(payload.value(1), [0, 255, 7]);
+        Ok(())
+    }
+}
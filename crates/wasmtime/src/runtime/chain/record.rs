@@ -0,0 +1,1735 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Automatic recording of component activity into a store's chain.
+//!
+//! Each recorded event has a well-known type string and a JSON-encoded
+//! payload struct defined here, so consumers of a chain can decode what the
+//! runtime wrote.
+//!
+//! Every payload also carries a `call_parent`: the hash of the
+//! `import-call` event of the host function running when it was recorded.
+//! Export calls are recorded once they finish, so an export's
+//! `function-call` event shares its `call_parent` with the imports it made,
+//! which come before it. [`Chain::call_tree`] puts the two together.
+
+use crate::chain::{
+    Chain, Digest, Event, FunctionSignature, ResourceRegistry, SerializableCoreVal,
+    SerializableResource, SerializableVal, Severity,
+};
+use crate::component::{Component, ResourceAny, Type, Val};
+use crate::prelude::*;
+use crate::store::StoreOpaque;
+use crate::{AsContextMut, Trap, WasmBacktrace};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Event type of a call into a component export.
+pub const FUNCTION_CALL: &str = "function-call";
+
+/// Payload of a [`FUNCTION_CALL`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    /// Export name, with nested instance names joined by `#`.
+    pub name: String,
+    pub params: Vec<SerializableVal>,
+    pub results: Vec<SerializableVal>,
+    /// Hash of the [`IMPORT_CALL`] event whose host function was running
+    /// when this was recorded, or `None` at the top level, see
+    /// [`Chain::call_tree`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+    /// The export's parameter and result types, when recorded with
+    /// [`Config::chain_record_types`](crate::Config::chain_record_types).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<FunctionSignature>,
+}
+
+impl FunctionCall {
+    /// Decodes the payload of a [`FUNCTION_CALL`] event.
+    pub fn decode(event: &Event) -> Result<FunctionCall> {
+        decode(event, FUNCTION_CALL)
+    }
+}
+
+/// Event type of the host finishing a component export call with
+/// [`Func::post_return`](crate::component::Func::post_return), see
+/// [`Config::chain_record_post_return`](crate::Config::chain_record_post_return).
+pub const POST_RETURN: &str = "post-return";
+
+/// Payload of a [`POST_RETURN`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostReturn {
+    /// Export name, as in [`FunctionCall::name`].
+    pub name: String,
+    /// Hash of the [`FUNCTION_CALL`] event of the call being finished.
+    pub function_call: Digest,
+    /// Whether the export's canonical options named a `post-return`
+    /// function which ran, rather than there being nothing to run.
+    pub ran: bool,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl PostReturn {
+    /// Decodes the payload of a [`POST_RETURN`] event.
+    pub fn decode(event: &Event) -> Result<PostReturn> {
+        decode(event, POST_RETURN)
+    }
+}
+
+/// Event type of a call into a function of a plain core module.
+pub const CORE_CALL: &str = "core-call";
+
+/// Payload of a [`CORE_CALL`] event.
+///
+/// Core calls aren't recorded automatically, since core functions don't
+/// know the name they were exported under; embedders record them with
+/// [`CoreCall::record`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreCall {
+    /// Export name, as given to [`CoreCall::record`].
+    pub name: String,
+    pub params: Vec<SerializableCoreVal>,
+    pub results: Vec<SerializableCoreVal>,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl CoreCall {
+    /// Decodes the payload of a [`CORE_CALL`] event.
+    pub fn decode(event: &Event) -> Result<CoreCall> {
+        decode(event, CORE_CALL)
+    }
+
+    /// Records a finished call of the core function exported as `name` to
+    /// the store's chain, returning the event's hash.
+    ///
+    /// References among the values are numbered through the store's
+    /// [`CoreRefRegistry`](crate::chain::CoreRefRegistry).
+    pub fn record(
+        mut store: impl AsContextMut,
+        name: &str,
+        params: &[crate::Val],
+        results: &[crate::Val],
+    ) -> Result<Digest> {
+        let mut store = store.as_context_mut();
+        let call = CoreCall {
+            name: name.to_string(),
+            params: SerializableCoreVal::from_vals(&mut store, params)?,
+            results: SerializableCoreVal::from_vals(&mut store, results)?,
+            call_parent: store.0.call_parent(),
+        };
+        let (chain, _) = store.0.chain_and_registry_mut()?;
+        add(chain, CORE_CALL, &call)
+    }
+}
+
+/// Event type of a call from a component into a host import.
+pub const IMPORT_CALL: &str = "import-call";
+
+/// Event type of a host import returning to the calling component.
+pub const IMPORT_RETURN: &str = "import-return";
+
+/// Payload of an [`IMPORT_CALL`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportCall {
+    /// Import name, with enclosing instance names joined by `#`.
+    pub name: String,
+    /// The arguments, or `None` for typed host functions whose signature
+    /// involves resources, since those can't be captured.
+    pub params: Option<Vec<SerializableVal>>,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl ImportCall {
+    /// Decodes the payload of an [`IMPORT_CALL`] event.
+    pub fn decode(event: &Event) -> Result<ImportCall> {
+        decode(event, IMPORT_CALL)
+    }
+}
+
+/// Payload of an [`IMPORT_RETURN`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReturn {
+    /// Import name, matching the preceding [`ImportCall`].
+    pub name: String,
+    /// The results, or `None` when the arguments were also not captured.
+    pub results: Option<Vec<SerializableVal>>,
+    /// Hash of the matching [`IMPORT_CALL`] event, unlike other payloads, so
+    /// the return ends up inside the call in [`Chain::call_tree`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl ImportReturn {
+    /// Decodes the payload of an [`IMPORT_RETURN`] event.
+    pub fn decode(event: &Event) -> Result<ImportReturn> {
+        decode(event, IMPORT_RETURN)
+    }
+}
+
+/// Event type of a component export call which trapped or otherwise failed.
+///
+/// These events are [`Severity::Error`].
+pub const TRAP: &str = "trap";
+
+/// Payload of a [`TRAP`] event.
+///
+/// A call which traps has no [`FUNCTION_CALL`] event, so its name and
+/// parameters are recorded here instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallTrap {
+    /// Export name, as in [`FunctionCall::name`].
+    pub name: String,
+    pub params: Vec<SerializableVal>,
+    /// The wasm trap code, such as `UnreachableCodeReached` or `OutOfFuel`,
+    /// or `None` for errors which aren't wasm traps, like those returned by
+    /// host functions.
+    pub code: Option<String>,
+    /// The root cause of the error.
+    pub message: String,
+    /// The wasm stack at the time of the trap, innermost frame first. This is
+    /// empty when [`Config::wasm_backtrace`](crate::Config::wasm_backtrace)
+    /// is disabled.
+    pub frames: Vec<TrapFrame>,
+    /// Hash of the [`IMPORT_CALL`] event in flight when the error happened,
+    /// if it was raised by a host import.
+    pub import: Option<Digest>,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+/// A wasm frame of a [`CallTrap`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrapFrame {
+    /// Name of the core module, if it has one.
+    pub module: Option<String>,
+    pub func_index: u32,
+    pub func_name: Option<String>,
+    /// Offset of the trapping instruction in the core module's binary.
+    pub offset: Option<usize>,
+}
+
+impl CallTrap {
+    /// Decodes the payload of a [`TRAP`] event.
+    pub fn decode(event: &Event) -> Result<CallTrap> {
+        decode(event, TRAP)
+    }
+}
+
+/// Event type of a resource handle being seen for the first time.
+pub const RESOURCE_NEW: &str = "resource-new";
+
+/// Event type of a resource handle crossing the component boundary.
+pub const RESOURCE_TRANSFER: &str = "resource-transfer";
+
+/// Event type of the host dropping a resource handle with
+/// [`ResourceAny::resource_drop`].
+pub const RESOURCE_DROP: &str = "resource-drop";
+
+/// Event type of a `borrow` handle the host lent to a component export
+/// being given back, once the export call's `post-return` finished, see
+/// [`Config::chain_record_post_return`](crate::Config::chain_record_post_return).
+pub const RESOURCE_RETURN: &str = "resource-return";
+
+/// Payload of [`RESOURCE_NEW`] and [`RESOURCE_DROP`] events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceLifecycle {
+    /// The handle's name in the store's
+    /// [`ResourceRegistry`](crate::chain::ResourceRegistry). Its `owned` field
+    /// tells an `own` handle from a `borrow`.
+    pub resource: SerializableResource,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl ResourceLifecycle {
+    /// Decodes the payload of a [`RESOURCE_NEW`] or [`RESOURCE_DROP`] event.
+    pub fn decode(event: &Event) -> Result<ResourceLifecycle> {
+        match event.type_() {
+            RESOURCE_NEW => decode(event, RESOURCE_NEW),
+            _ => decode(event, RESOURCE_DROP),
+        }
+    }
+}
+
+/// Payload of a [`RESOURCE_RETURN`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceReturn {
+    /// The borrowed handle, as named in its [`RESOURCE_TRANSFER`] event.
+    pub resource: SerializableResource,
+    /// Hash of the [`FUNCTION_CALL`] event of the call it was lent to.
+    pub function_call: Digest,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl ResourceReturn {
+    /// Decodes the payload of a [`RESOURCE_RETURN`] event.
+    pub fn decode(event: &Event) -> Result<ResourceReturn> {
+        decode(event, RESOURCE_RETURN)
+    }
+}
+
+/// Which way a resource handle crossed the component boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferDirection {
+    /// Passed as an export's argument or returned from an import.
+    ToGuest,
+    /// Returned from an export or passed as an import's argument.
+    ToHost,
+}
+
+/// Payload of a [`RESOURCE_TRANSFER`] event.
+///
+/// An `own` handle moves ownership in `direction`, while a `borrow` only
+/// lends it for the duration of the call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceTransfer {
+    pub resource: SerializableResource,
+    /// The export or import whose arguments or results held the handle.
+    pub call: String,
+    pub direction: TransferDirection,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl ResourceTransfer {
+    /// Decodes the payload of a [`RESOURCE_TRANSFER`] event.
+    pub fn decode(event: &Event) -> Result<ResourceTransfer> {
+        decode(event, RESOURCE_TRANSFER)
+    }
+}
+
+/// Event type recording the fuel consumed by a component export call, see
+/// [`Config::chain_record_fuel`](crate::Config::chain_record_fuel).
+pub const FUEL: &str = "fuel";
+
+/// Payload of a [`FUEL`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuelConsumed {
+    /// Fuel consumed by the call, including by any calls nested inside it.
+    pub consumed: u64,
+    /// Fuel left in the store once the call returned.
+    pub remaining: u64,
+    /// Hash of the [`FUNCTION_CALL`] or [`TRAP`] event of the call, unlike
+    /// other payloads, so the fuel ends up inside the call in
+    /// [`Chain::call_tree`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl FuelConsumed {
+    /// Decodes the payload of a [`FUEL`] event.
+    pub fn decode(event: &Event) -> Result<FuelConsumed> {
+        decode(event, FUEL)
+    }
+}
+
+/// Event type of the engine's epoch reaching a store's deadline, see
+/// [`Config::epoch_interruption`](crate::Config::epoch_interruption).
+pub const EPOCH_INTERRUPT: &str = "epoch-interrupt";
+
+/// Payload of an [`EPOCH_INTERRUPT`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpochInterrupt {
+    /// Name of the innermost recorded export running, as in
+    /// [`FunctionCall::name`], or `None` if there isn't one.
+    pub export: Option<String>,
+    pub action: EpochAction,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl EpochInterrupt {
+    /// Decodes the payload of an [`EPOCH_INTERRUPT`] event.
+    pub fn decode(event: &Event) -> Result<EpochInterrupt> {
+        decode(event, EPOCH_INTERRUPT)
+    }
+}
+
+/// What a store did when its epoch deadline was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EpochAction {
+    /// Trapped with [`Trap::Interrupt`].
+    Trap,
+    /// Carried on with a deadline `delta` ticks away.
+    Continue { delta: u64 },
+    /// Yielded to the async executor, then carried on with a deadline
+    /// `delta` ticks away.
+    Yield { delta: u64 },
+}
+
+/// Event type of wasm yielding to the async executor.
+pub const YIELD: &str = "yield";
+
+/// Event type of wasm resuming after a [`YIELD`].
+pub const RESUME: &str = "resume";
+
+/// Payload of [`YIELD`] and [`RESUME`] events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YieldPoint {
+    /// Name of the innermost recorded export running, as in
+    /// [`FunctionCall::name`], or `None` if there isn't one.
+    pub export: Option<String>,
+    pub reason: YieldReason,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl YieldPoint {
+    /// Decodes the payload of a [`YIELD`] or [`RESUME`] event.
+    pub fn decode(event: &Event) -> Result<YieldPoint> {
+        match event.type_() {
+            YIELD => decode(event, YIELD),
+            _ => decode(event, RESUME),
+        }
+    }
+}
+
+/// Why wasm yielded to the async executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum YieldReason {
+    /// The epoch deadline callback returned `UpdateDeadline::Yield`.
+    Epoch,
+    /// The store's fuel yield interval ran out, see
+    /// [`Store::fuel_async_yield_interval`](crate::Store::fuel_async_yield_interval).
+    Fuel,
+}
+
+/// Event type of wasm executing `memory.grow`.
+pub const MEMORY_GROW: &str = "memory-grow";
+
+/// Event type of wasm executing `table.grow`.
+pub const TABLE_GROW: &str = "table-grow";
+
+/// Payload of [`MEMORY_GROW`] and [`TABLE_GROW`] events.
+///
+/// Sizes are in bytes for memories and in elements for tables.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Growth {
+    /// Name of the core module which grew the memory or table, if it has
+    /// one.
+    pub module: Option<String>,
+    /// Index of the memory or table in that module.
+    pub index: u32,
+    pub old_size: u64,
+    /// The size after growing, or `None` if growing failed.
+    pub new_size: Option<u64>,
+    /// Name of the innermost recorded export running, as in
+    /// [`FunctionCall::name`], or `None` if there isn't one.
+    pub export: Option<String>,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl Growth {
+    /// Decodes the payload of a [`MEMORY_GROW`] or [`TABLE_GROW`] event.
+    pub fn decode(event: &Event) -> Result<Growth> {
+        match event.type_() {
+            MEMORY_GROW => decode(event, MEMORY_GROW),
+            _ => decode(event, TABLE_GROW),
+        }
+    }
+}
+
+/// Event type of a component being instantiated.
+pub const INSTANTIATE: &str = "instantiate";
+
+/// Payload of an [`INSTANTIATE`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instantiation {
+    /// SHA-256 digest of the component's binary, see
+    /// [`Component::sha256`](crate::component::Component::sha256), or `None`
+    /// if it was compiled by an engine which didn't record instantiations.
+    pub component: Option<Digest>,
+    /// Names of the component's imports, all of which the linker satisfied.
+    pub imports: Vec<String>,
+    pub determinism: Determinism,
+    /// See [`FunctionCall::call_parent`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl Instantiation {
+    /// Decodes the payload of an [`INSTANTIATE`] event.
+    pub fn decode(event: &Event) -> Result<Instantiation> {
+        decode(event, INSTANTIATE)
+    }
+}
+
+/// Engine configuration which affects whether running a component twice
+/// gives the same results, as recorded in an [`Instantiation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Determinism {
+    /// See [`Config::cranelift_nan_canonicalization`](crate::Config::cranelift_nan_canonicalization).
+    pub nan_canonicalization: bool,
+    /// See [`Config::relaxed_simd_deterministic`](crate::Config::relaxed_simd_deterministic).
+    pub relaxed_simd_deterministic: bool,
+    /// See [`Config::consume_fuel`](crate::Config::consume_fuel).
+    pub consume_fuel: bool,
+    /// See [`Config::epoch_interruption`](crate::Config::epoch_interruption).
+    pub epoch_interruption: bool,
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(event: &Event, type_: &str) -> Result<T> {
+    if event.type_() != type_ {
+        bail!("expected a `{type_}` event, found `{}`", event.type_());
+    }
+    Ok(serde_json::from_slice(&event.data())?)
+}
+
+fn add<P: Serialize>(chain: &mut Chain, type_: &str, payload: &P) -> Result<Digest> {
+    chain.try_add(Event::new(type_.to_string(), serde_json::to_vec(payload)?))
+}
+
+/// Records a `function-call` event, along with the export's signature if
+/// `types` holds its parameter and result types.
+pub(crate) fn function_call(
+    store: &mut StoreOpaque,
+    name: &str,
+    params: &[Val],
+    results: &[Val],
+    types: Option<(&[(String, Type)], &[Type])>,
+) -> Result<Digest> {
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    let mut vals =
+        |direction, vals| register_vals(chain, registry, name, call_parent, direction, vals);
+    let params = vals(TransferDirection::ToGuest, params)?;
+    let results = vals(TransferDirection::ToHost, results)?;
+    // Resource types are numbered after the values' own, so recording the
+    // signature doesn't change the `type_id`s the values get.
+    let signature =
+        types.map(|(params, results)| FunctionSignature::new(params, results, registry));
+    let call = FunctionCall {
+        name: name.to_string(),
+        params,
+        results,
+        call_parent,
+        signature,
+    };
+    add(chain, FUNCTION_CALL, &call)
+}
+
+pub(crate) fn import_call(
+    store: &mut StoreOpaque,
+    name: &str,
+    params: Option<&[Val]>,
+) -> Result<()> {
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    let params = params
+        .map(|p| {
+            register_vals(
+                chain,
+                registry,
+                name,
+                call_parent,
+                TransferDirection::ToHost,
+                p,
+            )
+        })
+        .transpose()?;
+    let call = ImportCall {
+        name: name.to_string(),
+        params,
+        call_parent,
+    };
+    let hash = add(chain, IMPORT_CALL, &call)?;
+    store.push_import_frame(hash);
+    Ok(())
+}
+
+pub(crate) fn import_return(
+    store: &mut StoreOpaque,
+    name: &str,
+    results: Option<&[Val]>,
+) -> Result<()> {
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    let results = results
+        .map(|r| {
+            register_vals(
+                chain,
+                registry,
+                name,
+                call_parent,
+                TransferDirection::ToGuest,
+                r,
+            )
+        })
+        .transpose()?;
+    let ret = ImportReturn {
+        name: name.to_string(),
+        results,
+        call_parent,
+    };
+    add(chain, IMPORT_RETURN, &ret)?;
+    store.pop_import_frame();
+    Ok(())
+}
+
+pub(crate) fn trap(
+    store: &mut StoreOpaque,
+    name: String,
+    params: &[Val],
+    error: &Error,
+) -> Result<Digest> {
+    let frames = match error.downcast_ref::<WasmBacktrace>() {
+        Some(backtrace) => backtrace
+            .frames()
+            .iter()
+            .map(|frame| TrapFrame {
+                module: frame.module().name().map(str::to_string),
+                func_index: frame.func_index(),
+                func_name: frame.func_name().map(str::to_string),
+                offset: frame.module_offset(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    let import = chain
+        .store()
+        .head()
+        .filter(|node| node.event().type_() == IMPORT_CALL)
+        .map(|node| node.hash());
+    let params = register_vals(
+        chain,
+        registry,
+        &name,
+        call_parent,
+        TransferDirection::ToGuest,
+        params,
+    )?;
+    let trap = CallTrap {
+        name,
+        params,
+        code: error.downcast_ref::<Trap>().map(|code| format!("{code:?}")),
+        message: error.root_cause().to_string(),
+        frames,
+        import,
+        call_parent,
+    };
+    let event = Event::new(TRAP.to_string(), serde_json::to_vec(&trap)?);
+    chain.try_add(event.with_severity(Severity::Error))
+}
+
+/// Records the fuel used by the call recorded as `call`, given the fuel the
+/// store had before it.
+pub(crate) fn fuel(store: &mut StoreOpaque, call: Digest, before: u64) -> Result<()> {
+    let remaining = store.get_fuel()?;
+    let (chain, _) = store.chain_and_registry_mut()?;
+    let fuel = FuelConsumed {
+        consumed: before.saturating_sub(remaining),
+        remaining,
+        call_parent: Some(call),
+    };
+    add(chain, FUEL, &fuel)?;
+    Ok(())
+}
+
+pub(crate) fn epoch_interrupt(store: &mut StoreOpaque, action: EpochAction) -> Result<()> {
+    let interrupt = EpochInterrupt {
+        export: store.current_export().map(str::to_string),
+        action,
+        call_parent: store.call_parent(),
+    };
+    let (chain, _) = store.chain_and_registry_mut()?;
+    add(chain, EPOCH_INTERRUPT, &interrupt)?;
+    Ok(())
+}
+
+pub(crate) fn yield_point(store: &mut StoreOpaque, type_: &str, reason: YieldReason) -> Result<()> {
+    let point = YieldPoint {
+        export: store.current_export().map(str::to_string),
+        reason,
+        call_parent: store.call_parent(),
+    };
+    let (chain, _) = store.chain_and_registry_mut()?;
+    add(chain, type_, &point)?;
+    Ok(())
+}
+
+pub(crate) fn growth(
+    store: &mut StoreOpaque,
+    type_: &str,
+    module: Option<String>,
+    index: u32,
+    old_size: usize,
+    new_size: Option<usize>,
+) -> Result<()> {
+    let growth = Growth {
+        module,
+        index,
+        old_size: u64::try_from(old_size)?,
+        new_size: new_size.map(u64::try_from).transpose()?,
+        export: store.current_export().map(str::to_string),
+        call_parent: store.call_parent(),
+    };
+    let (chain, _) = store.chain_and_registry_mut()?;
+    add(chain, type_, &growth)?;
+    Ok(())
+}
+
+pub(crate) fn instantiation(store: &mut StoreOpaque, component: &Component) -> Result<()> {
+    let engine = store.engine();
+    let tunables = engine.tunables();
+    let instantiation = Instantiation {
+        component: component.sha256(),
+        imports: component
+            .env_component()
+            .import_types
+            .values()
+            .map(|(name, _)| name.clone())
+            .collect(),
+        determinism: Determinism {
+            nan_canonicalization: engine.config().nan_canonicalization(),
+            relaxed_simd_deterministic: tunables.relaxed_simd_deterministic,
+            consume_fuel: tunables.consume_fuel,
+            epoch_interruption: tunables.epoch_interruption,
+        },
+        call_parent: store.call_parent(),
+    };
+    let (chain, _) = store.chain_and_registry_mut()?;
+    add(chain, INSTANTIATE, &instantiation)?;
+    Ok(())
+}
+
+pub(crate) fn resource_drop(store: &mut StoreOpaque, resource: &ResourceAny) -> Result<()> {
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    if let Some(resource) = registry.unregister(resource) {
+        let drop = ResourceLifecycle {
+            resource,
+            call_parent,
+        };
+        add(chain, RESOURCE_DROP, &drop)?;
+    }
+    Ok(())
+}
+
+/// A recorded export call still waiting for its `post-return`.
+#[derive(Debug)]
+pub(crate) struct PendingPostReturn {
+    function_call: Digest,
+    /// The name of the export called.
+    name: String,
+    /// The `borrow` handles the host lent to the call.
+    borrows: Vec<ResourceAny>,
+}
+
+impl PendingPostReturn {
+    pub(crate) fn new(
+        function_call: Digest,
+        name: String,
+        params: &[Val],
+        types: &[(String, Type)],
+    ) -> PendingPostReturn {
+        let mut borrows = Vec::new();
+        for (val, (_, ty)) in params.iter().zip(types) {
+            collect_borrows(val, ty, &mut borrows);
+        }
+        PendingPostReturn {
+            function_call,
+            name,
+            borrows,
+        }
+    }
+}
+
+/// Records the `post-return` of the export call `pending`, followed by the
+/// return of each handle lent to it.
+pub(crate) fn post_return(
+    store: &mut StoreOpaque,
+    pending: PendingPostReturn,
+    ran: bool,
+) -> Result<()> {
+    let call_parent = store.call_parent();
+    let (chain, registry) = store.chain_and_registry_mut()?;
+    let post_return = PostReturn {
+        name: pending.name,
+        function_call: pending.function_call,
+        ran,
+        call_parent,
+    };
+    add(chain, POST_RETURN, &post_return)?;
+    for resource in pending.borrows {
+        let Some(resource) = registry.lookup(&resource) else {
+            continue;
+        };
+        let returned = ResourceReturn {
+            resource,
+            function_call: pending.function_call,
+            call_parent,
+        };
+        add(chain, RESOURCE_RETURN, &returned)?;
+    }
+    Ok(())
+}
+
+/// Converts `vals`, which crossed the boundary in `direction` through
+/// `call`, recording the resources inside them as they're registered.
+fn register_vals(
+    chain: &mut Chain,
+    registry: &mut ResourceRegistry,
+    call: &str,
+    call_parent: Option<Digest>,
+    direction: TransferDirection,
+    vals: &[Val],
+) -> Result<Vec<SerializableVal>> {
+    let mut resources = Vec::new();
+    vals.iter()
+        .for_each(|v| collect_resources(v, &mut resources));
+    for resource in resources {
+        let new = registry.lookup(&resource).is_none();
+        let resource = registry.register(resource);
+        if new {
+            let new = ResourceLifecycle {
+                resource,
+                call_parent,
+            };
+            add(chain, RESOURCE_NEW, &new)?;
+        }
+        let transfer = ResourceTransfer {
+            resource,
+            call: call.to_string(),
+            direction,
+            call_parent,
+        };
+        add(chain, RESOURCE_TRANSFER, &transfer)?;
+    }
+    Ok(SerializableVal::from_vals_with(vals, registry)?)
+}
+
+fn collect_resources(val: &Val, resources: &mut Vec<ResourceAny>) {
+    match val {
+        Val::Resource(r) => resources.push(*r),
+        Val::List(vals) | Val::Tuple(vals) => {
+            vals.iter().for_each(|v| collect_resources(v, resources));
+        }
+        Val::Record(fields) => {
+            fields
+                .iter()
+                .for_each(|(_, v)| collect_resources(v, resources));
+        }
+        Val::Variant(_, Some(v))
+        | Val::Option(Some(v))
+        | Val::Result(Ok(Some(v)))
+        | Val::Result(Err(Some(v))) => collect_resources(v, resources),
+        _ => {}
+    }
+}
+
+/// Collects the resources in `val` which its type `ty` passes as `borrow`
+/// handles.
+fn collect_borrows(val: &Val, ty: &Type, borrows: &mut Vec<ResourceAny>) {
+    match (val, ty) {
+        (Val::Resource(r), Type::Borrow(_)) => borrows.push(*r),
+        (Val::List(vals), Type::List(list)) => {
+            let ty = list.ty();
+            vals.iter().for_each(|v| collect_borrows(v, &ty, borrows));
+        }
+        (Val::Tuple(vals), Type::Tuple(tuple)) => {
+            for (v, ty) in vals.iter().zip(tuple.types()) {
+                collect_borrows(v, &ty, borrows);
+            }
+        }
+        (Val::Record(fields), Type::Record(record)) => {
+            for ((_, v), field) in fields.iter().zip(record.fields()) {
+                collect_borrows(v, &field.ty, borrows);
+            }
+        }
+        (Val::Variant(name, Some(v)), Type::Variant(variant)) => {
+            let case = variant.cases().find(|case| case.name == name.as_str());
+            if let Some(ty) = case.and_then(|case| case.ty) {
+                collect_borrows(v, &ty, borrows);
+            }
+        }
+        (Val::Option(Some(v)), Type::Option(option)) => collect_borrows(v, &option.ty(), borrows),
+        (Val::Result(Ok(Some(v))), Type::Result(result)) => {
+            if let Some(ty) = result.ok() {
+                collect_borrows(v, &ty, borrows);
+            }
+        }
+        (Val::Result(Err(Some(v))), Type::Result(result)) => {
+            if let Some(ty) = result.err() {
+                collect_borrows(v, &ty, borrows);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::ChainWasiPolicy;
+    use crate::component::{Component, Linker};
+    use crate::{Config, Engine, Store};
+
+    const COMPONENT: &str = r#"
+        (component
+            (core module $m
+                (func (export "inc") (param i32) (result i32)
+                    (i32.add (local.get 0) (i32.const 1))))
+            (core instance $i (instantiate $m))
+            (func $inc (param "x" u32) (result u32) (canon lift (core func $i "inc")))
+            (export "inc" (func $inc))
+            (instance $nested (export "inc" (func $inc)))
+            (export "nested" (instance $nested))
+        )
+    "#;
+
+    fn call_inc(record: bool) -> Result<Store<()>> {
+        let mut config = Config::new();
+        config.chain_record(record);
+        call_inc_with(&config)
+    }
+
+    /// Calls `inc` and then `nested#inc`.
+    fn call_inc_with(config: &Config) -> Result<Store<()>> {
+        let engine = Engine::new(config)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+
+        let mut results = [Val::U32(0)];
+        let inc = instance.get_func(&mut store, "inc").unwrap();
+        inc.call(&mut store, &[Val::U32(1)], &mut results)?;
+        inc.post_return(&mut store)?;
+
+        let nested = instance.get_export(&mut store, None, "nested").unwrap();
+        let index = instance
+            .get_export(&mut store, Some(&nested), "inc")
+            .unwrap();
+        let inc = instance.get_func(&mut store, &index).unwrap();
+        inc.call(&mut store, &[Val::U32(5)], &mut results)?;
+        inc.post_return(&mut store)?;
+        Ok(store)
+    }
+
+    #[test]
+    fn records_export_calls() -> Result<()> {
+        let store = call_inc(true)?;
+        let chain = store.chain();
+        chain.verify()?;
+
+        let head = chain.head().unwrap();
+        let second = FunctionCall::decode(chain.get_event_by_hash(head).unwrap().event())?;
+        assert_eq!(second.name, "nested#inc");
+        assert!(matches!(&second.params[..], [SerializableVal::U32(5)]));
+        assert!(matches!(&second.results[..], [SerializableVal::U32(6)]));
+
+        let first = chain.get_parent(head).unwrap();
+        let first = FunctionCall::decode(first.event())?;
+        assert_eq!(first.name, "inc");
+        assert!(matches!(&first.results[..], [SerializableVal::U32(2)]));
+        Ok(())
+    }
+
+    #[test]
+    fn records_filtered_calls() -> Result<()> {
+        let cases: [(&[&str], &[&str], &[&str]); 4] = [
+            (&[], &[], &["inc", "nested#inc"]),
+            (&["nested#*"], &[], &["nested#inc"]),
+            (&[], &["inc"], &["nested#inc"]),
+            (&["*inc"], &["n?sted#*"], &["inc"]),
+        ];
+        for (allow, deny, expected) in cases {
+            let mut config = Config::new();
+            config
+                .chain_record(true)
+                .chain_record_allow(allow)
+                .chain_record_deny(deny);
+            let store = call_inc_with(&config)?;
+            let names = store
+                .chain()
+                .events()
+                .map(|node| FunctionCall::decode(node.event()).map(|call| call.name))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(names, expected, "allow {allow:?}, deny {deny:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn records_import_calls() -> Result<()> {
+        let component = r#"
+            (component
+                (import "host" (instance $host
+                    (export "double" (func (param "x" u32) (result u32)))
+                    (export "negate" (func (param "x" s32) (result s32)))
+                ))
+                (core func $double (canon lower (func $host "double")))
+                (core func $negate (canon lower (func $host "negate")))
+                (core module $m
+                    (import "" "double" (func $double (param i32) (result i32)))
+                    (import "" "negate" (func $negate (param i32) (result i32)))
+                    (func (export "run") (param i32) (result i32)
+                        (call $negate (call $double (local.get 0)))))
+                (core instance $i (instantiate $m
+                    (with "" (instance
+                        (export "double" (func $double))
+                        (export "negate" (func $negate))))))
+                (func (export "run") (param "x" s32) (result s32)
+                    (canon lift (core func $i "run")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::new(&engine);
+        let mut host = linker.instance("host")?;
+        host.func_wrap("double", |_, (x,): (u32,)| Ok((x * 2,)))?;
+        host.func_new("negate", |_, params, results| {
+            let Val::S32(x) = params[0] else {
+                bail!("expected s32")
+            };
+            results[0] = Val::S32(-x);
+            Ok(())
+        })?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let run = instance.get_func(&mut store, "run").unwrap();
+        let mut results = [Val::S32(0)];
+        run.call(&mut store, &[Val::S32(3)], &mut results)?;
+
+        let chain = store.chain();
+        chain.verify()?;
+        let mut events = Vec::new();
+        let mut cur = chain.head();
+        while let Some(hash) = cur {
+            let event = chain.get_event_by_hash(hash).unwrap().event();
+            events.push(event);
+            cur = event.parent();
+        }
+        events.reverse();
+        assert_eq!(events.len(), 5);
+
+        let call = ImportCall::decode(events[0])?;
+        assert_eq!(call.name, "host#double");
+        assert!(matches!(
+            call.params.as_deref(),
+            Some([SerializableVal::U32(3)])
+        ));
+        let ret = ImportReturn::decode(events[1])?;
+        assert!(matches!(
+            ret.results.as_deref(),
+            Some([SerializableVal::U32(6)])
+        ));
+
+        let call = ImportCall::decode(events[2])?;
+        assert_eq!(call.name, "host#negate");
+        assert!(matches!(
+            call.params.as_deref(),
+            Some([SerializableVal::S32(6)])
+        ));
+        let ret = ImportReturn::decode(events[3])?;
+        assert!(matches!(
+            ret.results.as_deref(),
+            Some([SerializableVal::S32(-6)])
+        ));
+
+        assert_eq!(FunctionCall::decode(events[4])?.name, "run");
+        Ok(())
+    }
+
+    #[test]
+    fn records_traps() -> Result<()> {
+        let component = r#"
+            (component
+                (import "fail" (func $fail))
+                (core func $fail (canon lower (func $fail)))
+                (core module $m
+                    (import "" "fail" (func $fail))
+                    (func (export "crash") (param i32)
+                        unreachable)
+                    (func (export "call-fail")
+                        (call $fail)))
+                (core instance $i (instantiate $m
+                    (with "" (instance (export "fail" (func $fail))))))
+                (func (export "crash") (param "x" u32)
+                    (canon lift (core func $i "crash")))
+                (func (export "call-fail")
+                    (canon lift (core func $i "call-fail")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::new(&engine);
+        linker
+            .root()
+            .func_new("fail", |_, _, _| bail!("host failure"))?;
+
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let crash = instance.get_func(&mut store, "crash").unwrap();
+        assert!(crash.call(&mut store, &[Val::U32(7)], &mut []).is_err());
+
+        let chain = store.chain();
+        let node = chain.store().head().unwrap();
+        let trap = CallTrap::decode(node.event())?;
+        assert_eq!(trap.name, "crash");
+        assert_eq!(node.event().severity(), Some(Severity::Error));
+        assert!(matches!(&trap.params[..], [SerializableVal::U32(7)]));
+        assert_eq!(trap.code.as_deref(), Some("UnreachableCodeReached"));
+        assert_eq!(trap.frames.len(), 1);
+        assert_eq!(trap.frames[0].func_index, 1);
+        assert!(trap.import.is_none());
+
+        // A failing host import is recorded along with the import call that
+        // was in flight.
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let call_fail = instance.get_func(&mut store, "call-fail").unwrap();
+        assert!(call_fail.call(&mut store, &[], &mut []).is_err());
+
+        let chain = store.chain();
+        chain.verify()?;
+        let head = chain.store().head().unwrap();
+        let trap = CallTrap::decode(head.event())?;
+        let import = chain.get_parent(head.hash()).unwrap();
+        assert_eq!(ImportCall::decode(import.event())?.name, "fail");
+        assert_eq!(trap.import, Some(import.hash()));
+        assert_eq!(trap.code, None);
+        assert_eq!(trap.message, "host failure");
+        Ok(())
+    }
+
+    #[test]
+    fn records_fuel() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m
+                    (func (export "spin") (param i32)
+                        (loop $l
+                            (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))))
+                (core instance $i (instantiate $m))
+                (func (export "spin") (param "n" u32)
+                    (canon lift (core func $i "spin")))
+            )
+        "#;
+        let mut config = Config::new();
+        config
+            .chain_record(true)
+            .chain_record_fuel(true)
+            .consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(10_000)?;
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let spin = instance.get_func(&mut store, "spin").unwrap();
+        let mut consumed = Vec::new();
+        for n in [10, 100] {
+            spin.call(&mut store, &[Val::U32(n)], &mut [])?;
+            spin.post_return(&mut store)?;
+            let chain = store.chain();
+            let fuel = FuelConsumed::decode(chain.store().head().unwrap().event())?;
+            let call = chain.get_parent(chain.head().unwrap()).unwrap();
+            assert_eq!(FunctionCall::decode(call.event())?.name, "spin");
+            assert_eq!(fuel.call_parent, Some(call.hash()));
+            assert_eq!(fuel.remaining, store.get_fuel()?);
+            consumed.push(fuel.consumed);
+        }
+        assert!(consumed[0] > 0 && consumed[1] > consumed[0]);
+
+        // Running out of fuel is recorded as a trap followed by the fuel
+        // used up to that point.
+        assert!(spin
+            .call(&mut store, &[Val::U32(100_000)], &mut [])
+            .is_err());
+        let chain = store.chain();
+        let fuel = FuelConsumed::decode(chain.store().head().unwrap().event())?;
+        assert_eq!(fuel.remaining, 0);
+        let trap = chain.get_parent(chain.head().unwrap()).unwrap();
+        assert_eq!(
+            CallTrap::decode(trap.event())?.code.as_deref(),
+            Some("OutOfFuel")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn records_types() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m (func (export "id") (param i32) (result i32) local.get 0))
+                (core instance $i (instantiate $m))
+                (func (export "id") (param "n" u32) (result u32)
+                    (canon lift (core func $i "id")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).chain_record_types(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let id = instance.get_func(&mut store, "id").unwrap();
+        let mut results = [Val::U32(0)];
+        id.call(&mut store, &[Val::U32(7)], &mut results)?;
+        id.post_return(&mut store)?;
+
+        let chain = store.chain();
+        let call = FunctionCall::decode(chain.store().head().unwrap().event())?;
+        let signature = call.signature.unwrap();
+        assert_eq!(signature.to_string(), "func(n: u32) -> u32");
+        assert_eq!(signature.check(&call.params, &call.results), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn records_core_calls() -> Result<()> {
+        let module = r#"
+            (module
+                (func (export "add") (param i32 i64) (result i64)
+                    local.get 0
+                    i64.extend_i32_s
+                    local.get 1
+                    i64.add)
+            )
+        "#;
+        let engine = Engine::default();
+        let module = crate::Module::new(&engine, module)?;
+        let mut store = Store::new(&engine, ());
+        let instance = crate::Instance::new(&mut store, &module, &[])?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        let params = [crate::Val::I32(-2), crate::Val::I64(5)];
+        let mut results = [crate::Val::I64(0)];
+        add.call(&mut store, &params, &mut results)?;
+        let hash = CoreCall::record(&mut store, "add", &params, &results)?;
+
+        let chain = store.chain();
+        assert_eq!(chain.head(), Some(hash));
+        let call = CoreCall::decode(chain.store().head().unwrap().event())?;
+        assert_eq!(call.name, "add");
+        assert_eq!(
+            call.params,
+            [SerializableCoreVal::I32(-2), SerializableCoreVal::I64(5)]
+        );
+        assert_eq!(call.results, [SerializableCoreVal::I64(3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn records_epoch_interrupts() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m (func (export "run")))
+                (core instance $i (instantiate $m))
+                (func (export "run") (canon lift (core func $i "run")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let run = instance.get_func(&mut store, "run").unwrap();
+
+        store.set_epoch_deadline(0);
+        store.epoch_deadline_callback(|_| Ok(crate::UpdateDeadline::Continue(1)));
+        run.call(&mut store, &[], &mut [])?;
+        run.post_return(&mut store)?;
+        store.epoch_deadline_trap();
+        store.set_epoch_deadline(0);
+        assert!(run.call(&mut store, &[], &mut []).is_err());
+
+        let chain = store.chain();
+        let events = chain.iter().map(|n| n.event()).collect::<Vec<_>>();
+        assert_eq!(events.len(), 4);
+        let interrupt = EpochInterrupt::decode(events[0])?;
+        assert_eq!(interrupt.export.as_deref(), Some("run"));
+        assert_eq!(interrupt.action, EpochAction::Continue { delta: 1 });
+        assert_eq!(events[1].type_(), FUNCTION_CALL);
+        assert_eq!(EpochInterrupt::decode(events[2])?.action, EpochAction::Trap);
+        assert_eq!(
+            CallTrap::decode(events[3])?.code.as_deref(),
+            Some("Interrupt")
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn records_fuel_yields() -> Result<()> {
+        use core::future::Future;
+        use core::pin::pin;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Noop;
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(Noop));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = pin!(future);
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
+
+        let component = r#"
+            (component
+                (core module $m
+                    (func (export "spin") (param i32)
+                        (loop $l
+                            (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))))
+                (core instance $i (instantiate $m))
+                (func (export "spin") (param "n" u32)
+                    (canon lift (core func $i "spin")))
+            )
+        "#;
+        let mut config = Config::new();
+        config
+            .chain_record(true)
+            .consume_fuel(true)
+            .async_support(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(u64::MAX)?;
+        store.fuel_async_yield_interval(Some(100))?;
+        block_on(async {
+            let instance = Linker::new(&engine)
+                .instantiate_async(&mut store, &component)
+                .await?;
+            let spin = instance.get_func(&mut store, "spin").unwrap();
+            spin.call_async(&mut store, &[Val::U32(200)], &mut [])
+                .await?;
+            spin.post_return_async(&mut store).await
+        })?;
+
+        let chain = store.chain();
+        let yields = chain.events_of_type(YIELD).collect::<Vec<_>>();
+        assert!(!yields.is_empty());
+        assert_eq!(chain.events_of_type(RESUME).count(), yields.len());
+        let point = YieldPoint::decode(yields[0].event())?;
+        assert_eq!(point.export.as_deref(), Some("spin"));
+        assert_eq!(point.reason, YieldReason::Fuel);
+        assert_eq!(chain.store().head().unwrap().event().type_(), FUNCTION_CALL);
+        Ok(())
+    }
+
+    #[test]
+    fn records_growth() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m
+                    (memory 1 2)
+                    (table 1 funcref)
+                    (func (export "grow") (result i32)
+                        (drop (table.grow (ref.null func) (i32.const 2)))
+                        (memory.grow (i32.const 1))))
+                (core instance $i (instantiate $m))
+                (func (export "grow") (result s32)
+                    (canon lift (core func $i "grow")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let grow = instance.get_func(&mut store, "grow").unwrap();
+        let mut results = [Val::S32(0)];
+        for _ in 0..2 {
+            grow.call(&mut store, &[], &mut results)?;
+            grow.post_return(&mut store)?;
+        }
+        assert_eq!(results[0], Val::S32(-1));
+
+        let chain = store.chain();
+        let types = chain.iter().map(|n| n.event().type_()).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                TABLE_GROW,
+                MEMORY_GROW,
+                FUNCTION_CALL,
+                TABLE_GROW,
+                MEMORY_GROW,
+                FUNCTION_CALL
+            ]
+        );
+        let growth = chain
+            .iter()
+            .filter(|n| n.event().type_() != FUNCTION_CALL)
+            .map(|n| Growth::decode(n.event()))
+            .collect::<Result<Vec<_>>>()?;
+        let sizes = growth
+            .iter()
+            .map(|g| (g.old_size, g.new_size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [
+                (1, Some(3)),
+                (65536, Some(131072)),
+                (3, Some(5)),
+                (131072, None)
+            ]
+        );
+        assert!(growth
+            .iter()
+            .all(|g| g.index == 0 && g.export.as_deref() == Some("grow")));
+        Ok(())
+    }
+
+    #[test]
+    fn sampled_out_calls_record_nothing() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m
+                    (memory 1)
+                    (func (export "grow") (result i32)
+                        (memory.grow (i32.const 1))))
+                (core instance $i (instantiate $m))
+                (func (export "grow") (result s32)
+                    (canon lift (core func $i "grow")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        store.set_chain_sampling(crate::chain::Sampling::OneIn(2));
+        store.epoch_deadline_callback(|_| Ok(crate::UpdateDeadline::Continue(1)));
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let grow = instance.get_func(&mut store, "grow").unwrap();
+        let mut results = [Val::S32(0)];
+        for _ in 0..2 {
+            store.set_epoch_deadline(0);
+            grow.call(&mut store, &[], &mut results)?;
+            grow.post_return(&mut store)?;
+        }
+        assert_eq!(results[0], Val::S32(2));
+
+        let types = store
+            .chain()
+            .iter()
+            .map(|n| n.event().type_())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [EPOCH_INTERRUPT, MEMORY_GROW, FUNCTION_CALL]);
+        Ok(())
+    }
+
+    #[test]
+    fn records_instantiation() -> Result<()> {
+        let component = r#"
+            (component
+                (import "host" (instance $host (export "log" (func))))
+                (import "level" (func $level (result u32)))
+            )
+        "#;
+        let mut config = Config::new();
+        config
+            .chain_record(true)
+            .chain_record_instantiation(true)
+            .cranelift_nan_canonicalization(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::new(&engine);
+        linker.instance("host")?.func_wrap("log", |_, ()| Ok(()))?;
+        linker.root().func_wrap("level", |_, ()| Ok((1u32,)))?;
+        let mut store = Store::new(&engine, ());
+        linker.instantiate(&mut store, &component)?;
+
+        let chain = store.chain();
+        let instantiation = Instantiation::decode(chain.store().head().unwrap().event())?;
+        assert!(component.sha256().is_some());
+        assert_eq!(instantiation.component, component.sha256());
+        assert_eq!(instantiation.imports, ["host", "level"]);
+        assert!(instantiation.determinism.nan_canonicalization);
+        assert!(!instantiation.determinism.consume_fuel);
+
+        // The digest is of the original binary, so it survives
+        // serialization and differs between components.
+        let serialized = component.serialize()?;
+        let deserialized = unsafe { Component::deserialize(&engine, serialized)? };
+        assert_eq!(deserialized.sha256(), component.sha256());
+        let other = Component::new(&engine, "(component)")?;
+        assert_ne!(other.sha256(), component.sha256());
+
+        // Engines which don't record instantiations don't digest binaries.
+        let other = Component::new(&Engine::default(), "(component)")?;
+        assert_eq!(other.sha256(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn records_resource_lifecycle() -> Result<()> {
+        let component = r#"
+            (component
+                (type $t' (resource (rep i32)))
+                (export $t "t" (type $t'))
+                (core func $t_ctor (canon resource.new $t))
+                (func (export "[constructor]t") (param "x" u32) (result (own $t))
+                    (canon lift (core func $t_ctor)))
+                (core func $t_drop (canon resource.drop $t))
+                (func (export "consume") (param "x" (own $t))
+                    (canon lift (core func $t_drop)))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let ctor = instance.get_func(&mut store, "[constructor]t").unwrap();
+        let consume = instance.get_func(&mut store, "consume").unwrap();
+
+        let mut results = [Val::Bool(false)];
+        ctor.call(&mut store, &[Val::U32(1)], &mut results)?;
+        ctor.post_return(&mut store)?;
+        consume.call(&mut store, &results, &mut [])?;
+        consume.post_return(&mut store)?;
+
+        ctor.call(&mut store, &[Val::U32(2)], &mut results)?;
+        ctor.post_return(&mut store)?;
+        let Val::Resource(handle) = results[0] else {
+            panic!("expected a resource")
+        };
+        handle.resource_drop(&mut store)?;
+
+        let chain = store.chain();
+        chain.verify()?;
+        let events = chain.events().map(|n| n.event()).collect::<Vec<_>>();
+        let types = events.iter().map(|e| e.type_()).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                RESOURCE_NEW,
+                RESOURCE_TRANSFER,
+                FUNCTION_CALL,
+                RESOURCE_TRANSFER,
+                FUNCTION_CALL,
+                RESOURCE_NEW,
+                RESOURCE_TRANSFER,
+                FUNCTION_CALL,
+                RESOURCE_DROP,
+            ]
+        );
+
+        let first = ResourceLifecycle::decode(events[0])?.resource;
+        assert!(first.owned);
+        let returned = ResourceTransfer::decode(events[1])?;
+        assert_eq!(returned.resource, first);
+        assert_eq!(returned.call, "[constructor]t");
+        assert_eq!(returned.direction, TransferDirection::ToHost);
+        let consumed = ResourceTransfer::decode(events[3])?;
+        assert_eq!(consumed.resource, first);
+        assert_eq!(consumed.direction, TransferDirection::ToGuest);
+
+        let second = ResourceLifecycle::decode(events[5])?.resource;
+        assert_ne!(second, first);
+        assert_eq!(ResourceLifecycle::decode(events[8])?.resource, second);
+        assert_eq!(store.resource_registry().lookup(&handle), None);
+        Ok(())
+    }
+
+    #[test]
+    fn records_post_returns() -> Result<()> {
+        let component = r#"
+            (component
+                (type $t' (resource (rep i32)))
+                (export $t "t" (type $t'))
+                (core func $t_ctor (canon resource.new $t))
+                (func (export "[constructor]t") (param "x" u32) (result (own $t))
+                    (canon lift (core func $t_ctor)))
+                (core module $m
+                    (func (export "peek") (param i32) (result i32) (local.get 0))
+                    (func (export "post") (param i32)))
+                (core instance $i (instantiate $m))
+                (func (export "peek") (param "x" (borrow $t)) (result u32)
+                    (canon lift (core func $i "peek") (post-return (func $i "post"))))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).chain_record_post_return(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let ctor = instance.get_func(&mut store, "[constructor]t").unwrap();
+        let peek = instance.get_func(&mut store, "peek").unwrap();
+
+        let mut results = [Val::Bool(false)];
+        ctor.call(&mut store, &[Val::U32(7)], &mut results)?;
+        ctor.post_return(&mut store)?;
+        let mut rep = [Val::U32(0)];
+        peek.call(&mut store, &results, &mut rep)?;
+        assert!(matches!(rep, [Val::U32(7)]));
+        peek.post_return(&mut store)?;
+
+        let chain = store.chain();
+        chain.verify()?;
+        let nodes = chain.events().collect::<Vec<_>>();
+        let types = nodes.iter().map(|n| n.event().type_()).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                RESOURCE_NEW,
+                RESOURCE_TRANSFER,
+                FUNCTION_CALL,
+                POST_RETURN,
+                RESOURCE_TRANSFER,
+                FUNCTION_CALL,
+                POST_RETURN,
+                RESOURCE_RETURN,
+            ]
+        );
+
+        let resource = ResourceLifecycle::decode(nodes[0].event())?.resource;
+        let ctor = PostReturn::decode(nodes[3].event())?;
+        assert_eq!(ctor.name, "[constructor]t");
+        assert_eq!(ctor.function_call, nodes[2].hash());
+        assert!(!ctor.ran);
+        let peek = PostReturn::decode(nodes[6].event())?;
+        assert_eq!(peek.name, "peek");
+        assert_eq!(peek.function_call, nodes[5].hash());
+        assert!(peek.ran);
+        let returned = ResourceReturn::decode(nodes[7].event())?;
+        assert_eq!(returned.resource, resource);
+        assert_eq!(returned.function_call, nodes[5].hash());
+        Ok(())
+    }
+
+    #[test]
+    fn wasi_recording_is_opt_in() -> Result<()> {
+        let component = r#"
+            (component
+                (import "wasi:random/random@0.2.0" (instance $random
+                    (export "get-random-u64" (func (result u64)))
+                ))
+                (core func $get (canon lower (func $random "get-random-u64")))
+                (core module $m
+                    (import "" "get" (func $get (result i64)))
+                    (func (export "roll") (result i64) (call $get)))
+                (core instance $i (instantiate $m
+                    (with "" (instance (export "get" (func $get))))))
+                (func (export "roll") (result u64)
+                    (canon lift (core func $i "roll")))
+            )
+        "#;
+        let roll = |policy: Option<ChainWasiPolicy>| -> Result<Vec<String>> {
+            let mut config = Config::new();
+            config.chain_record(true).chain_record_wasi(true);
+            if let Some(policy) = policy {
+                config.chain_wasi_policy(policy);
+            }
+            let engine = Engine::new(&config)?;
+            let component = Component::new(&engine, component)?;
+            let mut linker = Linker::new(&engine);
+            linker
+                .instance("wasi:random/random@0.2.0")?
+                .func_wrap("get-random-u64", |_, ()| Ok((4u64,)))?;
+            let mut store = Store::new(&engine, ());
+            let instance = linker.instantiate(&mut store, &component)?;
+            let roll = instance.get_func(&mut store, "roll").unwrap();
+            roll.call(&mut store, &[], &mut [Val::U64(0)])?;
+            let types = store
+                .chain()
+                .events()
+                .map(|n| n.event().type_().to_string());
+            Ok(types.collect())
+        };
+
+        let recorded = [IMPORT_CALL, IMPORT_RETURN, FUNCTION_CALL];
+        assert_eq!(roll(Some(ChainWasiPolicy::none()))?, [FUNCTION_CALL]);
+        assert_eq!(roll(None)?, recorded);
+        let policy = ChainWasiPolicy::all().random(false);
+        assert_eq!(roll(Some(policy))?, [FUNCTION_CALL]);
+        let policy = ChainWasiPolicy::none().clocks(true).random(true);
+        assert_eq!(roll(Some(policy))?, recorded);
+        Ok(())
+    }
+
+    #[test]
+    fn recording_is_opt_in() -> Result<()> {
+        let store = call_inc(false)?;
+        assert!(store.chain().head().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn host_functions_add_events() -> Result<()> {
+        let component = r#"
+            (component
+                (import "host" (instance $host (export "log" (func))))
+                (core func $log (canon lower (func $host "log")))
+                (core module $m
+                    (import "" "log" (func $log))
+                    (func (export "run") (call $log)))
+                (core instance $i (instantiate $m
+                    (with "" (instance (export "log" (func $log))))))
+                (func (export "run") (canon lift (core func $i "run")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::new(&engine);
+        linker.instance("host")?.func_wrap("log", |mut store, ()| {
+            store
+                .chain_mut()
+                .add(Event::new("log".to_string(), b"hello".to_vec()));
+            Ok(())
+        })?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let run = instance.get_func(&mut store, "run").unwrap();
+        run.call(&mut store, &[], &mut [])?;
+
+        let chain = store.chain();
+        chain.verify()?;
+        let types = chain
+            .events()
+            .map(|e| e.event().type_())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [IMPORT_CALL, "log", IMPORT_RETURN, FUNCTION_CALL]);
+        Ok(())
+    }
+}
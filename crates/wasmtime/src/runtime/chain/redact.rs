@@ -0,0 +1,256 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeping sensitive data out of chains.
+//!
+//! A chain with a [`ChainRedactor`] passes the payload of every event through
+//! it before the event is hashed and stored, see [`Chain::set_redactor`], so
+//! the original payload never reaches the chain's store. Child chains spawned
+//! from the chain use the same redactor.
+//!
+//! [`FieldRedactor`] redacts named fields of JSON payloads, such as those of
+//! recorded calls, while keeping their structure: values keep their types,
+//! so payloads still decode and [replays](crate::chain::replay) still know
+//! which calls were made. A replay compares the hashes of the events it
+//! records with the recorded ones, so its store's chain needs the same
+//! redactor for them to match.
+
+use crate::chain::{Chain, Digest};
+use crate::prelude::*;
+use core::fmt;
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use std::sync::Arc;
+
+/// Rewrites event payloads before they're added to a chain, see the
+/// [module documentation](self).
+pub trait ChainRedactor: fmt::Debug + Send + Sync {
+    /// Returns the payload to store for an event of type `event_type` in
+    /// place of `data`.
+    fn redact(&self, event_type: &str, data: Vec<u8>) -> Vec<u8>;
+}
+
+impl Chain {
+    /// Sets the redactor for events added from now on, and for child chains
+    /// spawned from now on, or stops redacting if `redactor` is `None`.
+    pub fn set_redactor(&mut self, redactor: Option<Arc<dyn ChainRedactor>>) {
+        self.redactor = redactor;
+    }
+
+    /// The redactor set with [`Chain::set_redactor`].
+    pub fn redactor(&self) -> Option<&Arc<dyn ChainRedactor>> {
+        self.redactor.as_ref()
+    }
+}
+
+/// How [`FieldRedactor`] rewrites a field's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Empties every string in the value and zeroes every number and
+    /// boolean.
+    Strip,
+    /// Replaces every string in the value with the hex SHA-256 digest of
+    /// the string, so equal values can still be told apart from different
+    /// ones. Numbers and booleans are kept.
+    Hash,
+}
+
+/// A [`ChainRedactor`] rewriting the values of fields with given names in
+/// JSON payloads.
+///
+/// A field is an entry of a JSON object, or a two-element array whose first
+/// element is a string naming it, which is how the fields of a
+/// [`SerializableVal::Record`](crate::chain::SerializableVal::Record) and
+/// lists of HTTP headers are encoded. Names are matched ignoring ASCII case,
+/// at any depth. Payloads which aren't JSON are stored as they are.
+///
+/// Single characters, encoded as `{"Char": "c"}`, are left alone so that
+/// they still decode.
+#[derive(Debug, Clone, Default)]
+pub struct FieldRedactor {
+    fields: Vec<(String, Redaction)>,
+    event_types: Option<Vec<String>>,
+}
+
+impl FieldRedactor {
+    /// Creates a redactor which doesn't redact anything yet.
+    pub fn new() -> FieldRedactor {
+        FieldRedactor::default()
+    }
+
+    /// Redacts fields called `name` with `redaction`.
+    pub fn field(mut self, name: &str, redaction: Redaction) -> FieldRedactor {
+        self.fields.push((name.to_string(), redaction));
+        self
+    }
+
+    /// Only redacts the payloads of events of type `event_type`; may be
+    /// called more than once. Without this, every event is redacted.
+    pub fn event_type(mut self, event_type: &str) -> FieldRedactor {
+        self.event_types
+            .get_or_insert_with(Vec::new)
+            .push(event_type.to_string());
+        self
+    }
+
+    fn redaction(&self, name: &str) -> Option<Redaction> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, redaction)| *redaction)
+    }
+
+    /// Redacts the fields inside `value`.
+    fn walk(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    match self.redaction(key) {
+                        Some(redaction) => apply(value, redaction),
+                        None => self.walk(value),
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let [Value::String(name), value] = &mut items[..] {
+                    if let Some(redaction) = self.redaction(name) {
+                        apply(value, redaction);
+                        return;
+                    }
+                }
+                for item in items {
+                    self.walk(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ChainRedactor for FieldRedactor {
+    fn redact(&self, event_type: &str, data: Vec<u8>) -> Vec<u8> {
+        if let Some(types) = &self.event_types {
+            if !types.iter().any(|t| t == event_type) {
+                return data;
+            }
+        }
+        let Ok(mut value) = serde_json::from_slice::<Value>(&data) else {
+            return data;
+        };
+        self.walk(&mut value);
+        serde_json::to_vec(&value).unwrap_or(data)
+    }
+}
+
+/// Rewrites every leaf of `value` according to `redaction`.
+fn apply(value: &mut Value, redaction: Redaction) {
+    match value {
+        Value::String(s) => match redaction {
+            Redaction::Strip => s.clear(),
+            Redaction::Hash => {
+                let digest: [u8; 32] = Sha256::digest(s.as_bytes()).into();
+                *s = Digest(digest).to_hex();
+            }
+        },
+        Value::Number(n) if redaction == Redaction::Strip => {
+            *n = if n.is_f64() {
+                serde_json::Number::from_f64(0.0).unwrap()
+            } else {
+                0.into()
+            };
+        }
+        Value::Bool(b) if redaction == Redaction::Strip => *b = false,
+        Value::Object(map) if map.len() == 1 && map.contains_key("Char") => {}
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                apply(value, redaction);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                apply(item, redaction);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::record::{FunctionCall, FUNCTION_CALL};
+    use crate::chain::{Event, SerializableVal};
+
+    #[test]
+    fn redacts_record_fields_and_headers() -> Result<()> {
+        let redactor = FieldRedactor::new()
+            .field("password", Redaction::Strip)
+            .field("email", Redaction::Hash)
+            .field("authorization", Redaction::Strip);
+        let mut chain = Chain::new();
+        chain.set_redactor(Some(Arc::new(redactor)));
+
+        let call = FunctionCall {
+            name: "login".to_string(),
+            params: vec![SerializableVal::Record(vec![
+                (
+                    "user".to_string(),
+                    SerializableVal::String("ann".to_string()),
+                ),
+                (
+                    "password".to_string(),
+                    SerializableVal::String("hunter2".to_string()),
+                ),
+                (
+                    "email".to_string(),
+                    SerializableVal::String("ann@example.com".to_string()),
+                ),
+            ])],
+            results: vec![SerializableVal::U32(7)],
+            call_parent: None,
+            signature: None,
+        };
+        let hash = chain.add(Event::new(
+            FUNCTION_CALL.to_string(),
+            serde_json::to_vec(&call)?,
+        ));
+        let stored = FunctionCall::decode(chain.get_event_by_hash(hash).unwrap().event())?;
+        let SerializableVal::Record(fields) = &stored.params[0] else {
+            panic!("expected a record, found {:?}", stored.params[0]);
+        };
+        assert!(matches!(&fields[0].1, SerializableVal::String(s) if s == "ann"));
+        assert!(matches!(&fields[1].1, SerializableVal::String(s) if s.is_empty()));
+        assert!(matches!(&fields[2].1, SerializableVal::String(s) if s.len() == 64));
+        assert!(matches!(stored.results[..], [SerializableVal::U32(7)]));
+        chain.verify()?;
+
+        let headers = serde_json::json!({
+            "headers": [["Authorization", "Bearer secret"], ["accept", "*/*"]],
+        });
+        let hash = chain.add(Event::new(
+            "http-request".to_string(),
+            serde_json::to_vec(&headers)?,
+        ));
+        let stored: Value =
+            serde_json::from_slice(&chain.get_event_by_hash(hash).unwrap().event().data())?;
+        assert_eq!(
+            stored["headers"],
+            serde_json::json!([["Authorization", ""], ["accept", "*/*"]])
+        );
+
+        let child = chain.spawn("child")?;
+        assert!(chain.child(child).unwrap().redactor().is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! References from one chain to events in another.
+//!
+//! An [`EventRef`] names an event by the [id](Chain::id) of its chain and
+//! its hash, so it stays meaningful outside the process which recorded it,
+//! such as for a message sent by one actor and received by another.
+//! [`Chain::add_reference`] records one as a [`REFERENCE`] event, and
+//! `EventRef`s can also be put in any other payload.
+//!
+//! References are resolved against whichever chains are at hand: a chain
+//! resolves those pointing into itself or into the children it spawned, see
+//! [`Chain::resolve`], and [`EventRef::resolve`] looks through a list of
+//! chains.
+
+use crate::chain::record::decode;
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Type of an event recording a reference to an event in another chain.
+pub const REFERENCE: &str = "reference";
+
+/// Names an event in a chain with an id, see the
+/// [module documentation](crate::chain::reference).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EventRef {
+    /// The [id](Chain::id) of the chain holding the event.
+    pub chain_id: Digest,
+    pub hash: Digest,
+}
+
+/// Written as `chain_id/hash`.
+impl fmt::Display for EventRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.chain_id, self.hash)
+    }
+}
+
+impl EventRef {
+    /// Looks the event up in whichever of `chains`, or of the children they
+    /// spawned, has its chain's id.
+    pub fn resolve<'a>(
+        &self,
+        chains: impl IntoIterator<Item = &'a Chain>,
+    ) -> Option<&'a MetaEvent> {
+        chains.into_iter().find_map(|chain| chain.resolve(self))
+    }
+}
+
+/// Payload of a [`REFERENCE`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    /// The event referred to.
+    pub target: EventRef,
+    /// How this chain relates to the event, such as `"sent"` for the receipt
+    /// of a message this chain sent.
+    pub relation: String,
+}
+
+impl Reference {
+    pub fn decode(event: &Event) -> Result<Reference> {
+        decode(event, REFERENCE)
+    }
+}
+
+impl Chain {
+    /// A reference to this chain's event with hash `hash`, or `None` if
+    /// there's no such event or this chain has no id to refer to it by.
+    pub fn event_ref(&self, hash: Digest) -> Option<EventRef> {
+        self.get_event_by_hash(hash)?;
+        Some(EventRef {
+            chain_id: self.id()?,
+            hash,
+        })
+    }
+
+    /// Records a reference to `target`, returning the hash of the
+    /// [`REFERENCE`] event.
+    pub fn add_reference(&mut self, target: EventRef, relation: &str) -> Result<Digest> {
+        let reference = Reference {
+            target,
+            relation: relation.to_string(),
+        };
+        self.try_add(Event::new(
+            REFERENCE.to_string(),
+            serde_json::to_vec(&reference)?,
+        ))
+    }
+
+    /// Iterates over the references this chain recorded, oldest first,
+    /// along with the hash of the event recording each.
+    pub fn references(&self) -> impl Iterator<Item = Result<(Digest, Reference)>> + '_ {
+        self.events_of_type(REFERENCE)
+            .map(|node| Ok((node.hash(), Reference::decode(node.event())?)))
+    }
+
+    /// Looks up the event `target` refers to in this chain or, recursively,
+    /// in the children it spawned.
+    pub fn resolve(&self, target: &EventRef) -> Option<&MetaEvent> {
+        if self.id() == Some(target.chain_id) {
+            return self.get_event_by_hash(target.hash);
+        }
+        self.children
+            .iter()
+            .find_map(|(_, child)| child.resolve(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refers_across_chains() -> Result<()> {
+        let mut a = Chain::genesis("a", Digest([1; Digest::LEN]))?;
+        let mut b = Chain::genesis("b", Digest([2; Digest::LEN]))?;
+        let receive = b.add(Event::new("receive".to_string(), vec![1]));
+        let target = b.event_ref(receive).unwrap();
+        assert_eq!(target.chain_id, b.id().unwrap());
+
+        let recorded = a.add_reference(target, "sent")?;
+        let references = a.references().collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            references,
+            [(
+                recorded,
+                Reference {
+                    target,
+                    relation: "sent".to_string()
+                }
+            )]
+        );
+        assert_eq!(target.resolve([&a, &b]).unwrap().hash(), receive);
+        assert!(a.resolve(&target).is_none());
+        assert!(Chain::new().event_ref(receive).is_none());
+
+        let spawn = a.spawn("child")?;
+        let child = a.child_mut(spawn).unwrap();
+        let hash = child.add(Event::new("x".to_string(), vec![]));
+        let nested = child.event_ref(hash).unwrap();
+        assert_eq!(a.resolve(&nested).unwrap().hash(), hash);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::component::{ResourceAny, ResourceType, Val};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::vec::Vec;
+
+/// Stable, serializable name for a resource handle registered with a
+/// [`ResourceRegistry`].
+///
+/// `ResourceAny` handles are indices into per-store tables and carry a
+/// `ResourceType` that only has meaning inside the process that created it.
+/// The registry instead hands out a `(type_id, rep)` pair: `type_id` numbers
+/// each distinct resource type in the order it was first registered and
+/// `rep` numbers each handle within that type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SerializableResource {
+    pub type_id: u32,
+    pub rep: u32,
+    pub owned: bool,
+}
+
+/// Per-store mapping between live `ResourceAny` handles and their
+/// [`SerializableResource`] names.
+#[derive(Default, Debug)]
+pub struct ResourceRegistry {
+    /// Each numbered type, or `None` for types numbered before the store
+    /// was restored from a [`ChainState`](crate::chain::ChainState).
+    types: Vec<Option<ResourceType>>,
+    next_rep: Vec<u32>,
+    by_handle: HashMap<ResourceAny, SerializableResource>,
+    by_name: HashMap<SerializableResource, ResourceAny>,
+}
+
+impl ResourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `resource`, returning its stable name.
+    ///
+    /// Registering the same handle twice returns the same name.
+    pub fn register(&mut self, resource: ResourceAny) -> SerializableResource {
+        if let Some(name) = self.by_handle.get(&resource) {
+            return *name;
+        }
+        let index = self.type_index(resource.ty());
+        let rep = self.next_rep[index];
+        self.next_rep[index] += 1;
+        let name = SerializableResource {
+            type_id: u32::try_from(index).unwrap(),
+            rep,
+            owned: resource.owned(),
+        };
+        self.by_handle.insert(resource, name);
+        self.by_name.insert(name, resource);
+        name
+    }
+
+    /// Registers every resource found inside `val`.
+    pub fn register_val(&mut self, val: &Val) {
+        match val {
+            Val::Resource(r) => {
+                self.register(*r);
+            }
+            Val::List(vals) | Val::Tuple(vals) => {
+                vals.iter().for_each(|v| self.register_val(v));
+            }
+            Val::Record(fields) => {
+                fields.iter().for_each(|(_, v)| self.register_val(v));
+            }
+            Val::Variant(_, Some(v))
+            | Val::Option(Some(v))
+            | Val::Result(Ok(Some(v)))
+            | Val::Result(Err(Some(v))) => self.register_val(v),
+            _ => {}
+        }
+    }
+
+    /// Forgets `resource`, typically after it has been dropped.
+    pub fn unregister(&mut self, resource: &ResourceAny) -> Option<SerializableResource> {
+        let name = self.by_handle.remove(resource)?;
+        self.by_name.remove(&name);
+        Some(name)
+    }
+
+    /// Returns the name `resource` was registered under, if any.
+    pub fn lookup(&self, resource: &ResourceAny) -> Option<SerializableResource> {
+        self.by_handle.get(resource).copied()
+    }
+
+    /// Returns the live handle registered under `name`, if any.
+    pub fn resolve(&self, name: &SerializableResource) -> Option<ResourceAny> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the number `ty` is recorded under, as the `type_id` of its
+    /// handles and in [`SerializableType`](crate::chain::SerializableType)s,
+    /// numbering it if it hasn't been seen before.
+    pub fn type_id(&mut self, ty: ResourceType) -> u32 {
+        u32::try_from(self.type_index(ty)).unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_handle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_handle.is_empty()
+    }
+
+    /// The next `rep` of each numbered type, indexed by `type_id`.
+    pub(crate) fn next_reps(&self) -> &[u32] {
+        &self.next_rep
+    }
+
+    /// Makes this empty registry continue the numbering of one whose types
+    /// had the next reps `next_rep`, so that handles registered from now on
+    /// don't reuse its names. Its types are never matched again.
+    pub(crate) fn continue_numbering(&mut self, next_rep: Vec<u32>) {
+        debug_assert!(self.is_empty());
+        self.types = vec![None; next_rep.len()];
+        self.next_rep = next_rep;
+    }
+
+    /// The names of every registered handle.
+    pub(crate) fn names(&self) -> impl Iterator<Item = &SerializableResource> + '_ {
+        self.by_name.keys()
+    }
+
+    fn type_index(&mut self, ty: ResourceType) -> usize {
+        if let Some(i) = self.types.iter().position(|t| *t == Some(ty)) {
+            return i;
+        }
+        self.types.push(Some(ty));
+        self.next_rep.push(0);
+        self.types.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::Resource;
+    use crate::{chain::SerializableVal, Store};
+
+    struct Dummy;
+
+    #[test]
+    fn register_round_trip() -> crate::Result<()> {
+        let mut store = Store::<()>::default();
+        let a = ResourceAny::try_from_resource(Resource::<Dummy>::new_own(1), &mut store)?;
+        let b = ResourceAny::try_from_resource(Resource::<Dummy>::new_own(2), &mut store)?;
+
+        let mut registry = ResourceRegistry::new();
+        let name_a = registry.register(a);
+        let name_b = registry.register(b);
+        assert_eq!(registry.register(a), name_a);
+        assert_eq!(name_a.type_id, name_b.type_id);
+        assert_ne!(name_a.rep, name_b.rep);
+        assert_eq!(registry.resolve(&name_b), Some(b));
+
+        let val = SerializableVal::from_val_with(&Val::Resource(a), &registry)?;
+        assert!(matches!(val, SerializableVal::Resource(n) if n == name_a));
+
+        registry.unregister(&a);
+        assert!(SerializableVal::from_val_with(&Val::Resource(a), &registry).is_err());
+        assert!(SerializableVal::from_val(&Val::Resource(b)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1141 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic replay of a recorded [`Chain`].
+//!
+//! A chain recorded with [`Config::chain_record`](crate::Config::chain_record)
+//! holds every export call made on an instance along with every host import
+//! it made in the process. [`Replay`] re-invokes those export calls in order
+//! against a component, answering its imports from the recorded
+//! `import-return` events instead of running real host functions.
+//!
+//! When the replaying engine also has `chain_record` enabled every event the
+//! replay produces is checked against the recording, and the first mismatch
+//! stops the replay with a [`ReplayDivergence`]. For those checks to line up
+//! the replay should start from a fresh store whose chain uses the same
+//! hasher as the recording.
+//!
+//! Calls recorded as trapping are expected to trap again. A host import
+//! which failed in the recording fails the same way during replay, with the
+//! recorded error message. Calls recorded along with their signature, see
+//! [`Config::chain_record_types`](crate::Config::chain_record_types), are
+//! only re-made if the export still has that signature.
+//!
+//! Resource imports are stubbed out with a placeholder type, so components
+//! which import resources can be instantiated, but replaying a call that
+//! passes a recorded resource handle to or from the host fails.
+//!
+//! [`compare`] replays one recording against two builds of a component,
+//! such as a guest and its upgrade, and reports the first output on which
+//! they disagree as a [`BuildDivergence`]. [`check_determinism`] replays a
+//! recording several times and fails with a [`Nondeterminism`] explaining
+//! the likely cause if any replay doesn't reproduce it.
+
+use crate::chain::record::{self, CallTrap, FunctionCall, ImportCall, ImportReturn};
+use crate::chain::{
+    metrics, Chain, Digest, ElementDiff, Event, FieldDiff, FunctionSignature, MetaEvent,
+    SerializableVal, ValDiff,
+};
+use crate::component::types::{ComponentFunc, ComponentItem};
+use crate::component::{Component, Instance, Linker, LinkerInstance, ResourceType, Type, Val};
+use crate::prelude::*;
+use crate::{AsContextMut, Engine, Store, StoreContextMut};
+use alloc::sync::Arc;
+use core::fmt;
+use std::sync::Mutex;
+
+/// Re-runs the export calls recorded in a [`Chain`].
+///
+/// Import stubs are added to a [`Linker`] with [`Replay::add_to_linker`],
+/// after which [`Replay::run`] drives an instance created from that linker.
+#[derive(Clone)]
+pub struct Replay {
+    state: Arc<Mutex<State>>,
+}
+
+/// Recorded events relevant to replay, in chain order.
+enum Step {
+    Call(FunctionCall),
+    ImportCall(ImportCall),
+    ImportReturn(ImportReturn),
+    Trap(CallTrap),
+}
+
+/// An export call to re-make.
+struct Call {
+    name: String,
+    params: Vec<SerializableVal>,
+    /// The export's signature, if it was recorded.
+    signature: Option<FunctionSignature>,
+    /// Whether the recorded call trapped.
+    trapped: bool,
+}
+
+struct State {
+    steps: Vec<Step>,
+    next: usize,
+    recorded: Vec<MetaEvent>,
+    checked: usize,
+}
+
+/// A replayed event whose hash differs from the recorded one.
+#[derive(Debug, Clone)]
+pub struct ReplayDivergence {
+    /// Position in the chain of the first differing event.
+    pub index: usize,
+    /// Type of the recorded event, or of the replayed one if the recording
+    /// has no event at `index`.
+    pub type_: String,
+    /// The recorded hash, or `None` if the replay produced extra events.
+    pub expected: Option<Digest>,
+    /// The replayed hash, or `None` if the replay stopped short.
+    pub found: Option<Digest>,
+    /// Line diff of the recorded (`-`) and replayed (`+`) payloads.
+    pub diff: String,
+    /// How the name, parameters and results of a replayed `function-call`
+    /// differ from the recorded one, when both are calls.
+    pub values: Option<ValDiff>,
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |d: &Option<Digest>| match d {
+            Some(d) => d.to_hex(),
+            None => "none".to_string(),
+        };
+        write!(
+            f,
+            "replay diverged at event {} (`{}`): expected {}, found {}",
+            self.index,
+            self.type_,
+            show(&self.expected),
+            show(&self.found),
+        )?;
+        match &self.values {
+            Some(values) if !values.is_same() => write!(f, "\n{}", values.to_string().trim_end())?,
+            _ if !self.diff.is_empty() => write!(f, "\n{}", self.diff)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for ReplayDivergence {}
+
+/// The first output on which two builds of a component replaying the same
+/// recording disagree, see [`compare`].
+///
+/// The outputs of a replay are its `function-call`, `import-call`,
+/// `import-return` and `trap` events, each as a variant named after the
+/// event's type whose payload is a record of the event's fields. Fields
+/// linking events to each other, and trap backtraces, are left out. A build
+/// whose replay failed has the error as a final `error` output.
+#[derive(Debug, Clone)]
+pub struct BuildDivergence {
+    /// Position of the differing outputs among those of each build.
+    pub index: usize,
+    /// The output of the first build, or `None` if it had no more.
+    pub a: Option<SerializableVal>,
+    /// The output of the second build, or `None` if it had no more.
+    pub b: Option<SerializableVal>,
+    /// How the output of the second build differs from that of the first,
+    /// when both have one.
+    pub values: Option<ValDiff>,
+}
+
+impl fmt::Display for BuildDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = |v: &Option<SerializableVal>| match v {
+            Some(SerializableVal::Variant(case, _)) => case.clone(),
+            Some(v) => v.desc().to_string(),
+            None => "none".to_string(),
+        };
+        write!(
+            f,
+            "builds diverged at output {}: `{}` and `{}`",
+            self.index,
+            case(&self.a),
+            case(&self.b),
+        )?;
+        if let Some(values) = &self.values {
+            write!(f, "\n{}", values.to_string().trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for BuildDivergence {}
+
+impl Replay {
+    /// Prepares to replay `chain`, decoding its recorded calls.
+    ///
+    /// Events of other types are skipped.
+    pub fn new(chain: &Chain) -> Result<Replay> {
+        Ok(Replay {
+            state: Arc::new(Mutex::new(State::new(
+                chain.events().cloned().collect(),
+                0,
+            )?)),
+        })
+    }
+
+    /// Replaces the calls left to replay with those recorded in
+    /// `recorded[start..]`, for a store whose chain already holds the events
+    /// before `start`.
+    pub(crate) fn reset(&self, recorded: Vec<MetaEvent>, start: usize) -> Result<()> {
+        *self.state.lock().unwrap() = State::new(recorded, start)?;
+        Ok(())
+    }
+
+    /// Defines every import of `component` in `linker` as a function which
+    /// returns the next recorded result for it.
+    pub fn add_to_linker<T>(&self, linker: &mut Linker<T>, component: &Component) -> Result<()> {
+        let engine = linker.engine().clone();
+        let ty = component.component_type();
+        let mut root = linker.root();
+        for (name, item) in ty.imports(&engine) {
+            self.define(&engine, &mut root, name, name.to_string(), item)?;
+        }
+        Ok(())
+    }
+
+    fn define<T>(
+        &self,
+        engine: &Engine,
+        linker: &mut LinkerInstance<'_, T>,
+        name: &str,
+        path: String,
+        item: ComponentItem,
+    ) -> Result<()> {
+        match item {
+            ComponentItem::ComponentFunc(func) => self.define_func(linker, name, path, &func),
+            ComponentItem::ComponentInstance(instance) => {
+                let mut linker = linker.instance(name)?;
+                for (export, item) in instance.exports(engine) {
+                    let path = format!("{path}#{export}");
+                    self.define(engine, &mut linker, export, path, item)?;
+                }
+                Ok(())
+            }
+            ComponentItem::Resource(_) => {
+                linker.resource(name, ResourceType::host::<Replayed>(), |_, _| Ok(()))
+            }
+            ComponentItem::Type(_) => Ok(()),
+            ComponentItem::CoreFunc(_) | ComponentItem::Module(_) | ComponentItem::Component(_) => {
+                bail!("cannot replay import `{path}`: only functions and instances are supported")
+            }
+        }
+    }
+
+    fn define_func<T>(
+        &self,
+        linker: &mut LinkerInstance<'_, T>,
+        name: &str,
+        path: String,
+        func: &ComponentFunc,
+    ) -> Result<()> {
+        let result_tys = func.results().collect::<Vec<_>>();
+        let replay = self.clone();
+        linker.func_new(name, move |store, _params, results| {
+            replay.answer(&store, &path, &result_tys, results)
+        })
+    }
+
+    /// Fills `results`, of types `result_tys`, with what the next recorded
+    /// call, which must be to the import `name`, returned.
+    pub(crate) fn answer<T>(
+        &self,
+        store: &StoreContextMut<'_, T>,
+        name: &str,
+        result_tys: &[Type],
+        results: &mut [Val],
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if store.engine().config().chain_record {
+            state.check(store.get_chain())?;
+        }
+        let recorded = state.import(name)?;
+        let vals = SerializableVal::to_vals(&recorded, result_tys)
+            .with_context(|| format!("rebuilding results of import `{name}`"))?;
+        for (slot, val) in results.iter_mut().zip(vals) {
+            *slot = val;
+        }
+        Ok(())
+    }
+
+    /// Re-invokes every recorded export call on `instance`, in order.
+    ///
+    /// `instance` must have been created from a linker set up with
+    /// [`Replay::add_to_linker`]. Each call must make exactly the imports
+    /// recorded for it.
+    pub fn run(&self, mut store: impl AsContextMut, instance: &Instance) -> Result<()> {
+        let check = store.as_context_mut().engine().config().chain_record;
+        self.run_with(store, instance, check, |_, _| Ok(()))
+    }
+
+    /// Like [`Replay::run`], checking the replayed events against the
+    /// recording only if `check` is set, and passing the results of each
+    /// call which returned to `returned` along with the call's position among
+    /// the recorded calls.
+    pub(crate) fn run_with(
+        &self,
+        mut store: impl AsContextMut,
+        instance: &Instance,
+        check: bool,
+        mut returned: impl FnMut(usize, &[Val]) -> Result<()>,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let mut index = 0;
+        while let Some(call) = self.next_call()? {
+            let func = lookup_func(&mut store, instance, &call.name)?;
+            let params = func.params(&store);
+            let result_tys = func.results(&store);
+            if let Some(signature) = &call.signature {
+                if !signature.matches(&params, &result_tys) {
+                    bail!(
+                        "export `{}` was recorded with signature `{signature}`, \
+                         which the component doesn't match",
+                        call.name
+                    );
+                }
+            }
+            for ((name, ty), val) in params.iter().zip(&call.params) {
+                val.typecheck(ty).with_context(|| {
+                    format!(
+                        "recorded argument `{name}` of export `{}` doesn't match the component",
+                        call.name
+                    )
+                })?;
+            }
+            let param_tys = params
+                .iter()
+                .map(|(_, ty)| ty.clone())
+                .collect::<Vec<Type>>();
+            let params =
+                SerializableVal::to_vals_with(&call.params, &param_tys, store.resource_registry())
+                    .with_context(|| format!("rebuilding params of export `{}`", call.name))?;
+            let mut results = vec![Val::Bool(false); result_tys.len()];
+            let result = func.call(&mut store, &params, &mut results);
+            if result.is_ok() {
+                func.post_return(&mut store)?;
+            }
+            let mut state = self.state.lock().unwrap();
+            if check {
+                state.check(store.get_chain())?;
+            }
+            match result {
+                Ok(()) if call.trapped => {
+                    bail!("export `{}` returned but was recorded trapping", call.name)
+                }
+                Ok(()) => returned(index, &results)?,
+                Err(e) if !call.trapped => return Err(e),
+                Err(_) => {}
+            }
+            state.finish_call(&call.name)?;
+            index += 1;
+        }
+        if check {
+            self.state.lock().unwrap().check_finished()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next export call to make, if any remain.
+    fn next_call(&self) -> Result<Option<Call>> {
+        let state = self.state.lock().unwrap();
+        let rest = &state.steps[state.next..];
+        match rest.iter().find_map(|s| match s {
+            Step::Call(call) => Some(Call {
+                name: call.name.clone(),
+                params: call.params.clone(),
+                signature: call.signature.clone(),
+                trapped: false,
+            }),
+            Step::Trap(trap) => Some(Call {
+                name: trap.name.clone(),
+                params: trap.params.clone(),
+                signature: None,
+                trapped: true,
+            }),
+            _ => None,
+        }) {
+            Some(call) => Ok(Some(call)),
+            None if rest.is_empty() => Ok(None),
+            None => bail!("chain ends with import events outside of any export call"),
+        }
+    }
+}
+
+impl State {
+    fn new(recorded: Vec<MetaEvent>, start: usize) -> Result<State> {
+        let mut steps = Vec::new();
+        for node in &recorded[start..] {
+            let event = node.event();
+            let step = match event.type_() {
+                record::FUNCTION_CALL => Step::Call(FunctionCall::decode(event)?),
+                record::IMPORT_CALL => Step::ImportCall(ImportCall::decode(event)?),
+                record::IMPORT_RETURN => Step::ImportReturn(ImportReturn::decode(event)?),
+                record::TRAP => Step::Trap(CallTrap::decode(event)?),
+                _ => continue,
+            };
+            steps.push(step);
+        }
+        Ok(State {
+            steps,
+            next: 0,
+            recorded,
+            checked: start,
+        })
+    }
+
+    /// Compares the events `chain` gained since the last check against the
+    /// recording.
+    fn check(&mut self, chain: &Chain) -> Result<(), ReplayDivergence> {
+        for (index, node) in (self.checked..).zip(chain.store().iter_from(self.checked)) {
+            match self.recorded.get(index) {
+                Some(recorded) if recorded.hash() == node.hash() => {}
+                recorded => {
+                    return Err(divergence(index, recorded, Some(node)));
+                }
+            }
+        }
+        self.checked = chain.len();
+        Ok(())
+    }
+
+    /// Fails if the recording has events which the replay never produced.
+    fn check_finished(&self) -> Result<(), ReplayDivergence> {
+        match self.recorded.get(self.checked) {
+            Some(recorded) => Err(divergence(self.checked, Some(recorded), None)),
+            None => Ok(()),
+        }
+    }
+
+    /// Consumes the recorded call to import `name`, returning its results.
+    ///
+    /// If the import failed in the recording this fails with the recorded
+    /// message instead.
+    fn import(&mut self, name: &str) -> Result<Vec<SerializableVal>> {
+        match self.steps.get(self.next) {
+            Some(Step::ImportCall(call)) if call.name == name => {}
+            Some(Step::ImportCall(call)) => {
+                bail!("expected a call to import `{}`, found `{name}`", call.name)
+            }
+            _ => bail!("unexpected call to import `{name}`"),
+        }
+        let results = match self.steps.get(self.next + 1) {
+            Some(Step::ImportReturn(ret)) if ret.name == name => ret.results.clone(),
+            Some(Step::Trap(trap)) => {
+                self.next += 1;
+                bail!("{}", trap.message)
+            }
+            _ => bail!("recorded call to import `{name}` never returned"),
+        };
+        self.next += 2;
+        results.ok_or_else(|| anyhow!("results of import `{name}` were not recorded"))
+    }
+
+    /// Consumes the recorded export call `name` once it has returned.
+    fn finish_call(&mut self, name: &str) -> Result<()> {
+        match self.steps.get(self.next) {
+            Some(Step::Call(FunctionCall { name: recorded, .. }))
+            | Some(Step::Trap(CallTrap { name: recorded, .. }))
+                if recorded == name =>
+            {
+                self.next += 1;
+                Ok(())
+            }
+            Some(Step::ImportCall(call)) => bail!(
+                "export `{name}` returned before making its recorded call to `{}`",
+                call.name
+            ),
+            _ => bail!("export `{name}` did not match the recording"),
+        }
+    }
+}
+
+fn divergence(
+    index: usize,
+    recorded: Option<&MetaEvent>,
+    replayed: Option<&MetaEvent>,
+) -> ReplayDivergence {
+    let payload = |node: Option<&MetaEvent>| node.map(|n| pretty(&n.event().data()));
+    let divergence = ReplayDivergence {
+        index,
+        type_: recorded
+            .or(replayed)
+            .map(|n| n.event().type_().to_string())
+            .unwrap_or_default(),
+        expected: recorded.map(|n| n.hash()),
+        found: replayed.map(|n| n.hash()),
+        diff: diff_lines(
+            payload(recorded).as_deref().unwrap_or(""),
+            payload(replayed).as_deref().unwrap_or(""),
+        ),
+        values: recorded
+            .and_then(call_value)
+            .zip(replayed.and_then(call_value))
+            .map(|(recorded, replayed)| recorded.diff(&replayed)),
+    };
+    metrics::report(|m| m.replay_diverged(&divergence));
+    divergence
+}
+
+/// A `function-call` event as a record of its name, parameters and results,
+/// for diffing.
+fn call_value(node: &MetaEvent) -> Option<SerializableVal> {
+    if node.event().type_() != record::FUNCTION_CALL {
+        return None;
+    }
+    let call = FunctionCall::decode(node.event()).ok()?;
+    Some(SerializableVal::Record(vec![
+        ("name".to_string(), SerializableVal::String(call.name)),
+        ("params".to_string(), SerializableVal::Tuple(call.params)),
+        ("results".to_string(), SerializableVal::Tuple(call.results)),
+    ]))
+}
+
+/// Renders a payload for diffing, pretty-printing JSON so that each value
+/// lands on its own line.
+fn pretty(data: &[u8]) -> String {
+    serde_json::from_slice::<serde_json::Value>(data)
+        .and_then(|v| serde_json::to_string_pretty(&v))
+        .unwrap_or_else(|_| String::from_utf8_lossy(data).into_owned())
+}
+
+/// A minimal line diff: lines only in `a` are prefixed with `-`, lines only
+/// in `b` with `+`, and common lines with a space.
+fn diff_lines(a: &str, b: &str) -> String {
+    let a = a.lines().collect::<Vec<_>>();
+    let b = b.lines().collect::<Vec<_>>();
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..]
+    // and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push_str(&format!(" {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("-{}\n", a[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", b[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Placeholder for resources imported by a replayed component.
+struct Replayed;
+
+/// Looks up an export by its recorded `#`-separated name.
+fn lookup_func(
+    mut store: impl AsContextMut,
+    instance: &Instance,
+    name: &str,
+) -> Result<crate::component::Func> {
+    let mut index = None;
+    for segment in name.split('#') {
+        index = Some(
+            instance
+                .get_export(&mut store, index.as_ref(), segment)
+                .ok_or_else(|| anyhow!("no export named `{name}`"))?,
+        );
+    }
+    let index = index.ok_or_else(|| anyhow!("no export named `{name}`"))?;
+    instance
+        .get_func(&mut store, &index)
+        .ok_or_else(|| anyhow!("export `{name}` is not a function"))
+}
+
+/// A replay which didn't reproduce its recording, see [`check_determinism`].
+#[derive(Debug, Clone)]
+pub struct Nondeterminism {
+    /// Which replay differed, counting from 0.
+    pub run: usize,
+    /// The first event of the replay which differs from the recording.
+    pub divergence: ReplayDivergence,
+    /// What likely made the replay differ.
+    pub cause: NondeterminismCause,
+}
+
+/// A likely source of [`Nondeterminism`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NondeterminismCause {
+    /// Calls to these imports aren't recorded, so replays can't return what
+    /// the recorded calls did.
+    UnrecordedImports(Vec<String>),
+    /// Float values differ, such as NaNs with different payloads.
+    Floats,
+    /// A list holds the same elements in another order.
+    Ordering,
+    /// None of the above.
+    Unknown,
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay {} is not deterministic: {}\n{}",
+            self.run, self.divergence, self.cause
+        )
+    }
+}
+
+impl core::error::Error for Nondeterminism {}
+
+impl fmt::Display for NondeterminismCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NondeterminismCause::UnrecordedImports(names) => write!(
+                f,
+                "calls to `{}` aren't recorded; record them with \
+                 `Config::chain_wasi_policy` or by adjusting \
+                 `Config::chain_record_allow` and `Config::chain_record_deny`",
+                names.join("`, `")
+            ),
+            NondeterminismCause::Floats => f.write_str(
+                "float values differ; NaN payloads aren't deterministic unless \
+                 `Config::cranelift_nan_canonicalization` is enabled",
+            ),
+            NondeterminismCause::Ordering => f.write_str(
+                "a list holds the same elements in another order; iterate \
+                 collections in a fixed order, or sort them, before returning them",
+            ),
+            NondeterminismCause::Unknown => f.write_str(
+                "the component behaved differently given the same imports; look \
+                 for other sources such as relaxed SIMD, threads or interruptions",
+            ),
+        }
+    }
+}
+
+/// Replays `chain` against `component` `runs` times, each in a new store, and
+/// fails with a [`Nondeterminism`] at the first event any replay records
+/// differently from `chain`.
+///
+/// `component`'s engine must have
+/// [`Config::chain_record`](crate::Config::chain_record) enabled and record
+/// the same kinds of events as the engine which recorded `chain`. Fails
+/// like [`Replay::run`] if a replay fails before it differs from `chain`.
+pub fn check_determinism(component: &Component, chain: &Chain, runs: usize) -> Result<()> {
+    let engine = component.engine();
+    ensure!(
+        engine.config().chain_record,
+        "checking determinism requires `Config::chain_record` to be enabled"
+    );
+    let hasher = crate::chain::hasher::hasher_by_name(chain.hasher().name())
+        .ok_or_else(|| anyhow!("chain uses unknown hasher `{}`", chain.hasher().name()))?;
+    let recorded = chain.iter().cloned().collect::<Vec<_>>();
+    for run in 0..runs {
+        let replay = Replay::new(chain)?;
+        let mut linker = Linker::new(engine);
+        replay.add_to_linker(&mut linker, component)?;
+        let mut store = Store::new(engine, ());
+        store.set_chain(Chain::with_hasher(hasher.clone()));
+        let instance = linker.instantiate(&mut store, component)?;
+        let result = replay.run_with(&mut store, &instance, false, |_, _| Ok(()));
+
+        let replayed = store.chain();
+        let index = (0..recorded.len().max(replayed.len())).find(|&i| {
+            recorded.get(i).map(MetaEvent::hash) != replayed.store().get(i).map(MetaEvent::hash)
+        });
+        if let Some(index) = index {
+            let divergence = divergence(index, recorded.get(index), replayed.store().get(index));
+            let cause = nondeterminism_cause(
+                engine,
+                component,
+                &divergence,
+                recorded.get(index),
+                replayed.store().get(index),
+            );
+            return Err(Nondeterminism {
+                run,
+                divergence,
+                cause,
+            }
+            .into());
+        }
+        result.with_context(|| format!("replay {run} failed"))?;
+    }
+    Ok(())
+}
+
+/// Guesses why `divergence`, between the events `recorded` and `replayed`,
+/// happened.
+fn nondeterminism_cause(
+    engine: &Engine,
+    component: &Component,
+    divergence: &ReplayDivergence,
+    recorded: Option<&MetaEvent>,
+    replayed: Option<&MetaEvent>,
+) -> NondeterminismCause {
+    let mut unrecorded = Vec::new();
+    for (name, item) in component.component_type().imports(engine) {
+        unrecorded_imports(engine, name.to_string(), item, &mut unrecorded);
+    }
+    if !unrecorded.is_empty() {
+        return NondeterminismCause::UnrecordedImports(unrecorded);
+    }
+    if divergence.values.as_ref().is_some_and(touches_floats) {
+        return NondeterminismCause::Floats;
+    }
+    match recorded
+        .and_then(call_value)
+        .zip(replayed.and_then(call_value))
+    {
+        Some((a, b)) if reordered(&a, &b) => NondeterminismCause::Ordering,
+        _ => NondeterminismCause::Unknown,
+    }
+}
+
+/// Collects the functions under the import `path` whose calls `engine`
+/// doesn't record.
+fn unrecorded_imports(engine: &Engine, path: String, item: ComponentItem, names: &mut Vec<String>) {
+    match item {
+        ComponentItem::ComponentFunc(_) if !engine.config().chain_records_import(&path) => {
+            names.push(path)
+        }
+        ComponentItem::ComponentInstance(instance) => {
+            for (export, item) in instance.exports(engine) {
+                unrecorded_imports(engine, format!("{path}#{export}"), item, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `diff` replaces a float.
+fn touches_floats(diff: &ValDiff) -> bool {
+    use SerializableVal as V;
+
+    let float = |v: &SerializableVal| matches!(v, V::Float32(_) | V::Float64(_));
+    match diff {
+        ValDiff::Replaced { old, new } => float(old) || float(new),
+        ValDiff::Fields(fields) => fields.iter().any(|f| match f {
+            FieldDiff::Changed(_, diff) => touches_floats(diff),
+            _ => false,
+        }),
+        ValDiff::Elements(elements) => elements.iter().any(|e| match e {
+            ElementDiff::Changed(_, diff) => touches_floats(diff),
+            _ => false,
+        }),
+        ValDiff::Payload { diff, .. } => touches_floats(diff),
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` only differ in the order of elements of a list.
+fn reordered(a: &SerializableVal, b: &SerializableVal) -> bool {
+    use SerializableVal as V;
+
+    let same_elements = |a: &[SerializableVal], b: &[SerializableVal]| {
+        a.len() == b.len() && a.iter().all(|x| b.contains(x)) && b.iter().all(|x| a.contains(x))
+    };
+    let zipped = |a: &[SerializableVal], b: &[SerializableVal]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a == b || reordered(a, b))
+    };
+    match (a, b) {
+        _ if a == b => false,
+        (V::List(a), V::List(b)) => same_elements(a, b) || zipped(a, b),
+        (V::Tuple(a), V::Tuple(b)) => zipped(a, b),
+        (V::Record(a), V::Record(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((n, a), (m, b))| n == m && (a == b || reordered(a, b)))
+        }
+        (V::Variant(n, Some(a)), V::Variant(m, Some(b))) => n == m && reordered(a, b),
+        (V::Option(Some(a)), V::Option(Some(b))) => reordered(a, b),
+        (V::Result(Ok(Some(a))), V::Result(Ok(Some(b))))
+        | (V::Result(Err(Some(a))), V::Result(Err(Some(b)))) => reordered(a, b),
+        _ => false,
+    }
+}
+
+/// Replays `chain` against two builds of a component and returns the first
+/// output on which they disagree, see [`BuildDivergence`], or `None` if they
+/// agree on all of them.
+///
+/// Each build is instantiated in a new store of its engine, which must have
+/// [`Config::chain_record`](crate::Config::chain_record) enabled. The
+/// replays aren't checked against the recording, so either build may differ
+/// from the one which recorded `chain`. Fails if either build can't be
+/// instantiated, or if both fail to replay `chain` in the same way.
+pub fn compare(
+    chain: &Chain,
+    component_a: &Component,
+    component_b: &Component,
+) -> Result<Option<BuildDivergence>> {
+    let (a, error) = replay_outputs(chain, component_a).context("replaying the first build")?;
+    let (b, _) = replay_outputs(chain, component_b).context("replaying the second build")?;
+    let Some(index) = (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i)) else {
+        return match error {
+            Some(e) => Err(e.context("neither build could replay the recording")),
+            None => Ok(None),
+        };
+    };
+    let (a, b) = (a.get(index).cloned(), b.get(index).cloned());
+    Ok(Some(BuildDivergence {
+        index,
+        values: a.as_ref().zip(b.as_ref()).map(|(a, b)| a.diff(b)),
+        a,
+        b,
+    }))
+}
+
+/// Replays `chain` against `component` in a new store, returning its outputs
+/// as described by [`BuildDivergence`] along with the error the replay
+/// failed with, if any.
+fn replay_outputs(
+    chain: &Chain,
+    component: &Component,
+) -> Result<(Vec<SerializableVal>, Option<Error>)> {
+    let engine = component.engine();
+    ensure!(
+        engine.config().chain_record,
+        "comparing builds requires `Config::chain_record` to be enabled"
+    );
+    let replay = Replay::new(chain)?;
+    let mut linker = Linker::new(engine);
+    replay.add_to_linker(&mut linker, component)?;
+    let mut store = Store::new(engine, ());
+    let instance = linker.instantiate(&mut store, component)?;
+    let error = replay
+        .run_with(&mut store, &instance, false, |_, _| Ok(()))
+        .err();
+    let mut outputs = store
+        .chain()
+        .iter()
+        .filter_map(|node| output(node.event()).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(e) = &error {
+        outputs.push(SerializableVal::Variant(
+            "error".to_string(),
+            Some(Box::new(SerializableVal::String(format!("{e:#}")))),
+        ));
+    }
+    Ok((outputs, error))
+}
+
+/// The output `event` stands for, see [`BuildDivergence`], if any.
+fn output(event: &Event) -> Result<Option<SerializableVal>> {
+    use SerializableVal as V;
+
+    let tuple = |vals: Option<Vec<SerializableVal>>| V::Option(vals.map(|v| Box::new(V::Tuple(v))));
+    let fields = match event.type_() {
+        record::FUNCTION_CALL => {
+            let call = FunctionCall::decode(event)?;
+            vec![
+                ("name", V::String(call.name)),
+                ("params", V::Tuple(call.params)),
+                ("results", V::Tuple(call.results)),
+            ]
+        }
+        record::IMPORT_CALL => {
+            let call = ImportCall::decode(event)?;
+            vec![
+                ("name", V::String(call.name)),
+                ("params", tuple(call.params)),
+            ]
+        }
+        record::IMPORT_RETURN => {
+            let ret = ImportReturn::decode(event)?;
+            vec![
+                ("name", V::String(ret.name)),
+                ("results", tuple(ret.results)),
+            ]
+        }
+        record::TRAP => {
+            let trap = CallTrap::decode(event)?;
+            vec![
+                ("name", V::String(trap.name)),
+                ("params", V::Tuple(trap.params)),
+                ("code", V::Option(trap.code.map(|c| Box::new(V::String(c))))),
+                ("message", V::String(trap.message)),
+            ]
+        }
+        _ => return Ok(None),
+    };
+    let fields = fields
+        .into_iter()
+        .map(|(name, val)| (name.to_string(), val))
+        .collect();
+    Ok(Some(V::Variant(
+        event.type_().to_string(),
+        Some(Box::new(V::Record(fields))),
+    )))
+}
+
+/// Instantiates `component` in `store` with imports answered from `chain`
+/// and replays every export call recorded in it.
+pub fn replay<T>(
+    mut store: impl AsContextMut<Data = T>,
+    component: &Component,
+    chain: &Chain,
+) -> Result<Instance> {
+    let replay = Replay::new(chain)?;
+    let mut linker = Linker::new(component.engine());
+    replay.add_to_linker(&mut linker, component)?;
+    let instance = linker.instantiate(&mut store, component)?;
+    replay.run(&mut store, &instance)?;
+    Ok(instance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Store};
+
+    const COMPONENT: &str = r#"
+        (component
+            (import "host" (instance $host
+                (export "next" (func (result u32)))
+            ))
+            (core func $next (canon lower (func $host "next")))
+            (core module $m
+                (import "" "next" (func $next (result i32)))
+                (func (export "add") (param i32) (result i32)
+                    (i32.add (local.get 0) (call $next))))
+            (core instance $i (instantiate $m
+                (with "" (instance (export "next" (func $next))))))
+            (func (export "add") (param "x" u32) (result u32)
+                (canon lift (core func $i "add")))
+        )
+    "#;
+
+    fn engine(record: bool) -> Result<Engine> {
+        let mut config = Config::new();
+        config.chain_record(record);
+        Engine::new(&config)
+    }
+
+    #[test]
+    fn replays_recorded_imports() -> Result<()> {
+        let engine = engine(true)?;
+        let component = Component::new(&engine, COMPONENT)?;
+
+        let mut linker = Linker::new(&engine);
+        let counter = Arc::new(Mutex::new(10u32));
+        let c = counter.clone();
+        linker.instance("host")?.func_wrap("next", move |_, ()| {
+            let mut n = c.lock().unwrap();
+            *n += 1;
+            Ok((*n,))
+        })?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        for x in [1, 2] {
+            add.call(&mut store, &[Val::U32(x)], &mut [Val::U32(0)])?;
+            add.post_return(&mut store)?;
+        }
+
+        // Without touching the real host function the replay produces an
+        // identical chain.
+        let mut replayed = Store::new(&engine, ());
+        replay(&mut replayed, &component, store.chain())?;
+        assert_eq!(*counter.lock().unwrap(), 12);
+        assert_eq!(replayed.chain().head(), store.chain().head());
+        Ok(())
+    }
+
+    #[test]
+    fn detects_divergence() -> Result<()> {
+        let engine = engine(true)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut linker = Linker::new(&engine);
+        linker
+            .instance("host")?
+            .func_wrap("next", |_, ()| Ok((1u32,)))?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        add.call(&mut store, &[Val::U32(1)], &mut [Val::U32(0)])?;
+        add.post_return(&mut store)?;
+
+        // The same call against a component that ignores its import returns
+        // a different result.
+        let modified = COMPONENT.replace(
+            "(i32.add (local.get 0) (call $next))",
+            "(drop (call $next)) (local.get 0)",
+        );
+        let modified = Component::new(&engine, modified)?;
+        let mut replayed = Store::new(&engine, ());
+        let err = match replay(&mut replayed, &modified, store.chain()) {
+            Ok(_) => panic!("replay should diverge"),
+            Err(e) => e,
+        };
+        let divergence = err.downcast_ref::<ReplayDivergence>().unwrap();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.type_, record::FUNCTION_CALL);
+        let changed = |prefix| {
+            divergence
+                .diff
+                .lines()
+                .filter(move |l| l.starts_with(prefix))
+                .map(|l| l[1..].trim())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(changed('-'), [r#""U32": 2"#]);
+        assert_eq!(changed('+'), [r#""U32": 1"#]);
+        let values = divergence.values.as_ref().unwrap();
+        assert_eq!(values.to_string(), "$.results[0]: 2 -> 1\n");
+        assert!(err.to_string().ends_with("\n$.results[0]: 2 -> 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn compares_builds() -> Result<()> {
+        let engine = engine(true)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut linker = Linker::new(&engine);
+        linker
+            .instance("host")?
+            .func_wrap("next", |_, ()| Ok((1u32,)))?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        for x in [1, 2] {
+            add.call(&mut store, &[Val::U32(x)], &mut [Val::U32(0)])?;
+            add.post_return(&mut store)?;
+        }
+        assert!(compare(store.chain(), &component, &component)?.is_none());
+
+        // An upgrade which only differs once its input is 2 diverges on the
+        // second call's results.
+        let upgrade = COMPONENT.replace(
+            "(i32.add (local.get 0) (call $next))",
+            "(i32.add (i32.mul (local.get 0) (local.get 0)) (call $next))",
+        );
+        let upgrade = Component::new(&engine, upgrade)?;
+        let divergence = compare(store.chain(), &component, &upgrade)?.unwrap();
+        assert_eq!(divergence.index, 5);
+        let results = |v: &Option<SerializableVal>| match v {
+            Some(SerializableVal::Variant(case, Some(call))) if case == record::FUNCTION_CALL => {
+                match &**call {
+                    SerializableVal::Record(fields) => fields[2].1.clone(),
+                    _ => unreachable!(),
+                }
+            }
+            _ => panic!("expected a function call, found {v:?}"),
+        };
+        assert_eq!(
+            results(&divergence.a),
+            SerializableVal::Tuple(vec![SerializableVal::U32(3)])
+        );
+        assert_eq!(
+            results(&divergence.b),
+            SerializableVal::Tuple(vec![SerializableVal::U32(5)])
+        );
+        assert!(!divergence.values.unwrap().is_same());
+
+        let unrecorded = Engine::new(&Config::new())?;
+        let unrecorded = Component::new(&unrecorded, COMPONENT)?;
+        assert!(compare(store.chain(), &component, &unrecorded).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn checks_determinism() -> Result<()> {
+        let record = |config: &Config| -> Result<(Component, Chain)> {
+            let engine = Engine::new(config)?;
+            let component = Component::new(&engine, COMPONENT)?;
+            let mut linker = Linker::new(&engine);
+            linker
+                .instance("host")?
+                .func_wrap("next", |_, ()| Ok((7u32,)))?;
+            let mut store = Store::new(&engine, ());
+            let instance = linker.instantiate(&mut store, &component)?;
+            let add = instance.get_func(&mut store, "add").unwrap();
+            add.call(&mut store, &[Val::U32(1)], &mut [Val::U32(0)])?;
+            add.post_return(&mut store)?;
+            Ok((component, store.chain().clone()))
+        };
+
+        let mut config = Config::new();
+        config.chain_record(true);
+        let (component, chain) = record(&config)?;
+        check_determinism(&component, &chain, 3)?;
+
+        // Without its import recorded the replay can't reproduce the call.
+        config.chain_record_deny(&["host#next"]);
+        let (component, chain) = record(&config)?;
+        let err = check_determinism(&component, &chain, 3).unwrap_err();
+        let nondeterminism = err.downcast_ref::<Nondeterminism>().unwrap();
+        assert_eq!(nondeterminism.run, 0);
+        assert_eq!(
+            nondeterminism.cause,
+            NondeterminismCause::UnrecordedImports(vec!["host#next".to_string()])
+        );
+        assert!(err.to_string().contains("Config::chain_record_deny"));
+
+        use SerializableVal as V;
+        let list = |vals: &[u32]| V::List(vals.iter().copied().map(V::U32).collect());
+        let record = |list| V::Record(vec![("results".to_string(), V::Tuple(vec![list]))]);
+        assert!(reordered(
+            &record(list(&[1, 2, 3])),
+            &record(list(&[3, 1, 2]))
+        ));
+        assert!(!reordered(
+            &record(list(&[1, 2, 3])),
+            &record(list(&[1, 2, 4]))
+        ));
+        assert!(touches_floats(&V::Float64(1.0).diff(&V::Float64(2.0))));
+        Ok(())
+    }
+
+    #[test]
+    fn unexpected_import_fails() -> Result<()> {
+        // Without recording there's nothing to compare against, so the
+        // mismatch is only noticed once the stray import is made.
+        let engine = engine(false)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut chain = Chain::new();
+        let call = FunctionCall {
+            name: "add".to_string(),
+            params: vec![SerializableVal::U32(1)],
+            results: vec![SerializableVal::U32(2)],
+            call_parent: None,
+            signature: None,
+        };
+        chain.add(crate::chain::Event::new(
+            record::FUNCTION_CALL.to_string(),
+            serde_json::to_vec(&call)?,
+        ));
+        let mut store = Store::new(&engine, ());
+        let err = match replay(&mut store, &component, &chain) {
+            Ok(_) => panic!("replay should fail"),
+            Err(e) => e,
+        };
+        assert!(format!("{err:?}").contains("unexpected call to import `host#next`"));
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bringing a restarted instance back to where a recording left off.
+//!
+//! [`Instance::resume_from_chain`] restores the last `snapshot` event of a
+//! chain, see [`Store::snapshot_to_chain`](crate::Store::snapshot_to_chain),
+//! and then [replays](crate::chain::replay) the export calls recorded after
+//! it. Unlike the [debugger](crate::chain::debugger) the instance is created
+//! from the embedder's own [`Linker`](crate::component::Linker): while it
+//! catches up its imports are answered from the recording, and once it
+//! reaches the head of the chain calls to it run the real host functions
+//! again. A supervisor can so restart a failed instance from its chain and
+//! carry on recording into it.
+
+use crate::chain::replay::Replay;
+use crate::chain::snapshot::{Snapshot, SNAPSHOT};
+use crate::chain::Chain;
+use crate::component::Instance;
+use crate::prelude::*;
+use crate::AsContextMut;
+
+impl Instance {
+    /// Puts this instance and its store into the state they were in at the
+    /// head of `chain`, see the [`resume` module](crate::chain::resume).
+    ///
+    /// `chain` must hold a snapshot, after which only calls recorded between
+    /// export calls are replayed. The store should be new, so that it has the
+    /// same instances as the store which took the snapshot. Afterwards the
+    /// store's chain is `chain`, and new calls are recorded after its head.
+    /// When the store records calls, the replayed calls are checked against
+    /// the recording as by [`Replay::run`].
+    pub fn resume_from_chain(&self, mut store: impl AsContextMut, chain: &Chain) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let events = chain.iter().cloned().collect::<Vec<_>>();
+        let Some(start) = events
+            .iter()
+            .rposition(|node| node.event().type_() == SNAPSHOT)
+        else {
+            bail!("no snapshot was taken in the chain to resume from");
+        };
+
+        Snapshot::decode(events[start].event())?.restore(&mut store)?;
+        let mut prefix = chain.clone();
+        prefix.rollback_to(events[start].hash())?;
+        *store.chain_mut() = prefix;
+
+        let replay = Replay::new(&Chain::new())?;
+        replay.reset(events, start + 1)?;
+        store.0.set_chain_resume(Some(replay.clone()));
+        let result = replay.run(&mut store, self);
+        store.0.set_chain_resume(None);
+        result.context("replaying the chain to resume from")?;
+
+        *store.chain_mut() = chain.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chain::Chain;
+    use crate::component::{Component, Instance, Linker, Val};
+    use crate::prelude::*;
+    use crate::{Config, Engine, Store};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    const COMPONENT: &str = r#"
+        (component
+            (import "host" (instance $host
+                (export "next" (func (result u32)))
+            ))
+            (core func $next (canon lower (func $host "next")))
+            (core module $m
+                (import "" "next" (func $next (result i32)))
+                (global $total (mut i32) (i32.const 0))
+                (func (export "add") (param i32)
+                    (global.set $total
+                        (i32.add (global.get $total)
+                            (i32.add (local.get 0) (call $next)))))
+                (func (export "total") (result i32) (global.get $total)))
+            (core instance $i (instantiate $m
+                (with "" (instance (export "next" (func $next))))))
+            (func (export "add") (param "x" u32)
+                (canon lift (core func $i "add")))
+            (func (export "total") (result u32)
+                (canon lift (core func $i "total")))
+        )
+    "#;
+
+    /// Instantiates `component` with a host whose `next` counts up by `step`.
+    fn instantiate(
+        engine: &Engine,
+        component: &Component,
+        step: u32,
+    ) -> Result<(Store<()>, Instance)> {
+        let mut linker = Linker::new(engine);
+        let counter = Arc::new(AtomicU32::new(0));
+        linker.instance("host")?.func_wrap("next", move |_, ()| {
+            Ok((counter.fetch_add(step, Ordering::SeqCst) + step,))
+        })?;
+        let mut store = Store::new(engine, ());
+        let instance = linker.instantiate(&mut store, component)?;
+        Ok((store, instance))
+    }
+
+    fn call(store: &mut Store<()>, instance: &Instance, name: &str, params: &[Val]) -> Result<u32> {
+        let func = instance.get_func(&mut *store, name).unwrap();
+        let mut results = vec![Val::U32(0); func.results(&*store).len()];
+        func.call(&mut *store, params, &mut results)?;
+        func.post_return(&mut *store)?;
+        Ok(match results.first() {
+            Some(Val::U32(n)) => *n,
+            _ => 0,
+        })
+    }
+
+    #[test]
+    fn resumes_to_the_chain_head() -> Result<()> {
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, COMPONENT)?;
+
+        let (mut store, instance) = instantiate(&engine, &component, 100)?;
+        store.snapshot_to_chain()?;
+        call(&mut store, &instance, "add", &[Val::U32(1)])?;
+        call(&mut store, &instance, "add", &[Val::U32(2)])?;
+        let recorded = store.chain().clone();
+        assert_eq!(call(&mut store, &instance, "total", &[])?, 303);
+
+        // The restarted host counts differently, so only calls made after
+        // resuming reach it.
+        let (mut resumed, instance) = instantiate(&engine, &component, 1000)?;
+        instance.resume_from_chain(&mut resumed, &recorded)?;
+        assert_eq!(resumed.chain().head(), recorded.head());
+        assert_eq!(call(&mut resumed, &instance, "total", &[])?, 303);
+        call(&mut resumed, &instance, "add", &[Val::U32(3)])?;
+        assert_eq!(call(&mut resumed, &instance, "total", &[])?, 1306);
+        resumed.chain().verify()?;
+        assert!(resumed.chain().len() > recorded.len());
+
+        let (mut fresh, instance) = instantiate(&engine, &component, 1)?;
+        assert!(instance
+            .resume_from_chain(&mut fresh, &Chain::new())
+            .is_err());
+        Ok(())
+    }
+}
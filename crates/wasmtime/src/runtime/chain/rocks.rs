@@ -0,0 +1,430 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RocksDB persistence for chains.
+//!
+//! Like a SQLite database, a RocksDB database opened with [`open_database`]
+//! can hold any number of chains, each identified by a name such as an actor
+//! id, so a node running thousands of them needs only one. Every key starts
+//! with the chain's name, and four column families hold:
+//!
+//! * `chains`: each chain's hasher.
+//! * `events`: each event, encoded as in a chain file, keyed by its sequence
+//!   number in big-endian so the events of a chain are in order.
+//! * `hashes`: the sequence number of each event, keyed by its hash.
+//! * `types`: an empty value keyed by each event's type and sequence number,
+//!   so the events of a type can be scanned in order.
+//!
+//! A [`RocksChainStore`] writes an event and its index entries in a single
+//! write batch, and a whole [`Chain::add_batch`] in one, so they're stored
+//! atomically. A [`RocksChainReader`] queries the database directly, without
+//! loading the chain into memory.
+
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{Chain, ChainStore, Digest, MemoryChainStore, MetaEvent};
+use crate::prelude::*;
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB};
+use std::path::Path;
+use std::sync::Arc;
+
+const CHAINS: &str = "chains";
+const EVENTS: &str = "events";
+const HASHES: &str = "hashes";
+const TYPES: &str = "types";
+
+/// The key of the database's format version in the default column family.
+const FORMAT_KEY: &[u8] = b"format";
+
+/// Opens the RocksDB database at `path` for chains, creating it if needed.
+///
+/// The database can be shared by the stores and readers of any number of
+/// chains.
+pub fn open_database(path: impl AsRef<Path>) -> Result<Arc<DB>> {
+    let path = path.as_ref();
+    let mut options = Options::default();
+    options.create_if_missing(true);
+    options.create_missing_column_families(true);
+    let db = DB::open_cf(&options, path, [CHAINS, EVENTS, HASHES, TYPES])
+        .with_context(|| format!("failed to open chain database `{}`", path.display()))?;
+    let version = match db.get(FORMAT_KEY)? {
+        Some(version) => u64::from_be_bytes(
+            version[..]
+                .try_into()
+                .context("invalid chain database format version")?,
+        ),
+        None => u64::from(FORMAT_VERSION),
+    };
+    migrate::check_version(version)
+        .with_context(|| format!("invalid chain database `{}`", path.display()))?;
+    db.put(FORMAT_KEY, u64::from(FORMAT_VERSION).to_be_bytes())?;
+    Ok(Arc::new(db))
+}
+
+/// The prefix of every key of the chain called `name`: the length of the
+/// name followed by the name, so no name's keys are a prefix of another's.
+fn chain_prefix(name: &str) -> Vec<u8> {
+    let mut prefix = u32::try_from(name.len())
+        .expect("chain names are shorter than 4GiB")
+        .to_be_bytes()
+        .to_vec();
+    prefix.extend_from_slice(name.as_bytes());
+    prefix
+}
+
+/// The keys of `event`, sequence number `seq` of the chain with `prefix`,
+/// in the `events`, `hashes` and `types` column families.
+fn event_keys(prefix: &[u8], seq: u64, event: &MetaEvent) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let events = [prefix, &seq.to_be_bytes()].concat();
+    let hashes = [prefix, &event.hash().as_bytes()[..]].concat();
+    let types = [
+        type_prefix(prefix, event.event().type_()),
+        seq.to_be_bytes().to_vec(),
+    ]
+    .concat();
+    (events, hashes, types)
+}
+
+fn type_prefix(prefix: &[u8], type_: &str) -> Vec<u8> {
+    [prefix, &chain_prefix(type_)].concat()
+}
+
+fn cf<'a>(db: &'a DB, name: &str) -> Result<&'a ColumnFamily> {
+    db.cf_handle(name)
+        .with_context(|| format!("chain database has no `{name}` column family"))
+}
+
+/// The entries of column family `cf` whose keys start with `prefix`, in
+/// order, with `prefix` stripped from their keys.
+fn scan<'a>(
+    db: &'a DB,
+    cf: &'a ColumnFamily,
+    prefix: Vec<u8>,
+) -> impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>)>> + 'a {
+    db.iterator_cf(cf, IteratorMode::From(&prefix, Direction::Forward))
+        .map(|entry| entry.map_err(Error::from))
+        .take_while(move |entry| match entry {
+            Ok((key, _)) => key.starts_with(&prefix),
+            Err(_) => true,
+        })
+}
+
+/// Decodes the sequence number at the end of `key`.
+fn seq_of(key: &[u8]) -> Result<u64> {
+    let bytes = key
+        .len()
+        .checked_sub(8)
+        .map(|start| &key[start..])
+        .context("invalid chain database key")?;
+    Ok(u64::from_be_bytes(bytes.try_into()?))
+}
+
+fn decode(value: &[u8]) -> Result<MetaEvent> {
+    postcard::from_bytes(value).context("invalid event in chain database")
+}
+
+/// Adds the entries of `event`, sequence number `seq`, to `batch`.
+fn put_event(
+    db: &DB,
+    batch: &mut WriteBatch,
+    prefix: &[u8],
+    seq: u64,
+    event: &MetaEvent,
+) -> Result<()> {
+    let (events, hashes, types) = event_keys(prefix, seq, event);
+    batch.put_cf(cf(db, EVENTS)?, events, postcard::to_allocvec(event)?);
+    batch.put_cf(cf(db, HASHES)?, hashes, seq.to_be_bytes());
+    batch.put_cf(cf(db, TYPES)?, types, b"");
+    Ok(())
+}
+
+/// A [`ChainStore`] which writes each event to a RocksDB database as it's
+/// added while also keeping them in memory.
+#[derive(Debug)]
+pub struct RocksChainStore {
+    db: Arc<DB>,
+    name: String,
+    prefix: Vec<u8>,
+    hasher: String,
+    events: MemoryChainStore,
+}
+
+impl RocksChainStore {
+    /// Opens the chain called `name` in `db`, see [`open_database`],
+    /// creating it if needed.
+    ///
+    /// A new chain is recorded as hashed by the hasher named `hasher`, while
+    /// an existing one keeps its hasher, see [`RocksChainStore::hasher`].
+    pub fn open(db: Arc<DB>, name: &str, hasher: &str) -> Result<RocksChainStore> {
+        let prefix = chain_prefix(name);
+        let chains = cf(&db, CHAINS)?;
+        let hasher = match db.get_cf(chains, &prefix)? {
+            Some(hasher) => String::from_utf8(hasher).context("invalid chain hasher name")?,
+            None => {
+                db.put_cf(chains, &prefix, hasher)?;
+                hasher.to_string()
+            }
+        };
+
+        let mut events = MemoryChainStore::new();
+        for entry in scan(&db, cf(&db, EVENTS)?, prefix.clone()) {
+            let (key, value) = entry?;
+            ensure!(
+                seq_of(&key)? == u64::try_from(events.len())?,
+                "chain `{name}` is missing events"
+            );
+            events.append(decode(&value)?)?;
+        }
+
+        Ok(RocksChainStore {
+            db,
+            name: name.to_string(),
+            prefix,
+            hasher,
+            events,
+        })
+    }
+
+    /// Name of the hasher this chain is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.hasher
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a reader querying this chain in the database.
+    pub fn reader(&self) -> RocksChainReader {
+        RocksChainReader::new(self.db.clone(), &self.name)
+    }
+
+    /// Writes `events` after the first `start` events in a single batch.
+    fn write(&self, mut batch: WriteBatch, start: usize, events: &[MetaEvent]) -> Result<()> {
+        for (seq, event) in (u64::try_from(start)?..).zip(events) {
+            put_event(&self.db, &mut batch, &self.prefix, seq, event)?;
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+impl ChainStore for RocksChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        self.write(
+            WriteBatch::default(),
+            self.events.len(),
+            core::slice::from_ref(&event),
+        )?;
+        self.events.append(event)
+    }
+
+    /// Writes `events` in a single batch.
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        self.write(WriteBatch::default(), self.events.len(), &events)?;
+        self.events.append_batch(events)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    /// Syncs the database's write-ahead log to disk.
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush_wal(true)?;
+        Ok(())
+    }
+
+    /// Deletes the chain's events and writes `events` in a single batch.
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (seq, event) in (0..).zip(self.events.iter_from(0)) {
+            let (events, hashes, types) = event_keys(&self.prefix, seq, event);
+            batch.delete_cf(cf(&self.db, EVENTS)?, events);
+            batch.delete_cf(cf(&self.db, HASHES)?, hashes);
+            batch.delete_cf(cf(&self.db, TYPES)?, types);
+        }
+        self.write(batch, 0, &events)?;
+        self.events = MemoryChainStore::from(events);
+        Ok(())
+    }
+}
+
+/// Read-only queries against a chain stored by a [`RocksChainStore`].
+///
+/// Readers go straight to the database, so they see events appended by the
+/// writing store as soon as each batch is written.
+#[derive(Debug, Clone)]
+pub struct RocksChainReader {
+    db: Arc<DB>,
+    prefix: Vec<u8>,
+}
+
+impl RocksChainReader {
+    /// Queries the chain called `name` in `db`.
+    pub fn new(db: Arc<DB>, name: &str) -> RocksChainReader {
+        RocksChainReader {
+            db,
+            prefix: chain_prefix(name),
+        }
+    }
+
+    /// The number of events in the chain.
+    pub fn len(&self) -> Result<usize> {
+        let last = [&self.prefix[..], &[0xff; 8]].concat();
+        let mode = IteratorMode::From(&last, Direction::Reverse);
+        match self.db.iterator_cf(cf(&self.db, EVENTS)?, mode).next() {
+            Some(entry) => {
+                let (key, _) = entry?;
+                if key.starts_with(&self.prefix) {
+                    Ok(usize::try_from(seq_of(&key)?)? + 1)
+                } else {
+                    Ok(0)
+                }
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Whether the chain has no events.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns the event with sequence number `seq`.
+    pub fn get(&self, seq: u64) -> Result<Option<MetaEvent>> {
+        let key = [&self.prefix[..], &seq.to_be_bytes()].concat();
+        self.db
+            .get_cf(cf(&self.db, EVENTS)?, key)?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    /// Returns the event with the given hash.
+    pub fn get_by_hash(&self, hash: Digest) -> Result<Option<MetaEvent>> {
+        let key = [&self.prefix[..], &hash.as_bytes()[..]].concat();
+        match self.db.get_cf(cf(&self.db, HASHES)?, key)? {
+            Some(seq) => self.get(seq_of(&seq)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterates over the events starting at sequence number `seq`, oldest
+    /// first.
+    pub fn iter_from(&self, seq: u64) -> Result<impl Iterator<Item = Result<MetaEvent>> + '_> {
+        let start = [&self.prefix[..], &seq.to_be_bytes()].concat();
+        let prefix = self.prefix.clone();
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        Ok(self
+            .db
+            .iterator_cf(cf(&self.db, EVENTS)?, mode)
+            .map(|entry| entry.map_err(Error::from))
+            .take_while(move |entry| match entry {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(|entry| decode(&entry?.1)))
+    }
+
+    /// Iterates over the events of type `type_`, oldest first.
+    pub fn events_of_type(
+        &self,
+        type_: &str,
+    ) -> Result<impl Iterator<Item = Result<MetaEvent>> + '_> {
+        let types = cf(&self.db, TYPES)?;
+        Ok(
+            scan(&self.db, types, type_prefix(&self.prefix, type_)).map(|entry| {
+                let seq = seq_of(&entry?.0)?;
+                self.get(seq)?
+                    .with_context(|| format!("chain database is missing event {seq}"))
+            }),
+        )
+    }
+}
+
+impl Chain {
+    /// Opens the chain called `name` in the RocksDB database `db`, see
+    /// [`open_database`], creating an empty SHA-256 chain if it doesn't
+    /// exist yet.
+    ///
+    /// See [`RocksChainStore`] for details.
+    pub fn open_rocksdb(db: &Arc<DB>, name: &str) -> Result<Chain> {
+        let store = RocksChainStore::open(db.clone(), name, "sha256")?;
+        let hasher = match hasher_by_name(store.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!("chain `{name}` uses unknown hasher `{}`", store.hasher()),
+        };
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain `{name}` failed verification"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn resume_and_query() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = open_database(dir.path().join("chains"))?;
+
+        let mut a = Chain::open_rocksdb(&db, "actor-a")?;
+        let mut b = Chain::open_rocksdb(&db, "actor-a2")?;
+        let mut hashes = (0..5u8)
+            .map(|i| a.add(Event::new("tick".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+        hashes.extend(a.add_batch(vec![
+            Event::new("tock".to_string(), vec![5]),
+            Event::new("tick".to_string(), vec![6]),
+        ])?);
+        b.add(Event::new("tick".to_string(), vec![]));
+
+        let reader = RocksChainReader::new(db.clone(), "actor-a");
+        assert_eq!(reader.len()?, 7);
+        let tail = reader.iter_from(5)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tail.iter().map(|e| e.hash()).collect::<Vec<_>>(),
+            &hashes[5..]
+        );
+        let found = reader.get_by_hash(hashes[4])?.unwrap();
+        assert_eq!(found.event().parent(), Some(hashes[3]));
+        let ticks = reader.events_of_type("tick")?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(ticks.len(), 6);
+        assert_eq!(ticks[5].hash(), hashes[6]);
+        drop((a, b));
+
+        let mut a = Chain::open_rocksdb(&db, "actor-a")?;
+        assert_eq!(a.head(), Some(hashes[6]));
+        let next = a.add(Event::new("tick".to_string(), vec![7]));
+        assert_eq!(reader.get_by_hash(next)?.unwrap().hash(), next);
+        assert_eq!(Chain::open_rocksdb(&db, "actor-a2")?.len(), 1);
+
+        let kept = a.iter().skip(6).cloned().collect::<Vec<_>>();
+        let mut store = RocksChainStore::open(db.clone(), "actor-a", "sha256")?;
+        store.rewrite(kept)?;
+        assert_eq!(reader.len()?, 2);
+        assert!(reader.get_by_hash(hashes[0])?.is_none());
+        assert_eq!(reader.events_of_type("tick")?.count(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording a sample of the calls made.
+//!
+//! A service handling many calls a second can't afford to record every one,
+//! so a store can be given a [`Sampling`] policy with
+//! [`Store::set_chain_sampling`](crate::Store::set_chain_sampling). Export
+//! calls the policy leaves out record nothing, nor do the imports they call,
+//! but they're still counted: the `function-call` event of the next call to
+//! the same export which is recorded is preceded by a [`SAMPLED`] event
+//! holding how many were left out since the last one.
+
+use crate::chain::record::decode;
+use crate::chain::{Digest, Event};
+use crate::prelude::*;
+use crate::store::StoreOpaque;
+use core::mem;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Event type counting the calls to an export which weren't recorded.
+pub const SAMPLED: &str = "sampled";
+
+/// Which export calls a store records, see the
+/// [module documentation](crate::chain::sample).
+///
+/// Calls to each export are sampled on their own, so rarely called exports
+/// aren't crowded out by frequent ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Sampling {
+    /// Records every call.
+    #[default]
+    All,
+    /// Records the first of every `n` calls to each export.
+    OneIn(u64),
+    /// Records at most `n` calls to each export per second.
+    PerSecond(u64),
+}
+
+/// Payload of a [`SAMPLED`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sampled {
+    /// Name of the export, as in
+    /// [`FunctionCall::name`](crate::chain::FunctionCall::name).
+    pub name: String,
+    /// How many calls to it weren't recorded since the last one which was.
+    pub skipped: u64,
+    /// See [`FunctionCall::call_parent`](crate::chain::FunctionCall::call_parent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_parent: Option<Digest>,
+}
+
+impl Sampled {
+    /// Decodes the payload of a [`SAMPLED`] event.
+    pub fn decode(event: &Event) -> Result<Sampled> {
+        decode(event, SAMPLED)
+    }
+}
+
+/// Whether a call is recorded, as decided by a [`Sampler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sample {
+    /// Record the call, after `skipped` calls to the same export which
+    /// weren't.
+    Record {
+        skipped: u64,
+    },
+    Skip,
+}
+
+/// Applies a [`Sampling`] to the calls made in a store.
+#[derive(Debug, Default)]
+pub(crate) struct Sampler {
+    sampling: Sampling,
+    counts: HashMap<String, Count>,
+}
+
+#[derive(Debug)]
+struct Count {
+    /// Calls seen, or for [`Sampling::PerSecond`] calls recorded, since
+    /// `since`.
+    calls: u64,
+    since: Instant,
+    /// Calls not recorded since the last one which was.
+    skipped: u64,
+}
+
+impl Sampler {
+    pub(crate) fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    /// Starts applying `sampling`, forgetting the calls counted so far.
+    pub(crate) fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = sampling;
+        self.counts.clear();
+    }
+
+    /// Decides whether the call to the export `name` starting now is
+    /// recorded.
+    pub(crate) fn sample(&mut self, name: &str) -> Sample {
+        if self.sampling == Sampling::All {
+            return Sample::Record { skipped: 0 };
+        }
+        let now = Instant::now();
+        if !self.counts.contains_key(name) {
+            let count = Count {
+                calls: 0,
+                since: now,
+                skipped: 0,
+            };
+            self.counts.insert(name.to_string(), count);
+        }
+        let count = self.counts.get_mut(name).unwrap();
+        let record = match self.sampling {
+            Sampling::All => true,
+            Sampling::OneIn(n) => {
+                count.calls += 1;
+                (count.calls - 1) % n.max(1) == 0
+            }
+            Sampling::PerSecond(n) => {
+                if now.duration_since(count.since) >= Duration::from_secs(1) {
+                    count.calls = 0;
+                    count.since = now;
+                }
+                count.calls < n && {
+                    count.calls += 1;
+                    true
+                }
+            }
+        };
+        if record {
+            Sample::Record {
+                skipped: mem::take(&mut count.skipped),
+            }
+        } else {
+            count.skipped += 1;
+            Sample::Skip
+        }
+    }
+}
+
+/// Records that `skipped` calls to the export `name` weren't recorded.
+pub(crate) fn record(store: &mut StoreOpaque, name: &str, skipped: u64) -> Result<()> {
+    let sampled = Sampled {
+        name: name.to_string(),
+        skipped,
+        call_parent: store.call_parent(),
+    };
+    let (chain, _) = store.chain_and_registry_mut()?;
+    chain.try_add(Event::new(
+        SAMPLED.to_string(),
+        serde_json::to_vec(&sampled)?,
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::FunctionCall;
+    use crate::component::{Component, Linker};
+    use crate::{Config, Engine, Store};
+
+    #[test]
+    fn samplers() {
+        use Sample::Skip;
+        let record = |skipped| Sample::Record { skipped };
+        let mut sampler = Sampler::default();
+        assert_eq!(sampler.sample("a"), record(0));
+
+        sampler.set_sampling(Sampling::OneIn(3));
+        let samples = (0..7).map(|_| sampler.sample("a")).collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [record(0), Skip, Skip, record(2), Skip, Skip, record(2)]
+        );
+        assert_eq!(sampler.sample("b"), record(0));
+
+        sampler.set_sampling(Sampling::PerSecond(2));
+        let samples = (0..4).map(|_| sampler.sample("a")).collect::<Vec<_>>();
+        assert_eq!(samples, [record(0), record(0), Skip, Skip]);
+    }
+
+    #[test]
+    fn records_sampled_calls() -> Result<()> {
+        let component = r#"
+            (component
+                (import "host" (func $host))
+                (core func $host (canon lower (func $host)))
+                (core module $m
+                    (import "" "host" (func $host))
+                    (func (export "run") call $host))
+                (core instance $i (instantiate $m
+                    (with "" (instance (export "host" (func $host))))))
+                (func (export "run") (canon lift (core func $i "run")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::new(&engine);
+        linker.root().func_wrap("host", |_, (): ()| Ok(()))?;
+        let mut store = Store::new(&engine, ());
+        store.set_chain_sampling(Sampling::OneIn(4));
+        let instance = linker.instantiate(&mut store, &component)?;
+        let run = instance.get_func(&mut store, "run").unwrap();
+        for _ in 0..5 {
+            run.call(&mut store, &[], &mut [])?;
+            run.post_return(&mut store)?;
+        }
+
+        let chain = store.chain();
+        let types = chain
+            .events()
+            .map(|node| node.event().type_())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "import-call",
+                "import-return",
+                "function-call",
+                "import-call",
+                "import-return",
+                SAMPLED,
+                "function-call"
+            ]
+        );
+        let sampled = chain.events_of_type(SAMPLED).next().unwrap();
+        let sampled = Sampled::decode(sampled.event())?;
+        assert_eq!((sampled.name.as_str(), sampled.skipped), ("run", 3));
+        let call = chain.get_event_by_hash(chain.head().unwrap()).unwrap();
+        assert_eq!(FunctionCall::decode(call.event())?.name, "run");
+        assert_eq!(store.chain_sampling(), Sampling::OneIn(4));
+        Ok(())
+    }
+}
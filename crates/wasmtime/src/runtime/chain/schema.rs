@@ -0,0 +1,362 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declaring the types of events embedders add, and evolving them.
+//!
+//! A [`SchemaRegistry`] holds the versions of each event type declared with
+//! [`SchemaRegistry::register`], each with a [`PayloadSchema`]. Once it's
+//! set on a chain with [`Chain::set_schemas`], every event of a declared
+//! type is stamped with the latest version, unless it already has one, and
+//! its payload is checked against that version's schema before it's added.
+//! Events of types which aren't declared are added as they are.
+//!
+//! The stamp is kept with the event, see [`Event::schema_version`], so
+//! events added before a type changed can still be told apart. A version
+//! declared with an upgrade from the version before it lets
+//! [`SchemaRegistry::decode`] read payloads of any version as the latest.
+//! Events from before the type was declared have no stamp and are taken to
+//! be of its oldest version.
+
+use crate::chain::{Chain, Event, SerializableType};
+use crate::prelude::*;
+use core::fmt;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// What the payload of an event must hold.
+#[derive(Clone)]
+pub enum PayloadSchema {
+    /// Any payload.
+    Any,
+    /// The JSON encoding of a
+    /// [`SerializableVal`](crate::chain::SerializableVal) of this type.
+    Type(SerializableType),
+    /// A payload the function accepts, see [`PayloadSchema::serde`].
+    Check(Arc<dyn Fn(&[u8]) -> Result<()> + Send + Sync>),
+}
+
+impl PayloadSchema {
+    /// The JSON encoding of a `T`.
+    pub fn serde<T: DeserializeOwned>() -> PayloadSchema {
+        PayloadSchema::Check(Arc::new(|data| {
+            serde_json::from_slice::<T>(data)?;
+            Ok(())
+        }))
+    }
+
+    /// Checks that `data` matches this schema.
+    pub fn check(&self, data: &[u8]) -> Result<()> {
+        match self {
+            PayloadSchema::Any => Ok(()),
+            PayloadSchema::Type(ty) => Ok(ty.check(&serde_json::from_slice(data)?)?),
+            PayloadSchema::Check(check) => check(data),
+        }
+    }
+}
+
+impl fmt::Debug for PayloadSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadSchema::Any => f.write_str("Any"),
+            PayloadSchema::Type(ty) => f.debug_tuple("Type").field(ty).finish(),
+            PayloadSchema::Check(_) => f.write_str("Check(..)"),
+        }
+    }
+}
+
+/// Turns a payload of the version before into one of this version.
+type Upgrade = Arc<dyn Fn(Vec<u8>) -> Result<Vec<u8>> + Send + Sync>;
+
+/// A version of an event type, see [`SchemaRegistry::register`].
+#[derive(Clone)]
+pub struct EventSchema {
+    version: u32,
+    payload: PayloadSchema,
+    upgrade: Option<Upgrade>,
+}
+
+impl EventSchema {
+    pub fn new(version: u32, payload: PayloadSchema) -> EventSchema {
+        EventSchema {
+            version,
+            payload,
+            upgrade: None,
+        }
+    }
+
+    /// Sets how to turn a payload of the version declared before this one
+    /// into one of this version, for [`SchemaRegistry::decode`].
+    pub fn with_upgrade(
+        mut self,
+        upgrade: impl Fn(Vec<u8>) -> Result<Vec<u8>> + Send + Sync + 'static,
+    ) -> EventSchema {
+        self.upgrade = Some(Arc::new(upgrade));
+        self
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn payload(&self) -> &PayloadSchema {
+        &self.payload
+    }
+}
+
+impl fmt::Debug for EventSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSchema")
+            .field("version", &self.version)
+            .field("payload", &self.payload)
+            .field("upgrade", &self.upgrade.is_some())
+            .finish()
+    }
+}
+
+/// The declared versions of event types, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct SchemaRegistry {
+    /// The versions of each type, oldest first.
+    types: HashMap<String, Vec<EventSchema>>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> SchemaRegistry {
+        SchemaRegistry::default()
+    }
+
+    /// Declares a version of the event type `type_`, which must be newer
+    /// than the versions already declared for it.
+    pub fn register(&mut self, type_: &str, schema: EventSchema) -> Result<()> {
+        let versions = self.types.entry(type_.to_string()).or_default();
+        if let Some(latest) = versions.last() {
+            ensure!(
+                schema.version > latest.version,
+                "version {} of `{type_}` events isn't newer than version {}",
+                schema.version,
+                latest.version
+            );
+        }
+        versions.push(schema);
+        Ok(())
+    }
+
+    /// The declared versions of `type_`, oldest first.
+    pub fn versions(&self, type_: &str) -> &[EventSchema] {
+        self.types.get(type_).map_or(&[], |versions| versions)
+    }
+
+    /// The newest declared version of `type_`.
+    pub fn latest(&self, type_: &str) -> Option<&EventSchema> {
+        self.versions(type_).last()
+    }
+
+    /// The schema `event` was stamped with, or the oldest version of its type
+    /// if it has no stamp. Returns `None` if its type isn't declared.
+    pub fn schema_of(&self, event: &Event) -> Result<Option<&EventSchema>> {
+        let versions = self.versions(event.type_());
+        let Some(version) = event.schema_version() else {
+            return Ok(versions.first());
+        };
+        match versions.iter().find(|schema| schema.version == version) {
+            Some(schema) => Ok(Some(schema)),
+            None if versions.is_empty() => Ok(None),
+            None => bail!(
+                "version {version} of `{}` events isn't declared",
+                event.type_()
+            ),
+        }
+    }
+
+    /// Checks that the payload of `event` matches the schema of its version.
+    pub fn check(&self, event: &Event) -> Result<()> {
+        match self.schema_of(event)? {
+            Some(schema) => schema
+                .payload
+                .check(&event.try_data()?)
+                .with_context(|| format!("invalid `{}` event", event.type_())),
+            None => Ok(()),
+        }
+    }
+
+    /// Stamps `event` with the latest version of its type if it has no
+    /// version yet, then checks it.
+    pub(crate) fn stamp(&self, event: &mut Event) -> Result<()> {
+        if event.schema_version.is_none() {
+            event.schema_version = self.latest(event.type_()).map(|schema| schema.version);
+        }
+        self.check(event)
+    }
+
+    /// The payload of `event`, upgraded to the latest version of its type.
+    pub fn upgrade(&self, event: &Event) -> Result<Vec<u8>> {
+        let mut data = event.try_data()?.into_owned();
+        let Some(schema) = self.schema_of(event)? else {
+            return Ok(data);
+        };
+        for newer in self
+            .versions(event.type_())
+            .iter()
+            .skip_while(|s| s.version <= schema.version)
+        {
+            let Some(upgrade) = &newer.upgrade else {
+                bail!(
+                    "no upgrade to version {} of `{}` events",
+                    newer.version,
+                    event.type_()
+                );
+            };
+            data = upgrade(data)?;
+        }
+        Ok(data)
+    }
+
+    /// Decodes the JSON payload of `event`, upgraded to the latest version
+    /// of its type.
+    pub fn decode<T: DeserializeOwned>(&self, event: &Event) -> Result<T> {
+        Ok(serde_json::from_slice(&self.upgrade(event)?)?)
+    }
+}
+
+impl Event {
+    /// The version of its type this event was stamped with when it was
+    /// added, see the [`schema` module](crate::chain::schema).
+    pub fn schema_version(&self) -> Option<u32> {
+        self.schema_version
+    }
+
+    /// Stamps this event with a version of its type, instead of the latest
+    /// one, or takes the stamp away if it's `None`.
+    pub fn with_schema_version(mut self, version: impl Into<Option<u32>>) -> Event {
+        self.schema_version = version.into();
+        self
+    }
+}
+
+impl Chain {
+    /// Sets the schemas which events added from now on, and to child chains
+    /// spawned from now on, are stamped with and checked against, or stops
+    /// checking events if `schemas` is `None`.
+    ///
+    /// [`Chain::add`] panics on an event which doesn't match its schema, so
+    /// events which might not should be added with [`Chain::try_add`].
+    pub fn set_schemas(&mut self, schemas: Option<Arc<SchemaRegistry>>) {
+        self.schemas = schemas;
+    }
+
+    /// The schemas set with [`Chain::set_schemas`].
+    pub fn schemas(&self) -> Option<&Arc<SchemaRegistry>> {
+        self.schemas.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::SerializableVal;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct OrderV1 {
+        item: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        item: String,
+        quantity: u32,
+    }
+
+    fn order_v1(item: &str) -> Event {
+        let payload = serde_json::to_vec(&OrderV1 { item: item.into() }).unwrap();
+        Event::new("order".to_string(), payload)
+    }
+
+    #[test]
+    fn checks_and_upgrades_events() -> Result<()> {
+        let mut chain = Chain::new();
+        let unstamped = chain.add(order_v1("tea"));
+
+        let mut schemas = SchemaRegistry::new();
+        schemas.register(
+            "order",
+            EventSchema::new(1, PayloadSchema::serde::<OrderV1>()),
+        )?;
+        let record = SerializableType::Record(vec![("n".to_string(), SerializableType::U32)]);
+        schemas.register("count", EventSchema::new(1, PayloadSchema::Type(record)))?;
+        chain.set_schemas(Some(Arc::new(schemas.clone())));
+
+        let v1 = chain.add(order_v1("coffee"));
+        let node = chain.get_event_by_hash(v1).unwrap();
+        assert_eq!(node.event().schema_version(), Some(1));
+        let head = chain.head();
+        let bad = Event::new("order".to_string(), b"{}".to_vec());
+        assert!(chain.try_add(bad).is_err());
+        assert!(chain
+            .try_add(order_v1("tea").with_schema_version(7))
+            .is_err());
+        let count = |n| {
+            let val = SerializableVal::Record(vec![("n".to_string(), n)]);
+            Event::new("count".to_string(), serde_json::to_vec(&val).unwrap())
+        };
+        assert!(chain.try_add(count(SerializableVal::S8(3))).is_err());
+        assert_eq!(chain.head(), head);
+        chain.try_add(count(SerializableVal::U32(3)))?;
+        chain.try_add(Event::new("undeclared".to_string(), vec![1]))?;
+
+        assert!(schemas
+            .register("order", EventSchema::new(1, PayloadSchema::Any))
+            .is_err());
+        let v2 = EventSchema::new(2, PayloadSchema::serde::<Order>()).with_upgrade(|data| {
+            let OrderV1 { item } = serde_json::from_slice(&data)?;
+            Ok(serde_json::to_vec(&Order { item, quantity: 1 })?)
+        });
+        schemas.register("order", v2)?;
+        chain.set_schemas(Some(Arc::new(schemas.clone())));
+        let payload = serde_json::to_vec(&Order {
+            item: "milk".into(),
+            quantity: 2,
+        })?;
+        let v2 = chain.add(Event::new("order".to_string(), payload));
+
+        let orders = chain
+            .events_of_type("order")
+            .map(|node| {
+                assert!([unstamped, v1, v2].contains(&node.hash()));
+                schemas.decode::<Order>(node.event())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let order = |item: &str, quantity| Order {
+            item: item.into(),
+            quantity,
+        };
+        assert_eq!(
+            orders,
+            [order("tea", 1), order("coffee", 1), order("milk", 2)]
+        );
+        assert!(chain
+            .events()
+            .all(|node| schemas.check(node.event()).is_ok()));
+
+        let round_tripped = Chain::from_bytes(&chain.to_bytes()?)?;
+        round_tripped.verify()?;
+        let node = round_tripped.get_event_by_hash(v2).unwrap();
+        assert_eq!(node.event().schema_version(), Some(2));
+        let json = serde_json::to_string(&chain)?;
+        assert_eq!(serde_json::from_str::<Chain>(&json)?.head(), chain.head());
+        Ok(())
+    }
+}
@@ -0,0 +1,597 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persisting a chain to a directory of rotated segment files.
+//!
+//! A [`SegmentedChainStore`] appends events to the newest of a series of
+//! segments, each a file in the [chain file format](crate::chain::file), and
+//! starts a new one once it's full by its [`Rotation`]. `manifest.json` lists
+//! the segments in order, along with the sequence number of the first event
+//! of each, how many events it holds and the hash of the last of them, its
+//! head.
+//!
+//! A full segment is synced before the manifest listing its successor is
+//! written, and the manifest is replaced atomically, so a crash leaves every
+//! segment but the newest as the manifest describes it. The newest one is
+//! read back like a chain file, dropping a frame cut short by the crash.
+//!
+//! Compacting the chain, see [`Chain::compact`], only writes the checkpoint
+//! and the events after it which shared a segment with folded events. The
+//! segments holding nothing but folded events are deleted once the new
+//! manifest is in place, so a chain compacted regularly takes up bounded
+//! disk space. Files left behind by a crash part way through are deleted
+//! when the store is next opened.
+
+use crate::chain::file::{push_frame, strip_magic, Frames, MAGIC};
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{Chain, ChainStore, Digest, MemoryChainStore, MetaEvent};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// How large a segment grows unless configured otherwise.
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 << 20;
+
+const MANIFEST: &str = "manifest.json";
+
+/// When a [`SegmentedChainStore`] starts a new segment.
+///
+/// A batch of events, see [`Chain::add_batch`], is always written to a
+/// single segment, which may so grow past its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Once the newest segment holds this many events.
+    Events(usize),
+    /// Once the newest segment's file is at least this many bytes long.
+    Bytes(u64),
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::Bytes(DEFAULT_SEGMENT_BYTES)
+    }
+}
+
+impl Rotation {
+    fn is_full(self, events: u64, bytes: u64) -> bool {
+        events > 0
+            && match self {
+                Rotation::Events(max) => events >= u64::try_from(max).unwrap_or(u64::MAX),
+                Rotation::Bytes(max) => bytes >= max,
+            }
+    }
+}
+
+/// The contents of `manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    format: u64,
+    hasher: String,
+    /// The number in the name of the next segment created, so that no name
+    /// is reused.
+    next_segment: u64,
+    segments: Vec<Segment>,
+}
+
+/// A segment of a [`SegmentedChainStore`], as listed in its manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    /// The segment's file name in the store's directory.
+    pub name: String,
+    /// The sequence number of its first event.
+    pub first: u64,
+    /// How many events it holds.
+    pub len: u64,
+    /// The hash of the last of them, `None` for a new segment.
+    pub head: Option<Digest>,
+}
+
+/// A [`ChainStore`] appending events to rotated segment files while also
+/// keeping them in memory, see the
+/// [module documentation](crate::chain::segment).
+#[derive(Debug)]
+pub struct SegmentedChainStore {
+    dir: PathBuf,
+    rotation: Rotation,
+    manifest: Manifest,
+    /// The newest segment, which events are appended to.
+    active: File,
+    /// The length of `active`.
+    active_bytes: u64,
+    events: MemoryChainStore,
+}
+
+impl SegmentedChainStore {
+    /// Opens or creates the segmented chain in the directory `dir`.
+    ///
+    /// A new chain is initialized for the hasher named `hasher`, while an
+    /// existing one keeps the hasher it was created with, see
+    /// [`SegmentedChainStore::hasher`].
+    pub fn open(
+        dir: impl AsRef<Path>,
+        hasher: &str,
+        rotation: Rotation,
+    ) -> Result<SegmentedChainStore> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create chain directory `{}`", dir.display()))?;
+        let manifest_path = dir.join(MANIFEST);
+        let mut manifest = match fs::read(&manifest_path) {
+            Ok(bytes) => {
+                let manifest: Manifest = serde_json::from_slice(&bytes).with_context(|| {
+                    format!("invalid chain manifest `{}`", manifest_path.display())
+                })?;
+                migrate::check_version(manifest.format)?;
+                manifest
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let mut manifest = Manifest {
+                    format: FORMAT_VERSION.into(),
+                    hasher: hasher.to_string(),
+                    next_segment: 0,
+                    segments: Vec::new(),
+                };
+                let (segment, _) = create_segment(dir, &mut manifest, 0, &[])?;
+                manifest.segments.push(segment);
+                write_manifest(dir, &manifest)?;
+                manifest
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "failed to read chain manifest `{}`",
+                        manifest_path.display()
+                    )
+                })
+            }
+        };
+        ensure!(
+            !manifest.segments.is_empty(),
+            "chain manifest `{}` lists no segments",
+            manifest_path.display()
+        );
+
+        let mut events: Vec<MetaEvent> = Vec::new();
+        let last = manifest.segments.len() - 1;
+        let mut active = None;
+        for (i, segment) in manifest.segments.iter_mut().enumerate() {
+            let path = dir.join(&segment.name);
+            let context = || format!("invalid chain segment `{}`", path.display());
+            let contents = fs::read(&path).with_context(context)?;
+            let (version, rest) = strip_magic(&contents).with_context(context)?;
+            ensure!(
+                version == FORMAT_VERSION,
+                "{}: unexpected format version {version}",
+                context()
+            );
+            let mut frames = Frames::new(rest);
+            ensure!(
+                frames.next() == Some(manifest.hasher.as_bytes()),
+                "{}: wrong hasher name",
+                context()
+            );
+            ensure!(
+                segment.first == u64::try_from(events.len())?,
+                "{}: doesn't follow on from the segment before it",
+                context()
+            );
+            let start = events.len();
+            for frame in &mut frames {
+                let event = postcard::from_bytes(frame)
+                    .with_context(|| format!("{}: event {} is corrupt", context(), events.len()))?;
+                events.push(event);
+            }
+            let len = u64::try_from(events.len() - start)?;
+            let head = events[start..].last().map(|e| e.hash());
+            if i < last {
+                ensure!(
+                    segment.len == len && segment.head == head,
+                    "{}: doesn't match the manifest",
+                    context()
+                );
+                continue;
+            }
+
+            // Like a chain file, the newest segment may end with a frame cut
+            // short by a crash, and holds more events than the manifest says.
+            let valid = MAGIC.len() + frames.valid;
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .with_context(context)?;
+            if valid < contents.len() {
+                log::warn!(
+                    "discarding {} trailing bytes of chain segment `{}`",
+                    contents.len() - valid,
+                    path.display()
+                );
+                file.set_len(u64::try_from(valid)?)?;
+            }
+            active = Some((file, u64::try_from(valid)?));
+            segment.len = len;
+            segment.head = head;
+        }
+
+        let (active, active_bytes) = active.unwrap();
+        let store = SegmentedChainStore {
+            dir: dir.to_path_buf(),
+            rotation,
+            manifest,
+            active,
+            active_bytes,
+            events: MemoryChainStore::from(events),
+        };
+        store.delete_unlisted()?;
+        Ok(store)
+    }
+
+    /// Name of the hasher the chain is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.manifest.hasher
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The chain's segments, oldest first.
+    pub fn segments(&self) -> &[Segment] {
+        &self.manifest.segments
+    }
+
+    fn segment_path(&self, index: usize) -> PathBuf {
+        self.dir.join(&self.manifest.segments[index].name)
+    }
+
+    /// Deletes the segment files the manifest doesn't list, left behind by
+    /// a crash while rotating or rewriting.
+    fn delete_unlisted(&self) -> Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let listed = self.manifest.segments.iter().any(|s| s.name == name);
+            if !listed && (name.ends_with(".seg") || name.ends_with(".seg.tmp")) {
+                log::info!("deleting unlisted chain segment `{}`", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a new segment if the newest one is full.
+    fn rotate_if_full(&mut self) -> Result<()> {
+        let active = self.manifest.segments.last().unwrap();
+        if !self.rotation.is_full(active.len, self.active_bytes) {
+            return Ok(());
+        }
+        let first = active.first + active.len;
+        self.flush()?;
+
+        let mut manifest = self.manifest.clone();
+        let (segment, bytes) = create_segment(&self.dir, &mut manifest, first, &[])?;
+        manifest.segments.push(segment);
+        write_manifest(&self.dir, &manifest)?;
+        self.manifest = manifest;
+        self.active = OpenOptions::new()
+            .append(true)
+            .open(self.segment_path(self.manifest.segments.len() - 1))?;
+        self.active_bytes = bytes;
+        Ok(())
+    }
+
+    /// Lists the segments of a rewritten chain in `manifest` and writes it:
+    /// new segments holding `fresh`, followed by the segments from `reused`
+    /// on. The names of the new segments are pushed to `written`.
+    fn write_rewritten(
+        &self,
+        manifest: &mut Manifest,
+        fresh: &[MetaEvent],
+        reused: usize,
+        written: &mut Vec<String>,
+    ) -> Result<()> {
+        manifest.segments.clear();
+        let header = u64::try_from(MAGIC.len() + 4 + manifest.hasher.len())?;
+        let mut first = 0;
+        let mut bytes = header;
+        for (index, event) in fresh.iter().enumerate() {
+            bytes += 4 + u64::try_from(postcard::to_allocvec(event)?.len())?;
+            let len = index + 1 - first;
+            if self.rotation.is_full(u64::try_from(len)?, bytes) || index + 1 == fresh.len() {
+                let chunk = &fresh[first..=index];
+                let (segment, _) =
+                    create_segment(&self.dir, manifest, u64::try_from(first)?, chunk)?;
+                written.push(segment.name.clone());
+                manifest.segments.push(segment);
+                first = index + 1;
+                bytes = header;
+            }
+        }
+        if fresh.is_empty() && reused == self.manifest.segments.len() {
+            let (segment, _) = create_segment(&self.dir, manifest, 0, &[])?;
+            written.push(segment.name.clone());
+            manifest.segments.push(segment);
+        }
+        let mut first = u64::try_from(fresh.len())?;
+        for segment in &self.manifest.segments[reused..] {
+            manifest.segments.push(Segment {
+                first,
+                ..segment.clone()
+            });
+            first += segment.len;
+        }
+        write_manifest(&self.dir, manifest)
+    }
+
+    /// Appends the frames of `events` to the newest segment, truncating it
+    /// back if that fails.
+    fn write(&mut self, events: &[MetaEvent]) -> Result<()> {
+        self.rotate_if_full()?;
+        let mut frames = Vec::new();
+        for event in events {
+            push_frame(&mut frames, &postcard::to_allocvec(event)?)?;
+        }
+        let index = self.manifest.segments.len() - 1;
+        let path = self.segment_path(index).display().to_string();
+        let context = move || format!("failed to append to `{path}`");
+        if let Err(e) = self.active.write_all(&frames) {
+            let _ = self.active.set_len(self.active_bytes);
+            return Err(e).with_context(context);
+        }
+        self.active_bytes += u64::try_from(frames.len())?;
+        let segment = &mut self.manifest.segments[index];
+        segment.len += u64::try_from(events.len())?;
+        segment.head = events.last().map(|e| e.hash()).or(segment.head);
+        Ok(())
+    }
+}
+
+impl ChainStore for SegmentedChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        self.write(core::slice::from_ref(&event))?;
+        self.events.append(event)
+    }
+
+    /// Writes the frames of `events` to the newest segment at once.
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        self.write(&events)?;
+        self.events.append_batch(events)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    /// Keeps the newest segments whose events end `events` unchanged, such
+    /// as those after a checkpoint, writes the rest of `events` to new
+    /// segments and deletes the segments which are no longer listed.
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let old_len = self.events.len();
+        let mut kept = 0;
+        for segment in self.manifest.segments.iter().rev() {
+            let first = usize::try_from(segment.first)?;
+            let Some(start) = (events.len() + first).checked_sub(old_len) else {
+                break;
+            };
+            let unchanged = self
+                .events
+                .iter_from(first)
+                .take(usize::try_from(segment.len)?)
+                .zip(&events[start..])
+                .all(|(a, b)| postcard::to_allocvec(a).ok() == postcard::to_allocvec(b).ok());
+            if !unchanged {
+                break;
+            }
+            kept += 1;
+        }
+        let reused = self.manifest.segments.len() - kept;
+        let fresh = match self.manifest.segments.get(reused) {
+            Some(segment) => events.len() + usize::try_from(segment.first)? - old_len,
+            None => events.len(),
+        };
+        if fresh == 0 && reused == 0 {
+            self.events = MemoryChainStore::from(events);
+            return Ok(());
+        }
+
+        let mut manifest = self.manifest.clone();
+        let mut written = Vec::new();
+        if let Err(e) = self.write_rewritten(&mut manifest, &events[..fresh], reused, &mut written)
+        {
+            for name in written {
+                let _ = fs::remove_file(self.dir.join(name));
+            }
+            return Err(e)
+                .with_context(|| format!("failed to rewrite chain in `{}`", self.dir.display()));
+        }
+
+        for segment in &self.manifest.segments[..reused] {
+            let path = self.dir.join(&segment.name);
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("failed to delete chain segment `{}`: {e}", path.display());
+            }
+        }
+        self.manifest = manifest;
+        let path = self.segment_path(self.manifest.segments.len() - 1);
+        self.active = OpenOptions::new().append(true).open(&path)?;
+        self.active_bytes = self.active.metadata()?.len();
+        self.events = MemoryChainStore::from(events);
+        Ok(())
+    }
+
+    /// Flushes appended events through to the underlying device.
+    fn flush(&mut self) -> Result<()> {
+        match self.active.sync_data() {
+            Ok(()) => Ok(()),
+            // Some platforms don't support syncing certain files, which isn't
+            // worth failing over.
+            Err(e) if e.kind() == ErrorKind::Unsupported => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Writes the next segment of `manifest`, holding `events` starting at
+/// sequence number `first`, returning it along with its length in bytes.
+///
+/// The segment is written to a temporary file which is then synced and
+/// renamed, but isn't added to `manifest`.
+fn create_segment(
+    dir: &Path,
+    manifest: &mut Manifest,
+    first: u64,
+    events: &[MetaEvent],
+) -> Result<(Segment, u64)> {
+    let name = format!("{:016}.seg", manifest.next_segment);
+    manifest.next_segment += 1;
+    let mut contents = MAGIC.to_vec();
+    push_frame(&mut contents, manifest.hasher.as_bytes())?;
+    for event in events {
+        push_frame(&mut contents, &postcard::to_allocvec(event)?)?;
+    }
+    let path = dir.join(&name);
+    write_synced(&path, &contents)
+        .with_context(|| format!("failed to write chain segment `{}`", path.display()))?;
+    let segment = Segment {
+        name,
+        first,
+        len: u64::try_from(events.len())?,
+        head: events.last().map(|e| e.hash()),
+    };
+    Ok((segment, u64::try_from(contents.len())?))
+}
+
+fn write_manifest(dir: &Path, manifest: &Manifest) -> Result<()> {
+    let path = dir.join(MANIFEST);
+    write_synced(&path, &serde_json::to_vec_pretty(manifest)?)
+        .with_context(|| format!("failed to write chain manifest `{}`", path.display()))
+}
+
+/// Replaces the file at `path` with `contents` through a synced temporary
+/// file, so that it's never seen half written.
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl Chain {
+    /// Opens the chain persisted in segments in the directory `dir`,
+    /// creating an empty SHA-256 chain there if it doesn't exist yet.
+    ///
+    /// See [`SegmentedChainStore`] for details.
+    pub fn open_segmented(dir: impl AsRef<Path>, rotation: Rotation) -> Result<Chain> {
+        let dir = dir.as_ref();
+        let store = SegmentedChainStore::open(dir, "sha256", rotation)?;
+        let hasher = match hasher_by_name(store.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!(
+                "chain in `{}` uses unknown hasher `{}`",
+                dir.display(),
+                store.hasher()
+            ),
+        };
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain in `{}` failed verification", dir.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    fn add(chain: &mut Chain, n: u8) -> Vec<Digest> {
+        (0..n)
+            .map(|i| chain.add(Event::new("event".to_string(), vec![i])))
+            .collect()
+    }
+
+    fn files(dir: &Path) -> Result<Vec<String>> {
+        let mut names = fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn rotates_and_deletes_checkpointed_segments() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chain");
+
+        let mut chain = Chain::open_segmented(&path, Rotation::Events(3))?;
+        let hashes = add(&mut chain, 10);
+        drop(chain);
+
+        let store = SegmentedChainStore::open(&path, "sha256", Rotation::Events(3))?;
+        let segments = store.segments();
+        assert_eq!(segments.len(), 4);
+        assert_eq!(
+            segments
+                .iter()
+                .map(|s| (s.first, s.len))
+                .collect::<Vec<_>>(),
+            [(0, 3), (3, 3), (6, 3), (9, 1)]
+        );
+        assert_eq!(segments[1].head, Some(hashes[5]));
+        assert_eq!(segments[3].head, Some(hashes[9]));
+        drop(store);
+
+        // Folding the first five events deletes the two segments holding
+        // them, keeping the segments after the one the checkpoint splits.
+        let mut chain = Chain::open_segmented(&path, Rotation::Events(3))?;
+        assert_eq!(chain.head(), Some(hashes[9]));
+        chain.compact(5, Digest([7; Digest::LEN]))?;
+        let head = add(&mut chain, 1)[0];
+        drop(chain);
+        assert_eq!(
+            files(&path)?,
+            [
+                "0000000000000002.seg",
+                "0000000000000003.seg",
+                "0000000000000004.seg",
+                "manifest.json"
+            ]
+        );
+
+        // Segments a crash left behind are deleted.
+        fs::write(path.join("0000000000000009.seg"), MAGIC)?;
+        let chain = Chain::open_segmented(&path, Rotation::Events(3))?;
+        assert_eq!(chain.head(), Some(head));
+        assert_eq!(chain.len(), 7);
+        assert_eq!(chain.get_parent(hashes[6]).unwrap().hash(), hashes[5]);
+        chain.verify()?;
+        assert_eq!(files(&path)?.len(), 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Event severities and error events.
+//!
+//! An event may be given a [`Severity`] with [`Event::with_severity`], which
+//! lets tooling pick the problems out of a long chain with
+//! [`Chain::events_at_least`] without decoding every payload. Most events
+//! have none. Recorded `trap` events are [`Severity::Error`], and
+//! [`Event::error`] turns an error into an [`ERROR`] event of that severity.
+//!
+//! The severity is hashed along with the rest of the event, so it can't be
+//! changed without changing the hash. Events without one hash as they did
+//! before events could have one.
+
+use crate::chain::record::decode;
+use crate::chain::{Chain, Event, MetaEvent};
+use crate::prelude::*;
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Type of an event describing an error, see [`Event::error`].
+pub const ERROR: &str = "error";
+
+/// How much an event matters to someone looking for problems, from least to
+/// most.
+///
+/// The discriminants are what's hashed, see [`Event::hash_input`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Trace = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Trace => "trace",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Severity> {
+        Ok(match s {
+            "trace" => Severity::Trace,
+            "info" => Severity::Info,
+            "warn" => Severity::Warn,
+            "error" => Severity::Error,
+            _ => bail!("unknown severity `{s}`, expected trace, info, warn or error"),
+        })
+    }
+}
+
+/// Payload of an [`ERROR`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorEvent {
+    /// The error's own message.
+    pub message: String,
+    /// Messages of the errors which caused it, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl ErrorEvent {
+    /// Captures `error` and its chain of causes.
+    pub fn new(error: &Error) -> ErrorEvent {
+        let mut chain = error.chain().map(|e| e.to_string());
+        ErrorEvent {
+            message: chain.next().unwrap_or_default(),
+            causes: chain.collect(),
+        }
+    }
+
+    /// Decodes the payload of an [`ERROR`] event.
+    pub fn decode(event: &Event) -> Result<ErrorEvent> {
+        decode(event, ERROR)
+    }
+}
+
+impl Event {
+    /// An [`ERROR`] event of [`Severity::Error`] describing `error`, see
+    /// [`ErrorEvent`].
+    pub fn error(error: &Error) -> Event {
+        let payload = serde_json::to_vec(&ErrorEvent::new(error)).unwrap();
+        Event::new(ERROR.to_string(), payload).with_severity(Severity::Error)
+    }
+
+    pub fn severity(&self) -> Option<Severity> {
+        self.severity
+    }
+
+    /// Gives this event a severity, or takes it away if it's `None`.
+    pub fn with_severity(mut self, severity: impl Into<Option<Severity>>) -> Event {
+        self.severity = severity.into();
+        self
+    }
+}
+
+impl Chain {
+    /// Iterates over the events with a severity of at least `severity`,
+    /// oldest first.
+    pub fn events_at_least(&self, severity: Severity) -> impl Iterator<Item = &MetaEvent> + '_ {
+        self.events()
+            .filter(move |node| node.event().severity().is_some_and(|s| s >= severity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_by_severity() -> Result<()> {
+        let mut chain = Chain::new();
+        let plain = Event::new("plain".to_string(), vec![1]);
+        let unrated = chain.hasher().hash_event(&plain);
+        chain.add(plain.clone());
+        chain.add(Event::new("info".to_string(), vec![]).with_severity(Severity::Info));
+        chain.add(Event::new("warn".to_string(), vec![]).with_severity(Severity::Warn));
+        let error = anyhow!("disk full").context("failed to save");
+        let error = chain.add(Event::error(&error));
+
+        // A severity changes the hash, but only if there is one.
+        let rated = plain.clone().with_severity(Severity::Trace);
+        assert_ne!(chain.hasher().hash_event(&rated), unrated);
+        assert_eq!(
+            chain.hasher().hash_event(&rated.with_severity(None)),
+            unrated
+        );
+
+        let problems = chain
+            .events_at_least(Severity::Warn)
+            .map(|node| node.event().type_())
+            .collect::<Vec<_>>();
+        assert_eq!(problems, ["warn", ERROR]);
+        assert_eq!(chain.events_at_least(Severity::Trace).count(), 3);
+
+        let event = ErrorEvent::decode(chain.get_event_by_hash(error).unwrap().event())?;
+        assert_eq!(event.message, "failed to save");
+        assert_eq!(event.causes, ["disk full"]);
+
+        let round_tripped = Chain::from_bytes(&chain.to_bytes()?)?;
+        assert_eq!(round_tripped.head(), chain.head());
+        round_tripped.verify()?;
+        let json = serde_json::to_string(&chain)?;
+        assert!(json.contains(r#""severity":"warn""#), "{json}");
+        assert_eq!(serde_json::from_str::<Chain>(&json)?.head(), chain.head());
+        assert_eq!("warn".parse::<Severity>()?, Severity::Warn);
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A chain shared between threads.
+//!
+//! Cloning a [`Chain`] copies its events, so two threads each adding to their
+//! own clone end up with diverging histories. Clones of a [`SharedChain`]
+//! instead all refer to the same chain, and events added through any of them
+//! are linked one after the other into a single history.
+
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A handle to a [`Chain`] which can be cloned and sent across threads and
+/// async tasks, see the [module documentation](crate::chain::shared).
+#[derive(Debug, Clone)]
+pub struct SharedChain {
+    chain: Arc<RwLock<Chain>>,
+}
+
+impl SharedChain {
+    /// Shares `chain` between the handles cloned from the returned one.
+    pub fn new(chain: Chain) -> SharedChain {
+        SharedChain {
+            chain: Arc::new(RwLock::new(chain)),
+        }
+    }
+
+    /// Locks the chain for reading, blocking while it's locked for writing.
+    ///
+    /// A thread panicking with the chain locked doesn't make it unusable:
+    /// adding an event either succeeds or leaves the chain unchanged.
+    pub fn read(&self) -> RwLockReadGuard<'_, Chain> {
+        self.chain.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the chain for writing, such as to add several events with no
+    /// others in between.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Chain> {
+        self.chain.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Appends `event` to the chain, see [`Chain::add`].
+    pub fn add(&self, event: Event) -> Digest {
+        self.write().add(event)
+    }
+
+    /// Appends `event` to the chain, see [`Chain::try_add`].
+    pub fn try_add(&self, event: Event) -> Result<Digest> {
+        self.write().try_add(event)
+    }
+
+    pub fn head(&self) -> Option<Digest> {
+        self.read().head()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    pub fn get_event_by_hash(&self, hash: Digest) -> Option<MetaEvent> {
+        self.read().get_event_by_hash(hash).cloned()
+    }
+
+    /// Copies the chain as it is now.
+    pub fn snapshot(&self) -> Chain {
+        self.read().clone()
+    }
+
+    /// Whether `self` and `other` are handles to the same chain.
+    pub fn ptr_eq(&self, other: &SharedChain) -> bool {
+        Arc::ptr_eq(&self.chain, &other.chain)
+    }
+}
+
+impl From<Chain> for SharedChain {
+    fn from(chain: Chain) -> SharedChain {
+        SharedChain::new(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_share_history() -> Result<()> {
+        let shared = SharedChain::new(Chain::new());
+        let threads = (0..4u8)
+            .map(|t| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25u8 {
+                        shared.add(Event::new("event".to_string(), vec![t, i]));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(shared.len(), 100);
+        let chain = shared.snapshot();
+        chain.verify()?;
+        assert_eq!(chain.head(), shared.head());
+        assert!(shared.ptr_eq(&shared.clone()));
+        assert!(!shared.ptr_eq(&SharedChain::from(chain)));
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing chain events.
+//!
+//! A chain with a [`ChainSigner`] signs every event as it's added, see
+//! [`Chain::set_signer`]. The signature covers the event's hash and parent
+//! link, as laid out by [`MetaEvent::signing_input`], and is kept alongside
+//! the event outside of its hash so signing doesn't change the chain itself.
+//!
+//! [`Chain::set_signer`]: crate::chain::Chain::set_signer
+//! [`MetaEvent::signing_input`]: crate::chain::MetaEvent::signing_input
+
+use crate::prelude::*;
+use core::fmt;
+
+/// Produces signatures for the events of a chain.
+pub trait ChainSigner: fmt::Debug + Send + Sync {
+    /// Signs `message`, returning the encoded signature.
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+#[cfg(feature = "chain-ed25519")]
+pub use self::ed25519::Ed25519Signer;
+
+#[cfg(feature = "chain-ed25519")]
+mod ed25519 {
+    use super::ChainSigner;
+    use crate::chain::{metrics, Chain, IntegrityError, IntegrityErrorKind};
+    use crate::prelude::*;
+    use core::fmt;
+    use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+    /// A [`ChainSigner`] producing 64-byte Ed25519 signatures.
+    pub struct Ed25519Signer {
+        key: SigningKey,
+    }
+
+    impl Ed25519Signer {
+        /// Creates a signer from a 32-byte Ed25519 secret key.
+        pub fn from_bytes(secret: &[u8; 32]) -> Ed25519Signer {
+            Ed25519Signer {
+                key: SigningKey::from_bytes(secret),
+            }
+        }
+
+        /// The public key to pass to [`Chain::verify_signatures`].
+        pub fn public_key(&self) -> [u8; 32] {
+            self.key.verifying_key().to_bytes()
+        }
+    }
+
+    // Keep the secret key out of logs.
+    impl fmt::Debug for Ed25519Signer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Ed25519Signer")
+                .field("public_key", &self.key.verifying_key())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl ChainSigner for Ed25519Signer {
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            self.key.sign(message).to_bytes().to_vec()
+        }
+    }
+
+    impl Chain {
+        /// Checks the chain with [`Chain::verify`] and then checks that every
+        /// event carries a valid Ed25519 signature by `public_key`.
+        ///
+        /// Per-event problems are reported as an [`IntegrityError`].
+        pub fn verify_signatures(&self, public_key: &[u8; 32]) -> Result<()> {
+            let key = VerifyingKey::from_bytes(public_key)
+                .map_err(|e| anyhow!("invalid Ed25519 public key: {e}"))?;
+            self.verify()?;
+            for (index, node) in self.events().enumerate() {
+                let error = |kind| {
+                    let error = IntegrityError {
+                        index,
+                        kind,
+                        expected: None,
+                        found: Some(node.hash()),
+                    };
+                    metrics::report(|m| m.verification_failed(&error));
+                    error
+                };
+                let Some(signature) = node.signature() else {
+                    return Err(error(IntegrityErrorKind::Unsigned).into());
+                };
+                let valid = Signature::from_slice(signature)
+                    .and_then(|s| key.verify(&node.signing_input(), &s));
+                if valid.is_err() {
+                    return Err(error(IntegrityErrorKind::BadSignature).into());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::chain::Event;
+        use alloc::sync::Arc;
+
+        #[test]
+        fn sign_and_verify() -> Result<()> {
+            let signer = Arc::new(Ed25519Signer::from_bytes(&[7; 32]));
+            let public_key = signer.public_key();
+            let mut chain = Chain::new();
+            chain.set_signer(Some(signer));
+            chain.add(Event::new("a".to_string(), vec![1]));
+            chain.add(Event::new("b".to_string(), vec![2]));
+            chain.verify_signatures(&public_key)?;
+
+            let other = Ed25519Signer::from_bytes(&[8; 32]).public_key();
+            let err = chain.verify_signatures(&other).unwrap_err();
+            let err = err.downcast_ref::<IntegrityError>().unwrap();
+            assert_eq!((err.index, err.kind), (0, IntegrityErrorKind::BadSignature));
+
+            // Signatures survive a round trip and stay optional.
+            let decoded = Chain::from_bytes(&chain.to_bytes()?)?;
+            decoded.verify_signatures(&public_key)?;
+            let json = serde_json::to_string(&chain)?;
+            let decoded: Chain = serde_json::from_str(&json)?;
+            decoded.verify_signatures(&public_key)?;
+
+            chain.set_signer(None);
+            chain.add(Event::new("c".to_string(), vec![3]));
+            chain.verify()?;
+            let err = chain.verify_signatures(&public_key).unwrap_err();
+            let err = err.downcast_ref::<IntegrityError>().unwrap();
+            assert_eq!((err.index, err.kind), (2, IntegrityErrorKind::Unsigned));
+            Ok(())
+        }
+    }
+}
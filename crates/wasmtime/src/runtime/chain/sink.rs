@@ -0,0 +1,206 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishing chain events onto an event bus.
+//!
+//! An [`EventSink`] sends events somewhere else, such as a Kafka topic with
+//! [`KafkaSink`](crate::chain::kafka::KafkaSink) or a NATS JetStream subject
+//! with [`NatsSink`](crate::chain::nats::NatsSink). A [`ChainPublisher`]
+//! feeds a chain's events to a sink in order and keeps the sequence number
+//! of the last one the sink acknowledged, its cursor.
+//!
+//! Delivery is at least once. The publisher only moves its cursor past
+//! events once [`EventSink::flush`] has confirmed them, so when sending
+//! fails, or the process restarts from a cursor it saved, events after the
+//! cursor are sent again, including some which may have arrived the first
+//! time. Every message is keyed by the event's hash for consumers to drop
+//! the duplicates.
+//!
+//! Sinks push back on the chain being published in two ways: the publisher
+//! flushes after at most [`ChainPublisher::max_in_flight`] events, so no
+//! more than that are ever unacknowledged, and [`EventSink::send`] may block
+//! while the sink's own buffers are full.
+
+use crate::chain::{Chain, MetaEvent};
+use crate::prelude::*;
+
+/// Somewhere events are published, see the
+/// [module documentation](crate::chain::sink).
+pub trait EventSink: Send {
+    /// Starts publishing `event`, which has sequence number `seq` in its
+    /// chain. May block while too many events are waiting to be published.
+    fn send(&mut self, seq: u64, event: &MetaEvent) -> Result<()>;
+
+    /// Waits until every event sent since the last flush has been
+    /// acknowledged, failing if any of them couldn't be published.
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl<S: EventSink + ?Sized> EventSink for Box<S> {
+    fn send(&mut self, seq: u64, event: &MetaEvent) -> Result<()> {
+        (**self).send(seq, event)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// The message published for `event`: its hash, which consumers deduplicate
+/// by, and the event as JSON.
+pub fn message(event: &MetaEvent) -> Result<(String, Vec<u8>)> {
+    Ok((event.hash().to_hex(), serde_json::to_vec(event)?))
+}
+
+/// Publishes a chain's events to an [`EventSink`], see the
+/// [module documentation](crate::chain::sink).
+#[derive(Debug)]
+pub struct ChainPublisher<S> {
+    sink: S,
+    acknowledged: Option<u64>,
+    max_in_flight: usize,
+}
+
+impl<S: EventSink> ChainPublisher<S> {
+    /// Publishes to `sink` from the start of the chain.
+    pub fn new(sink: S) -> ChainPublisher<S> {
+        ChainPublisher {
+            sink,
+            acknowledged: None,
+            max_in_flight: 1000,
+        }
+    }
+
+    /// Publishes the events after sequence number `cursor`, such as a
+    /// [`ChainPublisher::cursor`] saved before a restart.
+    pub fn resume_after(mut self, cursor: Option<u64>) -> ChainPublisher<S> {
+        self.acknowledged = cursor;
+        self
+    }
+
+    /// Sets how many events are sent before waiting for them to be
+    /// acknowledged, 1000 by default.
+    pub fn max_in_flight(mut self, max: usize) -> ChainPublisher<S> {
+        self.max_in_flight = max.max(1);
+        self
+    }
+
+    /// The sequence number of the last event the sink acknowledged.
+    pub fn cursor(&self) -> Option<u64> {
+        self.acknowledged
+    }
+
+    /// The sink events are published to.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// The sink events are published to, mutably.
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Publishes the events of `chain` after the cursor, returning how many
+    /// were acknowledged.
+    ///
+    /// When sending or flushing fails, the cursor is left after the last
+    /// acknowledged batch, so calling this again sends the rest again.
+    pub fn publish(&mut self, chain: &Chain) -> Result<usize> {
+        let mut published = 0;
+        loop {
+            let page = chain.page(self.acknowledged, self.max_in_flight);
+            if page.events.is_empty() {
+                return Ok(published);
+            }
+            let start = self.acknowledged.map_or(0, |cursor| cursor + 1);
+            for (seq, node) in (start..).zip(&page.events) {
+                self.sink
+                    .send(seq, node)
+                    .with_context(|| format!("failed to publish event {seq}"))?;
+            }
+            self.sink
+                .flush()
+                .with_context(|| format!("failed to publish events after {start}"))?;
+            published += page.events.len();
+            self.acknowledged = page.next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Digest, Event};
+    use core::mem;
+
+    /// Delivers events to `delivered` on flush, failing the first flush
+    /// after `fail_after` sends.
+    #[derive(Default)]
+    struct TestSink {
+        pending: Vec<Digest>,
+        delivered: Vec<Digest>,
+        fail_after: Option<usize>,
+    }
+
+    impl EventSink for TestSink {
+        fn send(&mut self, _: u64, event: &MetaEvent) -> Result<()> {
+            self.pending.push(event.hash());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let pending = mem::take(&mut self.pending);
+            if let Some(n) = self.fail_after.take() {
+                // Some events arrive before the connection drops.
+                self.delivered.extend(&pending[..n.min(pending.len())]);
+                bail!("connection lost");
+            }
+            self.delivered.extend(pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn publishes_at_least_once() -> Result<()> {
+        let mut chain = Chain::new();
+        let hashes = (0..5u8)
+            .map(|i| chain.add(Event::new("a".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+
+        let mut publisher = ChainPublisher::new(TestSink {
+            fail_after: Some(1),
+            ..TestSink::default()
+        })
+        .max_in_flight(2);
+        assert!(publisher.publish(&chain).is_err());
+        assert_eq!(publisher.cursor(), None);
+        assert_eq!(publisher.publish(&chain)?, 5);
+        assert_eq!(publisher.cursor(), Some(4));
+        let delivered = &publisher.sink().delivered;
+        assert_eq!(delivered[0], hashes[0]);
+        assert_eq!(delivered[1..], hashes[..]);
+
+        chain.add(Event::new("b".to_string(), vec![]));
+        assert_eq!(publisher.publish(&chain)?, 1);
+        assert_eq!(publisher.publish(&chain)?, 0);
+
+        let mut resumed = ChainPublisher::new(TestSink::default()).resume_after(Some(3));
+        assert_eq!(resumed.publish(&chain)?, 2);
+        assert_eq!(
+            resumed.sink().delivered[..],
+            [hashes[4], chain.head().unwrap()]
+        );
+        Ok(())
+    }
+}
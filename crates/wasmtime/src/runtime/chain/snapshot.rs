@@ -0,0 +1,360 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capturing a store's instance state into the chain and restoring it.
+//!
+//! [`Store::snapshot_to_chain`] adds a `snapshot` event holding the contents
+//! of every linear memory and the value of every global in the store, which
+//! [`Store::restore_from_snapshot`] later writes back. Unlike other recorded
+//! payloads a snapshot is encoded with `postcard` rather than JSON, and runs
+//! of zero bytes in memories are compressed away, as most of a fresh linear
+//! memory is zeroes.
+
+use crate::chain::{Digest, Event};
+use crate::prelude::*;
+use crate::{AsContextMut, Global, Memory, Mutability, Store, StoreContextMut, Val, V128};
+use serde::{Deserialize, Serialize};
+
+/// Type of the event added by [`Store::snapshot_to_chain`].
+pub const SNAPSHOT: &str = "snapshot";
+
+/// Payload of a [`SNAPSHOT`] event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Every linear memory in the store, in the order instances were
+    /// created.
+    pub memories: Vec<MemorySnapshot>,
+    /// Every global in the store, host-defined ones first. Reference-typed
+    /// globals aren't captured and are `None`.
+    pub globals: Vec<Option<GlobalValue>>,
+    /// Number of resources in the store's
+    /// [`ResourceRegistry`](crate::chain::ResourceRegistry). Resources aren't
+    /// captured, so this is only a summary.
+    pub resources: u64,
+}
+
+/// The contents of a linear memory in a [`Snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemorySnapshot {
+    /// Size of the memory in bytes.
+    pub size: u64,
+    /// The memory's contents with runs of zeroes compressed away.
+    data: Vec<u8>,
+}
+
+/// The value of a numeric global in a [`Snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    /// The bits of an `f32`.
+    F32(u32),
+    /// The bits of an `f64`.
+    F64(u64),
+    V128(u128),
+}
+
+/// Runs of fewer zero bytes than this are kept as they are.
+const MIN_ZEROS: usize = 8;
+
+impl Snapshot {
+    pub fn decode(event: &Event) -> Result<Snapshot> {
+        ensure!(
+            event.type_() == SNAPSHOT,
+            "expected a `{SNAPSHOT}` event, found `{}`",
+            event.type_()
+        );
+        postcard::from_bytes(&event.data()).context("failed to decode snapshot")
+    }
+
+    /// Captures the memories and globals of every instance in `store`.
+    ///
+    /// Fails if the store has a shared memory, which other threads may be
+    /// writing to.
+    pub fn capture(mut store: impl AsContextMut) -> Result<Snapshot> {
+        let mut store = store.as_context_mut();
+        let resources = u64::try_from(store.resource_registry().len())?;
+
+        let mut memories = Vec::new();
+        for memory in store.0.all_memories().collect::<Vec<_>>() {
+            ensure!(
+                !memory.ty(&store).is_shared(),
+                "shared memories can't be snapshotted"
+            );
+            let data = memory.data(&store);
+            memories.push(MemorySnapshot {
+                size: u64::try_from(data.len())?,
+                data: compress(data),
+            });
+        }
+
+        let globals = all_globals(&mut store)
+            .into_iter()
+            .map(|global| match global.get(&mut store) {
+                Val::I32(x) => Some(GlobalValue::I32(x)),
+                Val::I64(x) => Some(GlobalValue::I64(x)),
+                Val::F32(x) => Some(GlobalValue::F32(x)),
+                Val::F64(x) => Some(GlobalValue::F64(x)),
+                Val::V128(x) => Some(GlobalValue::V128(x.as_u128())),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Snapshot {
+            memories,
+            globals,
+            resources,
+        })
+    }
+
+    /// Writes this snapshot back into `store`, which must have the same
+    /// memories and globals as the store it was captured from.
+    ///
+    /// Memories smaller than when captured are grown, and bytes past the
+    /// captured size of larger ones are zeroed, since a memory can't shrink.
+    /// Immutable globals are left as they are.
+    pub fn restore(&self, mut store: impl AsContextMut) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let memories = store.0.all_memories().collect::<Vec<Memory>>();
+        ensure!(
+            memories.len() == self.memories.len(),
+            "snapshot has {} memories but the store has {}",
+            self.memories.len(),
+            memories.len()
+        );
+        for (memory, snapshot) in memories.iter().zip(&self.memories) {
+            let contents = snapshot.contents()?;
+            let size = memory.data_size(&store);
+            if size < contents.len() {
+                let page_size = memory.page_size(&store);
+                let missing = u64::try_from(contents.len() - size)?;
+                memory.grow(&mut store, missing.div_ceil(page_size))?;
+            }
+            let data = memory.data_mut(&mut store);
+            let (captured, rest) = data.split_at_mut(contents.len());
+            captured.copy_from_slice(&contents);
+            rest.fill(0);
+        }
+
+        let globals = all_globals(&mut store);
+        ensure!(
+            globals.len() == self.globals.len(),
+            "snapshot has {} globals but the store has {}",
+            self.globals.len(),
+            globals.len()
+        );
+        for (global, value) in globals.iter().zip(&self.globals) {
+            let Some(value) = value else { continue };
+            if global.ty(&store).mutability() == Mutability::Var {
+                global.set(&mut store, Val::from(*value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MemorySnapshot {
+    /// The memory's contents, [`size`](MemorySnapshot::size) bytes long.
+    pub fn contents(&self) -> Result<Vec<u8>> {
+        decompress(&self.data, usize::try_from(self.size)?)
+    }
+}
+
+impl From<GlobalValue> for Val {
+    fn from(value: GlobalValue) -> Val {
+        match value {
+            GlobalValue::I32(x) => Val::I32(x),
+            GlobalValue::I64(x) => Val::I64(x),
+            GlobalValue::F32(x) => Val::F32(x),
+            GlobalValue::F64(x) => Val::F64(x),
+            GlobalValue::V128(x) => Val::V128(V128::from(x)),
+        }
+    }
+}
+
+fn all_globals<T>(store: &mut StoreContextMut<'_, T>) -> Vec<Global> {
+    let mut globals = Vec::new();
+    store.0.for_each_global(|_, global| globals.push(global));
+    globals
+}
+
+/// Encodes `data` as pairs of a number of zero bytes and a run of literal
+/// bytes, each length a LEB128 varint.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|b| **b == 0).count();
+        i += zeros;
+        let start = i;
+        while i < data.len() && !data[i..].starts_with(&[0; MIN_ZEROS]) {
+            i += 1;
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - start);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+fn decompress(mut data: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    while !data.is_empty() {
+        let zeros = read_varint(&mut data)?;
+        let literal = read_varint(&mut data)?;
+        ensure!(
+            zeros <= len - out.len() && literal <= data.len(),
+            "snapshot memory data is corrupt"
+        );
+        out.resize(out.len() + zeros, 0);
+        ensure!(
+            literal <= len - out.len(),
+            "snapshot memory data is corrupt"
+        );
+        let (bytes, rest) = data.split_at(literal);
+        out.extend_from_slice(bytes);
+        data = rest;
+    }
+    out.resize(len, 0);
+    Ok(out)
+}
+
+#[allow(clippy::cast_possible_truncation)] // only the low seven bits are kept
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<usize> {
+    let mut n = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let Some((&byte, rest)) = data.split_first() else {
+            bail!("snapshot memory data is truncated");
+        };
+        *data = rest;
+        n |= usize::from(byte & 0x7f)
+            .checked_shl(shift)
+            .context("snapshot memory data is corrupt")?;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    bail!("snapshot memory data is corrupt")
+}
+
+impl<T> Store<T> {
+    /// Captures the memories and globals of every instance in this store
+    /// into a `snapshot` event added to its chain, returning the event's
+    /// hash.
+    ///
+    /// See [`Snapshot::capture`] for what's captured.
+    pub fn snapshot_to_chain(&mut self) -> Result<Digest> {
+        let snapshot = Snapshot::capture(&mut *self)?;
+        let data = postcard::to_allocvec(&snapshot)?;
+        self.chain_mut()
+            .try_add(Event::new(SNAPSHOT.to_string(), data))
+    }
+
+    /// Restores the state captured by the `snapshot` event `hash` of this
+    /// store's chain.
+    ///
+    /// See [`Snapshot::restore`] for what the store must look like.
+    pub fn restore_from_snapshot(&mut self, hash: Digest) -> Result<()> {
+        let Some(node) = self.chain().get_event_by_hash(hash) else {
+            bail!("no event {hash} in this store's chain");
+        };
+        let snapshot = Snapshot::decode(node.event())?;
+        snapshot.restore(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{Component, Linker, Val as ComponentVal};
+    use crate::Engine;
+
+    #[test]
+    fn compression_round_trips() -> Result<()> {
+        let mut data = vec![0; 70000];
+        data[3] = 1;
+        data[4..10].copy_from_slice(&[0, 0, 0, 0, 0, 2]);
+        data[65536..65540].fill(9);
+        let compressed = compress(&data);
+        assert!(compressed.len() < 32);
+        assert_eq!(decompress(&compressed, data.len())?, data);
+        assert!(decompress(&compressed, 100).is_err());
+        assert!(decompress(&compress(&[]), 0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn restores_memory_and_globals() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m
+                    (memory 1)
+                    (global $g (mut i32) (i32.const 0))
+                    (func (export "bump") (result i32)
+                        (global.set $g (i32.add (global.get $g) (i32.const 1)))
+                        (i32.store (i32.const 100) (global.get $g))
+                        (if (i32.eq (global.get $g) (i32.const 3))
+                            (then (drop (memory.grow (i32.const 1)))))
+                        (global.get $g))
+                    (func (export "load") (result i32)
+                        (i32.load (i32.const 100))))
+                (core instance $i (instantiate $m))
+                (func (export "bump") (result s32)
+                    (canon lift (core func $i "bump")))
+                (func (export "load") (result s32)
+                    (canon lift (core func $i "load")))
+            )
+        "#;
+        let engine = Engine::default();
+        let component = Component::new(&engine, component)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let bump = instance.get_func(&mut store, "bump").unwrap();
+        let load = instance.get_func(&mut store, "load").unwrap();
+        let call = |store: &mut Store<()>, func: crate::component::Func| -> Result<_> {
+            let mut results = [ComponentVal::S32(0)];
+            func.call(&mut *store, &[], &mut results)?;
+            func.post_return(&mut *store)?;
+            Ok(results[0].clone())
+        };
+
+        assert_eq!(call(&mut store, bump)?, ComponentVal::S32(1));
+        let hash = store.snapshot_to_chain()?;
+        let snapshot = Snapshot::decode(store.chain().get_event_by_hash(hash).unwrap().event())?;
+        assert_eq!(snapshot.memories.len(), 1);
+        assert_eq!(snapshot.memories[0].size, 65536);
+        assert!(snapshot.memories[0].data.len() < 16);
+        assert_eq!(snapshot.globals, [Some(GlobalValue::I32(1))]);
+
+        call(&mut store, bump)?;
+        assert_eq!(call(&mut store, bump)?, ComponentVal::S32(3));
+        store.restore_from_snapshot(hash)?;
+        assert_eq!(call(&mut store, load)?, ComponentVal::S32(1));
+        assert_eq!(call(&mut store, bump)?, ComponentVal::S32(2));
+
+        let other = store
+            .chain_mut()
+            .add(Event::new("other".to_string(), vec![]));
+        assert!(store.restore_from_snapshot(other).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chain identity and child chains spawned from a parent chain.
+//!
+//! A chain started with [`Chain::genesis`] begins with a `genesis` event, and
+//! the hash of that event is the chain's [id](Chain::id). Chains with
+//! different ids hold unrelated histories, so [`Chain::merge`] refuses to mix
+//! their events. Chains started with [`Chain::new`] have no id.
+//!
+//! [`Chain::spawn`] starts a new chain whose first event is a `genesis`
+//! event pointing at the parent's head, and records a `spawn` event in the
+//! parent holding the child's genesis hash. The two chains stay separate, so
+//! each can be verified on its own, while the `spawn` event ties the child's
+//! history to a point in the parent's.
+//!
+//! With [`Config::chain_per_instance`](crate::Config::chain_per_instance)
+//! each component instance in a store records into a child chain of the
+//! store's chain, spawned the first time the instance records an event.
+
+use crate::chain::compact::{Checkpoint, CHECKPOINT};
+use crate::chain::record::decode;
+use crate::chain::{Chain, Digest, Event, MetaEvent};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Type of the event recorded in a parent chain when a child is spawned.
+pub const SPAWN: &str = "spawn";
+
+/// Type of the first event of a spawned chain.
+pub const GENESIS: &str = "genesis";
+
+/// Payload of a [`SPAWN`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spawn {
+    /// Name given to the child chain.
+    pub name: String,
+    /// Hash of the child chain's [`GENESIS`] event.
+    pub child: Digest,
+}
+
+/// Payload of a [`GENESIS`] event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Genesis {
+    /// Name given to the chain when it was spawned.
+    pub name: String,
+    /// Head of the parent chain when this chain was spawned, or `None` if
+    /// the parent was empty or there is no parent.
+    pub parent: Option<Digest>,
+    /// Digest given to [`Chain::genesis`], such as of the configuration the
+    /// chain was started with or of random bytes, to tell apart chains
+    /// started under the same name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<Digest>,
+}
+
+impl Spawn {
+    pub fn decode(event: &Event) -> Result<Spawn> {
+        decode(event, SPAWN)
+    }
+}
+
+impl Genesis {
+    pub fn decode(event: &Event) -> Result<Genesis> {
+        decode(event, GENESIS)
+    }
+}
+
+impl Chain {
+    /// Creates a chain hashed with SHA-256 whose first event is a `genesis`
+    /// event holding `name` and `seed`, giving it an [id](Chain::id).
+    ///
+    /// Chains started with the same name and seed get the same id, so `seed`
+    /// should be unique to the chain, such as a digest of the configuration
+    /// it was started with or of random bytes.
+    pub fn genesis(name: &str, seed: Digest) -> Result<Chain> {
+        let mut chain = Chain::new();
+        let genesis = Genesis {
+            name: name.to_string(),
+            parent: None,
+            seed: Some(seed),
+        };
+        chain.try_add(Event::new(
+            GENESIS.to_string(),
+            serde_json::to_vec(&genesis)?,
+        ))?;
+        Ok(chain)
+    }
+
+    /// The hash of this chain's `genesis` event, which identifies the chain,
+    /// or `None` if it doesn't start with one.
+    ///
+    /// A chain compacted with [`Chain::compact`] keeps its id in its
+    /// checkpoint.
+    pub fn id(&self) -> Option<Digest> {
+        let first = self.store.get(0)?;
+        match first.event().type_() {
+            GENESIS => Some(first.hash()),
+            CHECKPOINT => Checkpoint::decode(first.event()).ok()?.id,
+            _ => None,
+        }
+    }
+
+    /// Starts a child chain called `name`, returning the hash of the `spawn`
+    /// event recorded for it in this chain.
+    ///
+    /// The child uses this chain's hasher and is kept in memory; look it up
+    /// again with [`Chain::child`].
+    pub fn spawn(&mut self, name: &str) -> Result<Digest> {
+        let mut child = Chain::with_hasher(self.hasher.clone());
+        child.redactor = self.redactor.clone();
+        child.schemas = self.schemas.clone();
+        child.compression_threshold = self.compression_threshold;
+        let genesis = Genesis {
+            name: name.to_string(),
+            parent: self.head(),
+            seed: None,
+        };
+        let child_hash = child.try_add(Event::new(
+            GENESIS.to_string(),
+            serde_json::to_vec(&genesis)?,
+        ))?;
+        let spawn = Spawn {
+            name: name.to_string(),
+            child: child_hash,
+        };
+        let hash = self.try_add(Event::new(SPAWN.to_string(), serde_json::to_vec(&spawn)?))?;
+        self.children.push((hash, child));
+        Ok(hash)
+    }
+
+    /// The child chain spawned by the `spawn` event with hash `spawn`.
+    pub fn child(&self, spawn: Digest) -> Option<&Chain> {
+        self.children
+            .iter()
+            .find(|(hash, _)| *hash == spawn)
+            .map(|(_, child)| child)
+    }
+
+    /// Mutable access to the child chain spawned by the `spawn` event with
+    /// hash `spawn`.
+    pub fn child_mut(&mut self, spawn: Digest) -> Option<&mut Chain> {
+        self.children
+            .iter_mut()
+            .find(|(hash, _)| *hash == spawn)
+            .map(|(_, child)| child)
+    }
+
+    /// Iterates over the child chains spawned from this one, oldest first,
+    /// along with the `spawn` event that started each.
+    ///
+    /// Only children spawned through this value are known; chains loaded
+    /// from storage start without any.
+    pub fn children(&self) -> impl Iterator<Item = (&MetaEvent, &Chain)> + '_ {
+        self.children
+            .iter()
+            .filter_map(|(hash, child)| Some((self.get_event_by_hash(*hash)?, child)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{Component, Linker, Val};
+    use crate::{Config, Engine, Store};
+
+    #[test]
+    fn spawn_links_child() -> Result<()> {
+        let mut parent = Chain::new();
+        parent.add(Event::new("a".to_string(), vec![]));
+        let head = parent.head();
+        let spawn = parent.spawn("worker")?;
+
+        let child = parent.child(spawn).unwrap();
+        child.verify()?;
+        let genesis = child.store().get(0).unwrap();
+        assert_eq!(Genesis::decode(genesis.event())?.parent, head);
+        assert_eq!(child.id(), Some(genesis.hash()));
+        let event = parent.get_event_by_hash(spawn).unwrap().event();
+        assert_eq!(Spawn::decode(event)?.child, genesis.hash());
+
+        parent
+            .child_mut(spawn)
+            .unwrap()
+            .add(Event::new("b".to_string(), vec![]));
+        let children = parent.children().collect::<Vec<_>>();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].0.hash(), spawn);
+        assert_eq!(children[0].1.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn chain_id() -> Result<()> {
+        assert_eq!(Chain::new().id(), None);
+        let a = Chain::genesis("actor", Digest([1; Digest::LEN]))?;
+        let b = Chain::genesis("actor", Digest([2; Digest::LEN]))?;
+        assert!(a.id().is_some());
+        assert_ne!(a.id(), b.id());
+
+        let mut c = a.clone();
+        c.add(Event::new("a".to_string(), vec![]));
+        c.add(Event::new("b".to_string(), vec![]));
+        c.compact(1, Digest::default())?;
+        assert_eq!(c.id(), a.id());
+
+        let mut a = a;
+        assert!(a.merge(&b).is_err());
+        assert!(a.merge(&Chain::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn chain_per_instance() -> Result<()> {
+        let component = r#"
+            (component
+                (core module $m
+                    (func (export "id") (param i32) (result i32) local.get 0))
+                (core instance $i (instantiate $m))
+                (func (export "id") (param "x" u32) (result u32)
+                    (canon lift (core func $i "id")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true).chain_per_instance(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let linker = Linker::new(&engine);
+        let mut store = Store::new(&engine, ());
+        let a = linker.instantiate(&mut store, &component)?;
+        let b = linker.instantiate(&mut store, &component)?;
+        for (instance, x) in [(a, 1), (b, 2), (a, 3)] {
+            let id = instance.get_func(&mut store, "id").unwrap();
+            let mut results = [Val::U32(0)];
+            id.call(&mut store, &[Val::U32(x)], &mut results)?;
+            id.post_return(&mut store)?;
+        }
+
+        let chain = store.chain();
+        let types = chain
+            .events()
+            .map(|e| e.event().type_())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [SPAWN, SPAWN]);
+        let lens = chain.children().map(|(_, c)| c.len()).collect::<Vec<_>>();
+        assert_eq!(lens, [3, 2]);
+        Ok(())
+    }
+}
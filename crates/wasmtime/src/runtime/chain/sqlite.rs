@@ -0,0 +1,368 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SQLite persistence for chains.
+//!
+//! A database can hold any number of chains, each identified by a name such
+//! as an actor id. Events live in a single `chain_events` table keyed by
+//! chain name and sequence number, with an index on the event hash. The
+//! database is put in WAL mode so [`SqliteChainReader`]s on other
+//! connections can query a chain while it's being appended to.
+//!
+//! The database's `user_version` is its
+//! [format version](crate::chain::migrate). Opening a database in an older
+//! format with a [`SqliteChainStore`] upgrades it, which a reader can't.
+
+use crate::chain::hasher::hasher_by_name;
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{Chain, ChainStore, Digest, Event, MemoryChainStore, MetaEvent, Severity};
+use crate::prelude::*;
+use core::ops::Range;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS chains (
+        name TEXT PRIMARY KEY,
+        hasher TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS chain_events (
+        chain TEXT NOT NULL REFERENCES chains(name),
+        seq INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        parent BLOB,
+        type TEXT NOT NULL,
+        data BLOB NOT NULL,
+        signature BLOB,
+        severity TEXT,
+        schema_version INTEGER,
+        PRIMARY KEY (chain, seq)
+    );
+    CREATE INDEX IF NOT EXISTS chain_events_hash ON chain_events (chain, hash);
+";
+
+const SELECT: &str =
+    "SELECT hash, parent, type, data, signature, severity, schema_version FROM chain_events";
+
+/// A [`ChainStore`] which inserts each event into a SQLite database as it's
+/// added while also keeping them in memory.
+#[derive(Debug)]
+pub struct SqliteChainStore {
+    path: PathBuf,
+    name: String,
+    hasher: String,
+    conn: Mutex<Connection>,
+    events: MemoryChainStore,
+}
+
+impl SqliteChainStore {
+    /// Opens the chain called `name` in the database at `path`, creating
+    /// either if needed.
+    ///
+    /// A new chain is recorded as hashed by the hasher named `hasher`, while
+    /// an existing one keeps its hasher, see [`SqliteChainStore::hasher`].
+    pub fn open(path: impl AsRef<Path>, name: &str, hasher: &str) -> Result<SqliteChainStore> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open chain database `{}`", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let context = || format!("invalid chain database `{}`", path.display());
+        migrate::check_version(user_version(&conn)?).with_context(context)?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases created before events could be signed, have a severity
+        // or be stamped with a schema version lack the columns.
+        let columns = [
+            ("signature", "BLOB"),
+            ("severity", "TEXT"),
+            ("schema_version", "INTEGER"),
+        ];
+        for (column, ty) in columns {
+            let present: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('chain_events') WHERE name = ?1",
+                params![column],
+                |row| row.get(0),
+            )?;
+            if present == 0 {
+                conn.execute(
+                    &format!("ALTER TABLE chain_events ADD COLUMN {column} {ty}"),
+                    [],
+                )?;
+            }
+        }
+        conn.pragma_update(None, "user_version", FORMAT_VERSION)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO chains (name, hasher) VALUES (?1, ?2)",
+            params![name, hasher],
+        )?;
+        let hasher = conn.query_row(
+            "SELECT hasher FROM chains WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+
+        let mut events = MemoryChainStore::new();
+        {
+            let mut stmt = conn.prepare(&format!("{SELECT} WHERE chain = ?1 ORDER BY seq"))?;
+            let mut rows = stmt.query(params![name])?;
+            while let Some(row) = rows.next()? {
+                events.append(event_from_row(row)?)?;
+            }
+        }
+
+        Ok(SqliteChainStore {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+            hasher,
+            conn: Mutex::new(conn),
+            events,
+        })
+    }
+
+    /// Name of the hasher this chain is hashed with.
+    pub fn hasher(&self) -> &str {
+        &self.hasher
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Opens a separate read-only connection for querying this chain.
+    pub fn reader(&self) -> Result<SqliteChainReader> {
+        SqliteChainReader::open(&self.path, &self.name)
+    }
+}
+
+impl ChainStore for SqliteChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        let seq = i64::try_from(self.events.len())?;
+        insert(&self.conn.lock().unwrap(), &self.name, seq, &event)?;
+        self.events.append(event)
+    }
+
+    /// Inserts `events` in a single transaction.
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (i, event) in events.iter().enumerate() {
+            insert(
+                &tx,
+                &self.name,
+                i64::try_from(self.events.len() + i)?,
+                event,
+            )?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.events.append_batch(events)
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        self.events.iter_from(index)
+    }
+
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM chain_events WHERE chain = ?1",
+            params![self.name],
+        )?;
+        for (seq, event) in events.iter().enumerate() {
+            insert(&tx, &self.name, i64::try_from(seq)?, event)?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.events = MemoryChainStore::from(events);
+        Ok(())
+    }
+}
+
+/// The [format version](crate::chain::migrate) of the database, 0 if it was
+/// created before formats were versioned.
+fn user_version(conn: &Connection) -> Result<u64> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(u64::try_from(version)?)
+}
+
+fn insert(conn: &Connection, chain: &str, seq: i64, event: &MetaEvent) -> Result<()> {
+    let e = event.event();
+    conn.execute(
+        "INSERT INTO chain_events
+         (chain, seq, hash, parent, type, data, signature, severity, schema_version)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            chain,
+            seq,
+            &event.hash().as_bytes()[..],
+            e.parent().map(|p| p.as_bytes().to_vec()),
+            e.type_(),
+            &*e.data(),
+            event.signature(),
+            e.severity().map(|s| s.as_str()),
+            e.schema_version(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Read-only queries against a chain stored by a [`SqliteChainStore`].
+///
+/// Readers go straight to the database, so they see events appended by the
+/// writing store as soon as each insert commits.
+#[derive(Debug)]
+pub struct SqliteChainReader {
+    name: String,
+    conn: Connection,
+}
+
+impl SqliteChainReader {
+    /// Opens the chain called `name` in the database at `path`.
+    pub fn open(path: impl AsRef<Path>, name: &str) -> Result<SqliteChainReader> {
+        let path = path.as_ref();
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open chain database `{}`", path.display()))?;
+        let version = user_version(&conn)?;
+        migrate::check_version(version)?;
+        ensure!(
+            version == u64::from(FORMAT_VERSION),
+            "chain database `{}` is in format version {version}; open it with a \
+             `SqliteChainStore` to upgrade it",
+            path.display()
+        );
+        Ok(SqliteChainReader {
+            name: name.to_string(),
+            conn,
+        })
+    }
+
+    /// The number of events in the chain.
+    pub fn len(&self) -> Result<usize> {
+        let len: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM chain_events WHERE chain = ?1",
+            params![self.name],
+            |row| row.get(0),
+        )?;
+        Ok(usize::try_from(len)?)
+    }
+
+    /// Returns the events with sequence numbers in `range`, oldest first.
+    pub fn range(&self, range: Range<usize>) -> Result<Vec<MetaEvent>> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{SELECT} WHERE chain = ?1 AND seq >= ?2 AND seq < ?3 ORDER BY seq"
+        ))?;
+        let mut rows = stmt.query(params![
+            self.name,
+            i64::try_from(range.start)?,
+            i64::try_from(range.end)?,
+        ])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(event_from_row(row)?);
+        }
+        Ok(events)
+    }
+
+    /// Returns the first event with the given hash.
+    pub fn get_by_hash(&self, hash: Digest) -> Result<Option<MetaEvent>> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{SELECT} WHERE chain = ?1 AND hash = ?2 ORDER BY seq LIMIT 1"
+        ))?;
+        let row = stmt
+            .query_row(params![self.name, &hash.as_bytes()[..]], |row| {
+                Ok(event_from_row(row))
+            })
+            .optional()?;
+        row.transpose()
+    }
+}
+
+fn event_from_row(row: &Row<'_>) -> Result<MetaEvent> {
+    let digest = |bytes: Vec<u8>| -> Result<Digest> {
+        match <[u8; Digest::LEN]>::try_from(bytes) {
+            Ok(bytes) => Ok(Digest(bytes)),
+            Err(_) => bail!("stored hash has the wrong length"),
+        }
+    };
+    let hash = digest(row.get(0)?)?;
+    let parent = row.get::<_, Option<Vec<u8>>>(1)?.map(digest).transpose()?;
+    let severity = row.get::<_, Option<String>>(5)?;
+    let mut event = Event::new(row.get(2)?, row.get(3)?)
+        .with_severity(severity.map(|s| s.parse::<Severity>()).transpose()?)
+        .with_schema_version(row.get::<_, Option<u32>>(6)?);
+    event.set_parent(parent);
+    Ok(MetaEvent::new(hash, event).with_signature(row.get(4)?))
+}
+
+impl Chain {
+    /// Opens the chain called `name` in the SQLite database at `path`,
+    /// creating an empty SHA-256 chain if it doesn't exist yet.
+    ///
+    /// See [`SqliteChainStore`] for details.
+    pub fn open_sqlite(path: impl AsRef<Path>, name: &str) -> Result<Chain> {
+        let path = path.as_ref();
+        let store = SqliteChainStore::open(path, name, "sha256")?;
+        let hasher = match hasher_by_name(store.hasher()) {
+            Some(hasher) => hasher,
+            None => bail!("chain `{name}` uses unknown hasher `{}`", store.hasher()),
+        };
+        Chain::with_store(hasher, Box::new(store))
+            .with_context(|| format!("chain `{name}` in `{}` failed verification", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_and_query() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("chains.db");
+
+        let mut a = Chain::open_sqlite(&path, "actor-a")?;
+        let mut b = Chain::open_sqlite(&path, "actor-b")?;
+        let hashes = (0..5u8)
+            .map(|i| a.add(Event::new("tick".to_string(), vec![i])))
+            .collect::<Vec<_>>();
+        b.add(Event::new("other".to_string(), vec![]));
+
+        let reader = SqliteChainReader::open(&path, "actor-a")?;
+        assert_eq!(reader.len()?, 5);
+        let range = reader.range(1..3)?;
+        assert_eq!(
+            range.iter().map(|e| e.hash()).collect::<Vec<_>>(),
+            &hashes[1..3]
+        );
+        let found = reader.get_by_hash(hashes[4])?.unwrap();
+        assert_eq!(found.event().parent(), Some(hashes[3]));
+        drop((a, b));
+
+        let mut a = Chain::open_sqlite(&path, "actor-a")?;
+        assert_eq!(a.head(), Some(hashes[4]));
+        let next = a.add(Event::new("tick".to_string(), vec![5]));
+        assert_eq!(reader.get_by_hash(next)?.unwrap().hash(), next);
+        assert_eq!(Chain::open_sqlite(&path, "actor-b")?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Moving a store's chains to another store, possibly in another process.
+//!
+//! [`Store::chain_state`] captures everything a store knows about its
+//! chains into a [`ChainState`]: its chain along with every child chain
+//! spawned from it, such as those of component instances with
+//! [`Config::chain_per_instance`], which instance records into which child,
+//! how the registries numbered resources and references, the store's
+//! [`Sampling`] and the engine's recording configuration.
+//! [`ChainState::to_bytes`] turns it into a single artifact.
+//!
+//! On the other side the recording configuration is applied to a fresh
+//! [`Config`] with [`ChainState::configure`], and
+//! [`Store::restore_chain_state`] loads the rest into a new store, which
+//! then carries on recording where the old one left off. Instances must be
+//! created in the same order as in the old store to record into their old
+//! chains.
+//!
+//! Live resource handles and references can't leave the store which created
+//! them, so registered handles aren't restored, but names given out from
+//! then on don't reuse those of the old store. Neither are the chain's
+//! signer, redactor or schemas, which are set again with
+//! [`Store::set_chain_signer`] and [`Chain::set_schemas`]. Chains persisted
+//! elsewhere, such as in a chain file, are restored into memory.
+
+use crate::chain::migrate::{self, FORMAT_VERSION};
+use crate::chain::{Chain, ChainWasiPolicy, Digest, Sampling, SerializableResource};
+use crate::prelude::*;
+use crate::{AsContextMut, Config, Store};
+use serde::{Deserialize, Serialize};
+
+/// Everything a [`Store`] knows about its chains, see the
+/// [module documentation](self).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainState {
+    /// The format the chains are encoded in, see
+    /// [`FORMAT_VERSION`](crate::chain::migrate::FORMAT_VERSION).
+    format: u64,
+    config: ChainConfig,
+    chain: SavedChain,
+    /// The component instance index recording into each child chain, with
+    /// the hash of the `spawn` event which started it.
+    instance_chains: Vec<(u64, Digest)>,
+    /// The next `rep` of each resource type.
+    resource_reps: Vec<u32>,
+    /// The next numbers of core function and external references.
+    core_refs: (u32, u32),
+    /// The handles registered when the state was captured.
+    resources: Vec<SerializableResource>,
+    sampling: Sampling,
+}
+
+/// The recording configuration of an engine, as captured in a
+/// [`ChainState`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainConfig {
+    /// See [`Config::chain_record`].
+    pub record: bool,
+    /// See [`Config::chain_wasi_policy`].
+    pub wasi: ChainWasiPolicy,
+    /// See [`Config::chain_per_instance`].
+    pub per_instance: bool,
+    /// See [`Config::chain_record_fuel`].
+    pub record_fuel: bool,
+    /// See [`Config::chain_record_instantiation`].
+    pub record_instantiation: bool,
+    /// See [`Config::chain_record_post_return`].
+    pub record_post_return: bool,
+    /// See [`Config::chain_record_types`].
+    pub record_types: bool,
+    /// See [`Config::chain_record_allow`].
+    pub allow: Vec<String>,
+    /// See [`Config::chain_record_deny`].
+    pub deny: Vec<String>,
+}
+
+/// A chain encoded with [`Chain::to_bytes`], which leaves out its children.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedChain {
+    bytes: Vec<u8>,
+    children: Vec<(Digest, SavedChain)>,
+}
+
+impl ChainConfig {
+    /// The recording configuration of `config`.
+    pub fn from_config(config: &Config) -> ChainConfig {
+        ChainConfig {
+            record: config.chain_record,
+            wasi: config.chain_wasi_policy.clone(),
+            per_instance: config.chain_per_instance,
+            record_fuel: config.chain_record_fuel,
+            record_instantiation: config.chain_record_instantiation,
+            record_post_return: config.chain_record_post_return,
+            record_types: config.chain_record_types,
+            allow: config.chain_record_allow.clone(),
+            deny: config.chain_record_deny.clone(),
+        }
+    }
+
+    /// Applies this recording configuration to `config`, leaving the rest
+    /// of it as it is.
+    pub fn configure(&self, config: &mut Config) {
+        config.chain_record = self.record;
+        config.chain_wasi_policy = self.wasi.clone();
+        config.chain_per_instance = self.per_instance;
+        config.chain_record_fuel = self.record_fuel;
+        config.chain_record_instantiation = self.record_instantiation;
+        config.chain_record_post_return = self.record_post_return;
+        config.chain_record_types = self.record_types;
+        config.chain_record_allow = self.allow.clone();
+        config.chain_record_deny = self.deny.clone();
+    }
+}
+
+impl SavedChain {
+    fn new(chain: &Chain) -> Result<SavedChain> {
+        Ok(SavedChain {
+            bytes: chain.to_bytes()?,
+            children: chain
+                .children
+                .iter()
+                .map(|(spawn, child)| Ok((*spawn, SavedChain::new(child)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn into_chain(self) -> Result<Chain> {
+        let mut chain = Chain::from_bytes(&self.bytes)?;
+        for (spawn, child) in self.children {
+            ensure!(
+                chain.get_event_by_hash(spawn).is_some(),
+                "child chain spawned by missing event {spawn}"
+            );
+            chain.children.push((spawn, child.into_chain()?));
+        }
+        Ok(chain)
+    }
+}
+
+impl ChainState {
+    /// The recording configuration of the engine the state was captured
+    /// from.
+    pub fn config(&self) -> &ChainConfig {
+        &self.config
+    }
+
+    /// Applies the recording configuration of the engine the state was
+    /// captured from to `config`, for the engine of the store it's restored
+    /// into.
+    pub fn configure(&self, config: &mut Config) {
+        self.config.configure(config);
+    }
+
+    /// The chain of the store the state was captured from, without its
+    /// children.
+    pub fn chain(&self) -> Result<Chain> {
+        Chain::from_bytes(&self.chain.bytes)
+    }
+
+    /// The names of the resource handles which were registered when the
+    /// state was captured, none of which are restored.
+    pub fn resources(&self) -> &[SerializableResource] {
+        &self.resources
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(postcard::to_allocvec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ChainState> {
+        let state: ChainState = postcard::from_bytes(bytes).context("invalid chain state")?;
+        migrate::check_version(state.format)?;
+        Ok(state)
+    }
+}
+
+impl<T> Store<T> {
+    /// Captures the chains of this store, see the
+    /// [`state` module](crate::chain::state).
+    pub fn chain_state(&mut self) -> Result<ChainState> {
+        let mut instance_chains = self
+            .as_context_mut()
+            .0
+            .instance_chains()
+            .iter()
+            .map(|(instance, spawn)| Ok((u64::try_from(*instance)?, *spawn)))
+            .collect::<Result<Vec<_>>>()?;
+        instance_chains.sort();
+        let mut resources = self
+            .resource_registry()
+            .names()
+            .copied()
+            .collect::<Vec<_>>();
+        resources.sort_by_key(|r| (r.type_id, r.rep));
+        Ok(ChainState {
+            format: FORMAT_VERSION.into(),
+            config: ChainConfig::from_config(self.engine().config()),
+            chain: SavedChain::new(self.chain())?,
+            instance_chains,
+            resource_reps: self.resource_registry().next_reps().to_vec(),
+            core_refs: self.core_ref_registry().next_ids(),
+            resources,
+            sampling: self.chain_sampling(),
+        })
+    }
+
+    /// Loads `state` into this store, which must not have recorded anything
+    /// yet.
+    ///
+    /// The engine's recording configuration should match the one `state`
+    /// was captured with, see [`ChainState::configure`], or the store
+    /// records differently from then on.
+    pub fn restore_chain_state(&mut self, state: ChainState) -> Result<()> {
+        ensure!(
+            self.chain().is_empty()
+                && self.resource_registry().is_empty()
+                && self.core_ref_registry().is_empty(),
+            "chain state can only be restored into a store which hasn't recorded anything"
+        );
+        if ChainConfig::from_config(self.engine().config()) != state.config {
+            log::warn!("restoring chain state captured with another recording configuration");
+        }
+        let chain = state.chain.into_chain()?;
+        let instance_chains = state
+            .instance_chains
+            .into_iter()
+            .map(|(instance, spawn)| Ok((usize::try_from(instance)?, spawn)))
+            .collect::<Result<_>>()?;
+
+        self.set_chain(chain);
+        self.set_chain_sampling(state.sampling);
+        self.resource_registry_mut()
+            .continue_numbering(state.resource_reps);
+        let store = self.as_context_mut();
+        *store.0.instance_chains_mut() = instance_chains;
+        store
+            .0
+            .core_ref_registry_mut()
+            .continue_numbering(state.core_refs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{Component, Linker, Val};
+    use crate::Engine;
+
+    const COMPONENT: &str = r#"
+        (component
+            (core module $m
+                (func (export "inc") (param i32) (result i32)
+                    (i32.add (local.get 0) (i32.const 1))))
+            (core instance $i (instantiate $m))
+            (func (export "inc") (param "x" u32) (result u32)
+                (canon lift (core func $i "inc")))
+        )
+    "#;
+
+    fn call_inc(config: &Config, restore: Option<ChainState>) -> Result<Store<()>> {
+        let engine = Engine::new(config)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut store = Store::new(&engine, ());
+        if let Some(state) = restore {
+            store.restore_chain_state(state)?;
+        }
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let inc = instance.get_func(&mut store, "inc").unwrap();
+        let mut results = [Val::U32(0)];
+        inc.call(&mut store, &[Val::U32(1)], &mut results)?;
+        inc.post_return(&mut store)?;
+        Ok(store)
+    }
+
+    #[test]
+    fn moves_chains_between_stores() -> Result<()> {
+        let mut config = Config::new();
+        config
+            .chain_record(true)
+            .chain_per_instance(true)
+            .chain_record_deny(&["other"]);
+        let mut store = call_inc(&config, None)?;
+        store.set_chain_sampling(Sampling::OneIn(1));
+        let state = store.chain_state()?;
+        assert_eq!(state.chain()?.head(), store.chain().head());
+        let bytes = state.to_bytes()?;
+
+        let state = ChainState::from_bytes(&bytes)?;
+        let mut config = Config::new();
+        state.configure(&mut config);
+        assert_eq!(config.chain_record_deny, ["other"]);
+        let restored = call_inc(&config, Some(state))?;
+        assert_eq!(restored.chain_sampling(), Sampling::OneIn(1));
+        let chain = restored.chain();
+        chain.verify()?;
+        assert_eq!(chain.len(), store.chain().len());
+        let (_, child) = chain.children().next().unwrap();
+        assert_eq!(child.len(), 3);
+        child.verify()?;
+
+        let mut store = call_inc(&config, None)?;
+        assert!(store
+            .restore_chain_state(ChainState::from_bytes(&bytes)?)
+            .is_err());
+        let mut state = store.chain_state()?;
+        state.format = u64::from(FORMAT_VERSION) + 1;
+        assert!(ChainState::from_bytes(&state.to_bytes()?).is_err());
+        Ok(())
+    }
+}
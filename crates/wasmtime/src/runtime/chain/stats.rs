@@ -0,0 +1,102 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::chain::{CallNode, Chain};
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+/// Summary of a chain's contents, see [`Chain::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainStats {
+    /// Number of events.
+    pub events: usize,
+    /// Number of events of each type.
+    pub by_type: BTreeMap<String, usize>,
+    /// Size of the events in their compact binary encoding, as used by
+    /// [`Chain::to_bytes`] and chain files, not counting framing.
+    pub serialized_size: usize,
+    /// Total size of the events' payloads.
+    pub payload_size: usize,
+    /// Nesting depth of the deepest recorded call, see [`Chain::call_tree`].
+    /// Top-level events are at depth 1.
+    pub depth: usize,
+}
+
+impl ChainStats {
+    /// Average size of an event's payload, or 0 for an empty chain.
+    pub fn average_payload_size(&self) -> f64 {
+        if self.events == 0 {
+            return 0.0;
+        }
+        self.payload_size as f64 / self.events as f64
+    }
+}
+
+impl Chain {
+    /// Counts this chain's events by type and measures their size and
+    /// nesting.
+    ///
+    /// This walks every event, so callers polling it for a long chain should
+    /// cache the result. Events don't carry timestamps, so none are
+    /// reported.
+    pub fn stats(&self) -> Result<ChainStats> {
+        let mut stats = ChainStats::default();
+        for node in self.events() {
+            stats.events += 1;
+            *stats
+                .by_type
+                .entry(node.event().type_().to_string())
+                .or_default() += 1;
+            stats.serialized_size += postcard::to_allocvec(node)?.len();
+            stats.payload_size += node.event().data().len();
+        }
+
+        fn depth(nodes: &[CallNode<'_>]) -> usize {
+            nodes
+                .iter()
+                .map(|n| 1 + depth(&n.children))
+                .max()
+                .unwrap_or(0)
+        }
+        stats.depth = depth(&self.call_tree());
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    #[test]
+    fn counts_events() -> Result<()> {
+        let mut chain = Chain::new();
+        assert_eq!(chain.stats()?, ChainStats::default());
+        assert_eq!(chain.stats()?.average_payload_size(), 0.0);
+
+        chain.add(Event::new("a".to_string(), vec![0; 4]));
+        chain.add(Event::new("b".to_string(), vec![0; 2]));
+        chain.add(Event::new("a".to_string(), vec![]));
+        let stats = chain.stats()?;
+        assert_eq!(stats.events, 3);
+        assert_eq!(stats.by_type["a"], 2);
+        assert_eq!(stats.by_type["b"], 1);
+        assert_eq!(stats.payload_size, 6);
+        assert_eq!(stats.average_payload_size(), 2.0);
+        assert_eq!(stats.depth, 1);
+        let framed = chain.to_bytes()?.len();
+        assert!(stats.serialized_size > 6 && stats.serialized_size < framed);
+        Ok(())
+    }
+}
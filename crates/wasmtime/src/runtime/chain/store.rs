@@ -0,0 +1,158 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::chain::MetaEvent;
+use crate::prelude::*;
+use core::fmt;
+
+/// Storage backing a [`Chain`].
+///
+/// A `Chain` links and hashes each event itself and then hands it to its
+/// store, which only has to keep events in order. Events are identified by
+/// their sequence number, starting from 0 for the oldest. Stores hand out
+/// borrowed events, so backends which persist elsewhere typically also keep a
+/// working copy in memory.
+///
+/// [`Chain`]: crate::chain::Chain
+pub trait ChainStore: fmt::Debug + Send + Sync {
+    /// Adds `event` after every event already stored.
+    ///
+    /// If this returns an error the event must not have been stored.
+    fn append(&mut self, event: MetaEvent) -> Result<()>;
+
+    /// Adds `events` after every event already stored, oldest first, for
+    /// [`Chain::add_batch`](crate::chain::Chain::add_batch).
+    ///
+    /// If this returns an error none of the events must have been stored.
+    /// The default appends them one at a time and, should one fail, rewrites
+    /// the store with the events it held before, so stores which can't
+    /// rewrite their events should override it.
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        let len = self.len();
+        for event in events {
+            if let Err(e) = self.append(event) {
+                if self.len() > len {
+                    let kept = self.iter_from(0).take(len).cloned().collect();
+                    self.rewrite(kept)
+                        .context("failed to undo a partially appended batch")?;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the event with sequence number `index`.
+    fn get(&self, index: usize) -> Option<&MetaEvent>;
+
+    /// Returns the most recently appended event.
+    fn head(&self) -> Option<&MetaEvent> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    /// The number of events stored.
+    fn len(&self) -> usize;
+
+    /// Iterates over the events starting at sequence number `index`, oldest
+    /// first.
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_>;
+
+    /// Makes sure every appended event has reached durable storage.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Replaces every stored event with `events`, for when a chain drops
+    /// history such as in [`Chain::compact`](crate::chain::Chain::compact).
+    ///
+    /// If this returns an error the stored events must be unchanged. Stores
+    /// which can't drop events keep the default, which always fails.
+    fn rewrite(&mut self, _events: Vec<MetaEvent>) -> Result<()> {
+        bail!("this chain store does not support rewriting its events")
+    }
+}
+
+/// The default [`ChainStore`], which keeps events in a `Vec`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryChainStore {
+    events: Vec<MetaEvent>,
+}
+
+impl MemoryChainStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl From<Vec<MetaEvent>> for MemoryChainStore {
+    fn from(events: Vec<MetaEvent>) -> Self {
+        MemoryChainStore { events }
+    }
+}
+
+impl ChainStore for MemoryChainStore {
+    fn append(&mut self, event: MetaEvent) -> Result<()> {
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn append_batch(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        self.events.extend(events);
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> Option<&MetaEvent> {
+        self.events.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iter_from(&self, index: usize) -> Box<dyn Iterator<Item = &MetaEvent> + '_> {
+        Box::new(self.events.get(index..).unwrap_or_default().iter())
+    }
+
+    fn rewrite(&mut self, events: Vec<MetaEvent>) -> Result<()> {
+        self.events = events;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{Chain, Event, Sha256Hasher};
+    use alloc::sync::Arc;
+
+    #[test]
+    fn resume_from_store() -> Result<()> {
+        let mut chain = Chain::new();
+        chain.add(Event::new("a".to_string(), vec![1]));
+        let head = chain.add(Event::new("b".to_string(), vec![2]));
+        let events = chain.events().cloned().collect::<Vec<_>>();
+
+        let store = Box::new(MemoryChainStore::from(events.clone()));
+        let mut resumed = Chain::with_store(Arc::new(Sha256Hasher), store)?;
+        assert_eq!(resumed.get_event_by_hash(head).unwrap().hash(), head);
+        let next = resumed.add(Event::new("c".to_string(), vec![3]));
+        assert_eq!(resumed.store().head().unwrap().hash(), next);
+        assert_eq!(resumed.store().iter_from(1).count(), 2);
+
+        let reordered = events.into_iter().rev().collect::<Vec<_>>();
+        let store = Box::new(MemoryChainStore::from(reordered));
+        assert!(Chain::with_store(Arc::new(Sha256Hasher), store).is_err());
+        Ok(())
+    }
+}
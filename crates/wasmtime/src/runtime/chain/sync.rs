@@ -0,0 +1,251 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeping replicas of a chain in step across processes.
+//!
+//! A replica catches up with another by pulling from it: [`Chain::pull`]
+//! asks for the other side's head with [`SyncRequest::Head`], and if that's
+//! an event it doesn't hold yet, asks for the events after its own head with
+//! [`SyncRequest::EventsAfter`], in batches, until it has caught up. The
+//! other side answers with [`Chain::serve_sync`]. Requests and responses are
+//! serializable, so a [`SyncTransport`] can carry them over anything.
+//!
+//! Every event received is checked before it's added: it must link to the
+//! replica's head and hash the same when added here, so a peer can't slip in
+//! events the rest of the chain doesn't commit to. A replica which added
+//! events of its own since the two last agreed can't be caught up by
+//! pulling; [`Chain::merge`] the other chain into it instead.
+
+use crate::chain::{Chain, Digest, MetaEvent};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How many events [`Chain::pull`] asks for at a time.
+pub const BATCH: usize = 256;
+
+/// A request from a replica pulling events, see the
+/// [module documentation](crate::chain::sync).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// What's your head?
+    Head,
+    /// Send up to `limit` events following `after`, or from the start if
+    /// `after` is `None`.
+    EventsAfter { after: Option<Digest>, limit: usize },
+}
+
+/// The answer to a [`SyncRequest`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    /// Answers [`SyncRequest::Head`].
+    Head {
+        /// Name of the chain's [`ChainHasher`](crate::chain::ChainHasher).
+        hasher: String,
+        /// See [`Chain::id`].
+        id: Option<Digest>,
+        head: Option<Digest>,
+    },
+    /// Answers [`SyncRequest::EventsAfter`], oldest first. Fewer events than
+    /// asked for means there are no more.
+    Events(Vec<MetaEvent>),
+    /// The event a [`SyncRequest::EventsAfter`] asked to start after isn't
+    /// part of the chain.
+    UnknownEvent(Digest),
+}
+
+/// Carries [`SyncRequest`]s to a replica and brings back its answers.
+pub trait SyncTransport {
+    fn request(&mut self, request: &SyncRequest) -> Result<SyncResponse>;
+}
+
+/// Answers requests from a chain in the same process.
+impl SyncTransport for &Chain {
+    fn request(&mut self, request: &SyncRequest) -> Result<SyncResponse> {
+        Ok(self.serve_sync(request))
+    }
+}
+
+/// What [`Chain::pull`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// The other replica had nothing this one didn't already hold.
+    UpToDate,
+    /// The other replica's `added` new events were appended.
+    Pulled { added: usize },
+}
+
+impl Chain {
+    /// Answers a request from a replica pulling from this chain.
+    pub fn serve_sync(&self, request: &SyncRequest) -> SyncResponse {
+        match *request {
+            SyncRequest::Head => SyncResponse::Head {
+                hasher: self.hasher().name().to_string(),
+                id: self.id(),
+                head: self.head(),
+            },
+            SyncRequest::EventsAfter { after, limit } => {
+                let events: Box<dyn Iterator<Item = &MetaEvent>> = match after {
+                    None => self.events(),
+                    Some(after) if self.position(after).is_some() => {
+                        Box::new(self.iter_from(after).skip(1))
+                    }
+                    Some(after) => return SyncResponse::UnknownEvent(after),
+                };
+                SyncResponse::Events(events.take(limit).cloned().collect())
+            }
+        }
+    }
+
+    /// Pulls the events this chain is missing from the replica behind
+    /// `transport`, see the [module documentation](crate::chain::sync).
+    ///
+    /// Events received before an error are kept.
+    pub fn pull(&mut self, transport: &mut dyn SyncTransport) -> Result<SyncOutcome> {
+        let (hasher, id, head) = match transport.request(&SyncRequest::Head)? {
+            SyncResponse::Head { hasher, id, head } => (hasher, id, head),
+            other => bail!("expected the replica's head, got {other:?}"),
+        };
+        ensure!(
+            hasher == self.hasher().name(),
+            "cannot pull from a chain hashed with `{hasher}` into one hashed with `{}`",
+            self.hasher().name()
+        );
+        ensure!(
+            self.is_empty() || id == self.id(),
+            "cannot pull from a replica of a different chain"
+        );
+        let Some(head) = head else {
+            return Ok(SyncOutcome::UpToDate);
+        };
+        if self.position(head).is_some() {
+            return Ok(SyncOutcome::UpToDate);
+        }
+
+        let mut added = 0;
+        while self.head() != Some(head) {
+            let request = SyncRequest::EventsAfter {
+                after: self.head(),
+                limit: BATCH,
+            };
+            let events = match transport.request(&request)? {
+                SyncResponse::Events(events) => events,
+                SyncResponse::UnknownEvent(_) => bail!(
+                    "the replica doesn't hold this chain's head, so the two have diverged; \
+                     merge them instead"
+                ),
+                other => bail!("expected events, got {other:?}"),
+            };
+            if events.is_empty() {
+                bail!("the replica stopped sending events before reaching its head {head}");
+            }
+            for node in events {
+                self.add_synced(&node)?;
+                added += 1;
+            }
+        }
+        Ok(SyncOutcome::Pulled { added })
+    }
+
+    /// Adds an event received from a replica, checking it follows on from
+    /// this chain's head and hashes the same here.
+    pub(crate) fn add_synced(&mut self, node: &MetaEvent) -> Result<()> {
+        ensure!(
+            node.event().parent() == self.head(),
+            "received event {} doesn't follow on from this chain's head",
+            node.hash()
+        );
+        ensure!(
+            self.hasher().hash_event(node.event()) == node.hash(),
+            "received event {} doesn't match its hash",
+            node.hash()
+        );
+        let hash = self.try_add(node.event().clone())?;
+        ensure!(
+            hash == node.hash(),
+            "received event {} was recorded as {hash}, so this chain rewrites events \
+             it adds, such as with a redactor",
+            node.hash()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Event;
+
+    fn event(ty: &str, data: u8) -> Event {
+        Event::new(ty.to_string(), vec![data])
+    }
+
+    #[test]
+    fn pulls_missing_events() -> Result<()> {
+        let mut source = Chain::new();
+        source.add(event("a", 1));
+        let mut replica = source.clone();
+        for i in 0..600u16 {
+            source.add(event("b", i.to_le_bytes()[0]));
+        }
+
+        let outcome = replica.pull(&mut &source)?;
+        assert_eq!(outcome, SyncOutcome::Pulled { added: 600 });
+        assert_eq!(replica.head(), source.head());
+        assert_eq!(replica.digest(), source.digest());
+        assert_eq!(replica.pull(&mut &source)?, SyncOutcome::UpToDate);
+
+        let mut empty = Chain::new();
+        empty.pull(&mut &source)?;
+        assert_eq!(empty.head(), source.head());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_diverged_and_tampered() -> Result<()> {
+        let mut source = Chain::new();
+        source.add(event("a", 1));
+        let mut replica = source.clone();
+        source.add(event("b", 2));
+        replica.add(event("c", 3));
+        let err = replica.pull(&mut &source).unwrap_err();
+        assert!(err.to_string().contains("diverged"), "{err}");
+
+        /// Serves `chain` with every payload flipped.
+        struct Tampering<'a>(&'a Chain);
+
+        impl SyncTransport for Tampering<'_> {
+            fn request(&mut self, request: &SyncRequest) -> Result<SyncResponse> {
+                Ok(match self.0.serve_sync(request) {
+                    SyncResponse::Events(events) => SyncResponse::Events(
+                        events
+                            .into_iter()
+                            .map(|node| {
+                                let mut event = node.event().clone();
+                                event.data.to_mut()[0] ^= 1;
+                                MetaEvent::new(node.hash(), event)
+                            })
+                            .collect(),
+                    ),
+                    other => other,
+                })
+            }
+        }
+
+        let mut replica = Chain::new();
+        let err = replica.pull(&mut Tampering(&source)).unwrap_err();
+        assert!(err.to_string().contains("doesn't match its hash"), "{err}");
+        assert!(replica.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Regression tests generated from recorded chains.
+//!
+//! [`TestSuite::from_chain`] turns the export calls recorded in a chain, such
+//! as one taken in production when something went wrong, into a
+//! [`TestSuite`] with a [`TestCase`] per call: its arguments, what it
+//! returned or that it trapped, and what each host import it made returned.
+//! The suite is plain data which serializes to JSON, to be checked in next
+//! to a component's other tests, and [`TestSuite::run`] is its runner. It
+//! instantiates the component with its imports answered from the suite,
+//! makes every call again and fails with a [`TestFailure`] at the first one
+//! that returns something else.
+//!
+//! Cases run in order on a single instance, since each call may depend on
+//! state left behind by the ones before it. Imports made by a call which
+//! never finished, such as one in flight when the chain was taken, are left
+//! out. As in [replay](crate::chain::replay), calls can't pass resources to
+//! or from the host.
+
+use crate::chain::record::{
+    CallTrap, FunctionCall, ImportCall, ImportReturn, FUNCTION_CALL, IMPORT_CALL, IMPORT_RETURN,
+    TRAP,
+};
+use crate::chain::replay::Replay;
+use crate::chain::{Chain, Digest, Event, SerializableVal, ValDiff};
+use crate::component::{Component, Linker, Val};
+use crate::prelude::*;
+use crate::AsContextMut;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Export calls to make on a component along with what they should return,
+/// see the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestSuite {
+    pub cases: Vec<TestCase>,
+}
+
+/// A call to an export, see [`TestSuite`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestCase {
+    /// The `#`-separated name of the export.
+    pub export: String,
+    pub params: Vec<SerializableVal>,
+    /// The calls the export makes to host imports, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub imports: Vec<TestImport>,
+    pub outcome: Outcome,
+    /// Hash of the event the case was generated from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Digest>,
+}
+
+/// A call to a host import made by a [`TestCase`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestImport {
+    pub name: String,
+    /// The arguments of the call, if they were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<SerializableVal>>,
+    /// What the import returns to the guest.
+    pub outcome: Outcome,
+}
+
+/// How a call ends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// The call returns these results.
+    Returns(Vec<SerializableVal>),
+    /// The call fails with this message.
+    Traps(String),
+}
+
+/// A [`TestCase`] which returned other results than expected.
+#[derive(Debug, Clone)]
+pub struct TestFailure {
+    /// Position of the case in its suite.
+    pub case: usize,
+    /// The export called.
+    pub export: String,
+    /// How the results differ from the expected ones.
+    pub values: ValDiff,
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "test case {} (`{}`) returned unexpected results\n{}",
+            self.case,
+            self.export,
+            self.values.to_string().trim_end()
+        )
+    }
+}
+
+impl core::error::Error for TestFailure {}
+
+impl TestSuite {
+    /// Generates a case for every export call recorded in `chain`, see the
+    /// [module documentation](self).
+    ///
+    /// Fails if the results of an import weren't recorded.
+    pub fn from_chain(chain: &Chain) -> Result<TestSuite> {
+        let mut cases = Vec::new();
+        let mut imports = Vec::new();
+        let mut pending: Option<ImportCall> = None;
+        for node in chain.iter() {
+            let event = node.event();
+            match event.type_() {
+                IMPORT_CALL => pending = Some(ImportCall::decode(event)?),
+                IMPORT_RETURN => {
+                    let ret = ImportReturn::decode(event)?;
+                    let call = pending
+                        .take()
+                        .filter(|call| call.name == ret.name)
+                        .ok_or_else(|| anyhow!("import `{}` returned without a call", ret.name))?;
+                    let results = ret.results.ok_or_else(|| {
+                        anyhow!("results of import `{}` were not recorded", ret.name)
+                    })?;
+                    imports.push(TestImport {
+                        name: call.name,
+                        params: call.params,
+                        outcome: Outcome::Returns(results),
+                    });
+                }
+                TRAP => {
+                    let trap = CallTrap::decode(event)?;
+                    if let Some(call) = pending.take() {
+                        imports.push(TestImport {
+                            name: call.name,
+                            params: call.params,
+                            outcome: Outcome::Traps(trap.message.clone()),
+                        });
+                    }
+                    cases.push(TestCase {
+                        export: trap.name,
+                        params: trap.params,
+                        imports: core::mem::take(&mut imports),
+                        outcome: Outcome::Traps(trap.message),
+                        event: Some(node.hash()),
+                    });
+                }
+                FUNCTION_CALL => {
+                    let call = FunctionCall::decode(event)?;
+                    cases.push(TestCase {
+                        export: call.name,
+                        params: call.params,
+                        imports: core::mem::take(&mut imports),
+                        outcome: Outcome::Returns(call.results),
+                        event: Some(node.hash()),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(TestSuite { cases })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<TestSuite> {
+        serde_json::from_str(json).context("invalid test suite")
+    }
+
+    /// Instantiates `component` in `store` with its imports answered from
+    /// the suite and runs every case on the instance, in order.
+    ///
+    /// Fails with a [`TestFailure`] at the first case which returns other
+    /// results than expected. Cases which return where they should trap, or
+    /// the other way around, or which make other imports than expected fail
+    /// as in [`Replay::run`].
+    pub fn run<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        component: &Component,
+    ) -> Result<()> {
+        let replay = Replay::new(&self.to_chain()?)?;
+        let mut linker = Linker::new(component.engine());
+        replay.add_to_linker(&mut linker, component)?;
+        let instance = linker.instantiate(&mut store, component)?;
+        replay.run_with(&mut store, &instance, false, |index, results| {
+            self.check(index, results)
+        })
+    }
+
+    /// The suite as the chain of events recording it, for [`Replay`].
+    fn to_chain(&self) -> Result<Chain> {
+        let mut chain = Chain::new();
+        let mut add =
+            |type_: &str, payload: Vec<u8>| chain.add(Event::new(type_.to_string(), payload));
+        for case in &self.cases {
+            let trap = |message: &str, import| CallTrap {
+                name: case.export.clone(),
+                params: case.params.clone(),
+                code: None,
+                message: message.to_string(),
+                frames: Vec::new(),
+                import,
+                call_parent: None,
+            };
+            let mut import_trapped = false;
+            for import in &case.imports {
+                let call = ImportCall {
+                    name: import.name.clone(),
+                    params: import.params.clone(),
+                    call_parent: None,
+                };
+                let call = add(IMPORT_CALL, serde_json::to_vec(&call)?);
+                match &import.outcome {
+                    Outcome::Returns(results) => {
+                        let ret = ImportReturn {
+                            name: import.name.clone(),
+                            results: Some(results.clone()),
+                            call_parent: None,
+                        };
+                        add(IMPORT_RETURN, serde_json::to_vec(&ret)?);
+                    }
+                    // The import's error is the export's trap.
+                    Outcome::Traps(message) => {
+                        add(TRAP, serde_json::to_vec(&trap(message, Some(call)))?);
+                        import_trapped = true;
+                    }
+                }
+            }
+            match &case.outcome {
+                Outcome::Returns(results) => {
+                    let call = FunctionCall {
+                        name: case.export.clone(),
+                        params: case.params.clone(),
+                        results: results.clone(),
+                        call_parent: None,
+                        signature: None,
+                    };
+                    add(FUNCTION_CALL, serde_json::to_vec(&call)?);
+                }
+                Outcome::Traps(_) if import_trapped => {}
+                Outcome::Traps(message) => {
+                    add(TRAP, serde_json::to_vec(&trap(message, None))?);
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Checks the results the case at `index` returned.
+    fn check(&self, index: usize, results: &[Val]) -> Result<()> {
+        let case = &self.cases[index];
+        let Outcome::Returns(expected) = &case.outcome else {
+            return Ok(());
+        };
+        let found = results
+            .iter()
+            .map(SerializableVal::from_val)
+            .collect::<Result<Vec<_>, _>>()?;
+        let values = SerializableVal::Tuple(expected.clone()).diff(&SerializableVal::Tuple(found));
+        if values.is_same() {
+            return Ok(());
+        }
+        Err(TestFailure {
+            case: index,
+            export: case.export.clone(),
+            values,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Engine, Store};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const COMPONENT: &str = r#"
+        (component
+            (import "host" (instance $host
+                (export "next" (func (result u32)))
+            ))
+            (core func $next (canon lower (func $host "next")))
+            (core module $m
+                (import "" "next" (func $next (result i32)))
+                (func (export "add") (param i32) (result i32)
+                    (i32.add (local.get 0) (call $next))))
+            (core instance $i (instantiate $m
+                (with "" (instance (export "next" (func $next))))))
+            (func (export "add") (param "x" u32) (result u32)
+                (canon lift (core func $i "add")))
+        )
+    "#;
+
+    #[test]
+    fn generates_and_runs_suites() -> Result<()> {
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, COMPONENT)?;
+        let mut linker = Linker::new(&engine);
+        let next = AtomicU32::new(0);
+        linker.instance("host")?.func_wrap("next", move |_, ()| {
+            let n = next.fetch_add(10, Ordering::SeqCst) + 10;
+            if n > 20 {
+                bail!("out of numbers");
+            }
+            Ok((n,))
+        })?;
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component)?;
+        let add = instance.get_func(&mut store, "add").unwrap();
+        for x in [1, 2] {
+            add.call(&mut store, &[Val::U32(x)], &mut [Val::U32(0)])?;
+            add.post_return(&mut store)?;
+        }
+        assert!(add
+            .call(&mut store, &[Val::U32(3)], &mut [Val::U32(0)])
+            .is_err());
+
+        let suite = TestSuite::from_chain(store.chain())?;
+        assert_eq!(suite.cases.len(), 3);
+        assert_eq!(suite.cases[1].params, [SerializableVal::U32(2)]);
+        assert_eq!(
+            suite.cases[1].imports[0].outcome,
+            Outcome::Returns(vec![SerializableVal::U32(20)])
+        );
+        assert_eq!(
+            suite.cases[1].outcome,
+            Outcome::Returns(vec![SerializableVal::U32(22)])
+        );
+        assert!(matches!(&suite.cases[2].outcome, Outcome::Traps(m) if m == "out of numbers"));
+        let suite = TestSuite::from_json(&suite.to_json()?)?;
+
+        // The runner needs neither the host nor recording.
+        let engine = Engine::new(&Config::new())?;
+        let component = Component::new(&engine, COMPONENT)?;
+        suite.run(Store::new(&engine, ()), &component)?;
+
+        let broken = COMPONENT.replace(
+            "(i32.add (local.get 0) (call $next))",
+            "(i32.sub (call $next) (local.get 0))",
+        );
+        let broken = Component::new(&engine, broken)?;
+        let err = suite.run(Store::new(&engine, ()), &broken).unwrap_err();
+        let failure = err.downcast_ref::<TestFailure>().unwrap();
+        assert_eq!(failure.case, 0);
+        assert_eq!(failure.values.to_string(), "$[0]: 11 -> 9\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The nesting of recorded calls, rebuilt from their `call_parent` links.
+
+use crate::chain::record::{FUNCTION_CALL, TRAP};
+use crate::chain::{Chain, Digest, MetaEvent};
+use crate::prelude::*;
+use core::mem;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// An event of a [`Chain::call_tree`] along with the events recorded inside
+/// it.
+#[derive(Debug, Clone)]
+pub struct CallNode<'a> {
+    pub event: &'a MetaEvent,
+    /// For a `function-call` or `trap`, the events recorded while the export
+    /// ran; for an `import-call`, those recorded while the host function ran,
+    /// ending with its `import-return`. Empty for other events.
+    pub children: Vec<CallNode<'a>>,
+}
+
+/// The part of every recorded payload [`Chain::call_tree`] needs.
+#[derive(Deserialize)]
+struct CallParent {
+    #[serde(default)]
+    call_parent: Option<Digest>,
+}
+
+impl Chain {
+    /// Arranges this chain's events into the tree of calls they were
+    /// recorded in, returning the top-level events in order.
+    ///
+    /// An `import-call` event's children are the events whose `call_parent`
+    /// is its hash. An export call is recorded once it finishes, so a
+    /// `function-call` or `trap` event adopts the events sharing its
+    /// `call_parent` which were recorded since the previous such event.
+    /// Events without a JSON payload holding a `call_parent`, such as ones
+    /// added by the embedder, are taken to be at the top level.
+    pub fn call_tree(&self) -> Vec<CallNode<'_>> {
+        let events = self.events().collect::<Vec<_>>();
+        let mut children = vec![Vec::new(); events.len()];
+        // Events with a given `call_parent` not yet adopted by an export
+        // call.
+        let mut pending = HashMap::<Option<Digest>, Vec<usize>>::new();
+        for (i, node) in events.iter().enumerate() {
+            let parent = serde_json::from_slice::<CallParent>(&node.event().data())
+                .ok()
+                .and_then(|p| p.call_parent);
+            let siblings = pending.entry(parent).or_default();
+            if matches!(node.event().type_(), FUNCTION_CALL | TRAP) {
+                children[i] = mem::take(siblings);
+            }
+            siblings.push(i);
+        }
+        for (i, node) in events.iter().enumerate() {
+            if let Some(inner) = pending.remove(&Some(node.hash())) {
+                children[i].extend(inner);
+            }
+        }
+
+        fn build<'a>(
+            i: usize,
+            events: &[&'a MetaEvent],
+            children: &mut Vec<Vec<usize>>,
+        ) -> CallNode<'a> {
+            let inner = mem::take(&mut children[i]);
+            CallNode {
+                event: events[i],
+                children: inner
+                    .into_iter()
+                    .map(|c| build(c, events, children))
+                    .collect(),
+            }
+        }
+        let roots = pending.remove(&None).unwrap_or_default();
+        // Anything left names a `call_parent` missing from this chain, such
+        // as the events of an instance's chain recorded inside an import
+        // made by another instance; treat those as top-level too.
+        let mut roots = roots
+            .into_iter()
+            .chain(pending.into_values().flatten())
+            .collect::<Vec<_>>();
+        roots.sort_unstable();
+        roots
+            .into_iter()
+            .map(|i| build(i, &events, &mut children))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::record::{IMPORT_CALL, IMPORT_RETURN};
+    use crate::component::{Component, Func, Linker};
+    use crate::{Config, Engine, Store};
+
+    fn shape(nodes: &[CallNode<'_>]) -> String {
+        nodes
+            .iter()
+            .map(|n| {
+                let ty = n.event.event().type_();
+                if n.children.is_empty() {
+                    ty.to_string()
+                } else {
+                    format!("{ty}[{}]", shape(&n.children))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn nested_calls() -> Result<()> {
+        // `outer` calls the host, which calls `inner` on a second instance,
+        // which calls the host again.
+        let component = r#"
+            (component
+                (import "host" (instance $host
+                    (export "reenter" (func))
+                    (export "leaf" (func))
+                ))
+                (core func $reenter (canon lower (func $host "reenter")))
+                (core func $leaf (canon lower (func $host "leaf")))
+                (core module $m
+                    (import "" "reenter" (func $reenter))
+                    (import "" "leaf" (func $leaf))
+                    (func (export "outer") (call $reenter))
+                    (func (export "inner") (call $leaf)))
+                (core instance $i (instantiate $m
+                    (with "" (instance
+                        (export "reenter" (func $reenter))
+                        (export "leaf" (func $leaf))))))
+                (func (export "outer") (canon lift (core func $i "outer")))
+                (func (export "inner") (canon lift (core func $i "inner")))
+            )
+        "#;
+        let mut config = Config::new();
+        config.chain_record(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::new(&engine, component)?;
+        let mut linker = Linker::<Option<Func>>::new(&engine);
+        let mut host = linker.instance("host")?;
+        host.func_wrap("reenter", |mut store, ()| {
+            let inner = store.data().unwrap();
+            inner.call(&mut store, &[], &mut [])?;
+            inner.post_return(&mut store)?;
+            Ok(())
+        })?;
+        host.func_wrap("leaf", |_, ()| Ok(()))?;
+        let mut store = Store::new(&engine, None);
+        let a = linker.instantiate(&mut store, &component)?;
+        let b = linker.instantiate(&mut store, &component)?;
+        *store.data_mut() = b.get_func(&mut store, "inner");
+        let outer = a.get_func(&mut store, "outer").unwrap();
+        outer.call(&mut store, &[], &mut [])?;
+        outer.post_return(&mut store)?;
+
+        let tree = store.chain().call_tree();
+        assert_eq!(
+            shape(&tree),
+            format!(
+                "{FUNCTION_CALL}[{IMPORT_CALL}[{FUNCTION_CALL}[{IMPORT_CALL}[{IMPORT_RETURN}]] \
+                 {IMPORT_RETURN}]]"
+            )
+        );
+        Ok(())
+    }
+}
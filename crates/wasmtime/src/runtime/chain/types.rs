@@ -0,0 +1,593 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording the types of component values.
+//!
+//! A [`SerializableVal`] doesn't say which type it was recorded as: an empty
+//! `list<u8>` looks the same as an empty `list<string>`, and a variant value
+//! doesn't list the other cases it could have taken. [`SerializableType`]
+//! mirrors [`component::Type`](Type) so types can be recorded along with
+//! values. With [`Config::chain_record_types`](crate::Config::chain_record_types)
+//! every `function-call` event carries the [`FunctionSignature`] of its
+//! export, which makes a chain self-describing: tools can decode and check
+//! its payloads without the component which produced it.
+//!
+//! Resource types are numbered by the store's [`ResourceRegistry`], so
+//! `own<2>` is the type of the handles recorded with a `type_id` of 2.
+
+use crate::chain::{ChainValueError, ResourceRegistry, SerializableVal};
+use crate::component::Type;
+use crate::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// A serializable mirror of a component [`Type`], see the
+/// [module documentation](crate::chain::types).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SerializableType {
+    Bool,
+    S8,
+    U8,
+    S16,
+    U16,
+    S32,
+    U32,
+    S64,
+    U64,
+    Float32,
+    Float64,
+    Char,
+    String,
+    List(Box<SerializableType>),
+    Record(Vec<(String, SerializableType)>),
+    Tuple(Vec<SerializableType>),
+    Variant(Vec<(String, Option<SerializableType>)>),
+    Enum(Vec<String>),
+    Option(Box<SerializableType>),
+    Result {
+        ok: Option<Box<SerializableType>>,
+        err: Option<Box<SerializableType>>,
+    },
+    Flags(Vec<String>),
+    /// An owned handle to the resource type numbered by the registry.
+    Own(u32),
+    /// A borrowed handle to the resource type numbered by the registry.
+    Borrow(u32),
+}
+
+impl SerializableType {
+    /// Converts `ty`, numbering the resource types it refers to through
+    /// `registry`.
+    pub fn from_type(ty: &Type, registry: &mut ResourceRegistry) -> SerializableType {
+        match ty {
+            Type::Bool => SerializableType::Bool,
+            Type::S8 => SerializableType::S8,
+            Type::U8 => SerializableType::U8,
+            Type::S16 => SerializableType::S16,
+            Type::U16 => SerializableType::U16,
+            Type::S32 => SerializableType::S32,
+            Type::U32 => SerializableType::U32,
+            Type::S64 => SerializableType::S64,
+            Type::U64 => SerializableType::U64,
+            Type::Float32 => SerializableType::Float32,
+            Type::Float64 => SerializableType::Float64,
+            Type::Char => SerializableType::Char,
+            Type::String => SerializableType::String,
+            Type::List(list) => {
+                SerializableType::List(Box::new(SerializableType::from_type(&list.ty(), registry)))
+            }
+            Type::Option(option) => SerializableType::Option(Box::new(
+                SerializableType::from_type(&option.ty(), registry),
+            )),
+            Type::Result(result) => SerializableType::Result {
+                ok: result
+                    .ok()
+                    .map(|ty| Box::new(SerializableType::from_type(&ty, registry))),
+                err: result
+                    .err()
+                    .map(|ty| Box::new(SerializableType::from_type(&ty, registry))),
+            },
+            Type::Record(record) => SerializableType::Record(
+                record
+                    .fields()
+                    .map(|field| {
+                        let ty = SerializableType::from_type(&field.ty, registry);
+                        (field.name.to_string(), ty)
+                    })
+                    .collect(),
+            ),
+            Type::Tuple(tuple) => SerializableType::Tuple(
+                tuple
+                    .types()
+                    .map(|ty| SerializableType::from_type(&ty, registry))
+                    .collect(),
+            ),
+            Type::Variant(variant) => SerializableType::Variant(
+                variant
+                    .cases()
+                    .map(|case| {
+                        let ty = case.ty.map(|ty| SerializableType::from_type(&ty, registry));
+                        (case.name.to_string(), ty)
+                    })
+                    .collect(),
+            ),
+            Type::Enum(e) => SerializableType::Enum(e.names().map(String::from).collect()),
+            Type::Flags(flags) => {
+                SerializableType::Flags(flags.names().map(String::from).collect())
+            }
+            Type::Own(ty) => SerializableType::Own(registry.type_id(*ty)),
+            Type::Borrow(ty) => SerializableType::Borrow(registry.type_id(*ty)),
+        }
+    }
+
+    /// Whether `ty` has the same shape as this type.
+    ///
+    /// Resource types are only told apart by whether they're owned or
+    /// borrowed, since those recorded are numbered by a registry which may
+    /// not be at hand.
+    pub fn matches(&self, ty: &Type) -> bool {
+        use SerializableType as T;
+
+        let payload = |a: Option<&T>, b: Option<Type>| match (a, b) {
+            (Some(a), Some(b)) => a.matches(&b),
+            (None, None) => true,
+            _ => false,
+        };
+        match (self, ty) {
+            (T::Bool, Type::Bool)
+            | (T::S8, Type::S8)
+            | (T::U8, Type::U8)
+            | (T::S16, Type::S16)
+            | (T::U16, Type::U16)
+            | (T::S32, Type::S32)
+            | (T::U32, Type::U32)
+            | (T::S64, Type::S64)
+            | (T::U64, Type::U64)
+            | (T::Float32, Type::Float32)
+            | (T::Float64, Type::Float64)
+            | (T::Char, Type::Char)
+            | (T::String, Type::String)
+            | (T::Own(_), Type::Own(_))
+            | (T::Borrow(_), Type::Borrow(_)) => true,
+            (T::List(elem), Type::List(list)) => elem.matches(&list.ty()),
+            (T::Option(some), Type::Option(option)) => some.matches(&option.ty()),
+            (T::Result { ok, err }, Type::Result(result)) => {
+                payload(ok.as_deref(), result.ok()) && payload(err.as_deref(), result.err())
+            }
+            (T::Record(fields), Type::Record(record)) => {
+                fields.len() == record.fields().len()
+                    && fields
+                        .iter()
+                        .zip(record.fields())
+                        .all(|((name, ty), field)| *name == field.name && ty.matches(&field.ty))
+            }
+            (T::Tuple(types), Type::Tuple(tuple)) => {
+                types.len() == tuple.types().len()
+                    && types.iter().zip(tuple.types()).all(|(a, b)| a.matches(&b))
+            }
+            (T::Variant(cases), Type::Variant(variant)) => {
+                cases.len() == variant.cases().len()
+                    && cases.iter().zip(variant.cases()).all(|((name, ty), case)| {
+                        *name == case.name && payload(ty.as_ref(), case.ty)
+                    })
+            }
+            (T::Enum(names), Type::Enum(e)) => names.iter().eq(e.names()),
+            (T::Flags(names), Type::Flags(flags)) => names.iter().eq(flags.names()),
+            _ => false,
+        }
+    }
+
+    /// Checks that `val` is a value of this type.
+    pub fn check(&self, val: &SerializableVal) -> Result<(), ChainValueError> {
+        use SerializableType as T;
+        use SerializableVal as V;
+
+        let payload = |v: &Option<Box<V>>, ty: Option<&T>, case: &str| match (v, ty) {
+            (Some(v), Some(ty)) => ty.check(v),
+            (None, None) => Ok(()),
+            (v, _) => Err(ChainValueError::PayloadMismatch {
+                case: case.to_string(),
+                expected: v.is_none(),
+            }),
+        };
+        match (self, val) {
+            (T::Bool, V::Bool(_))
+            | (T::S8, V::S8(_))
+            | (T::U8, V::U8(_))
+            | (T::S16, V::S16(_))
+            | (T::U16, V::U16(_))
+            | (T::S32, V::S32(_))
+            | (T::U32, V::U32(_))
+            | (T::S64, V::S64(_))
+            | (T::U64, V::U64(_))
+            | (T::Float32, V::Float32(_))
+            | (T::Float64, V::Float64(_))
+            | (T::Char, V::Char(_))
+            | (T::String, V::String(_)) => Ok(()),
+            (T::List(elem), V::List(vals)) => vals.iter().try_for_each(|v| elem.check(v)),
+            (T::Record(fields), V::Record(vals)) => {
+                if fields.len() != vals.len() {
+                    return Err(ChainValueError::LengthMismatch {
+                        what: "record",
+                        expected: fields.len(),
+                        found: vals.len(),
+                    });
+                }
+                fields
+                    .iter()
+                    .zip(vals)
+                    .try_for_each(|((expected, ty), (found, v))| {
+                        if expected != found {
+                            return Err(ChainValueError::FieldMismatch {
+                                expected: expected.clone(),
+                                found: found.clone(),
+                            });
+                        }
+                        ty.check(v)
+                    })
+            }
+            (T::Tuple(types), V::Tuple(vals)) => {
+                if types.len() != vals.len() {
+                    return Err(ChainValueError::LengthMismatch {
+                        what: "tuple",
+                        expected: types.len(),
+                        found: vals.len(),
+                    });
+                }
+                types.iter().zip(vals).try_for_each(|(ty, v)| ty.check(v))
+            }
+            (T::Variant(cases), V::Variant(name, v)) => {
+                match cases.iter().find(|(case, _)| case == name) {
+                    Some((_, ty)) => payload(v, ty.as_ref(), name),
+                    None => Err(ChainValueError::UnknownCase(name.clone())),
+                }
+            }
+            (T::Enum(names), V::Enum(name)) => {
+                if !names.contains(name) {
+                    return Err(ChainValueError::UnknownCase(name.clone()));
+                }
+                Ok(())
+            }
+            (T::Option(ty), V::Option(v)) => match v {
+                Some(v) => ty.check(v),
+                None => Ok(()),
+            },
+            (T::Result { ok, err }, V::Result(r)) => match r {
+                Ok(v) => payload(v, ok.as_deref(), "ok"),
+                Err(v) => payload(v, err.as_deref(), "err"),
+            },
+            (T::Flags(names), V::Flags(flags)) => {
+                match flags.iter().find(|flag| !names.contains(flag)) {
+                    Some(unknown) => Err(ChainValueError::UnknownFlag(unknown.clone())),
+                    None => Ok(()),
+                }
+            }
+            (T::Own(id), V::Resource(r)) | (T::Borrow(id), V::Resource(r)) => {
+                if r.type_id != *id || r.owned != matches!(self, T::Own(_)) {
+                    return Err(ChainValueError::ResourceTypeMismatch(*r));
+                }
+                Ok(())
+            }
+            (ty, val) => Err(ChainValueError::TypeMismatch {
+                expected: ty.desc(),
+                found: val.desc(),
+            }),
+        }
+    }
+
+    pub(crate) fn desc(&self) -> &'static str {
+        match self {
+            SerializableType::Bool => "bool",
+            SerializableType::S8 => "s8",
+            SerializableType::U8 => "u8",
+            SerializableType::S16 => "s16",
+            SerializableType::U16 => "u16",
+            SerializableType::S32 => "s32",
+            SerializableType::U32 => "u32",
+            SerializableType::S64 => "s64",
+            SerializableType::U64 => "u64",
+            SerializableType::Float32 => "float32",
+            SerializableType::Float64 => "float64",
+            SerializableType::Char => "char",
+            SerializableType::String => "string",
+            SerializableType::List(_) => "list",
+            SerializableType::Record(_) => "record",
+            SerializableType::Tuple(_) => "tuple",
+            SerializableType::Variant(_) => "variant",
+            SerializableType::Enum(_) => "enum",
+            SerializableType::Option(_) => "option",
+            SerializableType::Result { .. } => "result",
+            SerializableType::Flags(_) => "flags",
+            SerializableType::Own(_) => "own",
+            SerializableType::Borrow(_) => "borrow",
+        }
+    }
+}
+
+/// The type in WIT syntax, with records, variants, enums and flags written
+/// out in place, such as `record { name: string, tags: list<string> }`.
+impl fmt::Display for SerializableType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T>(
+            f: &mut fmt::Formatter<'_>,
+            items: &[T],
+            mut item: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+        ) -> fmt::Result {
+            for (i, x) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                item(f, x)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            SerializableType::List(elem) => write!(f, "list<{elem}>"),
+            SerializableType::Option(ty) => write!(f, "option<{ty}>"),
+            SerializableType::Result { ok, err } => match (ok, err) {
+                (Some(ok), Some(err)) => write!(f, "result<{ok}, {err}>"),
+                (Some(ok), None) => write!(f, "result<{ok}>"),
+                (None, Some(err)) => write!(f, "result<_, {err}>"),
+                (None, None) => f.write_str("result"),
+            },
+            SerializableType::Tuple(types) => {
+                f.write_str("tuple<")?;
+                list(f, types, |f, ty| write!(f, "{ty}"))?;
+                f.write_str(">")
+            }
+            SerializableType::Record(fields) => {
+                f.write_str("record { ")?;
+                list(f, fields, |f, (name, ty)| write!(f, "{name}: {ty}"))?;
+                f.write_str(" }")
+            }
+            SerializableType::Variant(cases) => {
+                f.write_str("variant { ")?;
+                list(f, cases, |f, (name, ty)| match ty {
+                    Some(ty) => write!(f, "{name}({ty})"),
+                    None => f.write_str(name),
+                })?;
+                f.write_str(" }")
+            }
+            SerializableType::Enum(names) | SerializableType::Flags(names) => {
+                f.write_str(self.desc())?;
+                f.write_str(" { ")?;
+                list(f, names, |f, name| f.write_str(name))?;
+                f.write_str(" }")
+            }
+            SerializableType::Own(id) => write!(f, "own<{id}>"),
+            SerializableType::Borrow(id) => write!(f, "borrow<{id}>"),
+            ty => f.write_str(ty.desc()),
+        }
+    }
+}
+
+/// The parameter and result types of a component function, as recorded in
+/// [`FunctionCall::signature`](crate::chain::FunctionCall::signature).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionSignature {
+    /// The parameter names and types.
+    pub params: Vec<(String, SerializableType)>,
+    pub results: Vec<SerializableType>,
+}
+
+impl FunctionSignature {
+    /// Converts the types of a function, as returned by
+    /// [`Func::params`](crate::component::Func::params) and
+    /// [`Func::results`](crate::component::Func::results).
+    pub fn new(
+        params: &[(String, Type)],
+        results: &[Type],
+        registry: &mut ResourceRegistry,
+    ) -> FunctionSignature {
+        FunctionSignature {
+            params: params
+                .iter()
+                .map(|(name, ty)| (name.clone(), SerializableType::from_type(ty, registry)))
+                .collect(),
+            results: results
+                .iter()
+                .map(|ty| SerializableType::from_type(ty, registry))
+                .collect(),
+        }
+    }
+
+    /// Whether a function with the given types has this signature, see
+    /// [`SerializableType::matches`].
+    pub fn matches(&self, params: &[(String, Type)], results: &[Type]) -> bool {
+        self.params.len() == params.len()
+            && self.results.len() == results.len()
+            && self
+                .params
+                .iter()
+                .zip(params)
+                .all(|((_, a), (_, b))| a.matches(b))
+            && self.results.iter().zip(results).all(|(a, b)| a.matches(b))
+    }
+
+    /// Checks that `params` and `results` are values of this signature's
+    /// types.
+    pub fn check(
+        &self,
+        params: &[SerializableVal],
+        results: &[SerializableVal],
+    ) -> Result<(), ChainValueError> {
+        fn check<'a>(
+            tys: impl ExactSizeIterator<Item = &'a SerializableType>,
+            vals: &[SerializableVal],
+        ) -> Result<(), ChainValueError> {
+            if tys.len() != vals.len() {
+                return Err(ChainValueError::LengthMismatch {
+                    what: "value list",
+                    expected: tys.len(),
+                    found: vals.len(),
+                });
+            }
+            tys.zip(vals).try_for_each(|(ty, v)| ty.check(v))
+        }
+        check(self.params.iter().map(|(_, ty)| ty), params)?;
+        check(self.results.iter(), results)
+    }
+}
+
+/// The signature in WIT syntax, such as `func(n: u32) -> string`.
+impl fmt::Display for FunctionSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("func(")?;
+        for (i, (name, ty)) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{name}: {ty}")?;
+        }
+        f.write_str(")")?;
+        match &self.results[..] {
+            [] => Ok(()),
+            [ty] => write!(f, " -> {ty}"),
+            results => {
+                f.write_str(" -> (")?;
+                for (i, ty) in results.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{ty}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::SerializableResource;
+    use crate::component::types::ComponentItem;
+    use crate::component::Component;
+    use crate::Engine;
+
+    fn signature() -> (Vec<(String, Type)>, Vec<Type>) {
+        let engine = Engine::default();
+        let component = Component::new(
+            &engine,
+            r#"
+                (component
+                    (core module $m
+                        (func (export "f") (param i32 i32 i32 i32 i32 i32 i32) (result i32)
+                            i32.const 0))
+                    (core instance $i (instantiate $m))
+                    (type $rec' (record (field "a" u32) (field "b" bool)))
+                    (export $rec "rec" (type $rec'))
+                    (type $v' (variant (case "none") (case "some" u8)))
+                    (export $v "v" (type $v'))
+                    (type $f' (flags "read" "write"))
+                    (export $f "perms" (type $f'))
+                    (func (export "f") (param "r" $rec) (param "v" $v) (param "f" $f)
+                        (param "o" (result u8 (error s8))) (result u32)
+                        (canon lift (core func $i "f")))
+                )
+            "#,
+        )
+        .unwrap();
+        match component.component_type().get_export(&engine, "f") {
+            Some(ComponentItem::ComponentFunc(f)) => (
+                f.params().map(|(n, ty)| (n.to_string(), ty)).collect(),
+                f.results().collect(),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn mirrors_component_types() {
+        let (params, results) = signature();
+        let signature = FunctionSignature::new(&params, &results, &mut ResourceRegistry::new());
+        assert_eq!(
+            signature.to_string(),
+            "func(r: record { a: u32, b: bool }, v: variant { none, some(u8) }, \
+             f: flags { read, write }, o: result<u8, s8>) -> u32"
+        );
+        assert!(signature.matches(&params, &results));
+        assert!(!signature.matches(&params[1..], &results));
+
+        let mut other = signature.clone();
+        other.params[2].1 = SerializableType::Flags(vec!["read".to_string()]);
+        assert!(!other.matches(&params, &results));
+
+        let json = serde_json::to_string(&signature).unwrap();
+        assert_eq!(
+            serde_json::from_str::<FunctionSignature>(&json).unwrap(),
+            signature
+        );
+    }
+
+    #[test]
+    fn checks_values() {
+        use SerializableVal as V;
+
+        let (params, results) = signature();
+        let signature = FunctionSignature::new(&params, &results, &mut ResourceRegistry::new());
+        let record = V::Record(vec![
+            ("a".to_string(), V::U32(1)),
+            ("b".to_string(), V::Bool(true)),
+        ]);
+        let mut args = vec![
+            record,
+            V::Variant("some".to_string(), Some(Box::new(V::U8(2)))),
+            V::Flags(vec!["write".to_string()]),
+            V::Result(Err(Some(Box::new(V::S8(-1))))),
+        ];
+        assert_eq!(signature.check(&args, &[V::U32(0)]), Ok(()));
+        assert!(matches!(
+            signature.check(&args, &[]),
+            Err(ChainValueError::LengthMismatch { .. })
+        ));
+
+        args[1] = V::Variant("none".to_string(), Some(Box::new(V::U8(2))));
+        assert_eq!(
+            signature.check(&args, &[V::U32(0)]),
+            Err(ChainValueError::PayloadMismatch {
+                case: "none".to_string(),
+                expected: false,
+            })
+        );
+        args[1] = V::Variant("none".to_string(), None);
+        args[2] = V::Flags(vec!["exec".to_string()]);
+        assert_eq!(
+            signature.check(&args, &[V::U32(0)]),
+            Err(ChainValueError::UnknownFlag("exec".to_string()))
+        );
+
+        let resource = SerializableResource {
+            type_id: 1,
+            rep: 0,
+            owned: true,
+        };
+        assert_eq!(
+            SerializableType::Own(1).check(&V::Resource(resource)),
+            Ok(())
+        );
+        assert_eq!(
+            SerializableType::Borrow(1).check(&V::Resource(resource)),
+            Err(ChainValueError::ResourceTypeMismatch(resource))
+        );
+        assert_eq!(
+            SerializableType::List(Box::new(SerializableType::U8)).check(&V::U8(1)),
+            Err(ChainValueError::TypeMismatch {
+                expected: "list",
+                found: "u8",
+            })
+        );
+    }
+}
@@ -12,12 +12,176 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::component::ResourceAny;
-use crate::component::Val;
+use crate::chain::registry::{ResourceRegistry, SerializableResource};
+use crate::component::{ResourceAny, Type, Val};
 use crate::prelude::*;
-use serde::{Deserialize, Serialize};
+use core::cell::Cell;
+use core::fmt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+/// Why a value couldn't be converted to or from a [`SerializableVal`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainValueError {
+    /// A resource handle in a [`Val`] isn't registered with the
+    /// [`ResourceRegistry`] used for the conversion.
+    UnregisteredResource(ResourceAny),
+    /// A recorded resource name doesn't refer to a live handle.
+    UnknownResource(SerializableResource),
+    /// A recorded resource refers to a handle of a different type.
+    ResourceTypeMismatch(SerializableResource),
+    /// The value has a different shape than the type it's rebuilt as.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A record, tuple or parameter list has the wrong number of elements.
+    LengthMismatch {
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A record field is named differently than in the type.
+    FieldMismatch { expected: String, found: String },
+    /// A record lacks a field which its type has.
+    MissingField(String),
+    /// A record has a field which its type doesn't, or has it twice.
+    UnexpectedField(String),
+    /// A variant or enum case which the type doesn't have.
+    UnknownCase(String),
+    /// A flag which the type doesn't have.
+    UnknownFlag(String),
+    /// A variant or result case has a payload when its type has none, or the
+    /// other way around.
+    PayloadMismatch { case: String, expected: bool },
+    /// A core value of a type which can't be recorded, such as an `anyref`.
+    Unsupported(&'static str),
+    /// A recorded `funcref` or `externref` number which isn't registered
+    /// with the store's [`CoreRefRegistry`](crate::chain::CoreRefRegistry).
+    UnknownReference(&'static str, u32),
+    /// A deserialized value is nested more deeply than
+    /// [`ValLimits::max_depth`].
+    TooDeep { max_depth: usize },
+    /// A deserialized value is bigger than [`ValLimits::max_bytes`].
+    TooLarge { max_bytes: usize },
+}
+
+impl fmt::Display for ChainValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainValueError::UnregisteredResource(r) => {
+                write!(f, "resource {r:?} is not registered with the chain")
+            }
+            ChainValueError::UnknownResource(name) => {
+                write!(f, "resource {name:?} is not registered with the chain")
+            }
+            ChainValueError::ResourceTypeMismatch(name) => {
+                write!(f, "mismatched resource types for {name:?}")
+            }
+            ChainValueError::TypeMismatch { expected, found } => {
+                write!(f, "type mismatch: expected {expected}, found {found}")
+            }
+            ChainValueError::LengthMismatch {
+                what,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {what} with {expected} element(s), found {found}"
+            ),
+            ChainValueError::FieldMismatch { expected, found } => {
+                write!(f, "expected record field `{expected}`, found `{found}`")
+            }
+            ChainValueError::MissingField(name) => write!(f, "missing record field `{name}`"),
+            ChainValueError::UnexpectedField(name) => {
+                write!(f, "unexpected record field `{name}`")
+            }
+            ChainValueError::UnknownCase(name) => write!(f, "unknown case `{name}`"),
+            ChainValueError::UnknownFlag(name) => write!(f, "unknown flag `{name}`"),
+            ChainValueError::PayloadMismatch {
+                case,
+                expected: true,
+            } => write!(f, "case `{case}` requires a payload"),
+            ChainValueError::PayloadMismatch {
+                case,
+                expected: false,
+            } => write!(f, "case `{case}` has no payload"),
+            ChainValueError::Unsupported(ty) => {
+                write!(f, "values of type `{ty}` can't be recorded")
+            }
+            ChainValueError::UnknownReference(ty, id) => {
+                write!(f, "{ty} {id} is not registered with the chain")
+            }
+            ChainValueError::TooDeep { max_depth } => {
+                write!(f, "value is nested more than {max_depth} levels deep")
+            }
+            ChainValueError::TooLarge { max_bytes } => {
+                write!(f, "value takes up more than {max_bytes} bytes")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ChainValueError {}
+
+/// Limits on the [`SerializableVal`]s deserialized from chain events.
+///
+/// Values are recursive and events may come from untrusted parties, so
+/// without a limit a deeply nested value could overflow the stack while it's
+/// decoded. Limits apply per thread and default to [`ValLimits::default`];
+/// use [`ValLimits::scope`] to change them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValLimits {
+    /// How deeply values may be nested. Scalars have a depth of 1.
+    pub max_depth: usize,
+    /// An upper bound on the memory taken by a single top-level value,
+    /// counting each nested value and the bytes of every string in it.
+    pub max_bytes: usize,
+}
+
+impl ValLimits {
+    const DEFAULT: ValLimits = ValLimits {
+        max_depth: 32,
+        max_bytes: 16 << 20,
+    };
+
+    /// Runs `f` with these limits in place for values deserialized on the
+    /// current thread.
+    pub fn scope<R>(self, f: impl FnOnce() -> R) -> R {
+        struct Restore(ValLimits);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                LIMITS.set(self.0);
+            }
+        }
+        let _restore = Restore(LIMITS.replace(self));
+        f()
+    }
+}
+
+/// 32 levels and 16 MiB.
+impl Default for ValLimits {
+    fn default() -> ValLimits {
+        ValLimits::DEFAULT
+    }
+}
+
+std::thread_local! {
+    static LIMITS: Cell<ValLimits> = const { Cell::new(ValLimits::DEFAULT) };
+    /// Depth of the value being deserialized, and the bytes taken so far by
+    /// the top-level value it's part of.
+    static PROGRESS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// A serializable mirror of a component [`Val`].
+///
+/// This covers every case of [`Val`] this version of wasmtime has. The
+/// async component model's futures, streams and error-contexts aren't
+/// supported by the runtime yet, so there's nothing to record for them.
+//
+// The derived impls are inherent functions, see the `Deserialize` impl below
+// which wraps them to enforce `ValLimits`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum SerializableVal {
     Bool(bool),
     S8(i8),
@@ -28,8 +192,8 @@ pub enum SerializableVal {
     U32(u32),
     S64(i64),
     U64(u64),
-    Float32(f32),
-    Float64(f64),
+    Float32(#[serde(with = "float_bits")] f32),
+    Float64(#[serde(with = "float_bits")] f64),
     Char(char),
     String(String),
     List(Vec<SerializableVal>),
@@ -40,12 +204,24 @@ pub enum SerializableVal {
     Option(Option<Box<SerializableVal>>),
     Result(Result<Option<Box<SerializableVal>>, Option<Box<SerializableVal>>>),
     Flags(Vec<String>),
-    Resource(#[serde(with = "fthat")] ResourceAny), // lets get rid of dynamic typing who even
-                                                    // wants that its lame and stupid
+    Resource(SerializableResource),
 }
 
 impl SerializableVal {
-    pub fn from_val(val: &Val) -> Result<SerializableVal> {
+    /// Converts `val` without a resource registry, failing if it contains any
+    /// resources.
+    pub fn from_val(val: &Val) -> Result<SerializableVal, ChainValueError> {
+        Self::from_val_with(val, &ResourceRegistry::new())
+    }
+
+    /// Converts `val`, naming any resources it contains through `registry`.
+    ///
+    /// Returns an error if a resource inside `val` hasn't been registered.
+    pub fn from_val_with(
+        val: &Val,
+        registry: &ResourceRegistry,
+    ) -> Result<SerializableVal, ChainValueError> {
+        let from_val = |v: &Val| SerializableVal::from_val_with(v, registry);
         Ok(match val {
             Val::Bool(b) => SerializableVal::Bool(*b),
             Val::S8(n) => SerializableVal::S8(*n),
@@ -60,70 +236,712 @@ impl SerializableVal {
             Val::Float64(n) => SerializableVal::Float64(*n),
             Val::Char(c) => SerializableVal::Char(*c),
             Val::String(s) => SerializableVal::String(s.clone()),
-            Val::List(l) => SerializableVal::List(
-                l.iter()
-                    .map(SerializableVal::from_val)
-                    .collect::<Result<Vec<_>>>()?,
-            ),
+            Val::List(l) => {
+                SerializableVal::List(l.iter().map(from_val).collect::<Result<Vec<_>, _>>()?)
+            }
             Val::Record(r) => SerializableVal::Record(
                 r.iter()
-                    .map(|(k, v)| Ok((k.clone(), SerializableVal::from_val(v)?)))
-                    .collect::<Result<Vec<_>>>()?,
-            ),
-            Val::Tuple(t) => SerializableVal::Tuple(
-                t.iter()
-                    .map(SerializableVal::from_val)
-                    .collect::<Result<Vec<_>>>()?,
+                    .map(|(k, v)| Ok((k.clone(), from_val(v)?)))
+                    .collect::<Result<Vec<_>, _>>()?,
             ),
+            Val::Tuple(t) => {
+                SerializableVal::Tuple(t.iter().map(from_val).collect::<Result<Vec<_>, _>>()?)
+            }
             Val::Variant(name, val) => SerializableVal::Variant(
                 name.clone(),
                 val.as_ref()
-                    .map(|v| -> Result<Box<SerializableVal>> {
-                        Ok(Box::new(SerializableVal::from_val(v)?))
-                    })
+                    .map(|v| -> Result<_, ChainValueError> { Ok(Box::new(from_val(v)?)) })
                     .transpose()?,
             ),
             Val::Enum(e) => SerializableVal::Enum(e.clone()),
             Val::Option(o) => SerializableVal::Option(
                 o.as_ref()
-                    .map(|v| -> Result<Box<SerializableVal>> {
-                        Ok(Box::new(SerializableVal::from_val(v)?))
-                    })
+                    .map(|v| -> Result<_, ChainValueError> { Ok(Box::new(from_val(v)?)) })
                     .transpose()?,
             ),
             Val::Result(r) => SerializableVal::Result(match r {
                 Ok(v) => Ok(v
                     .as_ref()
-                    .map(|v| -> Result<Box<SerializableVal>> {
-                        Ok(Box::new(SerializableVal::from_val(v)?))
-                    })
+                    .map(|v| -> Result<_, ChainValueError> { Ok(Box::new(from_val(v)?)) })
                     .transpose()?),
                 Err(v) => Err(v
                     .as_ref()
-                    .map(|v| -> Result<Box<SerializableVal>> {
-                        Ok(Box::new(SerializableVal::from_val(v)?))
-                    })
+                    .map(|v| -> Result<_, ChainValueError> { Ok(Box::new(from_val(v)?)) })
                     .transpose()?),
             }),
             Val::Flags(f) => SerializableVal::Flags(f.clone()),
-            Val::Resource(_r) => {
-                panic!("AHHHHHH: Resource serialization not yet implemented")
-            }
+            Val::Resource(r) => match registry.lookup(r) {
+                Some(name) => SerializableVal::Resource(name),
+                None => return Err(ChainValueError::UnregisteredResource(*r)),
+            },
         })
     }
 
-    pub fn from_vals(vals: &[Val]) -> Result<Vec<SerializableVal>> {
+    pub fn from_vals(vals: &[Val]) -> Result<Vec<SerializableVal>, ChainValueError> {
         vals.iter().map(SerializableVal::from_val).collect()
     }
+
+    pub fn from_vals_with(
+        vals: &[Val],
+        registry: &ResourceRegistry,
+    ) -> Result<Vec<SerializableVal>, ChainValueError> {
+        vals.iter()
+            .map(|v| SerializableVal::from_val_with(v, registry))
+            .collect()
+    }
+
+    /// Rebuilds a component [`Val`] of type `ty` from this value, failing if
+    /// it contains any resources.
+    pub fn to_val(&self, ty: &Type) -> Result<Val, ChainValueError> {
+        self.to_val_with(ty, &ResourceRegistry::new())
+    }
+
+    /// Rebuilds a component [`Val`] of type `ty`, resolving resources through
+    /// `registry`.
+    ///
+    /// The shape of `self` is checked against `ty` along the way so a value
+    /// recorded against one component can't silently be passed to a function
+    /// with a different signature.
+    pub fn to_val_with(
+        &self,
+        ty: &Type,
+        registry: &ResourceRegistry,
+    ) -> Result<Val, ChainValueError> {
+        let boxed = |v: &SerializableVal, ty: &Type| -> Result<Box<Val>, ChainValueError> {
+            Ok(Box::new(v.to_val_with(ty, registry)?))
+        };
+        Ok(match (self, ty) {
+            (SerializableVal::Bool(b), Type::Bool) => Val::Bool(*b),
+            (SerializableVal::S8(n), Type::S8) => Val::S8(*n),
+            (SerializableVal::U8(n), Type::U8) => Val::U8(*n),
+            (SerializableVal::S16(n), Type::S16) => Val::S16(*n),
+            (SerializableVal::U16(n), Type::U16) => Val::U16(*n),
+            (SerializableVal::S32(n), Type::S32) => Val::S32(*n),
+            (SerializableVal::U32(n), Type::U32) => Val::U32(*n),
+            (SerializableVal::S64(n), Type::S64) => Val::S64(*n),
+            (SerializableVal::U64(n), Type::U64) => Val::U64(*n),
+            (SerializableVal::Float32(n), Type::Float32) => Val::Float32(*n),
+            (SerializableVal::Float64(n), Type::Float64) => Val::Float64(*n),
+            (SerializableVal::Char(c), Type::Char) => Val::Char(*c),
+            (SerializableVal::String(s), Type::String) => Val::String(s.clone()),
+            (SerializableVal::List(l), Type::List(list)) => {
+                let elem = list.ty();
+                Val::List(
+                    l.iter()
+                        .map(|v| v.to_val_with(&elem, registry))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            (SerializableVal::Record(r), Type::Record(record)) => {
+                let fields = record.fields();
+                if fields.len() != r.len() {
+                    return Err(ChainValueError::LengthMismatch {
+                        what: "record",
+                        expected: fields.len(),
+                        found: r.len(),
+                    });
+                }
+                Val::Record(
+                    r.iter()
+                        .zip(fields)
+                        .map(|((name, v), field)| {
+                            if name != field.name {
+                                return Err(ChainValueError::FieldMismatch {
+                                    expected: field.name.to_string(),
+                                    found: name.clone(),
+                                });
+                            }
+                            Ok((name.clone(), v.to_val_with(&field.ty, registry)?))
+                        })
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            (SerializableVal::Tuple(t), Type::Tuple(tuple)) => {
+                let types = tuple.types();
+                if types.len() != t.len() {
+                    return Err(ChainValueError::LengthMismatch {
+                        what: "tuple",
+                        expected: types.len(),
+                        found: t.len(),
+                    });
+                }
+                Val::Tuple(
+                    t.iter()
+                        .zip(types)
+                        .map(|(v, ty)| v.to_val_with(&ty, registry))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            (SerializableVal::Variant(name, payload), Type::Variant(variant)) => {
+                let case = match variant.cases().find(|c| c.name == name) {
+                    Some(case) => case,
+                    None => return Err(ChainValueError::UnknownCase(name.clone())),
+                };
+                let payload = match (payload, &case.ty) {
+                    (Some(v), Some(ty)) => Some(boxed(v, ty)?),
+                    (None, None) => None,
+                    (Some(_), None) | (None, Some(_)) => {
+                        return Err(ChainValueError::PayloadMismatch {
+                            case: name.clone(),
+                            expected: case.ty.is_some(),
+                        })
+                    }
+                };
+                Val::Variant(name.clone(), payload)
+            }
+            (SerializableVal::Enum(name), Type::Enum(e)) => {
+                if !e.names().any(|n| n == name) {
+                    return Err(ChainValueError::UnknownCase(name.clone()));
+                }
+                Val::Enum(name.clone())
+            }
+            (SerializableVal::Option(o), Type::Option(option)) => {
+                Val::Option(o.as_ref().map(|v| boxed(v, &option.ty())).transpose()?)
+            }
+            (SerializableVal::Result(r), Type::Result(result)) => {
+                let payload = |v: &Option<Box<SerializableVal>>, ty: Option<Type>, case: &str| {
+                    match (v, ty) {
+                        (Some(v), Some(ty)) => Ok(Some(boxed(v, &ty)?)),
+                        (None, None) => Ok(None),
+                        (v, _) => Err(ChainValueError::PayloadMismatch {
+                            case: case.to_string(),
+                            expected: v.is_none(),
+                        }),
+                    }
+                };
+                Val::Result(match r {
+                    Ok(v) => Ok(payload(v, result.ok(), "ok")?),
+                    Err(v) => Err(payload(v, result.err(), "err")?),
+                })
+            }
+            (SerializableVal::Flags(f), Type::Flags(flags)) => {
+                if let Some(unknown) = f.iter().find(|n| !flags.names().any(|f| f == *n)) {
+                    return Err(ChainValueError::UnknownFlag(unknown.clone()));
+                }
+                Val::Flags(f.clone())
+            }
+            (SerializableVal::Resource(name), Type::Own(ty) | Type::Borrow(ty)) => {
+                let resource = match registry.resolve(name) {
+                    Some(resource) => resource,
+                    None => return Err(ChainValueError::UnknownResource(*name)),
+                };
+                if resource.ty() != *ty {
+                    return Err(ChainValueError::ResourceTypeMismatch(*name));
+                }
+                Val::Resource(resource)
+            }
+            (val, ty) => {
+                return Err(ChainValueError::TypeMismatch {
+                    expected: ty.desc(),
+                    found: val.desc(),
+                })
+            }
+        })
+    }
+
+    /// Rebuilds a list of parameters or results, as produced by
+    /// [`SerializableVal::from_vals`], against the given types.
+    pub fn to_vals(vals: &[SerializableVal], tys: &[Type]) -> Result<Vec<Val>, ChainValueError> {
+        SerializableVal::to_vals_with(vals, tys, &ResourceRegistry::new())
+    }
+
+    /// Like [`SerializableVal::to_vals`] but resolves resources through
+    /// `registry`.
+    pub fn to_vals_with(
+        vals: &[SerializableVal],
+        tys: &[Type],
+        registry: &ResourceRegistry,
+    ) -> Result<Vec<Val>, ChainValueError> {
+        if vals.len() != tys.len() {
+            return Err(ChainValueError::LengthMismatch {
+                what: "value list",
+                expected: tys.len(),
+                found: vals.len(),
+            });
+        }
+        vals.iter()
+            .zip(tys)
+            .map(|(v, ty)| v.to_val_with(ty, registry))
+            .collect()
+    }
+
+    /// Checks that this value is of type `ty`, naming the path to the first
+    /// part which isn't, such as `$.user.tags[2]`.
+    ///
+    /// Unlike [`SerializableVal::to_val`] nothing is built and no resources
+    /// are resolved, so recorded arguments can be checked against a
+    /// component which may have changed since they were recorded before
+    /// they're replayed into it. Enum and variant cases, flags and record
+    /// fields are all checked by name, and resource handles are only checked
+    /// to be handles. The error wraps a [`ChainValueError`].
+    pub fn typecheck(&self, ty: &Type) -> Result<()> {
+        self.typecheck_at(ty, "$")
+    }
+
+    fn typecheck_at(&self, ty: &Type, path: &str) -> Result<()> {
+        use SerializableVal as V;
+
+        let fail = |e: ChainValueError| -> Result<()> {
+            Err(anyhow::Error::new(e).context(format!("value at `{path}` doesn't match its type")))
+        };
+        let payload = |v: &Option<Box<V>>, ty: Option<Type>, case: &str| match (v, ty) {
+            (Some(v), Some(ty)) => v.typecheck_at(&ty, &format!("{path}.{case}")),
+            (None, None) => Ok(()),
+            (v, _) => fail(ChainValueError::PayloadMismatch {
+                case: case.to_string(),
+                expected: v.is_none(),
+            }),
+        };
+        match (self, ty) {
+            (V::Bool(_), Type::Bool)
+            | (V::S8(_), Type::S8)
+            | (V::U8(_), Type::U8)
+            | (V::S16(_), Type::S16)
+            | (V::U16(_), Type::U16)
+            | (V::S32(_), Type::S32)
+            | (V::U32(_), Type::U32)
+            | (V::S64(_), Type::S64)
+            | (V::U64(_), Type::U64)
+            | (V::Float32(_), Type::Float32)
+            | (V::Float64(_), Type::Float64)
+            | (V::Char(_), Type::Char)
+            | (V::String(_), Type::String)
+            | (V::Resource(_), Type::Own(_) | Type::Borrow(_)) => Ok(()),
+            (V::List(vals), Type::List(list)) => {
+                let elem = list.ty();
+                vals.iter()
+                    .enumerate()
+                    .try_for_each(|(i, v)| v.typecheck_at(&elem, &format!("{path}[{i}]")))
+            }
+            (V::Record(vals), Type::Record(record)) => {
+                let fields = record.fields().collect::<Vec<_>>();
+                for (i, (name, _)) in vals.iter().enumerate() {
+                    if !fields.iter().any(|f| f.name == name)
+                        || vals[..i].iter().any(|(n, _)| n == name)
+                    {
+                        return fail(ChainValueError::UnexpectedField(name.clone()));
+                    }
+                }
+                if let Some(field) = fields
+                    .iter()
+                    .find(|f| !vals.iter().any(|(n, _)| n == f.name))
+                {
+                    return fail(ChainValueError::MissingField(field.name.to_string()));
+                }
+                for ((name, v), field) in vals.iter().zip(&fields) {
+                    if name != field.name {
+                        return fail(ChainValueError::FieldMismatch {
+                            expected: field.name.to_string(),
+                            found: name.clone(),
+                        });
+                    }
+                    v.typecheck_at(&field.ty, &format!("{path}.{name}"))?;
+                }
+                Ok(())
+            }
+            (V::Tuple(vals), Type::Tuple(tuple)) => {
+                let types = tuple.types();
+                if types.len() != vals.len() {
+                    return fail(ChainValueError::LengthMismatch {
+                        what: "tuple",
+                        expected: types.len(),
+                        found: vals.len(),
+                    });
+                }
+                vals.iter()
+                    .zip(types)
+                    .enumerate()
+                    .try_for_each(|(i, (v, ty))| v.typecheck_at(&ty, &format!("{path}[{i}]")))
+            }
+            (V::Variant(name, v), Type::Variant(variant)) => {
+                match variant.cases().find(|c| c.name == name) {
+                    Some(case) => payload(v, case.ty, name),
+                    None => fail(ChainValueError::UnknownCase(name.clone())),
+                }
+            }
+            (V::Enum(name), Type::Enum(e)) => {
+                if !e.names().any(|n| n == name) {
+                    return fail(ChainValueError::UnknownCase(name.clone()));
+                }
+                Ok(())
+            }
+            (V::Option(v), Type::Option(option)) => match v {
+                Some(v) => v.typecheck_at(&option.ty(), &format!("{path}.some")),
+                None => Ok(()),
+            },
+            (V::Result(r), Type::Result(result)) => match r {
+                Ok(v) => payload(v, result.ok(), "ok"),
+                Err(v) => payload(v, result.err(), "err"),
+            },
+            (V::Flags(flags), Type::Flags(ty)) => {
+                match flags.iter().find(|f| !ty.names().any(|n| n == *f)) {
+                    Some(unknown) => fail(ChainValueError::UnknownFlag(unknown.clone())),
+                    None => Ok(()),
+                }
+            }
+            (val, ty) => fail(ChainValueError::TypeMismatch {
+                expected: ty.desc(),
+                found: val.desc(),
+            }),
+        }
+    }
+
+    /// Encodes this value in a stable binary form suitable for hashing and
+    /// comparing across processes and machines.
+    ///
+    /// Each value is a one-byte tag, its variant's position in this enum
+    /// starting from 0, followed by its contents:
+    ///
+    /// * integers and `char`s as little-endian fixed-width integers, and
+    ///   `bool`s as a byte of 0 or 1;
+    /// * floats as their little-endian bit pattern, with every NaN written as
+    ///   the canonical quiet NaN and `-0.0` written as `0.0`, matching `Eq`;
+    /// * strings and names as a little-endian `u64` byte length followed by
+    ///   their UTF-8 bytes;
+    /// * lists and tuples as a `u64` element count followed by the elements;
+    /// * records as a `u64` field count followed by name/value pairs sorted
+    ///   by name, and flags as a sorted, deduplicated list of names;
+    /// * optional payloads as a byte of 0 or 1 followed by the payload, with
+    ///   results prefixed by 0 for `ok` and 1 for `err`;
+    /// * resources as their `type_id` and `rep` followed by `owned`.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_canonical(&mut bytes);
+        bytes
+    }
+
+    fn encode_canonical(&self, out: &mut Vec<u8>) {
+        fn len(out: &mut Vec<u8>, n: usize) {
+            out.extend_from_slice(&u64::try_from(n).unwrap().to_le_bytes());
+        }
+        fn string(out: &mut Vec<u8>, s: &str) {
+            len(out, s.len());
+            out.extend_from_slice(s.as_bytes());
+        }
+        fn payload(out: &mut Vec<u8>, v: &Option<Box<SerializableVal>>) {
+            match v {
+                Some(v) => {
+                    out.push(1);
+                    v.encode_canonical(out);
+                }
+                None => out.push(0),
+            }
+        }
+
+        let tag = match self {
+            SerializableVal::Bool(_) => 0,
+            SerializableVal::S8(_) => 1,
+            SerializableVal::U8(_) => 2,
+            SerializableVal::S16(_) => 3,
+            SerializableVal::U16(_) => 4,
+            SerializableVal::S32(_) => 5,
+            SerializableVal::U32(_) => 6,
+            SerializableVal::S64(_) => 7,
+            SerializableVal::U64(_) => 8,
+            SerializableVal::Float32(_) => 9,
+            SerializableVal::Float64(_) => 10,
+            SerializableVal::Char(_) => 11,
+            SerializableVal::String(_) => 12,
+            SerializableVal::List(_) => 13,
+            SerializableVal::Record(_) => 14,
+            SerializableVal::Tuple(_) => 15,
+            SerializableVal::Variant(..) => 16,
+            SerializableVal::Enum(_) => 17,
+            SerializableVal::Option(_) => 18,
+            SerializableVal::Result(_) => 19,
+            SerializableVal::Flags(_) => 20,
+            SerializableVal::Resource(_) => 21,
+        };
+        out.push(tag);
+        match self {
+            SerializableVal::Bool(b) => out.push(u8::from(*b)),
+            SerializableVal::S8(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::U8(n) => out.push(*n),
+            SerializableVal::S16(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::U16(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::S32(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::U32(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::S64(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::U64(n) => out.extend_from_slice(&n.to_le_bytes()),
+            SerializableVal::Float32(f) => {
+                let bits = if f.is_nan() {
+                    0x7fc0_0000
+                } else if *f == 0.0 {
+                    0
+                } else {
+                    f.to_bits()
+                };
+                out.extend_from_slice(&bits.to_le_bytes());
+            }
+            SerializableVal::Float64(f) => {
+                let bits = if f.is_nan() {
+                    0x7ff8_0000_0000_0000
+                } else if *f == 0.0 {
+                    0
+                } else {
+                    f.to_bits()
+                };
+                out.extend_from_slice(&bits.to_le_bytes());
+            }
+            SerializableVal::Char(c) => out.extend_from_slice(&u32::from(*c).to_le_bytes()),
+            SerializableVal::String(s) | SerializableVal::Enum(s) => string(out, s),
+            SerializableVal::List(vals) | SerializableVal::Tuple(vals) => {
+                len(out, vals.len());
+                vals.iter().for_each(|v| v.encode_canonical(out));
+            }
+            SerializableVal::Record(fields) => {
+                let mut fields = fields.iter().collect::<Vec<_>>();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                len(out, fields.len());
+                for (name, v) in fields {
+                    string(out, name);
+                    v.encode_canonical(out);
+                }
+            }
+            SerializableVal::Variant(name, v) => {
+                string(out, name);
+                payload(out, v);
+            }
+            SerializableVal::Option(v) => payload(out, v),
+            SerializableVal::Result(r) => match r {
+                Ok(v) => {
+                    out.push(0);
+                    payload(out, v);
+                }
+                Err(v) => {
+                    out.push(1);
+                    payload(out, v);
+                }
+            },
+            SerializableVal::Flags(flags) => {
+                let mut flags = flags.iter().collect::<Vec<_>>();
+                flags.sort();
+                flags.dedup();
+                len(out, flags.len());
+                flags.into_iter().for_each(|f| string(out, f));
+            }
+            SerializableVal::Resource(r) => {
+                out.extend_from_slice(&r.type_id.to_le_bytes());
+                out.extend_from_slice(&r.rep.to_le_bytes());
+                out.push(u8::from(r.owned));
+            }
+        }
+    }
+
+    pub(crate) fn desc(&self) -> &'static str {
+        match self {
+            SerializableVal::Bool(_) => "bool",
+            SerializableVal::S8(_) => "s8",
+            SerializableVal::U8(_) => "u8",
+            SerializableVal::S16(_) => "s16",
+            SerializableVal::U16(_) => "u16",
+            SerializableVal::S32(_) => "s32",
+            SerializableVal::U32(_) => "u32",
+            SerializableVal::S64(_) => "s64",
+            SerializableVal::U64(_) => "u64",
+            SerializableVal::Float32(_) => "float32",
+            SerializableVal::Float64(_) => "float64",
+            SerializableVal::Char(_) => "char",
+            SerializableVal::String(_) => "string",
+            SerializableVal::List(_) => "list",
+            SerializableVal::Record(_) => "record",
+            SerializableVal::Tuple(_) => "tuple",
+            SerializableVal::Variant(..) => "variant",
+            SerializableVal::Enum(_) => "enum",
+            SerializableVal::Option(_) => "option",
+            SerializableVal::Result(_) => "result",
+            SerializableVal::Flags(_) => "flags",
+            SerializableVal::Resource(_) => "resource",
+        }
+    }
+}
+
+impl Serialize for SerializableVal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializableVal::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SerializableVal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let limits = LIMITS.get();
+        let (depth, bytes) = PROGRESS.get();
+        if depth >= limits.max_depth {
+            return Err(de::Error::custom(ChainValueError::TooDeep {
+                max_depth: limits.max_depth,
+            }));
+        }
+        // Nested values count towards the size of the outermost one.
+        let bytes = if depth == 0 { 0 } else { bytes };
+        PROGRESS.set((depth + 1, bytes));
+        let val = SerializableVal::deserialize(deserializer);
+        let (_, bytes) = PROGRESS.get();
+        PROGRESS.set((depth, bytes));
+        let val = val?;
+
+        let bytes = bytes + val.shallow_size();
+        if bytes > limits.max_bytes {
+            return Err(de::Error::custom(ChainValueError::TooLarge {
+                max_bytes: limits.max_bytes,
+            }));
+        }
+        PROGRESS.set((depth, bytes));
+        Ok(val)
+    }
+}
+
+/// Floats are written as their bit patterns in human-readable formats, such
+/// as `"0x7fc00000"`, since JSON has no NaNs and decimals lose NaN payloads,
+/// so payloads re-encode to the same bytes and hash the same. Decimals, and
+/// the `null` older versions wrote for NaNs, are still read.
+mod float_bits {
+    use crate::prelude::*;
+    use core::fmt;
+    use core::marker::PhantomData;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub trait Float: Copy + Serialize + for<'de> Deserialize<'de> {
+        /// The name of the type, for errors.
+        const NAME: &'static str;
+        fn to_hex(self) -> String;
+        fn from_hex(hex: &str) -> Option<Self>;
+        fn from_f64(x: f64) -> Self;
+    }
+
+    impl Float for f32 {
+        const NAME: &'static str = "float32";
+        fn to_hex(self) -> String {
+            format!("{:#010x}", self.to_bits())
+        }
+        fn from_hex(hex: &str) -> Option<f32> {
+            u32::from_str_radix(hex, 16).ok().map(f32::from_bits)
+        }
+        #[allow(clippy::cast_possible_truncation)] // rounding is intended
+        fn from_f64(x: f64) -> f32 {
+            x as f32
+        }
+    }
+
+    impl Float for f64 {
+        const NAME: &'static str = "float64";
+        fn to_hex(self) -> String {
+            format!("{:#018x}", self.to_bits())
+        }
+        fn from_hex(hex: &str) -> Option<f64> {
+            u64::from_str_radix(hex, 16).ok().map(f64::from_bits)
+        }
+        fn from_f64(x: f64) -> f64 {
+            x
+        }
+    }
+
+    pub fn serialize<F: Float, S: Serializer>(x: &F, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&x.to_hex())
+        } else {
+            x.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, F: Float, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<F, D::Error> {
+        if !deserializer.is_human_readable() {
+            return F::deserialize(deserializer);
+        }
+
+        struct Visitor<F>(PhantomData<F>);
+
+        impl<'de, F: Float> de::Visitor<'de> for Visitor<F> {
+            type Value = F;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a {} bit pattern or number", F::NAME)
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<F, E> {
+                s.strip_prefix("0x")
+                    .and_then(F::from_hex)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))
+            }
+
+            fn visit_f64<E: de::Error>(self, x: f64) -> Result<F, E> {
+                Ok(F::from_f64(x))
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<F, E> {
+                Ok(F::from_f64(n as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<F, E> {
+                Ok(F::from_f64(n as f64))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<F, E> {
+                Ok(F::from_f64(f64::NAN))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor(PhantomData))
+    }
+}
+
+impl SerializableVal {
+    /// The memory taken by this value excluding nested values, as counted
+    /// against [`ValLimits::max_bytes`].
+    fn shallow_size(&self) -> usize {
+        let strings = match self {
+            SerializableVal::String(s) | SerializableVal::Enum(s) => s.len(),
+            SerializableVal::Variant(name, _) => name.len(),
+            SerializableVal::Record(fields) => fields.iter().map(|(name, _)| name.len()).sum(),
+            SerializableVal::Flags(flags) => flags.iter().map(|f| f.len()).sum(),
+            _ => 0,
+        };
+        core::mem::size_of::<SerializableVal>() + strings
+    }
+}
+
+/// Floats compare the way they hash: every NaN equals every other NaN and
+/// `-0.0` equals `0.0`, which makes this a proper equivalence relation.
+impl PartialEq for SerializableVal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Float32(a), Self::Float32(b)) => (a.is_nan() && b.is_nan()) || a == b,
+            (Self::Float64(a), Self::Float64(b)) => (a.is_nan() && b.is_nan()) || a == b,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::S8(a), Self::S8(b)) => a == b,
+            (Self::U8(a), Self::U8(b)) => a == b,
+            (Self::S16(a), Self::S16(b)) => a == b,
+            (Self::U16(a), Self::U16(b)) => a == b,
+            (Self::S32(a), Self::S32(b)) => a == b,
+            (Self::U32(a), Self::U32(b)) => a == b,
+            (Self::S64(a), Self::S64(b)) => a == b,
+            (Self::U64(a), Self::U64(b)) => a == b,
+            (Self::Char(a), Self::Char(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Record(a), Self::Record(b)) => a == b,
+            (Self::Tuple(a), Self::Tuple(b)) => a == b,
+            (Self::Variant(a, x), Self::Variant(b, y)) => a == b && x == y,
+            (Self::Enum(a), Self::Enum(b)) => a == b,
+            (Self::Option(a), Self::Option(b)) => a == b,
+            (Self::Result(a), Self::Result(b)) => a == b,
+            (Self::Flags(a), Self::Flags(b)) => a == b,
+            (Self::Resource(a), Self::Resource(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
+impl Eq for SerializableVal {}
+
 impl std::hash::Hash for SerializableVal {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         // First hash the discriminant to differentiate between variants
         std::mem::discriminant(self).hash(state);
 
         match self {
-            // For floats, we need special handling to match potential PartialEq implementation
+            // For floats, we need special handling to match the PartialEq implementation
             // where NaN == NaN and -0.0 == 0.0
             Self::Float32(f) => {
                 if f.is_nan() {
@@ -170,26 +988,328 @@ impl std::hash::Hash for SerializableVal {
             Self::Option(v) => v.hash(state),
             Self::Result(v) => v.hash(state),
             Self::Flags(v) => v.hash(state),
-            Self::Resource(_) => panic!("AHHHHHH: Resource serialization not yet implemented"),
+            Self::Resource(v) => v.hash(state),
         }
     }
 }
-mod fthat {
 
-    use crate::component::ResourceAny;
-    use serde::{Deserializer, Serializer};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::types::ComponentItem;
+    use crate::component::Component;
+    use crate::Engine;
+
+    fn param_types() -> Vec<Type> {
+        let engine = Engine::default();
+        let component = Component::new(
+            &engine,
+            r#"
+                (component
+                    (core module $m (func (export "f") (param i32 i32 i32 i32 i32)))
+                    (core instance $i (instantiate $m))
+                    (type $rec' (record (field "a" u32) (field "b" bool)))
+                    (export $rec "rec" (type $rec'))
+                    (type $e' (enum "x" "y"))
+                    (export $e "e" (type $e'))
+                    (func (export "f") (param "r" $rec) (param "e" $e) (param "o" (option u8))
+                        (canon lift (core func $i "f")))
+                )
+            "#,
+        )
+        .unwrap();
+        match component.component_type().get_export(&engine, "f") {
+            Some(ComponentItem::ComponentFunc(f)) => f.params().map(|(_, ty)| ty).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn to_val_round_trip() -> Result<()> {
+        let tys = param_types();
+        let vals = [
+            Val::Record(vec![
+                ("a".to_string(), Val::U32(1)),
+                ("b".to_string(), Val::Bool(true)),
+            ]),
+            Val::Enum("y".to_string()),
+            Val::Option(Some(Box::new(Val::U8(3)))),
+        ];
+        let serialized = SerializableVal::from_vals(&vals)?;
+        let rebuilt = SerializableVal::to_vals(&serialized, &tys)?;
+        assert_eq!(rebuilt, vals);
+        Ok(())
+    }
+
+    #[test]
+    fn floats_keep_their_bits_in_json() -> Result<()> {
+        let nan = f32::from_bits(0x7fc0_0001);
+        let vals = SerializableVal::Tuple(vec![
+            SerializableVal::Float32(nan),
+            SerializableVal::Float64(-0.0),
+            SerializableVal::Float64(f64::from_bits(0xfff8_0000_0000_0002)),
+        ]);
+        let json = serde_json::to_string(&vals)?;
+        assert_eq!(
+            json,
+            r#"{"Tuple":[{"Float32":"0x7fc00001"},{"Float64":"0x8000000000000000"},{"Float64":"0xfff8000000000002"}]}"#
+        );
+        let decoded = serde_json::from_str::<SerializableVal>(&json)?;
+        assert_eq!(serde_json::to_string(&decoded)?, json);
+
+        // Values recorded as decimals, with NaNs as `null`, are still read.
+        let old = r#"{"Tuple":[{"Float32":1.5},{"Float64":null},{"Float64":2}]}"#;
+        match serde_json::from_str::<SerializableVal>(old)? {
+            SerializableVal::Tuple(vals) => assert!(matches!(
+                vals[..],
+                [
+                    SerializableVal::Float32(a),
+                    SerializableVal::Float64(b),
+                    SerializableVal::Float64(c),
+                ] if a == 1.5 && b.is_nan() && c == 2.0
+            )),
+            val => panic!("unexpected {val:?}"),
+        }
+        assert!(serde_json::from_str::<SerializableVal>(r#"{"Float32":"1.5"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eq_matches_hash() {
+        use std::collections::HashSet;
+        use std::hash::{BuildHasher, RandomState};
+
+        let state = RandomState::new();
+        let pairs = [
+            (
+                SerializableVal::Float32(f32::NAN),
+                SerializableVal::Float32(-f32::NAN),
+            ),
+            (
+                SerializableVal::Float32(0.0),
+                SerializableVal::Float32(-0.0),
+            ),
+            (
+                SerializableVal::Float64(f64::NAN),
+                SerializableVal::Float64(f64::NAN),
+            ),
+            (
+                SerializableVal::Float64(-0.0),
+                SerializableVal::Float64(0.0),
+            ),
+            (
+                SerializableVal::List(vec![SerializableVal::Float64(f64::NAN)]),
+                SerializableVal::List(vec![SerializableVal::Float64(-f64::NAN)]),
+            ),
+        ];
+        for (a, b) in &pairs {
+            assert_eq!(a, b);
+            assert_eq!(state.hash_one(a), state.hash_one(b));
+        }
+
+        assert_ne!(SerializableVal::Float32(1.0), SerializableVal::Float64(1.0));
+        assert_ne!(SerializableVal::U8(1), SerializableVal::S8(1));
+        assert_ne!(
+            SerializableVal::Float64(f64::NAN),
+            SerializableVal::Float64(0.0)
+        );
+
+        let set = pairs
+            .into_iter()
+            .flat_map(|(a, b)| [a, b])
+            .collect::<HashSet<_>>();
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    fn to_val_type_mismatch() {
+        let tys = param_types();
+        assert_eq!(
+            SerializableVal::U32(1).to_val(&tys[0]),
+            Err(ChainValueError::TypeMismatch {
+                expected: "record",
+                found: "u32",
+            })
+        );
+        assert!(SerializableVal::Enum("z".to_string())
+            .to_val(&tys[1])
+            .is_err());
+        let wrong_field = SerializableVal::Record(vec![
+            ("a".to_string(), SerializableVal::U32(1)),
+            ("c".to_string(), SerializableVal::Bool(true)),
+        ]);
+        assert_eq!(
+            wrong_field.to_val(&tys[0]),
+            Err(ChainValueError::FieldMismatch {
+                expected: "b".to_string(),
+                found: "c".to_string(),
+            })
+        );
+        assert!(SerializableVal::Option(None).to_val(&tys[2]).is_ok());
+    }
+
+    #[test]
+    fn typecheck() {
+        let tys = param_types();
+        let record = |fields: &[&str]| {
+            SerializableVal::Record(
+                fields
+                    .iter()
+                    .map(|name| (name.to_string(), SerializableVal::Bool(true)))
+                    .collect(),
+            )
+        };
+        let error = |val: SerializableVal, ty: &Type| {
+            let err = val.typecheck(ty).unwrap_err();
+            (
+                err.to_string(),
+                err.downcast_ref::<ChainValueError>().unwrap().clone(),
+            )
+        };
+
+        let ok = SerializableVal::Record(vec![
+            ("a".to_string(), SerializableVal::U32(1)),
+            ("b".to_string(), SerializableVal::Bool(true)),
+        ]);
+        assert!(ok.typecheck(&tys[0]).is_ok());
+        assert_eq!(
+            error(record(&["a", "b"]), &tys[0]),
+            (
+                "value at `$.a` doesn't match its type".to_string(),
+                ChainValueError::TypeMismatch {
+                    expected: "u32",
+                    found: "bool",
+                }
+            )
+        );
+        assert_eq!(
+            error(record(&["a"]), &tys[0]).1,
+            ChainValueError::MissingField("b".to_string())
+        );
+        assert_eq!(
+            error(record(&["a", "b", "a"]), &tys[0]).1,
+            ChainValueError::UnexpectedField("a".to_string())
+        );
+        assert_eq!(
+            error(record(&["b", "a"]), &tys[0]).1,
+            ChainValueError::FieldMismatch {
+                expected: "a".to_string(),
+                found: "b".to_string(),
+            }
+        );
 
-    pub fn serialize<S>(_resource: &ResourceAny, _serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        unimplemented!("AHHHHHH: Resource serialization not yet implemented")
+        assert!(SerializableVal::Enum("x".to_string())
+            .typecheck(&tys[1])
+            .is_ok());
+        assert_eq!(
+            error(SerializableVal::Enum("z".to_string()), &tys[1]).1,
+            ChainValueError::UnknownCase("z".to_string())
+        );
+        let some = |v| SerializableVal::Option(Some(Box::new(v)));
+        assert!(some(SerializableVal::U8(1)).typecheck(&tys[2]).is_ok());
+        assert_eq!(
+            error(some(SerializableVal::S8(1)), &tys[2]).0,
+            "value at `$.some` doesn't match its type"
+        );
     }
 
-    pub fn deserialize<'de, D>(_deserializer: D) -> Result<ResourceAny, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        unimplemented!("AHHHHHH: Resource deserialization not yet implemented")
+    #[test]
+    fn deserialize_limits() -> Result<()> {
+        let nested = |depth: usize| {
+            let mut json = r#"{"U8":1}"#.to_string();
+            for _ in 1..depth {
+                json = format!(r#"{{"List":[{json}]}}"#);
+            }
+            json
+        };
+        let ok = serde_json::from_str::<SerializableVal>(&nested(32))?;
+        assert!(matches!(ok, SerializableVal::List(_)));
+        let err = serde_json::from_str::<SerializableVal>(&nested(33)).unwrap_err();
+        assert!(err.to_string().contains("nested more than 32 levels"));
+
+        let strict = ValLimits {
+            max_depth: 2,
+            max_bytes: 1024,
+        };
+        strict.scope(|| {
+            assert!(serde_json::from_str::<SerializableVal>(&nested(2)).is_ok());
+            assert!(serde_json::from_str::<SerializableVal>(&nested(3)).is_err());
+            let big = format!(r#"{{"String":"{}"}}"#, "x".repeat(1024));
+            let err = serde_json::from_str::<SerializableVal>(&big).unwrap_err();
+            assert!(err.to_string().contains("more than 1024 bytes"));
+        });
+        assert!(serde_json::from_str::<SerializableVal>(&nested(3)).is_ok());
+
+        // Sibling values add up.
+        let list = format!(r#"{{"List":[{}]}}"#, vec![r#"{"U8":1}"#; 100].join(","));
+        ValLimits {
+            max_depth: 2,
+            max_bytes: 50 * core::mem::size_of::<SerializableVal>(),
+        }
+        .scope(|| assert!(serde_json::from_str::<SerializableVal>(&list).is_err()));
+        assert_eq!(
+            serde_json::to_string(&serde_json::from_str::<SerializableVal>(&list)?)?,
+            list
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn canonical_bytes() {
+        let record = |fields: &[(&str, SerializableVal)]| {
+            SerializableVal::Record(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect(),
+            )
+        };
+        let a = record(&[
+            ("b", SerializableVal::Float64(-0.0)),
+            ("a", SerializableVal::Float32(f32::NAN)),
+        ]);
+        let b = record(&[
+            ("a", SerializableVal::Float32(-f32::NAN)),
+            ("b", SerializableVal::Float64(0.0)),
+        ]);
+        assert_eq!(a.canonical_bytes(), b.canonical_bytes());
+
+        // The encoding is specified, so it must never change.
+        assert_eq!(
+            SerializableVal::Option(Some(Box::new(SerializableVal::String("hi".to_string()))))
+                .canonical_bytes(),
+            [18, 1, 12, 2, 0, 0, 0, 0, 0, 0, 0, b'h', b'i']
+        );
+        assert_eq!(SerializableVal::U16(0x0102).canonical_bytes(), [4, 2, 1]);
+
+        assert_ne!(
+            SerializableVal::U8(1).canonical_bytes(),
+            SerializableVal::S8(1).canonical_bytes()
+        );
+        assert_ne!(
+            SerializableVal::List(vec![SerializableVal::U8(1)]).canonical_bytes(),
+            SerializableVal::Tuple(vec![SerializableVal::U8(1)]).canonical_bytes()
+        );
+        let flags = |f: &[&str]| SerializableVal::Flags(f.iter().map(|s| s.to_string()).collect());
+        assert_eq!(
+            flags(&["x", "y"]).canonical_bytes(),
+            flags(&["y", "x"]).canonical_bytes()
+        );
+    }
+
+    #[test]
+    fn resources_are_errors() -> Result<()> {
+        use crate::component::Resource;
+        use crate::Store;
+
+        struct Dummy;
+        let mut store = Store::<()>::default();
+        let r = ResourceAny::try_from_resource(Resource::<Dummy>::new_own(1), &mut store)?;
+        assert_eq!(
+            SerializableVal::from_val(&Val::Resource(r)),
+            Err(ChainValueError::UnregisteredResource(r))
+        );
+        assert!(serde_json::to_string(&Val::Resource(r)).is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,71 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::chain::Digest;
+use crate::prelude::*;
+use core::fmt;
+
+/// The first problem found by [`Chain::verify`](crate::chain::Chain::verify).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityError {
+    /// Position in the chain of the offending event.
+    pub index: usize,
+    pub kind: IntegrityErrorKind,
+    /// The hash the chain expected at this point: the previous event's hash
+    /// for a broken link, or the recomputed hash for a mismatch.
+    pub expected: Option<Digest>,
+    /// The hash actually found: the event's recorded parent for a broken
+    /// link, or its stored hash for a mismatch.
+    pub found: Option<Digest>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntegrityErrorKind {
+    /// The event's parent pointer doesn't name the event before it.
+    BrokenLink,
+    /// The event's stored hash doesn't match its contents.
+    HashMismatch,
+    /// The event has no signature.
+    Unsigned,
+    /// The event's signature doesn't verify against the expected key.
+    BadSignature,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |d: &Option<Digest>| match d {
+            Some(d) => d.to_hex(),
+            None => "none".to_string(),
+        };
+        let what = match self.kind {
+            IntegrityErrorKind::BrokenLink => "has a broken parent link",
+            IntegrityErrorKind::HashMismatch => "does not match its hash",
+            IntegrityErrorKind::Unsigned => {
+                return write!(f, "chain event {} is not signed", self.index);
+            }
+            IntegrityErrorKind::BadSignature => {
+                return write!(f, "chain event {} has an invalid signature", self.index);
+            }
+        };
+        write!(
+            f,
+            "chain event {} {what}: expected {}, found {}",
+            self.index,
+            show(&self.expected),
+            show(&self.found),
+        )
+    }
+}
+
+impl core::error::Error for IntegrityError {}
@@ -0,0 +1,136 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Choosing which WASI interfaces are recorded.
+//!
+//! Calls to WASI imports are only recorded for the packages a
+//! [`ChainWasiPolicy`] enables, see
+//! [`Config::chain_wasi_policy`](crate::Config::chain_wasi_policy). Clocks
+//! and randomness are all a replay needs to be deterministic and are cheap
+//! to record, while recording every filesystem read or socket write of a
+//! busy workload can cost more than the work itself, so a policy usually
+//! enables the former and leaves the latter out.
+
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// Which WASI packages have their calls recorded, see the
+/// [module documentation](crate::chain::wasi).
+///
+/// Packages are named without their `wasi:` namespace and version, such as
+/// `clocks` for `wasi:clocks/wall-clock@0.2.0`. Packages without a setting
+/// of their own follow the policy's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainWasiPolicy {
+    default: bool,
+    packages: BTreeMap<String, bool>,
+}
+
+impl ChainWasiPolicy {
+    /// A policy recording every WASI package.
+    pub fn all() -> ChainWasiPolicy {
+        ChainWasiPolicy {
+            default: true,
+            packages: BTreeMap::new(),
+        }
+    }
+
+    /// A policy recording no WASI package, the default.
+    pub fn none() -> ChainWasiPolicy {
+        ChainWasiPolicy::default()
+    }
+
+    /// Configures whether calls to the WASI package `package`, such as
+    /// `clocks` or `filesystem`, are recorded.
+    pub fn package(mut self, package: &str, enable: bool) -> ChainWasiPolicy {
+        self.packages.insert(package.to_string(), enable);
+        self
+    }
+
+    /// Configures whether calls to `wasi:clocks` are recorded.
+    pub fn clocks(self, enable: bool) -> ChainWasiPolicy {
+        self.package("clocks", enable)
+    }
+
+    /// Configures whether calls to `wasi:random` are recorded.
+    pub fn random(self, enable: bool) -> ChainWasiPolicy {
+        self.package("random", enable)
+    }
+
+    /// Configures whether calls to `wasi:filesystem` are recorded.
+    pub fn filesystem(self, enable: bool) -> ChainWasiPolicy {
+        self.package("filesystem", enable)
+    }
+
+    /// Configures whether calls to `wasi:sockets` are recorded.
+    pub fn sockets(self, enable: bool) -> ChainWasiPolicy {
+        self.package("sockets", enable)
+    }
+
+    /// Configures whether calls to `wasi:cli` are recorded.
+    pub fn cli(self, enable: bool) -> ChainWasiPolicy {
+        self.package("cli", enable)
+    }
+
+    /// Configures whether calls to `wasi:io` are recorded.
+    pub fn io(self, enable: bool) -> ChainWasiPolicy {
+        self.package("io", enable)
+    }
+
+    /// Configures whether calls to `wasi:http` are recorded.
+    pub fn http(self, enable: bool) -> ChainWasiPolicy {
+        self.package("http", enable)
+    }
+
+    /// Whether calls to the WASI package `package` are recorded.
+    pub fn records_package(&self, package: &str) -> bool {
+        self.packages.get(package).copied().unwrap_or(self.default)
+    }
+
+    /// Whether calls to the WASI import recorded as `name`, such as
+    /// `wasi:clocks/wall-clock@0.2.0#now`, are recorded.
+    pub fn records(&self, name: &str) -> bool {
+        match package(name) {
+            Some(package) => self.records_package(package),
+            None => self.default,
+        }
+    }
+}
+
+/// The package of the WASI import recorded as `name`, if it's one.
+pub fn package(name: &str) -> Option<&str> {
+    let name = name.strip_prefix("wasi:")?;
+    let end = name.find(['/', '@', '#']).unwrap_or(name.len());
+    Some(&name[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_chosen_packages() {
+        let policy = ChainWasiPolicy::all().filesystem(false).sockets(false);
+        assert!(policy.records("wasi:clocks/wall-clock@0.2.0#now"));
+        assert!(policy.records("wasi:random/random@0.2.0#get-random-u64"));
+        assert!(!policy.records("wasi:filesystem/types@0.2.0#[method]descriptor.read"));
+
+        let policy = ChainWasiPolicy::none().clocks(true).random(true);
+        assert!(policy.records("wasi:clocks/monotonic-clock@0.2.0#now"));
+        assert!(!policy.records("wasi:cli/stdout@0.2.0#get-stdout"));
+        assert!(!ChainWasiPolicy::none().records("wasi:random/random#get-random-u64"));
+        assert_eq!(package("host#next"), None);
+    }
+}
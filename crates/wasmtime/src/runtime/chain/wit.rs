@@ -0,0 +1,347 @@
+// Copyright 2024 Colin Rozzi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Printing recorded values in WIT value syntax.
+//!
+//! [`SerializableVal`]'s `Display` writes values the way WIT tooling such as
+//! `wasm-tools` and `wasmtime run --invoke` does: records as
+//! `{name: "x", count: 1}`, lists as `[1, 2]`, tuples as `(1, "a")`, flags
+//! as `{read, write}`, options as `some(1)` or `none`, results as `ok(1)` or
+//! `err("boom")`, and variant and enum cases by name. Names clashing with a
+//! keyword, such as a case called `ok`, are prefixed with `%`. Resources,
+//! which have no value syntax, are written as `own<T>#R` or `borrow<T>#R`
+//! for a handle of type id `T` and representation `R`.
+//!
+//! A [`WitFormat`] can cut long strings and lists and deep nesting short for
+//! log output, marking what was left out with `...`. The alternate form,
+//! `{:#}`, uses [`WitFormat::SHORT`].
+
+use crate::chain::SerializableVal;
+use crate::prelude::*;
+use core::fmt;
+
+/// How much of a value [`SerializableVal::to_wit_string_with`] writes, see
+/// the [module documentation](crate::chain::wit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WitFormat {
+    /// How many elements of a list, tuple, record or flags are written
+    /// before the rest are left out.
+    pub max_items: Option<usize>,
+    /// How many characters of a string are written before the rest are
+    /// left out.
+    pub max_chars: Option<usize>,
+    /// How deeply values nest before nested lists, tuples, records and
+    /// payloads are left out.
+    pub max_depth: Option<usize>,
+}
+
+impl WitFormat {
+    /// Writes values in full.
+    pub const FULL: WitFormat = WitFormat {
+        max_items: None,
+        max_chars: None,
+        max_depth: None,
+    };
+
+    /// Keeps values to about a line, for logs: 8 items, 64 characters and
+    /// 4 levels of nesting.
+    pub const SHORT: WitFormat = WitFormat {
+        max_items: Some(8),
+        max_chars: Some(64),
+        max_depth: Some(4),
+    };
+}
+
+/// Writes values in full.
+impl Default for WitFormat {
+    fn default() -> WitFormat {
+        WitFormat::FULL
+    }
+}
+
+impl SerializableVal {
+    /// Writes this value in WIT value syntax, in full.
+    pub fn to_wit_string(&self) -> String {
+        self.to_wit_string_with(WitFormat::FULL)
+    }
+
+    /// Writes this value in WIT value syntax, leaving out what `format`
+    /// says to.
+    pub fn to_wit_string_with(&self, format: WitFormat) -> String {
+        Wit {
+            val: self,
+            format,
+            depth: 0,
+        }
+        .to_string()
+    }
+}
+
+/// Writes this value in WIT value syntax, in full, or shortened with
+/// [`WitFormat::SHORT`] in the alternate form.
+impl fmt::Display for SerializableVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = if f.alternate() {
+            WitFormat::SHORT
+        } else {
+            WitFormat::FULL
+        };
+        fmt::Display::fmt(
+            &Wit {
+                val: self,
+                format,
+                depth: 0,
+            },
+            f,
+        )
+    }
+}
+
+/// A value being written at nesting level `depth`.
+struct Wit<'a> {
+    val: &'a SerializableVal,
+    format: WitFormat,
+    depth: usize,
+}
+
+impl Wit<'_> {
+    fn nested<'b>(&self, val: &'b SerializableVal) -> Wit<'b> {
+        Wit {
+            val,
+            format: self.format,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Writes `items` with `item`, between `open` and `close`.
+    fn seq<T>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        open: &str,
+        close: &str,
+        items: &[T],
+        mut item: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+    ) -> fmt::Result {
+        f.write_str(open)?;
+        if self.format.max_depth.is_some_and(|max| self.depth >= max) && !items.is_empty() {
+            f.write_str("...")?;
+            return f.write_str(close);
+        }
+        let shown = self.format.max_items.unwrap_or(usize::MAX).min(items.len());
+        for (i, x) in items[..shown].iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            item(f, x)?;
+        }
+        if shown < items.len() {
+            if shown > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "...{} more", items.len() - shown)?;
+        }
+        f.write_str(close)
+    }
+
+    /// Writes the payload of a case after its name, in parentheses if
+    /// there is one.
+    fn payload(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        payload: Option<&SerializableVal>,
+    ) -> fmt::Result {
+        match payload {
+            Some(payload) => self.seq(f, "(", ")", &[payload], |f, v| {
+                fmt::Display::fmt(&self.nested(v), f)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Wit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SerializableVal as V;
+
+        match self.val {
+            V::Bool(b) => write!(f, "{b}"),
+            V::S8(n) => write!(f, "{n}"),
+            V::U8(n) => write!(f, "{n}"),
+            V::S16(n) => write!(f, "{n}"),
+            V::U16(n) => write!(f, "{n}"),
+            V::S32(n) => write!(f, "{n}"),
+            V::U32(n) => write!(f, "{n}"),
+            V::S64(n) => write!(f, "{n}"),
+            V::U64(n) => write!(f, "{n}"),
+            V::Float32(x) => float(f, f64::from(*x), &x.to_string()),
+            V::Float64(x) => float(f, *x, &x.to_string()),
+            V::Char(c) => {
+                f.write_str("'")?;
+                escape(f, *c, '\'')?;
+                f.write_str("'")
+            }
+            V::String(s) => {
+                f.write_str("\"")?;
+                let max = self.format.max_chars.unwrap_or(usize::MAX);
+                let mut chars = s.chars();
+                for c in chars.by_ref().take(max) {
+                    escape(f, c, '"')?;
+                }
+                f.write_str("\"")?;
+                match chars.count() {
+                    0 => Ok(()),
+                    rest => write!(f, "...{rest} more"),
+                }
+            }
+            V::List(items) => self.seq(f, "[", "]", items, |f, v| {
+                fmt::Display::fmt(&self.nested(v), f)
+            }),
+            V::Tuple(items) => self.seq(f, "(", ")", items, |f, v| {
+                fmt::Display::fmt(&self.nested(v), f)
+            }),
+            V::Record(fields) => self.seq(f, "{", "}", fields, |f, (name, v)| {
+                label(f, name)?;
+                f.write_str(": ")?;
+                fmt::Display::fmt(&self.nested(v), f)
+            }),
+            V::Flags(flags) => self.seq(f, "{", "}", flags, |f, name| label(f, name)),
+            V::Variant(name, payload) => {
+                label(f, name)?;
+                self.payload(f, payload.as_deref())
+            }
+            V::Enum(name) => label(f, name),
+            V::Option(None) => f.write_str("none"),
+            V::Option(Some(v)) => {
+                f.write_str("some")?;
+                self.payload(f, Some(v))
+            }
+            V::Result(Ok(v)) => {
+                f.write_str("ok")?;
+                self.payload(f, v.as_deref())
+            }
+            V::Result(Err(v)) => {
+                f.write_str("err")?;
+                self.payload(f, v.as_deref())
+            }
+            V::Resource(r) => {
+                let kind = if r.owned { "own" } else { "borrow" };
+                write!(f, "{kind}<{}>#{}", r.type_id, r.rep)
+            }
+        }
+    }
+}
+
+/// Writes a float, spelling out the values without digits.
+fn float(f: &mut fmt::Formatter<'_>, x: f64, digits: &str) -> fmt::Result {
+    if x.is_nan() {
+        f.write_str("nan")
+    } else if x.is_infinite() {
+        f.write_str(if x > 0.0 { "inf" } else { "-inf" })
+    } else {
+        f.write_str(digits)
+    }
+}
+
+/// Writes a field, case or flag name, marking those which are keywords.
+fn label(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    const KEYWORDS: &[&str] = &["true", "false", "inf", "nan", "some", "none", "ok", "err"];
+    if KEYWORDS.contains(&name) {
+        f.write_str("%")?;
+    }
+    f.write_str(name)
+}
+
+/// Writes `c` as it appears between `quote`s.
+fn escape(f: &mut fmt::Formatter<'_>, c: char, quote: char) -> fmt::Result {
+    match c {
+        '\\' => f.write_str("\\\\"),
+        '\n' => f.write_str("\\n"),
+        '\r' => f.write_str("\\r"),
+        '\t' => f.write_str("\\t"),
+        c if c == quote => write!(f, "\\{c}"),
+        c if c.is_control() => write!(f, "\\u{{{:x}}}", u32::from(c)),
+        c => write!(f, "{c}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::SerializableResource;
+    use SerializableVal as V;
+
+    fn s(s: &str) -> V {
+        V::String(s.to_string())
+    }
+
+    #[test]
+    fn writes_wit_syntax() {
+        let val = V::Record(vec![
+            ("name".to_string(), s("say \"hi\"\n")),
+            ("count".to_string(), V::U32(3)),
+            ("ok".to_string(), V::Bool(true)),
+            (
+                "tags".to_string(),
+                V::List(vec![V::Char('a'), V::Char('\'')]),
+            ),
+            (
+                "pair".to_string(),
+                V::Tuple(vec![V::Float64(f64::NEG_INFINITY), V::Float32(1.5)]),
+            ),
+            ("mode".to_string(), V::Flags(vec!["read".to_string()])),
+            (
+                "next".to_string(),
+                V::Option(Some(Box::new(V::Result(Err(Some(Box::new(V::Enum(
+                    "none".to_string(),
+                )))))))),
+            ),
+            ("shape".to_string(), V::Variant("empty".to_string(), None)),
+            (
+                "handle".to_string(),
+                V::Resource(SerializableResource {
+                    type_id: 2,
+                    rep: 7,
+                    owned: false,
+                }),
+            ),
+        ]);
+        assert_eq!(
+            val.to_string(),
+            "{name: \"say \\\"hi\\\"\\n\", count: 3, %ok: true, tags: ['a', '\\''], \
+             pair: (-inf, 1.5), mode: {read}, next: some(err(%none)), shape: empty, \
+             handle: borrow<2>#7}"
+        );
+        assert_eq!(V::Result(Ok(None)).to_wit_string(), "ok");
+        assert_eq!(V::Float32(f32::NAN).to_wit_string(), "nan");
+    }
+
+    #[test]
+    fn truncates() {
+        let list = V::List((0..20).map(V::U8).collect());
+        assert_eq!(format!("{list:#}"), "[0, 1, 2, 3, 4, 5, 6, 7, ...12 more]");
+        let format = WitFormat {
+            max_chars: Some(3),
+            ..WitFormat::FULL
+        };
+        assert_eq!(s("abcdef").to_wit_string_with(format), "\"abc\"...3 more");
+
+        let nested = V::List(vec![V::List(vec![V::List(vec![V::U8(1)])])]);
+        let format = WitFormat {
+            max_depth: Some(2),
+            ..WitFormat::FULL
+        };
+        assert_eq!(nested.to_wit_string_with(format), "[[[...]]]");
+        assert_eq!(nested.to_wit_string(), "[[[1]]]");
+    }
+}
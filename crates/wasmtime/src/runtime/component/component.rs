@@ -1,3 +1,4 @@
+use crate::chain::Digest;
 use crate::component::matching::InstanceType;
 use crate::component::types;
 use crate::component::InstanceExportLookup;
@@ -93,6 +94,10 @@ struct ComponentInner {
     /// `realloc`, to avoid the need to look up types in the registry and take
     /// locks when calling `realloc` via `TypedFunc::call_raw`.
     realloc_func_type: Arc<dyn Any + Send + Sync>,
+
+    /// SHA-256 digest of the binary this component was compiled from, see
+    /// [`Component::sha256`].
+    sha256: Option<Digest>,
 }
 
 pub(crate) struct AllCallFuncPointers {
@@ -386,6 +391,7 @@ impl Component {
             info,
             types,
             static_modules,
+            sha256,
         } = match artifacts {
             Some(artifacts) => artifacts,
             None => postcard::from_bytes(code_memory.wasmtime_info())?,
@@ -432,10 +438,26 @@ impl Component {
                 code,
                 info,
                 realloc_func_type,
+                sha256: sha256.map(Digest),
             }),
         })
     }
 
+    /// Returns the SHA-256 digest of the binary this component was compiled
+    /// from.
+    ///
+    /// Components compiled from the text format are digested after being
+    /// converted to binary, and deserialized components keep the digest of
+    /// the binary they were originally compiled from.
+    ///
+    /// The digest is only computed for the instantiation events of
+    /// [`Config::chain_record_instantiation`](crate::Config::chain_record_instantiation),
+    /// so this returns `None` for components compiled by an engine which
+    /// doesn't record them.
+    pub fn sha256(&self) -> Option<Digest> {
+        self.inner.sha256
+    }
+
     pub(crate) fn ty(&self) -> TypeComponentIndex {
         self.inner.ty
     }
@@ -752,6 +774,30 @@ impl Component {
         exports.get(name, &NameMapNoIntern).copied()
     }
 
+    /// Returns the name `index` is exported under, with names of nested
+    /// instances joined by `#` (e.g. `wasi:cli/run@0.2.0#run`).
+    pub(crate) fn export_name(&self, index: ExportIndex) -> Option<String> {
+        fn find(
+            info: &wasmtime_environ::component::Component,
+            exports: &wasmtime_environ::component::NameMap<String, ExportIndex>,
+            index: ExportIndex,
+        ) -> Option<String> {
+            for (name, i) in exports.raw_iter() {
+                if *i == index {
+                    return Some(name.clone());
+                }
+                if let Export::Instance { exports, .. } = &info.export_items[*i] {
+                    if let Some(inner) = find(info, exports, index) {
+                        return Some(format!("{name}#{inner}"));
+                    }
+                }
+            }
+            None
+        }
+        let info = self.env_component();
+        find(info, &info.exports, index)
+    }
+
     pub(crate) fn id(&self) -> CompiledModuleId {
         self.inner.id
     }
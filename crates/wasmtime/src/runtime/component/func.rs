@@ -1,10 +1,10 @@
 // Modified 2024 Colin Rozzi - Added event tracking for chaining feature
+use crate::chain::sample::Sample;
 use crate::component::instance::{Instance, InstanceData};
 use crate::component::storage::storage_as_slice;
 use crate::component::types::Type;
 use crate::component::values::Val;
 use crate::prelude::*;
-use crate::runtime::chain::Event;
 use crate::runtime::vm::component::ResourceTables;
 use crate::runtime::vm::{Export, ExportFunction};
 use crate::store::{StoreOpaque, Stored};
@@ -12,7 +12,6 @@ use crate::{AsContext, AsContextMut, StoreContextMut, ValRaw};
 use alloc::sync::Arc;
 use core::mem::{self, MaybeUninit};
 use core::ptr::NonNull;
-use serde_json::json;
 use wasmtime_environ::component::{
     CanonicalOptions, ComponentTypes, CoreDef, InterfaceType, RuntimeComponentInstanceIndex,
     TypeFuncIndex, TypeTuple, MAX_FLAT_PARAMS, MAX_FLAT_RESULTS,
@@ -51,6 +50,12 @@ pub struct FuncData {
     component_instance: RuntimeComponentInstanceIndex,
     post_return: Option<ExportFunction>,
     post_return_arg: Option<ValRaw>,
+    /// Export name recorded in chain events, only resolved when
+    /// `Config::chain_record` is enabled.
+    name: Option<String>,
+    /// The recorded call awaiting its post-return, when
+    /// `Config::chain_record_post_return` is enabled.
+    pending_post_return: Option<crate::chain::record::PendingPostReturn>,
 }
 
 impl Func {
@@ -61,6 +66,7 @@ impl Func {
         ty: TypeFuncIndex,
         func: &CoreDef,
         options: &CanonicalOptions,
+        name: Option<String>,
     ) -> Func {
         let export = match data.lookup_def(store, func) {
             Export::Function(f) => f,
@@ -85,6 +91,8 @@ impl Func {
             component_instance,
             post_return,
             post_return_arg: None,
+            name,
+            pending_post_return: None,
         }))
     }
 
@@ -324,8 +332,6 @@ impl Func {
     ) -> Result<()> {
         let store = &mut store.as_context_mut();
 
-        let mut results_copy = Vec::with_capacity(results.len());
-
         let param_tys = self.params(&store);
         let result_tys = self.results(&store);
 
@@ -344,12 +350,48 @@ impl Func {
             );
         }
 
-        store.0.add_event_to_chain(Event::new(
-            "WasmCall".to_string(),
-            serde_json::to_vec(&json!(params))?,
-        ));
+        let config = store.0.engine().config();
+        let name = store.0[self.0].name.as_deref().unwrap_or_default();
+        let (record, per_instance) = (config.chain_records_export(name), config.chain_per_instance);
+        // Calls the store's sampling leaves out record nothing, see
+        // `Store::set_chain_sampling`. Sampling needs the store mutably, so
+        // the name is moved out for it rather than cloned.
+        let sample = record.then(|| {
+            let name = store.0[self.0].name.take();
+            let sample = store.0.sample_chain_call(name.as_deref().unwrap_or_default());
+            store.0[self.0].name = name;
+            sample
+        });
+        let (record, skipped) = match sample {
+            Some(Sample::Record { skipped }) => (true, skipped),
+            _ => (false, 0),
+        };
+        // Only calls which are recorded need a name of their own.
+        let name = record.then(|| store.0[self.0].name.clone().unwrap_or_default());
+        let config = store.0.engine().config();
+        let types = (record && config.chain_record_types).then_some((&*param_tys, &*result_tys));
+        let post_return = record && config.chain_record_post_return;
+        let fuel = if record && config.chain_record_fuel {
+            store.0.get_fuel().ok()
+        } else {
+            None
+        };
+        if per_instance {
+            let instance = store.0[self.0].instance.0.index();
+            match (store.0.current_chain_instance(), &name) {
+                // A host function of another instance is calling this one.
+                (Some(sender), Some(name)) if sender != instance => {
+                    crate::chain::message::record(store.0, sender, instance, name)?;
+                }
+                _ => {}
+            }
+            store.0.push_chain_instance(instance);
+        }
+        if let Some(name) = name {
+            store.0.push_export_frame(name);
+        }
 
-        let res = self.call_raw(
+        let result = self.call_raw(
             store,
             params,
             |cx, params, params_ty, dst: &mut MaybeUninit<[ValRaw; MAX_FLAT_PARAMS]>| {
@@ -378,27 +420,56 @@ impl Func {
                 };
                 if results_ty.abi.flat_count(MAX_FLAT_RESULTS).is_some() {
                     let mut flat = src.iter();
-                    for (ty, slot) in results_ty.types.iter().zip(results) {
+                    for (ty, slot) in results_ty.types.iter().zip(&mut *results) {
                         *slot = Val::lift(cx, *ty, &mut flat)?;
-                        results_copy.push(slot.clone());
                     }
                     Ok(())
                 } else {
-                    let result = Self::load_results(cx, results_ty, results, &mut src.iter());
-                    if result.is_ok() {
-                        results_copy.extend(results.iter().cloned());
-                    }
-                    result
+                    Self::load_results(cx, results_ty, results, &mut src.iter())
                 }
             },
         );
 
-        store.0.add_event_to_chain(Event::new(
-            "WasmReturn".to_string(),
-            serde_json::to_vec(&json!(results_copy))?,
-        ));
-
-        res
+        let recorded = if record {
+            let name = store.0.pop_export_frame();
+            let sampled = match skipped {
+                0 => Ok(()),
+                _ => crate::chain::sample::record(store.0, &name, skipped),
+            };
+            let call = sampled.and_then(|()| match &result {
+                Ok(()) => {
+                    let call =
+                        crate::chain::record::function_call(store.0, &name, params, results, types)?;
+                    if post_return {
+                        let pending = crate::chain::record::PendingPostReturn::new(
+                            call,
+                            name,
+                            params,
+                            &param_tys,
+                        );
+                        store.0[self.0].pending_post_return = Some(pending);
+                    }
+                    Ok(call)
+                }
+                Err(e) => crate::chain::record::trap(store.0, name, params, e),
+            });
+            match (call, fuel) {
+                (Ok(call), Some(before)) if !store.0.chain_sampled_out() => {
+                    crate::chain::record::fuel(store.0, call, before)
+                }
+                (call, _) => call.map(drop),
+            }
+        } else {
+            Ok(())
+        };
+        if per_instance {
+            store.0.pop_chain_instance();
+        }
+        if sample == Some(Sample::Skip) {
+            store.0.end_unsampled_call();
+        }
+        recorded?;
+        result
     }
 
     /// Invokes the underlying wasm function, lowering arguments and lifting the
@@ -599,6 +670,7 @@ impl Func {
         let post_return = data.post_return;
         let component_instance = data.component_instance;
         let post_return_arg = data.post_return_arg.take();
+        let pending = data.pending_post_return.take();
         let instance = store.0[instance.0].as_ref().unwrap().instance_ptr();
 
         unsafe {
@@ -661,6 +733,9 @@ impl Func {
             }
             .exit_call()?;
         }
+        if let Some(pending) = pending {
+            crate::chain::record::post_return(store.0, pending, post_return.is_some())?;
+        }
         Ok(())
     }
 
@@ -1,9 +1,9 @@
 // Modified 2024 Colin Rozzi - Added event tracking for chaining feature
-use crate::chain::Event;
+use crate::chain::record;
 use crate::component::func::{LiftContext, LowerContext, Options};
 use crate::component::matching::InstanceType;
 use crate::component::storage::slice_to_storage_mut;
-use crate::component::{ComponentNamedList, ComponentType, Lift, Lower, Val};
+use crate::component::{ComponentNamedList, ComponentType, Lift, Lower, Type, Val};
 use crate::prelude::*;
 use crate::runtime::vm::component::{
     ComponentInstance, InstanceFlags, VMComponentContext, VMLowering, VMLoweringCallee,
@@ -12,14 +12,11 @@ use crate::runtime::vm::{VMFuncRef, VMMemoryDefinition, VMOpaqueContext};
 use crate::{AsContextMut, CallHook, StoreContextMut, ValRaw};
 use alloc::sync::Arc;
 use core::any::Any;
-use core::fmt::Debug;
 use core::marker::Copy;
 use core::mem::{self, MaybeUninit};
 use core::ptr::NonNull;
-use serde::Serialize;
-use serde_json::json;
 use wasmtime_environ::component::{
-    CanonicalAbiInfo, ComponentTypes, InterfaceType, StringEncoding, TypeFuncIndex,
+    CanonicalAbiInfo, ComponentTypes, InterfaceType, StringEncoding, TypeFuncIndex, TypeTuple,
     MAX_FLAT_PARAMS, MAX_FLAT_RESULTS,
 };
 
@@ -29,18 +26,25 @@ pub struct HostFunc {
     func: Box<dyn Any + Send + Sync>,
 }
 
+/// The closure behind a `HostFunc` along with the name its calls are recorded
+/// under when `Config::chain_record` is enabled.
+struct Closure<F> {
+    name: String,
+    func: F,
+}
+
 impl HostFunc {
-    pub(crate) fn from_closure<T, F, P, R>(func: F) -> Arc<HostFunc>
+    pub(crate) fn from_closure<T, F, P, R>(name: String, func: F) -> Arc<HostFunc>
     where
         F: Fn(StoreContextMut<T>, P) -> Result<R> + Send + Sync + 'static,
-        P: ComponentNamedList + Lift + 'static + Debug + Serialize,
-        R: ComponentNamedList + Lower + 'static + Debug + Clone + Serialize,
+        P: ComponentNamedList + Lift + 'static,
+        R: ComponentNamedList + Lower + 'static,
     {
         let entrypoint = Self::entrypoint::<T, F, P, R>;
         Arc::new(HostFunc {
             entrypoint,
             typecheck: Box::new(typecheck::<P, R>),
-            func: Box::new(func),
+            func: Box::new(Closure { name, func }),
         })
     }
 
@@ -57,29 +61,30 @@ impl HostFunc {
     ) -> bool
     where
         F: Fn(StoreContextMut<T>, P) -> Result<R>,
-        P: ComponentNamedList + Lift + 'static + Debug + Serialize,
-        R: ComponentNamedList + Lower + 'static + Debug + Clone + Serialize,
+        P: ComponentNamedList + Lift + 'static,
+        R: ComponentNamedList + Lower + 'static,
     {
-        let data = data as *const F;
+        let data = data as *const Closure<F>;
         unsafe {
             call_host_and_handle_result::<T>(cx, |instance, types, store| {
                 call_host::<_, _, _, _>(
                     instance,
                     types,
                     store,
+                    &(*data).name,
                     TypeFuncIndex::from_u32(ty),
                     InstanceFlags::from_raw(flags),
                     memory,
                     realloc,
                     StringEncoding::from_u8(string_encoding).unwrap(),
                     core::slice::from_raw_parts_mut(storage, storage_len),
-                    |store, args| (*data)(store, args),
+                    |store, args| ((*data).func)(store, args),
                 )
             })
         }
     }
 
-    pub(crate) fn new_dynamic<T, F>(func: F) -> Arc<HostFunc>
+    pub(crate) fn new_dynamic<T, F>(name: String, func: F) -> Arc<HostFunc>
     where
         F: Fn(StoreContextMut<'_, T>, &[Val], &mut [Val]) -> Result<()> + Send + Sync + 'static,
     {
@@ -89,7 +94,7 @@ impl HostFunc {
             // not need to perform up-front type checks. Instead everything is
             // dynamically managed at runtime.
             typecheck: Box::new(move |_expected_index, _expected_types| Ok(())),
-            func: Box::new(func),
+            func: Box::new(Closure { name, func }),
         })
     }
 
@@ -143,17 +148,18 @@ unsafe fn call_host<T, Params, Return, F>(
     instance: *mut ComponentInstance,
     types: &Arc<ComponentTypes>,
     mut cx: StoreContextMut<'_, T>,
+    name: &str,
     ty: TypeFuncIndex,
     mut flags: InstanceFlags,
     memory: *mut VMMemoryDefinition,
     realloc: *mut VMFuncRef,
     string_encoding: StringEncoding,
-    storage: &mut [MaybeUninit<ValRaw>],
+    raw: &mut [MaybeUninit<ValRaw>],
     closure: F,
 ) -> Result<()>
 where
-    Params: Lift + Debug + Serialize,
-    Return: Lower + Debug + Clone + Serialize,
+    Params: Lift,
+    Return: Lower,
     F: FnOnce(StoreContextMut<'_, T>, Params) -> Result<Return>,
 {
     /// Representation of arguments to this function when a return pointer is in
@@ -174,6 +180,24 @@ where
         ret: U,
     }
 
+    // While an instance is resumed from a recording its imports are answered
+    // from the recording instead, see `Instance::resume_from_chain`.
+    if cx.0.chain_resume().is_some() {
+        return call_host_dynamic(
+            instance,
+            types,
+            cx,
+            name,
+            ty,
+            flags,
+            memory,
+            realloc,
+            string_encoding,
+            raw,
+            |_, _, _| unreachable!(),
+        );
+    }
+
     let options = Options::new(
         cx.0.id(),
         NonNull::new(memory),
@@ -192,6 +216,26 @@ where
     let param_tys = InterfaceType::Tuple(ty.params);
     let result_tys = InterfaceType::Tuple(ty.results);
 
+    // When recording, the arguments are additionally lifted as `Val`s for the
+    // `import-call` event. Resource handles can't be lifted twice, so
+    // signatures which mention resources are recorded without values.
+    let record = cx.0.engine().config().chain_records_import(name) && !cx.0.chain_sampled_out();
+    let mut lift = LiftContext::new(cx.0, &options, types, instance);
+    lift.enter_call();
+    let captured = if record
+        && !contains_resources(types, &param_tys)
+        && !contains_resources(types, &result_tys)
+    {
+        Some(lift_params_dynamic(
+            &mut lift,
+            types,
+            &types[ty.params],
+            raw,
+        )?)
+    } else {
+        None
+    };
+
     // There's a 2x2 matrix of whether parameters and results are stored on the
     // stack or on the heap. Each of the 4 branches here have a different
     // representation of the storage of arguments/returns.
@@ -203,40 +247,46 @@ where
     // branch, but today is not that day.
     let mut storage: Storage<'_, Params, Return> = if Params::flatten_count() <= MAX_FLAT_PARAMS {
         if Return::flatten_count() <= MAX_FLAT_RESULTS {
-            Storage::Direct(slice_to_storage_mut(storage))
+            Storage::Direct(slice_to_storage_mut(raw))
         } else {
-            Storage::ResultsIndirect(slice_to_storage_mut(storage).assume_init_ref())
+            Storage::ResultsIndirect(slice_to_storage_mut(raw).assume_init_ref())
         }
     } else {
         if Return::flatten_count() <= MAX_FLAT_RESULTS {
-            Storage::ParamsIndirect(slice_to_storage_mut(storage))
+            Storage::ParamsIndirect(slice_to_storage_mut(raw))
         } else {
-            Storage::Indirect(slice_to_storage_mut(storage).assume_init_ref())
+            Storage::Indirect(slice_to_storage_mut(raw).assume_init_ref())
         }
     };
-    let mut lift = LiftContext::new(cx.0, &options, types, instance);
-    lift.enter_call();
     let params = storage.lift_params(&mut lift, param_tys)?;
 
-    cx.0.add_event_to_chain(Event::new(
-        "HostCall".to_string(),
-        serde_json::to_vec(&json!(params)).unwrap(),
-    ));
+    if record {
+        let args = captured.as_ref().map(|(args, _)| &args[..]);
+        record::import_call(cx.0, name, args)?;
+    }
 
     let ret = closure(cx.as_context_mut(), params)?;
     flags.set_may_leave(false);
-
-    cx.0.add_event_to_chain(Event::new(
-        "HostReturn".to_string(),
-        serde_json::to_vec(&json!(ret)).unwrap(),
-    ));
-
-    let mut lower = LowerContext::new(cx, &options, types, instance);
+    let mut lower = LowerContext::new(cx.as_context_mut(), &options, types, instance);
     storage.lower_results(&mut lower, result_tys, ret)?;
     flags.set_may_leave(true);
 
     lower.exit_call()?;
 
+    if record {
+        let results = match captured {
+            Some((_, ret_index)) => {
+                let mut lift = LiftContext::new(cx.0, &options, types, instance);
+                let result_tys = &types[ty.results];
+                Some(lift_results_dynamic(
+                    &mut lift, types, result_tys, raw, ret_index,
+                )?)
+            }
+            None => None,
+        };
+        record::import_return(cx.0, name, results.as_deref())?;
+    }
+
     return Ok(());
 
     enum Storage<'a, P: ComponentType, R: ComponentType> {
@@ -332,6 +382,7 @@ unsafe fn call_host_dynamic<T, F>(
     instance: *mut ComponentInstance,
     types: &Arc<ComponentTypes>,
     mut store: StoreContextMut<'_, T>,
+    name: &str,
     ty: TypeFuncIndex,
     mut flags: InstanceFlags,
     memory: *mut VMMemoryDefinition,
@@ -357,57 +408,40 @@ where
         bail!("cannot leave component instance");
     }
 
-    let args;
-    let ret_index;
-
     let func_ty = &types[ty];
     let param_tys = &types[func_ty.params];
     let result_tys = &types[func_ty.results];
     let mut cx = LiftContext::new(store.0, &options, types, instance);
     cx.enter_call();
-    if let Some(param_count) = param_tys.abi.flat_count(MAX_FLAT_PARAMS) {
-        // NB: can use `MaybeUninit::slice_assume_init_ref` when that's stable
-        let mut iter =
-            mem::transmute::<&[MaybeUninit<ValRaw>], &[ValRaw]>(&storage[..param_count]).iter();
-        args = param_tys
-            .types
-            .iter()
-            .map(|ty| Val::lift(&mut cx, *ty, &mut iter))
-            .collect::<Result<Box<[_]>>>()?;
-        ret_index = param_count;
-        assert!(iter.next().is_none());
-    } else {
-        let mut offset =
-            validate_inbounds_dynamic(&param_tys.abi, cx.memory(), storage[0].assume_init_ref())?;
-        args = param_tys
-            .types
-            .iter()
-            .map(|ty| {
-                let abi = types.canonical_abi(ty);
-                let size = usize::try_from(abi.size32).unwrap();
-                let memory = &cx.memory()[abi.next_field32_size(&mut offset)..][..size];
-                Val::load(&mut cx, *ty, memory)
-            })
-            .collect::<Result<Box<[_]>>>()?;
-        ret_index = 1;
-    };
+    let (args, ret_index) = lift_params_dynamic(&mut cx, types, param_tys, storage)?;
 
-    store.0.add_event_to_chain(Event::new(
-        "HostCall".to_string(),
-        serde_json::to_vec(&json!(args))?,
-    ));
+    let record =
+        store.0.engine().config().chain_records_import(name) && !store.0.chain_sampled_out();
+    if record {
+        record::import_call(store.0, name, Some(&args))?;
+    }
 
     let mut result_vals = Vec::with_capacity(result_tys.types.len());
     for _ in result_tys.types.iter() {
         result_vals.push(Val::Bool(false));
     }
-    closure(store.as_context_mut(), &args, &mut result_vals)?;
+    match store.0.chain_resume().cloned() {
+        Some(replay) => {
+            let instance_ty = InstanceType::new(&*instance);
+            let tys = result_tys
+                .types
+                .iter()
+                .map(|ty| Type::from(ty, &instance_ty))
+                .collect::<Vec<_>>();
+            replay.answer(&store, name, &tys, &mut result_vals)?;
+        }
+        None => closure(store.as_context_mut(), &args, &mut result_vals)?,
+    }
     flags.set_may_leave(false);
 
-    store.0.add_event_to_chain(Event::new(
-        "HostReturn".to_string(),
-        serde_json::to_vec(&json!(result_vals))?,
-    ));
+    if record {
+        record::import_return(store.0, name, Some(&result_vals))?;
+    }
 
     let mut cx = LowerContext::new(store, &options, types, instance);
     if let Some(cnt) = result_tys.abi.flat_count(MAX_FLAT_RESULTS) {
@@ -432,6 +466,104 @@ where
     return Ok(());
 }
 
+/// Lifts the arguments of a call described by `param_tys` out of `storage`,
+/// returning them along with the index of the return pointer, if any.
+unsafe fn lift_params_dynamic(
+    cx: &mut LiftContext<'_>,
+    types: &ComponentTypes,
+    param_tys: &TypeTuple,
+    storage: &[MaybeUninit<ValRaw>],
+) -> Result<(Box<[Val]>, usize)> {
+    if let Some(param_count) = param_tys.abi.flat_count(MAX_FLAT_PARAMS) {
+        // NB: can use `MaybeUninit::slice_assume_init_ref` when that's stable
+        let mut iter =
+            mem::transmute::<&[MaybeUninit<ValRaw>], &[ValRaw]>(&storage[..param_count]).iter();
+        let args = param_tys
+            .types
+            .iter()
+            .map(|ty| Val::lift(cx, *ty, &mut iter))
+            .collect::<Result<Box<[_]>>>()?;
+        assert!(iter.next().is_none());
+        Ok((args, param_count))
+    } else {
+        let mut offset =
+            validate_inbounds_dynamic(&param_tys.abi, cx.memory(), storage[0].assume_init_ref())?;
+        let args = param_tys
+            .types
+            .iter()
+            .map(|ty| {
+                let abi = types.canonical_abi(ty);
+                let size = usize::try_from(abi.size32).unwrap();
+                let memory = &cx.memory()[abi.next_field32_size(&mut offset)..][..size];
+                Val::load(cx, *ty, memory)
+            })
+            .collect::<Result<Box<[_]>>>()?;
+        Ok((args, 1))
+    }
+}
+
+/// Lifts the results of a call described by `result_tys` back out of
+/// `storage` after they've been lowered.
+unsafe fn lift_results_dynamic(
+    cx: &mut LiftContext<'_>,
+    types: &ComponentTypes,
+    result_tys: &TypeTuple,
+    storage: &[MaybeUninit<ValRaw>],
+    ret_index: usize,
+) -> Result<Vec<Val>> {
+    if let Some(cnt) = result_tys.abi.flat_count(MAX_FLAT_RESULTS) {
+        let mut iter = mem::transmute::<&[MaybeUninit<ValRaw>], &[ValRaw]>(&storage[..cnt]).iter();
+        result_tys
+            .types
+            .iter()
+            .map(|ty| Val::lift(cx, *ty, &mut iter))
+            .collect()
+    } else {
+        let ret_ptr = storage[ret_index].assume_init_ref();
+        let mut offset = validate_inbounds_dynamic(&result_tys.abi, cx.memory(), ret_ptr)?;
+        result_tys
+            .types
+            .iter()
+            .map(|ty| {
+                let abi = types.canonical_abi(ty);
+                let size = usize::try_from(abi.size32).unwrap();
+                let memory = &cx.memory()[abi.next_field32_size(&mut offset)..][..size];
+                Val::load(cx, *ty, memory)
+            })
+            .collect()
+    }
+}
+
+/// Returns whether a value of type `ty` can contain a resource handle.
+fn contains_resources(types: &ComponentTypes, ty: &InterfaceType) -> bool {
+    match ty {
+        InterfaceType::Own(_) | InterfaceType::Borrow(_) => true,
+        InterfaceType::Record(i) => types[*i]
+            .fields
+            .iter()
+            .any(|f| contains_resources(types, &f.ty)),
+        InterfaceType::Variant(i) => types[*i]
+            .cases
+            .values()
+            .flatten()
+            .any(|ty| contains_resources(types, ty)),
+        InterfaceType::List(i) => contains_resources(types, &types[*i].element),
+        InterfaceType::Tuple(i) => types[*i]
+            .types
+            .iter()
+            .any(|ty| contains_resources(types, ty)),
+        InterfaceType::Option(i) => contains_resources(types, &types[*i].ty),
+        InterfaceType::Result(i) => {
+            let ty = &types[*i];
+            ty.ok
+                .iter()
+                .chain(&ty.err)
+                .any(|ty| contains_resources(types, ty))
+        }
+        _ => false,
+    }
+}
+
 fn validate_inbounds_dynamic(abi: &CanonicalAbiInfo, memory: &[u8], ptr: &ValRaw) -> Result<usize> {
     // FIXME: needs memory64 support
     let ptr = usize::try_from(ptr.get_u32())?;
@@ -462,20 +594,21 @@ extern "C" fn dynamic_entrypoint<T, F>(
 where
     F: Fn(StoreContextMut<'_, T>, &[Val], &mut [Val]) -> Result<()> + Send + Sync + 'static,
 {
-    let data = data as *const F;
+    let data = data as *const Closure<F>;
     unsafe {
         call_host_and_handle_result(cx, |instance, types, store| {
             call_host_dynamic::<T, _>(
                 instance,
                 types,
                 store,
+                &(*data).name,
                 TypeFuncIndex::from_u32(ty),
                 InstanceFlags::from_raw(flags),
                 memory,
                 realloc,
                 StringEncoding::from_u8(string_encoding).unwrap(),
                 core::slice::from_raw_parts_mut(storage, storage_len),
-                |store, params, results| (*data)(store, params, results),
+                |store, params, results| ((*data).func)(store, params, results),
             )
         })
     }
@@ -170,9 +170,16 @@ impl Instance {
         let data = store[self.0].take().unwrap();
         let ret = name.lookup(&data.component).and_then(|index| {
             match &data.component.env_component().export_items[index] {
-                Export::LiftedFunction { ty, func, options } => Some(Func::from_lifted_func(
-                    store, self, &data, *ty, func, options,
-                )),
+                Export::LiftedFunction { ty, func, options } => {
+                    let name = if store.engine().config().chain_record {
+                        data.component.export_name(index)
+                    } else {
+                        None
+                    };
+                    Some(Func::from_lifted_func(
+                        store, self, &data, *ty, func, options, name,
+                    ))
+                }
                 _ => None,
             }
         });
@@ -841,6 +848,11 @@ impl<T> InstancePre<T> {
         let data = Box::new(instantiator.data);
         let instance = Instance(store.0.store_data_mut().insert(Some(data)));
         store.0.push_component_instance(instance);
+        let record =
+            store.engine().config().chain_records_instantiation() && !store.0.chain_sampled_out();
+        if record {
+            crate::chain::record::instantiation(store.0, &self.component)?;
+        }
         Ok(instance)
     }
 }
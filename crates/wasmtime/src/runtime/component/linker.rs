@@ -10,11 +10,9 @@ use crate::hash_map::HashMap;
 use crate::prelude::*;
 use crate::{AsContextMut, Engine, Module, StoreContextMut};
 use alloc::sync::Arc;
-use core::fmt::Debug;
 use core::future::Future;
 use core::marker;
 use core::pin::Pin;
-use serde::Serialize;
 use wasmtime_environ::component::{NameMap, NameMapIntern};
 use wasmtime_environ::PrimaryMap;
 
@@ -406,10 +404,13 @@ impl<T> LinkerInstance<'_, T> {
     pub fn func_wrap<F, Params, Return>(&mut self, name: &str, func: F) -> Result<()>
     where
         F: Fn(StoreContextMut<T>, Params) -> Result<Return> + Send + Sync + 'static,
-        Params: ComponentNamedList + Lift + 'static + Debug + Serialize,
-        Return: ComponentNamedList + Lower + 'static + Debug + Clone + Serialize,
+        Params: ComponentNamedList + Lift + 'static,
+        Return: ComponentNamedList + Lower + 'static,
     {
-        self.insert(name, Definition::Func(HostFunc::from_closure(func)))?;
+        self.insert(
+            name,
+            Definition::Func(HostFunc::from_closure(self.import_name(name), func)),
+        )?;
         Ok(())
     }
 
@@ -427,8 +428,8 @@ impl<T> LinkerInstance<'_, T> {
             + Send
             + Sync
             + 'static,
-        Params: ComponentNamedList + Lift + 'static + Debug + Serialize,
-        Return: ComponentNamedList + Lower + 'static + Debug + Clone + Serialize,
+        Params: ComponentNamedList + Lift + 'static,
+        Return: ComponentNamedList + Lower + 'static,
     {
         assert!(
             self.engine.config().async_support,
@@ -546,7 +547,10 @@ impl<T> LinkerInstance<'_, T> {
         name: &str,
         func: impl Fn(StoreContextMut<'_, T>, &[Val], &mut [Val]) -> Result<()> + Send + Sync + 'static,
     ) -> Result<()> {
-        self.insert(name, Definition::Func(HostFunc::new_dynamic(func)))?;
+        self.insert(
+            name,
+            Definition::Func(HostFunc::new_dynamic(self.import_name(name), func)),
+        )?;
         Ok(())
     }
 
@@ -677,6 +681,17 @@ impl<T> LinkerInstance<'_, T> {
         Ok(self)
     }
 
+    /// Returns `name` prefixed with the names of the instances enclosing this
+    /// one, joined by `#`, which is how calls to it are recorded in a chain.
+    fn import_name(&self, name: &str) -> String {
+        let mut names = self.path[..self.path_len]
+            .iter()
+            .map(|i| &*self.strings.strings[*i])
+            .collect::<Vec<_>>();
+        names.push(name);
+        names.join("#")
+    }
+
     fn insert(&mut self, name: &str, item: Definition) -> Result<usize> {
         self.map
             .insert(name, self.strings, self.allow_shadowing, item)
@@ -995,6 +995,14 @@ impl ResourceAny {
     }
 
     fn resource_drop_impl<T>(self, store: &mut StoreContextMut<'_, T>) -> Result<()> {
+        self.destroy(store)?;
+        if store.0.engine().config().chain_record {
+            crate::chain::record::resource_drop(store.0, &self)?;
+        }
+        Ok(())
+    }
+
+    fn destroy<T>(self, store: &mut StoreContextMut<'_, T>) -> Result<()> {
         // Attempt to remove `self.idx` from the host table in `store`.
         //
         // This could fail if the index is invalid or if this is removing an
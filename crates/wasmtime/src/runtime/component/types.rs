@@ -663,7 +663,7 @@ impl Type {
         }
     }
 
-    fn desc(&self) -> &'static str {
+    pub(crate) fn desc(&self) -> &'static str {
         match self {
             Type::Bool => "bool",
             Type::S8 => "s8",
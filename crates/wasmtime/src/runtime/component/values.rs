@@ -87,27 +87,33 @@ pub enum Val {
     Option(Option<Box<Val>>),
     Result(Result<Option<Box<Val>>, Option<Box<Val>>>),
     Flags(Vec<String>),
-    Resource(#[serde(with = "temporary_unimplemented")] ResourceAny), // lets get rid of dynamic typing who even
-                                                                      // wants that its lame and stupid
+    Resource(#[serde(with = "unserializable_resource")] ResourceAny),
 }
 
-mod temporary_unimplemented {
-
+/// Resource handles only have meaning inside the store that created them, so
+/// `Val`s holding them can't be serialized directly. Convert them to a
+/// [`SerializableVal`](crate::chain::SerializableVal) through a
+/// [`ResourceRegistry`](crate::chain::ResourceRegistry) instead.
+mod unserializable_resource {
     use crate::component::ResourceAny;
-    use serde::{Deserializer, Serializer};
+    use serde::{de, ser, Deserializer, Serializer};
 
     pub fn serialize<S>(_resource: &ResourceAny, _serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        unimplemented!("AHHHHHH: Resource serialization not yet implemented")
+        Err(ser::Error::custom(
+            "resources cannot be serialized without a resource registry",
+        ))
     }
 
     pub fn deserialize<'de, D>(_deserializer: D) -> Result<ResourceAny, D::Error>
     where
         D: Deserializer<'de>,
     {
-        unimplemented!("AHHHHHH: Resource deserialization not yet implemented")
+        Err(de::Error::custom(
+            "resources cannot be deserialized without a resource registry",
+        ))
     }
 }
 
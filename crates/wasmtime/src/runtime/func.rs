@@ -1578,7 +1578,6 @@ impl Func {
     /// Even if the same underlying function is added to the `StoreData`
     /// multiple times and becomes multiple `wasmtime::Func`s, this hash key
     /// will be consistent across all of these functions.
-    #[allow(dead_code)] // Not used yet, but added for consistency.
     pub(crate) fn hash_key(&self, store: &mut StoreOpaque) -> impl core::hash::Hash + Eq + use<> {
         self.vm_func_ref(store).as_ptr() as usize
     }
@@ -2230,6 +2229,21 @@ impl<T> Caller<'_, T> {
     pub fn fuel_async_yield_interval(&mut self, interval: Option<u64>) -> Result<()> {
         self.store.fuel_async_yield_interval(interval)
     }
+
+    /// Returns the chain of events recorded in this store.
+    ///
+    /// Same as [`Store::chain`](crate::Store::chain).
+    pub fn chain(&self) -> &crate::chain::Chain {
+        self.store.chain()
+    }
+
+    /// Mutable access to the chain of events recorded in this store, so host
+    /// functions can add events of their own.
+    ///
+    /// Same as [`Store::chain_mut`](crate::Store::chain_mut).
+    pub fn chain_mut(&mut self) -> &mut crate::chain::Chain {
+        self.store.chain_mut()
+    }
 }
 
 impl<T> AsContext for Caller<'_, T> {
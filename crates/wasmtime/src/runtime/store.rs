@@ -77,7 +77,13 @@
 //! contents of `StoreOpaque`. This is an invariant that we, as the authors of
 //! `wasmtime`, must uphold for the public interface to be safe.
 
-use crate::chain::{Chain, Event};
+use crate::chain::record::EpochAction;
+#[cfg(feature = "async")]
+use crate::chain::record::YieldReason;
+use crate::chain::replay::Replay;
+use crate::chain::sample::{Sample, Sampler, Sampling};
+use crate::chain::{Chain, ChainSigner, CoreRefRegistry, Digest, Event, ResourceRegistry};
+use crate::hash_map::HashMap;
 use crate::hash_set::HashSet;
 use crate::instance::InstanceData;
 use crate::linker::Definition;
@@ -399,6 +405,32 @@ pub struct StoreOpaque {
 
     /// Chain of function calls that have been made in this store.
     chain: Chain,
+    /// Stable names for resources that appear in chain events.
+    resource_registry: ResourceRegistry,
+    /// Stable names for the references in recorded core values.
+    core_ref_registry: CoreRefRegistry,
+    /// Component instances whose exports are running, innermost last, for
+    /// `Config::chain_per_instance`.
+    chain_instances: Vec<usize>,
+    /// Hash of the `spawn` event of each component instance's chain.
+    instance_chains: HashMap<usize, Digest>,
+    /// Recorded calls in progress, innermost last.
+    call_frames: Vec<CallFrame>,
+    /// Decides which export calls are recorded.
+    chain_sampler: Sampler,
+    /// Export calls running which the sampler left out.
+    unsampled_calls: usize,
+    /// Answers host calls from a recording while an instance is resumed
+    /// from it, see `Instance::resume_from_chain`.
+    chain_resume: Option<Replay>,
+}
+
+/// A recorded call in progress, see `StoreOpaque::call_frames`.
+enum CallFrame {
+    /// An export call, with the name it's recorded under.
+    Export(String),
+    /// An import call, with the hash of its `import-call` event.
+    Import(Digest),
 }
 
 #[cfg(feature = "async")]
@@ -593,6 +625,14 @@ impl<T> Store<T> {
                     None
                 },
                 chain: Chain::new(),
+                resource_registry: ResourceRegistry::new(),
+                core_ref_registry: CoreRefRegistry::new(),
+                chain_instances: Vec::new(),
+                instance_chains: HashMap::new(),
+                call_frames: Vec::new(),
+                chain_sampler: Sampler::default(),
+                unsampled_calls: 0,
+                chain_resume: None,
             },
             limiter: None,
             call_hook: None,
@@ -1077,6 +1117,63 @@ impl<T> Store<T> {
     pub fn get_chain(&self) -> &Chain {
         &self.inner.inner.chain
     }
+
+    /// Returns the chain of events recorded in this store.
+    ///
+    /// See [`Config::chain_record`](crate::Config::chain_record) for having
+    /// component calls recorded automatically.
+    pub fn chain(&self) -> &Chain {
+        &self.inner.inner.chain
+    }
+
+    /// Mutable access to the chain of events recorded in this store, for
+    /// adding events of the embedder's own.
+    pub fn chain_mut(&mut self) -> &mut Chain {
+        &mut self.inner.inner.chain
+    }
+
+    /// Replaces the chain this store records into, returning the old one.
+    ///
+    /// Combined with [`Chain::open`] this resumes recording into a chain
+    /// persisted by an earlier run.
+    pub fn set_chain(&mut self, chain: Chain) -> Chain {
+        mem::replace(&mut self.inner.inner.chain, chain)
+    }
+
+    /// Signs every event added to this store's chain from now on with
+    /// `signer`, or stops signing if it's `None`.
+    ///
+    /// See [`Chain::set_signer`].
+    pub fn set_chain_signer(&mut self, signer: Option<Arc<dyn ChainSigner>>) {
+        self.inner.inner.chain.set_signer(signer);
+    }
+
+    /// Records only the export calls picked by `sampling` from now on, see
+    /// [`Sampling`].
+    pub fn set_chain_sampling(&mut self, sampling: Sampling) {
+        self.inner.inner.chain_sampler.set_sampling(sampling);
+    }
+
+    /// The sampling set with [`Store::set_chain_sampling`].
+    pub fn chain_sampling(&self) -> Sampling {
+        self.inner.inner.chain_sampler.sampling()
+    }
+
+    /// Returns the registry used to name resources in chain events.
+    pub fn resource_registry(&self) -> &ResourceRegistry {
+        &self.inner.inner.resource_registry
+    }
+
+    /// Mutable access to the registry used to name resources in chain events.
+    pub fn resource_registry_mut(&mut self) -> &mut ResourceRegistry {
+        &mut self.inner.inner.resource_registry
+    }
+
+    /// Returns the registry used to name the references in recorded core
+    /// values.
+    pub fn core_ref_registry(&self) -> &CoreRefRegistry {
+        &self.inner.inner.core_ref_registry
+    }
 }
 
 impl<'a, T> StoreContext<'a, T> {
@@ -1107,6 +1204,11 @@ impl<'a, T> StoreContext<'a, T> {
     pub fn get_chain(&self) -> &Chain {
         &self.0.inner.chain
     }
+
+    /// Same as [`Store::chain`].
+    pub fn chain(&self) -> &'a Chain {
+        &self.0.inner.chain
+    }
 }
 
 impl<'a, T> StoreContextMut<'a, T> {
@@ -1201,6 +1303,34 @@ impl<'a, T> StoreContextMut<'a, T> {
     pub fn get_chain(&self) -> &Chain {
         &self.0.inner.chain
     }
+
+    /// Same as [`Store::chain`].
+    pub fn chain(&self) -> &Chain {
+        &self.0.inner.chain
+    }
+
+    /// Same as [`Store::chain_mut`].
+    ///
+    /// This lets host functions add events inline, for example with
+    /// `caller.chain_mut().add(Event::new(...))`.
+    pub fn chain_mut(&mut self) -> &mut Chain {
+        &mut self.0.inner.chain
+    }
+
+    /// Same as [`Store::resource_registry`].
+    pub fn resource_registry(&self) -> &ResourceRegistry {
+        &self.0.inner.resource_registry
+    }
+
+    /// Same as [`Store::resource_registry_mut`].
+    pub fn resource_registry_mut(&mut self) -> &mut ResourceRegistry {
+        &mut self.0.inner.resource_registry
+    }
+
+    /// Same as [`Store::core_ref_registry`].
+    pub fn core_ref_registry(&self) -> &CoreRefRegistry {
+        &self.0.inner.core_ref_registry
+    }
 }
 
 impl<T> StoreInner<T> {
@@ -1271,6 +1401,27 @@ impl<T> StoreInner<T> {
     }
 }
 
+/// The child of `chain` recording the component instance `instance`, looked
+/// up in or added to `instance_chains`, see
+/// [`StoreOpaque::instance_chain_mut`].
+fn instance_chain_mut<'a>(
+    chain: &'a mut Chain,
+    instance_chains: &mut HashMap<usize, Digest>,
+    instance: usize,
+) -> Result<&'a mut Chain> {
+    let spawn = match instance_chains.get(&instance) {
+        // The store's chain may have been replaced since the instance's
+        // chain was spawned.
+        Some(&spawn) if chain.child(spawn).is_some() => spawn,
+        _ => {
+            let spawn = chain.spawn(&format!("instance {instance}"))?;
+            instance_chains.insert(instance, spawn);
+            spawn
+        }
+    };
+    Ok(chain.child_mut(spawn).unwrap())
+}
+
 fn get_fuel(injected_fuel: i64, fuel_reserve: u64) -> u64 {
     fuel_reserve.saturating_add_signed(-injected_fuel)
 }
@@ -1917,8 +2068,24 @@ impl StoreOpaque {
     ///
     /// This only works on async futures and stores, and assumes that we're
     /// executing on a fiber. This will yield execution back to the caller once.
+    ///
+    /// With `Config::chain_record` enabled, `yield` and `resume` events are
+    /// recorded around the yield.
+    #[cfg(feature = "async")]
+    fn async_yield_impl(&mut self, reason: YieldReason) -> Result<()> {
+        let record = self.engine().config().chain_record && !self.chain_sampled_out();
+        if record {
+            crate::chain::record::yield_point(self, crate::chain::record::YIELD, reason)?;
+        }
+        self.async_yield_unrecorded()?;
+        if record {
+            crate::chain::record::yield_point(self, crate::chain::record::RESUME, reason)?;
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "async")]
-    fn async_yield_impl(&mut self) -> Result<()> {
+    fn async_yield_unrecorded(&mut self) -> Result<()> {
         use crate::runtime::vm::Yield;
 
         let mut future = Yield::new();
@@ -2173,6 +2340,135 @@ at https://bytecodealliance.org/security.
         self.chain.add(event);
     }
 
+    /// The chain events are being recorded into along with the resource
+    /// registry.
+    ///
+    /// With `Config::chain_per_instance` this is the chain of the innermost
+    /// running component instance, which is spawned on first use.
+    pub(crate) fn chain_and_registry_mut(&mut self) -> Result<(&mut Chain, &mut ResourceRegistry)> {
+        let instance = match self.chain_instances.last() {
+            Some(&instance) if self.engine.config().chain_per_instance => instance,
+            _ => return Ok((&mut self.chain, &mut self.resource_registry)),
+        };
+        let chain = instance_chain_mut(&mut self.chain, &mut self.instance_chains, instance)?;
+        Ok((chain, &mut self.resource_registry))
+    }
+
+    /// The child chain the component instance `instance` records into with
+    /// `Config::chain_per_instance`, spawned on first use.
+    pub(crate) fn instance_chain_mut(&mut self, instance: usize) -> Result<&mut Chain> {
+        instance_chain_mut(&mut self.chain, &mut self.instance_chains, instance)
+    }
+
+    /// Hash of the `spawn` event of each component instance's chain.
+    pub(crate) fn instance_chains(&self) -> &HashMap<usize, Digest> {
+        &self.instance_chains
+    }
+
+    pub(crate) fn instance_chains_mut(&mut self) -> &mut HashMap<usize, Digest> {
+        &mut self.instance_chains
+    }
+
+    /// The recording answering host calls while an instance is resumed, if
+    /// any.
+    pub(crate) fn chain_resume(&self) -> Option<&Replay> {
+        self.chain_resume.as_ref()
+    }
+
+    pub(crate) fn set_chain_resume(&mut self, replay: Option<Replay>) {
+        self.chain_resume = replay;
+    }
+
+    /// The innermost component instance running a recorded export, if any.
+    pub(crate) fn current_chain_instance(&self) -> Option<usize> {
+        self.chain_instances.last().copied()
+    }
+
+    /// Records events into the chain of the component instance `instance`
+    /// until the matching [`StoreOpaque::pop_chain_instance`].
+    pub(crate) fn push_chain_instance(&mut self, instance: usize) {
+        self.chain_instances.push(instance);
+    }
+
+    pub(crate) fn pop_chain_instance(&mut self) {
+        self.chain_instances.pop();
+    }
+
+    /// Decides whether the call to the export `name` starting now is
+    /// recorded. Calls inside one which isn't aren't either.
+    ///
+    /// Calls which aren't recorded must be ended with
+    /// [`StoreOpaque::end_unsampled_call`].
+    pub(crate) fn sample_chain_call(&mut self, name: &str) -> Sample {
+        let sample = match self.unsampled_calls {
+            0 => self.chain_sampler.sample(name),
+            _ => Sample::Skip,
+        };
+        if sample == Sample::Skip {
+            self.unsampled_calls += 1;
+        }
+        sample
+    }
+
+    pub(crate) fn end_unsampled_call(&mut self) {
+        self.unsampled_calls -= 1;
+    }
+
+    /// Whether an export call the sampler left out is running, so nothing
+    /// is recorded.
+    pub(crate) fn chain_sampled_out(&self) -> bool {
+        self.unsampled_calls > 0
+    }
+
+    pub(crate) fn core_ref_registry_mut(&mut self) -> &mut CoreRefRegistry {
+        &mut self.core_ref_registry
+    }
+
+    /// Hash of the `import-call` event of the innermost host function
+    /// running, which is the `call_parent` of events recorded now.
+    pub(crate) fn call_parent(&self) -> Option<Digest> {
+        self.call_frames.iter().rev().find_map(|frame| match frame {
+            CallFrame::Import(hash) => Some(*hash),
+            CallFrame::Export(_) => None,
+        })
+    }
+
+    /// Name of the innermost recorded export call running.
+    pub(crate) fn current_export(&self) -> Option<&str> {
+        self.call_frames.iter().rev().find_map(|frame| match frame {
+            CallFrame::Export(name) => Some(name.as_str()),
+            CallFrame::Import(_) => None,
+        })
+    }
+
+    /// Opens a frame for a recorded call to the export `name`.
+    pub(crate) fn push_export_frame(&mut self, name: String) {
+        self.call_frames.push(CallFrame::Export(name));
+    }
+
+    /// Opens a frame for the import call recorded as `hash`.
+    pub(crate) fn push_import_frame(&mut self, hash: Digest) {
+        self.call_frames.push(CallFrame::Import(hash));
+    }
+
+    /// Closes the innermost frame, which belongs to an import call.
+    pub(crate) fn pop_import_frame(&mut self) {
+        self.call_frames.pop();
+    }
+
+    /// Closes the innermost export call's frame along with any import calls
+    /// inside it whose host functions failed before returning, returning the
+    /// export's name.
+    pub(crate) fn pop_export_frame(&mut self) -> String {
+        loop {
+            match self.call_frames.pop() {
+                Some(CallFrame::Import(_)) => {}
+                Some(CallFrame::Export(name)) => return name,
+                None => return String::new(),
+            }
+        }
+    }
+
     pub(crate) fn interpreter(&mut self) -> Option<InterpreterRef<'_>> {
         let i = self.interpreter.as_mut()?;
         Some(i.as_interpreter_ref())
@@ -2682,7 +2978,7 @@ unsafe impl<T> crate::runtime::vm::VMStore for StoreInner<T> {
         }
         #[cfg(feature = "async")]
         if self.fuel_yield_interval.is_some() {
-            self.async_yield_impl()?;
+            self.async_yield_impl(YieldReason::Fuel)?;
         }
         Ok(())
     }
@@ -2691,11 +2987,20 @@ unsafe impl<T> crate::runtime::vm::VMStore for StoreInner<T> {
         // Temporarily take the configured behavior to avoid mutably borrowing
         // multiple times.
         let mut behavior = self.epoch_deadline_behavior.take();
+        let record = self.engine().config().chain_record && !self.chain_sampled_out();
         let delta_result = match &mut behavior {
+            None if record => crate::chain::record::epoch_interrupt(self, EpochAction::Trap)
+                .and_then(|()| Err(Trap::Interrupt.into())),
             None => Err(Trap::Interrupt.into()),
             Some(callback) => callback((&mut *self).as_context_mut()).and_then(|update| {
                 let delta = match update {
-                    UpdateDeadline::Continue(delta) => delta,
+                    UpdateDeadline::Continue(delta) => {
+                        if record {
+                            let action = EpochAction::Continue { delta };
+                            crate::chain::record::epoch_interrupt(self, action)?;
+                        }
+                        delta
+                    }
 
                     #[cfg(feature = "async")]
                     UpdateDeadline::Yield(delta) => {
@@ -2703,9 +3008,13 @@ unsafe impl<T> crate::runtime::vm::VMStore for StoreInner<T> {
                             self.async_support(),
                             "cannot use `UpdateDeadline::Yield` without enabling async support in the config"
                         );
+                        if record {
+                            let action = EpochAction::Yield { delta };
+                            crate::chain::record::epoch_interrupt(self, action)?;
+                        }
                         // Do the async yield. May return a trap if future was
                         // canceled while we're yielded.
-                        self.async_yield_impl()?;
+                        self.async_yield_impl(YieldReason::Epoch)?;
                         delta
                     }
                 };
@@ -55,7 +55,7 @@
 //! ```
 
 use crate::prelude::*;
-use crate::runtime::vm::table::{Table, TableElementType};
+use crate::runtime::vm::table::{Table, TableElement, TableElementType};
 use crate::runtime::vm::vmcontext::VMFuncRef;
 use crate::runtime::vm::{HostResultHasUnwindSentinel, Instance, TrapReason, VMGcRef, VMStore};
 use core::convert::Infallible;
@@ -156,16 +156,71 @@ fn memory32_grow(
     delta: u64,
     memory_index: u32,
 ) -> Result<Option<AllocationSize>, TrapReason> {
+    let index = memory_index;
     let memory_index = MemoryIndex::from_u32(memory_index);
-    let result = instance
-        .memory_grow(store, memory_index, delta)?
-        .map(|size_in_bytes| {
-            AllocationSize(size_in_bytes / instance.memory_page_size(memory_index))
-        });
+    let grown = instance.memory_grow(store, memory_index, delta)?;
+    if records_chain(store) {
+        let size = instance.get_memory(memory_index).current_length();
+        let type_ = crate::chain::record::MEMORY_GROW;
+        record_growth(store, instance, type_, index, grown, size)?;
+    }
+    let result = grown.map(|size_in_bytes| {
+        AllocationSize(size_in_bytes / instance.memory_page_size(memory_index))
+    });
 
     Ok(result)
 }
 
+/// Whether `store` records into its chain right now, which it doesn't in
+/// calls its sampling leaves out.
+fn records_chain(store: &dyn VMStore) -> bool {
+    let store = store.store_opaque();
+    store.engine().config().chain_record && !store.chain_sampled_out()
+}
+
+/// Records a `memory.grow` or `table.grow` of the item with `index` in
+/// `instance`, given the previous size if it grew and its size now.
+fn record_growth(
+    store: &mut dyn VMStore,
+    instance: &Instance,
+    type_: &str,
+    index: u32,
+    grown: Option<usize>,
+    size: usize,
+) -> Result<()> {
+    let (old_size, new_size) = match grown {
+        Some(old_size) => (old_size, Some(size)),
+        None => (size, None),
+    };
+    let module = instance.env_module().name.clone();
+    crate::chain::record::growth(
+        store.store_opaque_mut(),
+        type_,
+        module,
+        index,
+        old_size,
+        new_size,
+    )
+}
+
+/// Calls `table.grow` on `instance` and records it when the store records
+/// into its chain.
+unsafe fn table_grow(
+    store: &mut dyn VMStore,
+    instance: &mut Instance,
+    table_index: TableIndex,
+    delta: u64,
+    element: TableElement,
+) -> Result<Option<AllocationSize>> {
+    let grown = instance.table_grow(store, table_index, delta, element)?;
+    if records_chain(store) {
+        let size = (*instance.get_table(table_index)).size();
+        let type_ = crate::chain::record::TABLE_GROW;
+        record_growth(store, instance, type_, table_index.as_u32(), grown, size)?;
+    }
+    Ok(grown.map(AllocationSize))
+}
+
 /// A helper structure to represent the return value of a memory or table growth
 /// call.
 ///
@@ -218,10 +273,7 @@ unsafe fn table_grow_func_ref(
         TableElementType::GcRef => unreachable!(),
     };
 
-    let result = instance
-        .table_grow(store, table_index, delta, element)?
-        .map(AllocationSize);
-    Ok(result)
+    table_grow(store, instance, table_index, delta, element)
 }
 
 /// Implementation of `table.grow` for GC-reference tables.
@@ -247,10 +299,7 @@ unsafe fn table_grow_gc_ref(
             .into(),
     };
 
-    let result = instance
-        .table_grow(store, table_index, delta, element)?
-        .map(AllocationSize);
-    Ok(result)
+    table_grow(store, instance, table_index, delta, element)
 }
 
 /// Implementation of `table.fill` for `funcref`s.
@@ -58,6 +58,10 @@ enum Subcommand {
     #[cfg(feature = "run")]
     Run(wasmtime_cli::commands::RunCommand),
 
+    /// Inspects and checks recorded event chains
+    #[cfg(feature = "chain")]
+    Chain(wasmtime_cli::commands::ChainCommand),
+
     /// Controls Wasmtime configuration settings
     #[cfg(feature = "cache")]
     Config(wasmtime_cli::commands::ConfigCommand),
@@ -99,6 +103,9 @@ impl Wasmtime {
             #[cfg(feature = "run")]
             Subcommand::Run(c) => c.execute(),
 
+            #[cfg(feature = "chain")]
+            Subcommand::Chain(c) => c.execute(),
+
             #[cfg(feature = "cache")]
             Subcommand::Config(c) => c.execute(),
 
@@ -1,5 +1,10 @@
 //! The module for the Wasmtime CLI commands.
 
+#[cfg(feature = "chain")]
+mod chain;
+#[cfg(feature = "chain")]
+pub use self::chain::*;
+
 #[cfg(feature = "run")]
 mod run;
 #[cfg(feature = "run")]
@@ -0,0 +1,485 @@
+//! The module that implements the `wasmtime chain` command.
+
+use anyhow::{bail, Context as _, Result};
+use clap::{Parser, Subcommand};
+use std::io::Write as _;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use wasmtime::chain::hasher::hasher_by_name;
+use wasmtime::chain::{
+    record, wasi, Chain, ChainWasiPolicy, FunctionCall, ImportCall, MetaEvent, SerializableVal,
+    TestSuite,
+};
+use wasmtime::component::Component;
+use wasmtime::{Engine, Store};
+use wasmtime_cli_flags::CommonOptions;
+
+/// Inspects and checks recorded event chains
+#[derive(Parser)]
+pub struct ChainCommand {
+    #[command(subcommand)]
+    subcommand: ChainSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ChainSubcommand {
+    /// Prints the events of a chain file
+    Inspect(ChainInspectCommand),
+    /// Checks the integrity of a chain file
+    Verify(ChainVerifyCommand),
+    /// Replays the calls recorded in a chain file against a component
+    Replay(ChainReplayCommand),
+    /// Converts the calls recorded in a chain file into OpenTelemetry spans
+    Otlp(ChainOtlpCommand),
+    /// Renders chain files as a Graphviz graph
+    Dot(ChainDotCommand),
+    /// Generates a test suite from the calls recorded in a chain file
+    Testgen(ChainTestgenCommand),
+    /// Runs a generated test suite against a component
+    Test(ChainTestCommand),
+}
+
+impl ChainCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        match self.subcommand {
+            ChainSubcommand::Inspect(c) => c.execute(),
+            ChainSubcommand::Verify(c) => c.execute(),
+            ChainSubcommand::Replay(c) => c.execute(),
+            ChainSubcommand::Otlp(c) => c.execute(),
+            ChainSubcommand::Dot(c) => c.execute(),
+            ChainSubcommand::Testgen(c) => c.execute(),
+            ChainSubcommand::Test(c) => c.execute(),
+        }
+    }
+}
+
+/// Opens the chain file at `path`, which unlike `Chain::open` must already
+/// exist.
+fn open(path: &Path) -> Result<Chain> {
+    if !path.exists() {
+        bail!("chain file `{}` does not exist", path.display());
+    }
+    Chain::open(path)
+}
+
+/// Prints the events of a chain file
+#[derive(Parser)]
+pub struct ChainInspectCommand {
+    /// The path of the chain file to inspect
+    #[arg(value_name = "CHAIN_FILE")]
+    path: PathBuf,
+
+    /// Only print events of this type; may be given more than once
+    #[arg(long = "type", value_name = "TYPE")]
+    types: Vec<String>,
+
+    /// Only print events whose index is in this range, such as `10..20`,
+    /// `10..`, `..20` or `10`
+    #[arg(long, value_name = "START..END")]
+    range: Option<EventRange>,
+}
+
+impl ChainInspectCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        let chain = open(&self.path)?;
+        let range = self.range.map(|r| r.0).unwrap_or(0..usize::MAX);
+        let events = chain
+            .store()
+            .iter_from(range.start)
+            .zip(range.clone())
+            .filter(|(node, _)| {
+                self.types.is_empty() || self.types.iter().any(|t| t == node.event().type_())
+            });
+        for (node, index) in events {
+            print_event(index, node);
+        }
+        Ok(())
+    }
+}
+
+/// Checks the integrity of a chain file
+///
+/// Every event must link to the one before it and match its stored hash. With
+/// `--public-key` every event must also carry a valid Ed25519 signature by
+/// that key. The first problem found is reported and the command fails.
+#[derive(Parser)]
+pub struct ChainVerifyCommand {
+    /// The path of the chain file to verify
+    #[arg(value_name = "CHAIN_FILE")]
+    path: PathBuf,
+
+    /// Hex-encoded Ed25519 public key the events must be signed with
+    #[arg(long, value_name = "HEX", value_parser = parse_public_key)]
+    public_key: Option<[u8; 32]>,
+}
+
+impl ChainVerifyCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        // Opening a chain file verifies its hash links.
+        let chain = open(&self.path)?;
+        match &self.public_key {
+            Some(key) => {
+                chain.verify_signatures(key).with_context(|| {
+                    format!("chain file `{}` failed verification", self.path.display())
+                })?;
+                println!(
+                    "{}: {} events verified, all signed",
+                    self.path.display(),
+                    chain.len()
+                );
+            }
+            None => {
+                println!("{}: {} events verified", self.path.display(), chain.len());
+                let signed = chain
+                    .store()
+                    .iter_from(0)
+                    .filter(|node| node.signature().is_some())
+                    .count();
+                if signed > 0 {
+                    println!(
+                        "{signed} events are signed; pass `--public-key` to check their signatures"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replays the calls recorded in a chain file against a component
+///
+/// Every export call in the recording is made again, in order, with the
+/// component's imports answered from the recorded results instead of real
+/// host functions. The replay fails if the component makes imports other
+/// than the recorded ones.
+#[derive(Parser)]
+pub struct ChainReplayCommand {
+    #[command(flatten)]
+    common: CommonOptions,
+
+    /// Also record the replay and fail at the first event which differs
+    /// from the recording
+    #[arg(long)]
+    verify: bool,
+
+    /// The path of the chain file to replay
+    #[arg(value_name = "CHAIN_FILE")]
+    path: PathBuf,
+
+    /// The component to replay the recording against
+    #[arg(value_name = "COMPONENT")]
+    component: PathBuf,
+}
+
+impl ChainReplayCommand {
+    /// Executes the command.
+    pub fn execute(mut self) -> Result<()> {
+        self.common.init_logging()?;
+
+        let recording = open(&self.path)?;
+        let mut config = self.common.config(None)?;
+        if self.verify {
+            // The replay has to record the same kinds of events as the
+            // recording for the two to line up.
+            let wasi = recording
+                .store()
+                .iter_from(0)
+                .filter(|node| node.event().type_() == record::IMPORT_CALL)
+                .filter_map(|node| ImportCall::decode(node.event()).ok())
+                .fold(
+                    ChainWasiPolicy::none(),
+                    |policy, call| match wasi::package(&call.name) {
+                        Some(package) => policy.package(package, true),
+                        None => policy,
+                    },
+                );
+            let instantiation = recording
+                .store()
+                .iter_from(0)
+                .any(|node| node.event().type_() == record::INSTANTIATE);
+            let post_return = recording
+                .store()
+                .iter_from(0)
+                .any(|node| node.event().type_() == record::POST_RETURN);
+            let types = recording.store().iter_from(0).any(|node| {
+                node.event().type_() == record::FUNCTION_CALL
+                    && FunctionCall::decode(node.event()).is_ok_and(|c| c.signature.is_some())
+            });
+            config
+                .chain_record(true)
+                .chain_wasi_policy(wasi)
+                .chain_record_instantiation(instantiation)
+                .chain_record_post_return(post_return)
+                .chain_record_types(types);
+        }
+        let engine = Engine::new(&config)?;
+        let component = Component::from_file(&engine, &self.component)
+            .with_context(|| format!("failed to load component `{}`", self.component.display()))?;
+
+        let mut store = Store::new(&engine, ());
+        if self.verify {
+            let hasher = hasher_by_name(recording.hasher().name())
+                .context("chain file uses an unknown hasher")?;
+            store.set_chain(Chain::with_hasher(hasher));
+        }
+        wasmtime::chain::replay(&mut store, &component, &recording).with_context(|| {
+            format!(
+                "failed to replay `{}` against `{}`",
+                self.path.display(),
+                self.component.display()
+            )
+        })?;
+
+        if self.verify {
+            println!(
+                "{}: replayed {} events without divergence",
+                self.path.display(),
+                recording.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn parse_public_key(s: &str) -> Result<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        bail!("expected 64 hex digits");
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).context("invalid hex digit")?;
+    }
+    Ok(key)
+}
+
+fn print_event(index: usize, node: &MetaEvent) {
+    let event = node.event();
+    println!("#{index} {}", node.hash());
+    match event.parent() {
+        Some(parent) => println!("  parent:    {parent}"),
+        None => println!("  parent:    none"),
+    }
+    println!("  type:      {}", event.type_());
+    if let Some(signature) = node.signature() {
+        println!("  signature: {}", hex(signature));
+    }
+    if event.type_() == record::FUNCTION_CALL {
+        if let Ok(call) = FunctionCall::decode(event) {
+            println!(
+                "  call:      {}{:#} -> {:#}",
+                call.name,
+                SerializableVal::Tuple(call.params),
+                SerializableVal::Tuple(call.results)
+            );
+            if let Some(signature) = call.signature {
+                println!("  types:     {signature}");
+            }
+        }
+    }
+    // Recorded events carry JSON payloads, so show those structured and fall
+    // back to the raw text or bytes for anything else.
+    let data = event.data();
+    let data = match serde_json::from_slice::<serde_json::Value>(&data) {
+        Ok(json) => serde_json::to_string_pretty(&json).unwrap(),
+        Err(_) => match std::str::from_utf8(&data) {
+            Ok(text) => format!("{text:?}"),
+            Err(_) => hex(&data),
+        },
+    };
+    let mut lines = data.lines();
+    println!("  data:      {}", lines.next().unwrap_or(""));
+    for line in lines {
+        println!("             {line}");
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Converts the calls recorded in a chain file into OpenTelemetry spans
+///
+/// The spans are written as an OTLP/JSON trace export request, which can be
+/// posted to the `/v1/traces` endpoint of an OpenTelemetry collector, Jaeger
+/// or Tempo. Chains don't record timestamps, so the first event is placed at
+/// the current time and each later event one microsecond after the previous.
+#[derive(Parser)]
+pub struct ChainOtlpCommand {
+    /// The path of the chain file to convert
+    #[arg(value_name = "CHAIN_FILE")]
+    path: PathBuf,
+
+    /// The `service.name` the spans are reported under
+    #[arg(long, value_name = "NAME", default_value = "wasmtime")]
+    service_name: String,
+
+    /// Write the spans to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+impl ChainOtlpCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        let chain = open(&self.path)?;
+        let json = chain.to_otlp_json(&self.service_name, SystemTime::now())?;
+        match &self.output {
+            Some(path) => std::fs::write(path, json)
+                .with_context(|| format!("failed to write `{}`", path.display()))?,
+            None => std::io::stdout().write_all(&json)?,
+        }
+        Ok(())
+    }
+}
+
+/// Renders chain files as a Graphviz graph
+///
+/// Every event is drawn as a node, with edges for parent links, the nesting
+/// of recorded calls, spawned child chains and merges. Passing the files of
+/// related chains together, such as a parent and its children, draws the
+/// edges between them. Render the output with, for example, `dot -Tsvg`.
+#[derive(Parser)]
+pub struct ChainDotCommand {
+    /// The paths of the chain files to render
+    #[arg(value_name = "CHAIN_FILE", required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Write the graph to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+impl ChainDotCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        let chains = self
+            .paths
+            .iter()
+            .map(|path| open(path))
+            .collect::<Result<Vec<_>>>()?;
+        let dot = wasmtime::chain::to_dot(&chains.iter().collect::<Vec<_>>());
+        match &self.output {
+            Some(path) => std::fs::write(path, dot)
+                .with_context(|| format!("failed to write `{}`", path.display()))?,
+            None => print!("{dot}"),
+        }
+        Ok(())
+    }
+}
+
+/// Generates a test suite from the calls recorded in a chain file
+///
+/// Every export call becomes a test case holding its arguments, what it
+/// returned or that it trapped, and what the host imports it made returned.
+/// The suite is written as JSON and run with `wasmtime chain test`.
+#[derive(Parser)]
+pub struct ChainTestgenCommand {
+    /// The path of the chain file to generate tests from
+    #[arg(value_name = "CHAIN_FILE")]
+    path: PathBuf,
+
+    /// Write the suite to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+impl ChainTestgenCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        let chain = open(&self.path)?;
+        let json = TestSuite::from_chain(&chain)?.to_json()?;
+        match &self.output {
+            Some(path) => std::fs::write(path, json)
+                .with_context(|| format!("failed to write `{}`", path.display()))?,
+            None => println!("{json}"),
+        }
+        Ok(())
+    }
+}
+
+/// Runs a generated test suite against a component
+///
+/// The cases are run in order on a single instance, with the component's
+/// imports answered from the suite, and the command fails at the first case
+/// which doesn't behave as it did when recorded.
+#[derive(Parser)]
+pub struct ChainTestCommand {
+    #[command(flatten)]
+    common: CommonOptions,
+
+    /// The path of the test suite, as written by `wasmtime chain testgen`
+    #[arg(value_name = "SUITE")]
+    suite: PathBuf,
+
+    /// The component to run the suite against
+    #[arg(value_name = "COMPONENT")]
+    component: PathBuf,
+}
+
+impl ChainTestCommand {
+    /// Executes the command.
+    pub fn execute(mut self) -> Result<()> {
+        self.common.init_logging()?;
+
+        let json = std::fs::read_to_string(&self.suite)
+            .with_context(|| format!("failed to read `{}`", self.suite.display()))?;
+        let suite = TestSuite::from_json(&json)?;
+        let engine = Engine::new(&self.common.config(None)?)?;
+        let component = Component::from_file(&engine, &self.component)
+            .with_context(|| format!("failed to load component `{}`", self.component.display()))?;
+        suite
+            .run(Store::new(&engine, ()), &component)
+            .with_context(|| {
+                format!(
+                    "`{}` failed against `{}`",
+                    self.suite.display(),
+                    self.component.display()
+                )
+            })?;
+        println!(
+            "{}: {} cases passed",
+            self.suite.display(),
+            suite.cases.len()
+        );
+        Ok(())
+    }
+}
+
+/// A range of event indices parsed from the command line.
+#[derive(Clone, Debug)]
+struct EventRange(Range<usize>);
+
+impl FromStr for EventRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<EventRange> {
+        let index = |s: &str| {
+            s.parse::<usize>()
+                .with_context(|| format!("invalid event index `{s}`"))
+        };
+        let range = match s.split_once("..") {
+            Some((start, end)) => {
+                let start = if start.is_empty() { 0 } else { index(start)? };
+                let end = if end.is_empty() {
+                    usize::MAX
+                } else {
+                    index(end)?
+                };
+                start..end
+            }
+            None => {
+                let i = index(s)?;
+                let end = i
+                    .checked_add(1)
+                    .with_context(|| format!("event index `{s}` is too large"))?;
+                i..end
+            }
+        };
+        Ok(EventRange(range))
+    }
+}
@@ -69,6 +69,26 @@ pub struct RunCommand {
     #[arg(long)]
     pub argv0: Option<String>,
 
+    /// Record the component's instantiation and calls, including WASI calls,
+    /// into an event chain written to this file.
+    ///
+    /// An existing file at this path is replaced. The recording can be looked
+    /// at with `wasmtime chain inspect`.
+    #[cfg(feature = "chain")]
+    #[arg(long, value_name = "PATH")]
+    pub record_chain: Option<PathBuf>,
+
+    /// Only record calls to these WASI packages into `--record-chain`, such
+    /// as `clocks,random`, instead of calls to all of them.
+    #[cfg(feature = "chain")]
+    #[arg(
+        long,
+        value_name = "PACKAGES",
+        value_delimiter = ',',
+        requires = "record_chain"
+    )]
+    pub record_chain_wasi: Option<Vec<String>>,
+
     /// The WebAssembly module to run and arguments to pass to it.
     ///
     /// Arguments passed to the wasm module will be configured as WASI CLI
@@ -106,6 +126,24 @@ impl RunCommand {
             None => {}
         }
 
+        #[cfg(feature = "chain")]
+        if self.record_chain.is_some() {
+            let wasi = match &self.record_chain_wasi {
+                Some(packages) => packages
+                    .iter()
+                    .fold(wasmtime::chain::ChainWasiPolicy::none(), |policy, p| {
+                        policy.package(p, true)
+                    }),
+                None => wasmtime::chain::ChainWasiPolicy::all(),
+            };
+            config
+                .chain_record(true)
+                .chain_wasi_policy(wasi)
+                .chain_record_instantiation(true)
+                .chain_record_post_return(true)
+                .chain_record_types(true);
+        }
+
         let engine = Engine::new(&config)?;
 
         // Read the wasm module binary either as `*.wat` or a raw binary.
@@ -152,6 +190,19 @@ impl RunCommand {
         };
 
         let mut store = Store::new(&engine, host);
+
+        #[cfg(feature = "chain")]
+        if let Some(path) = &self.record_chain {
+            if let RunTarget::Core(_) = &main {
+                bail!("`--record-chain` is only supported with components");
+            }
+            if path.exists() {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("failed to replace `{}`", path.display()))?;
+            }
+            store.set_chain(wasmtime::chain::Chain::open(path)?);
+        }
+
         self.populate_with_wasi(&mut linker, &mut store, &main)?;
 
         store.data_mut().limits = self.run.store_limits();
@@ -230,6 +281,15 @@ impl RunCommand {
             .await
         });
 
+        // Make sure the recording survives the process exiting below.
+        #[cfg(feature = "chain")]
+        if self.record_chain.is_some() {
+            store
+                .set_chain(wasmtime::chain::Chain::new())
+                .flush()
+                .context("failed to write the recorded chain")?;
+        }
+
         // Load the main wasm module.
         match result.unwrap_or_else(|elapsed| {
             Err(anyhow::Error::from(wasmtime::Trap::Interrupt))
@@ -472,15 +532,9 @@ impl RunCommand {
 
                 let component = module.unwrap_component();
 
-                let command = wasmtime_wasi::bindings::Command::instantiate_async(
-                    &mut *store,
-                    component,
-                    linker,
-                )
-                .await?;
-                let result = command
-                    .wasi_cli_run()
-                    .call_run(&mut *store)
+                let instance = linker.instantiate_async(&mut *store, component).await?;
+                let result = self
+                    .call_run(&mut *store, component, &instance)
                     .await
                     .context("failed to invoke `run` function")
                     .map_err(|e| self.handle_core_dump(&mut *store, e));
@@ -498,6 +552,47 @@ impl RunCommand {
         result
     }
 
+    /// Calls the `wasi:cli/run` export of a component instance.
+    #[cfg(feature = "component-model")]
+    async fn call_run(
+        &self,
+        store: &mut Store<Host>,
+        component: &wasmtime::component::Component,
+        instance: &wasmtime::component::Instance,
+    ) -> Result<Result<(), ()>> {
+        // Calls through typed bindings aren't recorded into the chain, so
+        // when recording look up and call `run` dynamically instead.
+        #[cfg(feature = "chain")]
+        if self.record_chain.is_some() {
+            use wasmtime::component::Val;
+
+            let engine = store.engine().clone();
+            let interface = component
+                .component_type()
+                .exports(&engine)
+                .map(|(name, _)| name)
+                .find(|name| name.starts_with("wasi:cli/run@"))
+                .ok_or_else(|| anyhow!("no `wasi:cli/run` export found"))?
+                .to_string();
+            let run = component
+                .export_index(None, &interface)
+                .and_then(|(_, i)| component.export_index(Some(&i), "run"))
+                .and_then(|(_, i)| instance.get_func(&mut *store, &i))
+                .ok_or_else(|| anyhow!("no `run` function exported from `{interface}`"))?;
+            let mut results = [Val::Bool(false)];
+            run.call_async(&mut *store, &[], &mut results).await?;
+            run.post_return_async(&mut *store).await?;
+            return match &results[0] {
+                Val::Result(Ok(_)) => Ok(Ok(())),
+                Val::Result(Err(_)) => Ok(Err(())),
+                _ => bail!("`run` returned an unexpected value"),
+            };
+        }
+
+        let command = wasmtime_wasi::bindings::Command::new(&mut *store, instance)?;
+        command.wasi_cli_run().call_run(&mut *store).await
+    }
+
     async fn invoke_func(&self, store: &mut Store<Host>, func: Func) -> Result<()> {
         let ty = func.ty(&store);
         if ty.params().len() > 0 {
@@ -9,15 +9,18 @@ use std::{
         Arc,
     },
 };
-use wasmtime::component::Linker;
+use wasmtime::component::{Linker, Resource};
 use wasmtime::{Engine, Store, StoreLimits};
 use wasmtime_wasi::{StreamError, StreamResult, WasiCtx, WasiCtxBuilder, WasiView};
-use wasmtime_wasi_http::bindings::http::types::Scheme;
+use wasmtime_wasi_http::bindings::http::types::{ErrorCode, Scheme};
 use wasmtime_wasi_http::bindings::ProxyPre;
 use wasmtime_wasi_http::io::TokioIo;
+use wasmtime_wasi_http::types::{
+    default_send_request, HostFutureIncomingResponse, HostIncomingRequest, OutgoingRequestConfig,
+};
 use wasmtime_wasi_http::{
-    body::HyperOutgoingBody, WasiHttpCtx, WasiHttpView, DEFAULT_OUTGOING_BODY_BUFFER_CHUNKS,
-    DEFAULT_OUTGOING_BODY_CHUNK_SIZE,
+    body::HyperOutgoingBody, HttpError, HttpResult, WasiHttpCtx, WasiHttpView,
+    DEFAULT_OUTGOING_BODY_BUFFER_CHUNKS, DEFAULT_OUTGOING_BODY_CHUNK_SIZE,
 };
 
 #[cfg(feature = "wasi-config")]
@@ -27,6 +30,9 @@ use wasmtime_wasi_keyvalue::{WasiKeyValue, WasiKeyValueCtx, WasiKeyValueCtxBuild
 #[cfg(feature = "wasi-nn")]
 use wasmtime_wasi_nn::wit::WasiNnCtx;
 
+#[cfg(feature = "chain")]
+mod record;
+
 struct Host {
     table: wasmtime::component::ResourceTable,
     ctx: WasiCtx,
@@ -44,6 +50,9 @@ struct Host {
 
     #[cfg(feature = "wasi-keyvalue")]
     wasi_keyvalue: Option<WasiKeyValueCtx>,
+
+    #[cfg(feature = "chain")]
+    recorder: Option<record::RequestRecorder>,
 }
 
 impl WasiView for Host {
@@ -74,6 +83,18 @@ impl WasiHttpView for Host {
         self.http_outgoing_body_chunk_size
             .unwrap_or_else(|| DEFAULT_OUTGOING_BODY_CHUNK_SIZE)
     }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        #[cfg(feature = "chain")]
+        if let Some(recorder) = &self.recorder {
+            return recorder.outgoing(request, config).map_err(HttpError::trap);
+        }
+        Ok(default_send_request(request, config))
+    }
 }
 
 const DEFAULT_ADDR: std::net::SocketAddr = std::net::SocketAddr::new(
@@ -96,6 +117,17 @@ pub struct ServeCommand {
     #[arg(long = "no-logging-prefix")]
     no_logging_prefix: bool,
 
+    /// Record every request served, its response, and the HTTP requests the
+    /// component makes into an event chain written to this file.
+    ///
+    /// An existing file at this path is replaced. Request bodies are read in
+    /// full before the component is run, and bodies are recorded by their
+    /// SHA-256 digest. The recording can be looked at with
+    /// `wasmtime chain inspect`.
+    #[cfg(feature = "chain")]
+    #[arg(long, value_name = "PATH")]
+    record_chain: Option<PathBuf>,
+
     /// The WebAssembly component to run.
     #[arg(value_name = "WASM", required = true)]
     component: PathBuf,
@@ -185,6 +217,9 @@ impl ServeCommand {
             wasi_config: None,
             #[cfg(feature = "wasi-keyvalue")]
             wasi_keyvalue: None,
+
+            #[cfg(feature = "chain")]
+            recorder: None,
         };
 
         if self.run.common.wasi.nn == Some(true) {
@@ -403,7 +438,7 @@ impl ServeCommand {
 
         log::info!("Listening on {}", self.addr);
 
-        let handler = ProxyHandler::new(self, engine, instance);
+        let handler = ProxyHandler::new(self, engine, instance)?;
 
         loop {
             let (stream, _) = listener.accept().await?;
@@ -468,6 +503,8 @@ struct ProxyHandlerInner {
     engine: Engine,
     instance_pre: ProxyPre<Host>,
     next_id: AtomicU64,
+    #[cfg(feature = "chain")]
+    recorder: Option<record::HttpRecorder>,
 }
 
 impl ProxyHandlerInner {
@@ -480,18 +517,29 @@ impl ProxyHandlerInner {
 struct ProxyHandler(Arc<ProxyHandlerInner>);
 
 impl ProxyHandler {
-    fn new(cmd: ServeCommand, engine: Engine, instance_pre: ProxyPre<Host>) -> Self {
-        Self(Arc::new(ProxyHandlerInner {
+    fn new(cmd: ServeCommand, engine: Engine, instance_pre: ProxyPre<Host>) -> Result<Self> {
+        #[cfg(feature = "chain")]
+        let recorder = cmd
+            .record_chain
+            .as_deref()
+            .map(record::HttpRecorder::create)
+            .transpose()?;
+        Ok(Self(Arc::new(ProxyHandlerInner {
             cmd,
             engine,
             instance_pre,
             next_id: AtomicU64::from(0),
-        }))
+            #[cfg(feature = "chain")]
+            recorder,
+        })))
     }
 }
 
 type Request = hyper::Request<hyper::body::Incoming>;
 
+type ResponseResult = Result<hyper::Response<HyperOutgoingBody>, ErrorCode>;
+type ResponseSender = tokio::sync::oneshot::Sender<ResponseResult>;
+
 async fn handle_request(
     ProxyHandler(inner): ProxyHandler,
     req: Request,
@@ -508,7 +556,45 @@ async fn handle_request(
 
     let mut store = inner.cmd.new_store(&inner.engine, req_id)?;
 
+    #[cfg(feature = "chain")]
+    let recorder = inner.recorder.as_ref().map(|r| r.request(req_id));
+    #[cfg(feature = "chain")]
+    let req = match &recorder {
+        Some(recorder) => {
+            let req = recorder.incoming(req).await?;
+            store.data_mut().recorder = Some(recorder.clone());
+            store.data_mut().new_incoming_request(Scheme::Http, req)?
+        }
+        None => store.data_mut().new_incoming_request(Scheme::Http, req)?,
+    };
+    #[cfg(not(feature = "chain"))]
     let req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
+
+    let result = respond(&inner, store, req, sender, receiver, req_id).await;
+
+    #[cfg(feature = "chain")]
+    if let Some(recorder) = &recorder {
+        return match result {
+            Ok(resp) => Ok(recorder.response(resp)),
+            Err(e) => {
+                recorder.failed(&e)?;
+                Err(e)
+            }
+        };
+    }
+    result
+}
+
+/// Runs the component's handler for `req`, returning the response it sent
+/// through `sender`.
+async fn respond(
+    inner: &ProxyHandlerInner,
+    mut store: Store<Host>,
+    req: Resource<HostIncomingRequest>,
+    sender: ResponseSender,
+    receiver: tokio::sync::oneshot::Receiver<ResponseResult>,
+    req_id: u64,
+) -> Result<hyper::Response<HyperOutgoingBody>> {
     let out = store.data_mut().new_response_outparam(sender)?;
     let proxy = inner.instance_pre.instantiate_async(&mut store).await?;
 
@@ -0,0 +1,318 @@
+//! Recording the HTTP traffic of `wasmtime serve` into an event chain.
+//!
+//! Every incoming request is recorded as an `http-request` event once its
+//! body has been read, and the response sent for it as an `http-response`
+//! event once its body has been sent. Requests made by the component through
+//! `wasi:http/outgoing-handler` are recorded as `http-outgoing-request` and
+//! `http-outgoing-response` events. Bodies are recorded as their SHA-256
+//! digest and size rather than in full. Every payload is JSON and names the
+//! incoming request it belongs to by the id also given to the component in
+//! its `REQUEST_ID` environment variable.
+
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use http_body_util::combinators::MapErr;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame, SizeHint};
+use serde_derive::Serialize;
+use sha2::{Digest as _, Sha256};
+use std::convert::Infallible;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use wasmtime::chain::{Chain, Digest, Event, SharedChain};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request_handler, HostFutureIncomingResponse, OutgoingRequestConfig,
+};
+
+/// Event type of a request received by the server.
+const HTTP_REQUEST: &str = "http-request";
+
+/// Event type of the response sent for a request.
+const HTTP_RESPONSE: &str = "http-response";
+
+/// Event type of a request sent by the component.
+const HTTP_OUTGOING_REQUEST: &str = "http-outgoing-request";
+
+/// Event type of the response to a request sent by the component.
+const HTTP_OUTGOING_RESPONSE: &str = "http-outgoing-response";
+
+#[derive(Serialize)]
+struct HttpRequest {
+    request: u64,
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: BodyDigest,
+}
+
+#[derive(Serialize)]
+struct HttpResponse {
+    request: u64,
+    status: Option<u16>,
+    headers: Vec<(String, String)>,
+    /// What was sent of the body, or `None` if no response was sent.
+    body: Option<BodyDigest>,
+    /// Why the response, or its body, couldn't be sent in full.
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HttpOutgoingRequest {
+    request: u64,
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Serialize)]
+struct HttpOutgoingResponse {
+    request: u64,
+    /// Hash of the `http-outgoing-request` event this responds to.
+    outgoing: Digest,
+    status: Option<u16>,
+    headers: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BodyDigest {
+    sha256: String,
+    size: u64,
+}
+
+/// An incoming request's body, read in full before it was recorded.
+pub(super) type BufferedBody = MapErr<Full<Bytes>, fn(Infallible) -> hyper::Error>;
+
+/// Records HTTP traffic into a chain shared by every request.
+#[derive(Clone)]
+pub(super) struct HttpRecorder {
+    chain: SharedChain,
+}
+
+/// Records the traffic of one incoming request, see [`HttpRecorder::request`].
+#[derive(Clone)]
+pub(super) struct RequestRecorder {
+    chain: SharedChain,
+    id: u64,
+}
+
+impl HttpRecorder {
+    /// Records into a new chain file at `path`, replacing any existing file.
+    pub(super) fn create(path: &Path) -> Result<HttpRecorder> {
+        if path.exists() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to replace `{}`", path.display()))?;
+        }
+        Ok(HttpRecorder {
+            chain: SharedChain::new(Chain::open(path)?),
+        })
+    }
+
+    /// Starts recording the incoming request with the given id.
+    pub(super) fn request(&self, id: u64) -> RequestRecorder {
+        RequestRecorder {
+            chain: self.chain.clone(),
+            id,
+        }
+    }
+}
+
+impl RequestRecorder {
+    fn add(&self, type_: &str, payload: &impl serde::Serialize) -> Result<Digest> {
+        let event = Event::new(type_.to_string(), serde_json::to_vec(payload)?);
+        self.chain
+            .try_add(event)
+            .context("failed to record HTTP traffic")
+    }
+
+    /// Reads the whole body of `req` and records it, returning an equivalent
+    /// request to hand to the component.
+    pub(super) async fn incoming(
+        &self,
+        req: hyper::Request<hyper::body::Incoming>,
+    ) -> Result<hyper::Request<BufferedBody>> {
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await?.to_bytes();
+        self.add(
+            HTTP_REQUEST,
+            &HttpRequest {
+                request: self.id,
+                method: parts.method.to_string(),
+                uri: parts.uri.to_string(),
+                headers: headers(&parts.headers),
+                body: BodyDigest {
+                    sha256: hex(Sha256::digest(&body).into()),
+                    size: body.len() as u64,
+                },
+            },
+        )?;
+        let body = Full::new(body).map_err(never as fn(Infallible) -> hyper::Error);
+        Ok(hyper::Request::from_parts(parts, body))
+    }
+
+    /// Wraps the body of `resp` so that the response is recorded once the
+    /// body has been sent.
+    pub(super) fn response(
+        &self,
+        resp: hyper::Response<HyperOutgoingBody>,
+    ) -> hyper::Response<HyperOutgoingBody> {
+        let (parts, body) = resp.into_parts();
+        let body = RecordedBody {
+            body,
+            hasher: Sha256::new(),
+            size: 0,
+            pending: Some((self.clone(), parts.status.as_u16(), headers(&parts.headers))),
+        };
+        hyper::Response::from_parts(parts, body.boxed())
+    }
+
+    /// Records that no response could be sent, because of `error`.
+    pub(super) fn failed(&self, error: &anyhow::Error) -> Result<()> {
+        self.add(
+            HTTP_RESPONSE,
+            &HttpResponse {
+                request: self.id,
+                status: None,
+                headers: Vec::new(),
+                body: None,
+                error: Some(format!("{error:#}")),
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Records `request`, sends it, and records the response once its head
+    /// arrives.
+    pub(super) fn outgoing(
+        &self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> Result<HostFutureIncomingResponse> {
+        let outgoing = self.add(
+            HTTP_OUTGOING_REQUEST,
+            &HttpOutgoingRequest {
+                request: self.id,
+                method: request.method().to_string(),
+                uri: request.uri().to_string(),
+                headers: headers(request.headers()),
+            },
+        )?;
+        let recorder = self.clone();
+        let handle = wasmtime_wasi::runtime::spawn(async move {
+            let result = default_send_request_handler(request, config).await;
+            let (status, headers, error) = match &result {
+                Ok(resp) => (
+                    Some(resp.resp.status().as_u16()),
+                    headers(resp.resp.headers()),
+                    None,
+                ),
+                Err(e) => (None, Vec::new(), Some(e.to_string())),
+            };
+            recorder.add(
+                HTTP_OUTGOING_RESPONSE,
+                &HttpOutgoingResponse {
+                    request: recorder.id,
+                    outgoing,
+                    status,
+                    headers,
+                    error,
+                },
+            )?;
+            Ok(result)
+        });
+        Ok(HostFutureIncomingResponse::pending(handle))
+    }
+}
+
+/// A response body which records the response once it's been sent in full,
+/// or once sending it fails or is abandoned.
+struct RecordedBody {
+    body: HyperOutgoingBody,
+    hasher: Sha256,
+    size: u64,
+    /// The recorder along with the response's status and headers, until the
+    /// response is recorded.
+    pending: Option<(RequestRecorder, u16, Vec<(String, String)>)>,
+}
+
+impl RecordedBody {
+    fn finish(&mut self, error: Option<String>) {
+        let Some((recorder, status, headers)) = self.pending.take() else {
+            return;
+        };
+        let response = HttpResponse {
+            request: recorder.id,
+            status: Some(status),
+            headers,
+            body: Some(BodyDigest {
+                sha256: hex(self.hasher.clone().finalize().into()),
+                size: self.size,
+            }),
+            error,
+        };
+        if let Err(e) = recorder.add(HTTP_RESPONSE, &response) {
+            log::error!("[{}] :: {e:#}", recorder.id);
+        }
+    }
+}
+
+impl Body for RecordedBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, ErrorCode>>> {
+        let frame = ready!(Pin::new(&mut self.body).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    self.hasher.update(data);
+                    self.size += data.len() as u64;
+                }
+            }
+            Some(Err(e)) => {
+                let error = e.to_string();
+                self.finish(Some(error));
+            }
+            None => self.finish(None),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl Drop for RecordedBody {
+    fn drop(&mut self) {
+        self.finish(Some("the response body was not sent in full".to_string()));
+    }
+}
+
+fn headers(headers: &hyper::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn never(never: Infallible) -> hyper::Error {
+    match never {}
+}
+
+fn hex(digest: [u8; 32]) -> String {
+    Digest(digest).to_hex()
+}